@@ -23,7 +23,8 @@ impl<'a, D: Device> Device for &'a mut D {
 /// [`Device::step()`] method is invoked.
 #[derive(Debug, Clone, PartialEq)]
 pub struct StepContext {
-    /// The new position, in steps.
+    /// The step's direction: positive for a forward step, otherwise
+    /// backward.
     pub position: i64,
     /// The time (as dictated by [`crate::SystemClock::elapsed()`]) this step
     /// was taken.
@@ -46,12 +47,10 @@ where
     Infallible {
         forward,
         backward,
-        previous_position: 0,
     }
 }
 
 struct Infallible<F, B> {
-    previous_position: i64,
     forward: F,
     backward: B,
 }
@@ -65,15 +64,12 @@ where
 
     #[inline]
     fn step(&mut self, ctx: &StepContext) -> Result<(), Self::Error> {
-        let diff = ctx.position - self.previous_position;
-
-        if diff > 0 {
+        if ctx.position > 0 {
             (self.forward)();
-        } else if diff < 0 {
+        } else {
             (self.backward)();
         }
 
-        self.previous_position = ctx.position;
         Ok(())
     }
 }
@@ -92,12 +88,10 @@ where
     Fallible {
         forward,
         backward,
-        previous_position: 0,
     }
 }
 
 struct Fallible<F, B> {
-    previous_position: i64,
     forward: F,
     backward: B,
 }
@@ -111,15 +105,12 @@ where
 
     #[inline]
     fn step(&mut self, ctx: &StepContext) -> Result<(), Self::Error> {
-        let diff = ctx.position - self.previous_position;
-
-        if diff > 0 {
+        if ctx.position > 0 {
             (self.forward)()?;
-        } else if diff < 0 {
+        } else {
             (self.backward)()?;
         }
 
-        self.previous_position = ctx.position;
         Ok(())
     }
 }
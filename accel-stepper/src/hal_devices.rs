@@ -1,23 +1,59 @@
 use core::time::Duration;
 
 use crate::{Device, StepContext};
-use embedded_hal::digital::OutputPin;
+use embedded_hal::{delay::DelayNs, digital::OutputPin};
+
+/// How long the step pin is held high by default.
+pub const DEFAULT_PULSE_WIDTH: Duration = Duration::from_micros(1);
 
 /// A [`Device`] which has step and direction pins.
 ///
+/// The step pin is held high for the pulse width (see
+/// [`StepAndDirection::with_pulse_width()`]), timed by `Delay`.
+///
 /// Requires the `hal` feature.
-#[derive(Debug, Default, Clone, PartialEq)]
-pub struct StepAndDirection<Step, Direction> {
+#[derive(Debug, Clone, PartialEq)]
+pub struct StepAndDirection<Step, Direction, Delay> {
     step: Step,
     direction: Direction,
+    delay: Delay,
+    pulse_width: Duration,
 }
 
-impl<Step, Direction> StepAndDirection<Step, Direction> {
-    pub fn new(step: Step, direction: Direction) -> Self {
-        StepAndDirection { step, direction }
+impl<Step, Direction, Delay> StepAndDirection<Step, Direction, Delay> {
+    pub fn new(step: Step, direction: Direction, delay: Delay) -> Self {
+        StepAndDirection {
+            step,
+            direction,
+            delay,
+            pulse_width: DEFAULT_PULSE_WIDTH,
+        }
+    }
+
+    /// Hold the step pin high for `pulse_width`, e.g. to meet a driver's
+    /// minimum pulse.
+    pub fn with_pulse_width(mut self, pulse_width: Duration) -> Self {
+        self.pulse_width = pulse_width;
+        self
     }
 
-    pub fn into_inner(self) -> (Step, Direction) { (self.step, self.direction) }
+    pub fn pulse_width(&self) -> Duration { self.pulse_width }
+
+    pub fn into_inner(self) -> (Step, Direction, Delay) {
+        (self.step, self.direction, self.delay)
+    }
+}
+
+impl<Step: Default, Direction: Default, Delay: Default> Default
+    for StepAndDirection<Step, Direction, Delay>
+{
+    fn default() -> Self {
+        StepAndDirection::new(
+            Step::default(),
+            Direction::default(),
+            Delay::default(),
+        )
+    }
 }
 
 fn set_output<P: OutputPin>(pin: &mut P, mask: u8) -> Result<(), P::Error> {
@@ -28,7 +64,7 @@ fn set_output<P: OutputPin>(pin: &mut P, mask: u8) -> Result<(), P::Error> {
     }
 }
 
-impl<Step, Direction, E> StepAndDirection<Step, Direction>
+impl<Step, Direction, Delay, E> StepAndDirection<Step, Direction, Delay>
 where
     Step: OutputPin<Error = E>,
     Direction: OutputPin<Error = E>,
@@ -41,10 +77,11 @@ where
     }
 }
 
-impl<Step, Direction, E> Device for StepAndDirection<Step, Direction>
+impl<Step, Direction, Delay, E> Device for StepAndDirection<Step, Direction, Delay>
 where
     Step: OutputPin<Error = E>,
     Direction: OutputPin<Error = E>,
+    Delay: DelayNs,
 {
     type Error = E;
 
@@ -54,7 +91,71 @@ where
         // println!("{}", ctx.position);
         self.set_output(if ctx.position > 0 { 0b10 } else { 0b00 });
         self.set_output(if ctx.position > 0 { 0b11 } else { 0b01 });
-        std::thread::sleep(Duration::from_micros(1));
+        let pulse_ns = self.pulse_width.as_nanos().min(u32::MAX as u128) as u32;
+        self.delay.delay_ns(pulse_ns);
         self.set_output(if ctx.position > 0 { 0b10 } else { 0b00 })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::convert::Infallible;
+    use embedded_hal::digital::ErrorType;
+    use std::vec::Vec;
+
+    #[derive(Debug, Default)]
+    struct Pin;
+
+    impl ErrorType for Pin {
+        type Error = Infallible;
+    }
+
+    impl OutputPin for Pin {
+        fn set_low(&mut self) -> Result<(), Self::Error> { Ok(()) }
+
+        fn set_high(&mut self) -> Result<(), Self::Error> { Ok(()) }
+    }
+
+    #[derive(Debug, Default)]
+    struct RecordingDelay {
+        delays_ns: Vec<u32>,
+    }
+
+    impl DelayNs for RecordingDelay {
+        fn delay_ns(&mut self, ns: u32) { self.delays_ns.push(ns); }
+    }
+
+    fn step_once(device: &mut StepAndDirection<Pin, Pin, RecordingDelay>) {
+        let ctx = StepContext {
+            position: 1,
+            step_time: Duration::from_secs(0),
+        };
+        device.step(&ctx).unwrap();
+    }
+
+    #[test]
+    fn default_pulse_is_one_microsecond() {
+        let mut device =
+            StepAndDirection::new(Pin, Pin, RecordingDelay::default());
+        step_once(&mut device);
+        assert_eq!(device.into_inner().2.delays_ns, vec![1_000]);
+    }
+
+    #[test]
+    fn default_device_uses_the_default_pulse() {
+        let device: StepAndDirection<Pin, Pin, RecordingDelay> =
+            StepAndDirection::default();
+        assert_eq!(device.pulse_width(), DEFAULT_PULSE_WIDTH);
+    }
+
+    #[test]
+    fn pulse_width_is_configurable() {
+        let mut device =
+            StepAndDirection::new(Pin, Pin, RecordingDelay::default())
+                .with_pulse_width(Duration::from_micros(5));
+        assert_eq!(device.pulse_width(), Duration::from_micros(5));
+        step_once(&mut device);
+        assert_eq!(device.into_inner().2.delays_ns, vec![5_000]);
+    }
+}
@@ -82,6 +82,8 @@ mod device;
 mod driver;
 #[cfg(feature = "hal")]
 mod hal_devices;
+#[cfg(feature = "std")]
+mod mock_device;
 mod multi_driver;
 mod utils;
 
@@ -95,6 +97,8 @@ pub use crate::{
 
 #[cfg(feature = "std")]
 pub use crate::clock::OperatingSystemClock;
+#[cfg(feature = "std")]
+pub use crate::mock_device::{MockDevice, RecordedStep, StepDirection};
 
 #[cfg(feature = "hal")]
 pub use crate::hal_devices::*;
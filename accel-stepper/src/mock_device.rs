@@ -0,0 +1,145 @@
+use crate::{Device, StepContext};
+use core::time::Duration;
+use std::vec::Vec;
+use void::Void;
+
+/// Which way a step went.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum StepDirection {
+    Forward,
+    Backward,
+}
+
+/// One step taken by a [`MockDevice`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecordedStep {
+    pub direction: StepDirection,
+    /// The [`StepContext::step_time`] of the step.
+    pub step_time: Duration,
+}
+
+/// A [`Device`] which doesn't drive any hardware, it just records every step
+/// so tests can check how far and which way a move went.
+///
+/// Like [`crate::StepAndDirection`], a step is forward when
+/// [`StepContext::position`] is positive.
+///
+/// Requires the `std` feature.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct MockDevice {
+    steps: Vec<RecordedStep>,
+}
+
+impl MockDevice {
+    pub fn new() -> MockDevice { MockDevice::default() }
+
+    /// Every step taken so far, oldest first.
+    pub fn steps(&self) -> &[RecordedStep] { &self.steps }
+
+    /// Forward steps minus backward steps.
+    pub fn net_position(&self) -> i64 {
+        self.steps
+            .iter()
+            .map(|step| match step.direction {
+                StepDirection::Forward => 1,
+                StepDirection::Backward => -1,
+            })
+            .sum()
+    }
+
+    /// Forget the steps recorded so far.
+    pub fn clear(&mut self) { self.steps.clear(); }
+}
+
+impl Device for MockDevice {
+    type Error = Void;
+
+    fn step(&mut self, ctx: &StepContext) -> Result<(), Self::Error> {
+        let direction = if ctx.position > 0 {
+            StepDirection::Forward
+        } else {
+            StepDirection::Backward
+        };
+        self.steps.push(RecordedStep {
+            direction,
+            step_time: ctx.step_time,
+        });
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Driver, SystemClock};
+    use core::cell::Cell;
+
+    // Advances 1ms every time it is read.
+    #[derive(Debug, Default)]
+    struct TickingClock {
+        ticks: Cell<u64>,
+    }
+
+    impl SystemClock for TickingClock {
+        fn elapsed(&self) -> Duration {
+            let ticks = self.ticks.get();
+            self.ticks.set(ticks + 1);
+            Duration::from_millis(ticks)
+        }
+    }
+
+    fn run_to_completion(
+        driver: &mut Driver,
+        device: &mut MockDevice,
+        clock: &TickingClock,
+    ) {
+        for _ in 0..100_000 {
+            if !driver.is_running() {
+                return;
+            }
+            driver.poll(&mut *device, clock).unwrap();
+        }
+        panic!("move never finished");
+    }
+
+    fn driver() -> Driver {
+        let mut driver = Driver::new();
+        driver.set_max_speed(100.0);
+        driver.set_acceleration(100.0);
+        driver
+    }
+
+    #[test]
+    fn records_a_forward_move() {
+        let mut driver = driver();
+        let mut device = MockDevice::new();
+        let clock = TickingClock::default();
+        driver.move_by(25);
+        run_to_completion(&mut driver, &mut device, &clock);
+
+        assert_eq!(device.steps().len(), 25);
+        assert!(device
+            .steps()
+            .iter()
+            .all(|step| step.direction == StepDirection::Forward));
+        assert_eq!(device.net_position(), 25);
+    }
+
+    #[test]
+    fn net_position_follows_the_driver() {
+        let mut driver = driver();
+        let mut device = MockDevice::new();
+        let clock = TickingClock::default();
+        driver.move_by(10);
+        run_to_completion(&mut driver, &mut device, &clock);
+        driver.move_by(-15);
+        run_to_completion(&mut driver, &mut device, &clock);
+
+        assert_eq!(device.steps().len(), 25);
+        assert_eq!(device.net_position(), -5);
+        assert_eq!(device.net_position(), driver.current_position());
+
+        device.clear();
+        assert!(device.steps().is_empty());
+    }
+}
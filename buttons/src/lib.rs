@@ -1,29 +1,45 @@
 pub mod buttons {
-    use button_driver::{Button, ButtonConfig};
+    pub use button_driver::ButtonConfig;
+    use button_driver::{Button, PinWrapper};
     use esp_idf_svc::hal::gpio::{Gpio4, Gpio5, Gpio6, Input, PinDriver};
     use std::time::{Duration, Instant};
 
+    // A button is being touched whenever its state machine is not idle.
+    fn is_touched<P: PinWrapper>(button: &Button<P, Instant, Duration>) -> bool {
+        let state = button.raw_state();
+        !(state.is_released() || state.is_unknown())
+    }
+
+    // A finished press that lasted past the hold time.
+    fn is_long_pressed<P: PinWrapper>(button: &Button<P, Instant, Duration>) -> bool {
+        button.holds() > 0
+    }
+
+    // The ongoing hold, or else the last finished one.
+    fn held_duration<P: PinWrapper>(button: &Button<P, Instant, Duration>) -> Option<Duration> {
+        button.current_holding_time().or_else(|| button.held_time())
+    }
+
     pub struct Buttons<'a> {
         m_button: Button<PinDriver<'a, Gpio5, Input>, Instant, Duration>,
         e_button: Button<PinDriver<'a, Gpio4, Input>, Instant, Duration>,
         w_button: Button<PinDriver<'a, Gpio6, Input>, Instant, Duration>,
+        // Last time any button was touched, used for maintenance inactivity timeouts.
+        last_activity: Instant,
     }
 
     impl Buttons<'_> {
         pub fn new<'a>(mb: Gpio5, eb: Gpio4, wb: Gpio6) -> Buttons<'a> {
+            Buttons::with_config(mb, eb, wb, ButtonConfig::default())
+        }
+
+        /// Buttons sharing `config`; its `hold` time is the long-press threshold.
+        pub fn with_config<'a>(mb: Gpio5, eb: Gpio4, wb: Gpio6, config: ButtonConfig) -> Buttons<'a> {
             Buttons {
-                m_button: Button::<_, Instant>::new(
-                    PinDriver::input(mb).unwrap(),
-                    ButtonConfig::default(),
-                ),
-                e_button: Button::<_, Instant>::new(
-                    PinDriver::input(eb).unwrap(),
-                    ButtonConfig::default(),
-                ),
-                w_button: Button::<_, Instant>::new(
-                    PinDriver::input(wb).unwrap(),
-                    ButtonConfig::default(),
-                ),
+                m_button: Button::<_, Instant>::new(PinDriver::input(mb).unwrap(), config),
+                e_button: Button::<_, Instant>::new(PinDriver::input(eb).unwrap(), config),
+                w_button: Button::<_, Instant>::new(PinDriver::input(wb).unwrap(), config),
+                last_activity: Instant::now(),
             }
         }
 
@@ -35,6 +51,16 @@ pub mod buttons {
             self.m_button.is_double_clicked()
         }
 
+        /// Maintenance was held past the hold time and released. Never also reported as a click.
+        pub fn is_maintenance_long_pressed(&mut self) -> bool {
+            is_long_pressed(&self.m_button)
+        }
+
+        /// How long maintenance has been held so far, or was held the last time until `reset`.
+        pub fn maintenance_held_duration(&mut self) -> Option<Duration> {
+            held_duration(&self.m_button)
+        }
+
         pub fn is_east_pressed(&mut self) -> bool {
             self.e_button.is_clicked()
         }
@@ -43,12 +69,58 @@ pub mod buttons {
             self.w_button.is_clicked()
         }
 
+        /// East is being held down past the hold time (a long press), e.g. for continuous jog.
+        pub fn is_east_held(&self) -> bool {
+            self.e_button.raw_state().is_held()
+        }
+
+        pub fn is_west_held(&self) -> bool {
+            self.w_button.raw_state().is_held()
+        }
+
+        pub fn is_east_long_pressed(&mut self) -> bool {
+            is_long_pressed(&self.e_button)
+        }
+
+        pub fn east_held_duration(&mut self) -> Option<Duration> {
+            held_duration(&self.e_button)
+        }
+
+        pub fn is_west_long_pressed(&mut self) -> bool {
+            is_long_pressed(&self.w_button)
+        }
+
+        pub fn west_held_duration(&mut self) -> Option<Duration> {
+            held_duration(&self.w_button)
+        }
+
         pub fn tick(&mut self) {
             self.m_button.tick();
             self.e_button.tick();
             self.w_button.tick();
+
+            if is_touched(&self.m_button) || is_touched(&self.e_button) || is_touched(&self.w_button) {
+                self.last_activity = Instant::now();
+            }
+        }
+
+        /// Clear the click counts once they have been handled, so a click is reported once.
+        pub fn reset(&mut self) {
+            self.m_button.reset();
+            self.e_button.reset();
+            self.w_button.reset();
+        }
+
+        /// Restart the inactivity timer, e.g. when entering maintenance mode.
+        pub fn mark_activity(&mut self) {
+            self.last_activity = Instant::now();
+        }
+
+        /// Time since any button was last touched.
+        pub fn idle_for(&self) -> Duration {
+            self.last_activity.elapsed()
         }
     }
 }
 
-pub use buttons::Buttons;
+pub use buttons::{ButtonConfig, Buttons};
@@ -0,0 +1,88 @@
+//! Keeping the DS3231 on time between NTP syncs.
+//!
+//! Every `DEFAULT_RESYNC_INTERVAL` the RTC is compared against NTP time and set back on time
+//! (`Clock::correct_time`). The corrections add up to a drift rate, which the aging offset
+//! register can cancel for good (`DriftTracker`, `Clock::adjust_aging_offset`).
+
+use std::time::Duration;
+
+pub const DEFAULT_RESYNC_INTERVAL: Duration = Duration::from_secs(24 * 3600);
+
+// One aging offset LSB moves the oscillator by about 0.1 ppm at 25°C; positive values slow it.
+const AGING_LSB_PPM: f64 = 0.1;
+// The RTC only counts whole seconds, so a rate is estimated once this much drift has built up...
+const MIN_AGING_DRIFT_SECS: i64 = 2;
+// ...over at least this long.
+const MIN_AGING_WINDOW: Duration = Duration::from_secs(24 * 3600);
+
+/// Aging offset change (LSBs) that cancels an RTC gaining `drift_secs` over `elapsed`.
+pub fn aging_offset_correction(drift_secs: f64, elapsed: Duration) -> i8 {
+    if elapsed.is_zero() {
+        return 0;
+    }
+    let ppm = drift_secs / elapsed.as_secs_f64() * 1e6;
+    (ppm / AGING_LSB_PPM).round().clamp(i8::MIN as f64, i8::MAX as f64) as i8
+}
+
+/// Adds up the drift corrected at each resync until there is enough to estimate a rate.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct DriftTracker {
+    drift_secs: i64,
+    window: Duration,
+}
+
+impl DriftTracker {
+    /// Record that the RTC had gained `drift_secs` (negative if slow) `elapsed` after the
+    /// previous correction. Returns the aging offset change to apply once the accumulated drift
+    /// is measurable, and starts a new window.
+    pub fn record(&mut self, drift_secs: i64, elapsed: Duration) -> Option<i8> {
+        self.drift_secs += drift_secs;
+        self.window += elapsed;
+        if self.drift_secs.abs() < MIN_AGING_DRIFT_SECS || self.window < MIN_AGING_WINDOW {
+            return None;
+        }
+        let correction = aging_offset_correction(self.drift_secs as f64, self.window);
+        *self = DriftTracker::default();
+        Some(correction)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DAY: Duration = Duration::from_secs(24 * 3600);
+
+    #[test]
+    fn fast_rtc_gets_a_positive_correction() {
+        // 2 s in 10 days is ~2.3 ppm.
+        assert_eq!(23, aging_offset_correction(2.0, DAY * 10));
+        assert_eq!(-23, aging_offset_correction(-2.0, DAY * 10));
+        assert_eq!(0, aging_offset_correction(0.0, DAY));
+    }
+
+    #[test]
+    fn correction_saturates() {
+        assert_eq!(i8::MAX, aging_offset_correction(60.0, DAY));
+        assert_eq!(i8::MIN, aging_offset_correction(-60.0, DAY));
+        assert_eq!(0, aging_offset_correction(5.0, Duration::ZERO));
+    }
+
+    #[test]
+    fn tracker_waits_for_measurable_drift() {
+        let mut tracker = DriftTracker::default();
+        assert_eq!(None, tracker.record(0, DAY));
+        assert_eq!(None, tracker.record(1, DAY));
+        assert_eq!(None, tracker.record(0, DAY));
+        // 2 s over 4 days.
+        assert_eq!(Some(58), tracker.record(1, DAY));
+        // A new window starts after a correction.
+        assert_eq!(None, tracker.record(1, DAY));
+    }
+
+    #[test]
+    fn quick_resyncs_do_not_trigger_a_correction() {
+        let mut tracker = DriftTracker::default();
+        assert_eq!(None, tracker.record(3, Duration::from_secs(3600)));
+    }
+}
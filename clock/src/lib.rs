@@ -1,14 +1,155 @@
+pub mod drift;
+pub mod sunrise;
+
 pub mod clock {
     use chrono::prelude::*;
-    use chrono::MappedLocalTime;
+    use chrono::Duration;
     use chrono::Utc;
-    use ds323x::{DateTimeAccess, Ds323x, NaiveDate, Rtcc};
+    use crate::sunrise::{self, STANDARD_DEPRESSION_DEG};
+    use ds323x::{ic, interface::I2cInterface, DateTimeAccess, Ds323x, Error, NaiveDate, Rtcc};
 
     pub struct Clock<I2C> {
-        rtc: Ds323x<I2C>,
+        rtc: Ds323x<I2cInterface<I2C>, ic::DS3231>,
+        latitude: f64,
+        longitude: f64,
+        altitude: f64,
+        timezone: FixedOffset,
+        // Sunrise/sunset are when the sun's centre is this far below the horizon.
+        twilight_deg: f64,
+        // Window either side of sunrise and sunset (see `SolarPhase`), and the last
+        // `is_daytime` answer, kept while inside one.
+        hysteresis: Duration,
+        daytime: Option<bool>,
+    }
+
+    /// Default `Clock::with_hysteresis` window.
+    pub const DEFAULT_SUN_HYSTERESIS: Duration = Duration::minutes(5);
+
+    /// Times before this year can't be real: a DS3231 that lost power restarts from 2000-01-01.
+    pub const MIN_PLAUSIBLE_YEAR: i32 = 2024;
+    /// Nor after this one, the last the DS3231's century bit reaches before wrapping.
+    pub const MAX_PLAUSIBLE_YEAR: i32 = 2099;
+
+    // `set_date_time` accepts a read-back this far from what it wrote: the seconds may tick on.
+    const SET_TIME_TOLERANCE_SECS: i64 = 2;
+
+    /// Whether an RTC reading (or a time about to be set) could be the actual time rather than a
+    /// power-loss default or a garbled value.
+    pub fn is_plausible_time(time: &NaiveDateTime) -> bool {
+        (MIN_PLAUSIBLE_YEAR..=MAX_PLAUSIBLE_YEAR).contains(&time.year())
+    }
+
+    /// 24-hour clock hour of a DS3231 hours register: 12 AM is midnight (0) and 12 PM is noon
+    /// (12); other PM hours are 12 on.
+    pub fn hours_to_h24(hours: ds323x::Hours) -> u8 {
+        match hours {
+            ds323x::Hours::H24(h) => h,
+            ds323x::Hours::AM(12) => 0,
+            ds323x::Hours::AM(h) => h,
+            ds323x::Hours::PM(12) => 12,
+            ds323x::Hours::PM(h) => h + 12,
+        }
+    }
+
+    /// Where the current time falls relative to today's sunrise and sunset, with a hysteresis
+    /// window either side of each.
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub enum SolarPhase {
+        /// Clearly before sunrise or after sunset, or a polar night.
+        Night,
+        /// Within the window around sunrise.
+        PreSunrise,
+        /// Clearly between sunrise and sunset, or a polar day.
+        Day,
+        /// Within the window around sunset.
+        PostSunset,
+    }
+
+    /// What the sun does on a given day at a given place.
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub enum SolarDay {
+        Normal {
+            sunrise: DateTime<FixedOffset>,
+            sunset: DateTime<FixedOffset>,
+        },
+        /// The sun stays up all day (midnight sun).
+        PolarDay,
+        /// The sun never rises.
+        PolarNight,
+    }
+
+    /// Sunrise and sunset on `date` at the given location for a depression angle of
+    /// `twilight_deg` (see `sunrise`), or which polar case applies when the sun doesn't cross it.
+    pub fn solar_day(
+        date: NaiveDate,
         latitude: f64,
         longitude: f64,
         altitude: f64,
+        timezone: FixedOffset,
+        twilight_deg: f64,
+    ) -> SolarDay {
+        if let Some((sunrise, sunset)) =
+            sun_times_at(date, latitude, longitude, altitude, timezone, twilight_deg)
+        {
+            return SolarDay::Normal { sunrise, sunset };
+        }
+        // No crossing: the sun is either up or down all day, so its noon elevation decides.
+        // Declination from Cooper's approximation, plenty for telling the two apart.
+        let declination = 23.44 * (360.0 / 365.0 * (284.0 + date.ordinal() as f64)).to_radians().sin();
+        let noon_elevation = 90.0 - (latitude - declination).abs();
+        if noon_elevation > -twilight_deg {
+            SolarDay::PolarDay
+        } else {
+            SolarDay::PolarNight
+        }
+    }
+
+    /// The phase of `day` at `now`, counting anything less than `hysteresis` from sunrise or
+    /// sunset as inside that boundary's window.
+    pub fn solar_phase_at(now: DateTime<FixedOffset>, day: SolarDay, hysteresis: Duration) -> SolarPhase {
+        match day {
+            SolarDay::Normal { sunrise, sunset } => {
+                if (now - sunrise).abs() < hysteresis {
+                    SolarPhase::PreSunrise
+                } else if (now - sunset).abs() < hysteresis {
+                    SolarPhase::PostSunset
+                } else if sunrise < now && now < sunset {
+                    SolarPhase::Day
+                } else {
+                    SolarPhase::Night
+                }
+            }
+            SolarDay::PolarDay => SolarPhase::Day,
+            SolarDay::PolarNight => SolarPhase::Night,
+        }
+    }
+
+    /// Sunrise and sunset on `date` at the given location, expressed in `timezone`.
+    pub fn sun_times_in(
+        date: NaiveDate,
+        latitude: f64,
+        longitude: f64,
+        altitude: f64,
+        timezone: FixedOffset,
+    ) -> Option<(DateTime<FixedOffset>, DateTime<FixedOffset>)> {
+        sun_times_at(date, latitude, longitude, altitude, timezone, STANDARD_DEPRESSION_DEG)
+    }
+
+    /// As `sun_times_in`, for the sun's centre `twilight_deg` below the horizon.
+    pub fn sun_times_at(
+        date: NaiveDate,
+        latitude: f64,
+        longitude: f64,
+        altitude: f64,
+        timezone: FixedOffset,
+        twilight_deg: f64,
+    ) -> Option<(DateTime<FixedOffset>, DateTime<FixedOffset>)> {
+        sunrise::sun_times(date, latitude, longitude, altitude, twilight_deg).map(|(sunrise, sunset)| {
+            (
+                DateTime::from_naive_utc_and_offset(sunrise.naive_utc(), timezone),
+                DateTime::from_naive_utc_and_offset(sunset.naive_utc(), timezone),
+            )
+        })
     }
 
     impl<I2C> Clock<I2C>
@@ -16,38 +157,93 @@ pub mod clock {
         I2C: embedded_hal::i2c::I2c,
     {
         // Constructor for Clock
-        pub fn new(i2c: I2C, latitude: f64, longitude: f64, altitude: f64) -> Clock<I2C> {
+        // The RTC holds local time in `timezone`.
+        pub fn new(
+            i2c: I2C,
+            latitude: f64,
+            longitude: f64,
+            altitude: f64,
+            timezone: FixedOffset,
+        ) -> Clock<I2C> {
             Clock {
                 rtc: Ds323x::new_ds3231(i2c),
                 latitude,
                 longitude,
                 altitude,
+                timezone,
+                twilight_deg: STANDARD_DEPRESSION_DEG,
+                hysteresis: DEFAULT_SUN_HYSTERESIS,
+                daytime: None,
             }
         }
 
+        /// Only switch `is_daytime` once `window` past sunrise or sunset, so RTC jitter at the
+        /// boundary can't bounce the tower between tracking and parking.
+        pub fn with_hysteresis(mut self, window: Duration) -> Self {
+            self.hysteresis = window;
+            self
+        }
+
+        pub fn hysteresis(&self) -> Duration {
+            self.hysteresis
+        }
+
+        /// Treat the sun as up once its centre is less than `deg` below the horizon, e.g.
+        /// `sunrise::CIVIL_TWILIGHT_DEG` to pre-position the panel before sunrise.
+        pub fn with_twilight(mut self, deg: f64) -> Self {
+            self.twilight_deg = deg;
+            self
+        }
+
+        /// The depression angle sunrise and sunset are computed for.
+        pub fn twilight_deg(&self) -> f64 {
+            self.twilight_deg
+        }
+
+        /// Method to get the timezone offset
+        pub fn timezone_offset(&self) -> FixedOffset {
+            self.timezone
+        }
+
+        /// Method to get the timezone offset in (possibly fractional) hours east of UTC
+        pub fn timezone_offset_hours(&self) -> f32 {
+            self.timezone.local_minus_utc() as f32 / 3600.0
+        }
+
+        /// Today's sunrise and sunset (RTC date), or the polar case.
+        pub fn solar_day(&mut self) -> Result<SolarDay, Error> {
+            Ok(solar_day(
+                self.rtc.date()?,
+                self.latitude,
+                self.longitude,
+                self.altitude,
+                self.timezone,
+                self.twilight_deg,
+            ))
+        }
+
         /// Calculate sunrise and sunset times in UTC
-        pub fn sunrise_times(&mut self) -> Option<DateTime<FixedOffset>> {
+        pub fn sunrise_times(&mut self) -> Result<Option<DateTime<FixedOffset>>, Error> {
             //Calculate date in utc
 
-            let times = sun_times::sun_times(
-                self.rtc.date().unwrap(),
+            let times = sun_times_at(
+                self.rtc.date()?,
                 self.latitude,
                 self.longitude,
                 self.altitude,
+                self.timezone,
+                self.twilight_deg,
             );
 
             match times {
-                Some((sunrise, _sunset)) => Some(DateTime::from_naive_utc_and_offset(
-                    sunrise.naive_utc(),
-                    FixedOffset::west_opt(5 * 3600).unwrap(),
-                )),
-                None => None,
+                Some((sunrise, _sunset)) => Ok(Some(sunrise)),
+                None => Ok(None),
             }
         }
 
-        pub fn sunset_times(&mut self) -> Option<DateTime<FixedOffset>> {
+        pub fn sunset_times(&mut self) -> Result<Option<DateTime<FixedOffset>>, Error> {
             //Calculate date in utc
-            let prop_date = self.rtc.date().unwrap();
+            let prop_date = self.rtc.date()?;
 
             let year = prop_date.year();
             let month = prop_date.month();
@@ -56,49 +252,103 @@ pub mod clock {
             let date = NaiveDate::from_ymd_opt(year as i32, month as u32, day as u32)
                 .expect("Invalid date provided");
 
-            let times = sun_times::sun_times(date, self.latitude, self.longitude, self.altitude);
+            let times = sun_times_at(
+                date,
+                self.latitude,
+                self.longitude,
+                self.altitude,
+                self.timezone,
+                self.twilight_deg,
+            );
             match times {
-                Some((_sunrise, sunset)) => Some(DateTime::from_naive_utc_and_offset(
-                    sunset.naive_utc(),
-                    FixedOffset::west_opt(5 * 3600).unwrap(),
-                )),
-                None => None, // Handle the case where `None` is returned
+                Some((_sunrise, sunset)) => Ok(Some(sunset)),
+                None => Ok(None), // Handle the case where `None` is returned
             }
         }
 
-        /// Method to get the hours
-        pub fn get_hour(&mut self) -> u8 {
-            let hour = self.rtc.hours().unwrap();
-            match hour {
-                ds323x::Hours::AM(h) => h,
-                ds323x::Hours::PM(h) => h + 11,
-                ds323x::Hours::H24(h) => h,
+        /// Method to get the hours (0-23, whichever mode the RTC is in)
+        pub fn get_hour(&mut self) -> Result<u8, Error> {
+            Ok(hours_to_h24(self.rtc.hours()?))
+        }
+
+        /// Switch the RTC to 24-hour mode, keeping the hour, if it is in 12-hour mode; returns
+        /// whether it was. `set_date_time` always writes 24-hour mode, this covers an RTC set
+        /// by something else.
+        pub fn ensure_24h_mode(&mut self) -> Result<bool, Error> {
+            match self.rtc.hours()? {
+                ds323x::Hours::H24(_) => Ok(false),
+                hours => {
+                    self.rtc.set_hours(ds323x::Hours::H24(hours_to_h24(hours)))?;
+                    Ok(true)
+                }
             }
         }
 
         /// Method to get the minutes
-        pub fn get_minutes(&mut self) -> u8 {
-            self.rtc.minutes().unwrap()
+        pub fn get_minutes(&mut self) -> Result<u8, Error> {
+            self.rtc.minutes()
         }
 
         /// Method to get the seconds
-        pub fn get_seconds(&mut self) -> u8 {
-            self.rtc.seconds().unwrap()
+        pub fn get_seconds(&mut self) -> Result<u8, Error> {
+            self.rtc.seconds()
         }
 
         /// Method to get the day
-        pub fn get_day(&mut self) -> u32 {
-            self.rtc.date().unwrap().ordinal()
+        pub fn get_day(&mut self) -> Result<u32, Error> {
+            Ok(self.rtc.date()?.ordinal())
         }
 
         /// Method to get the day
-        pub fn get_month(&mut self) -> u8 {
-            self.rtc.month().unwrap()
+        pub fn get_month(&mut self) -> Result<u8, Error> {
+            self.rtc.month()
         }
 
         /// Method to get the day
-        pub fn get_year(&mut self) -> u16 {
-            self.rtc.year().unwrap()
+        pub fn get_year(&mut self) -> Result<u16, Error> {
+            self.rtc.year()
+        }
+
+        /// Method to get the RTC die temperature in °C (0.25°C resolution, refreshed every 64 s)
+        pub fn rtc_temperature(&mut self) -> Result<f32, Error> {
+            self.rtc.temperature()
+        }
+
+        /// Arm Alarm2 to fire at `hour:minute` (local time) every day.
+        ///
+        /// INTCN is set so the INT/SQW pin carries the alarm instead of the square wave: it is
+        /// pulled low on a match and stays low until the flag is cleared with
+        /// `clear_wakeup_alarm`. With INTCN clear the alarm flag is still set, but the pin
+        /// keeps outputting the square wave and cannot wake the MCU.
+        pub fn set_wakeup_alarm(&mut self, hour: u8, minute: u8) -> Result<(), Error> {
+            let time = NaiveTime::from_hms_opt(hour.into(), minute.into(), 0).ok_or(Error::InvalidInputData)?;
+            self.rtc.set_alarm2_hm(time)?;
+            self.rtc.clear_alarm2_matched_flag()?;
+            self.rtc.use_int_sqw_output_as_interrupt()?;
+            self.rtc.enable_alarm2_interrupts()
+        }
+
+        /// Arm the wakeup alarm for today's sunrise, which is within a couple of minutes of
+        /// tomorrow's. Returns the alarm time, or `None` on a day without sunrise.
+        pub fn set_sunrise_alarm(&mut self) -> Result<Option<NaiveTime>, Error> {
+            match self.sunrise_times()? {
+                Some(sunrise) => {
+                    let time = sunrise.time();
+                    self.set_wakeup_alarm(time.hour() as u8, time.minute() as u8)?;
+                    Ok(Some(time))
+                }
+                None => Ok(None),
+            }
+        }
+
+        /// Method for returning whether the wakeup alarm has fired since it was last cleared
+        pub fn wakeup_alarm_fired(&mut self) -> Result<bool, Error> {
+            self.rtc.has_alarm2_matched()
+        }
+
+        /// Acknowledge the wakeup alarm, releasing the INT/SQW pin
+        pub fn clear_wakeup_alarm(&mut self) -> Result<(), Error> {
+            self.rtc.clear_alarm2_matched_flag()
         }
 
         /// Method to get the longitude
@@ -116,51 +366,476 @@ pub mod clock {
             self.altitude
         }
 
-        /// Method for setting a datetime string
-        pub fn set_date_time(&mut self, dateTime: &NaiveDateTime) {
-            self.rtc.set_datetime(dateTime);
+        /// Set the RTC to `date_time` (local time) in 24-hour mode and read it back: a read-back
+        /// more than a couple of seconds off fails with `Error::InvalidDeviceState`.
+        pub fn set_date_time(&mut self, date_time: &NaiveDateTime) -> Result<(), Error> {
+            self.rtc.set_datetime(date_time)?;
+            let stored = self.rtc.datetime()?;
+            if (stored - *date_time).num_seconds().abs() > SET_TIME_TOLERANCE_SECS {
+                return Err(Error::InvalidDeviceState);
+            }
+            Ok(())
         }
 
         /// Method for returning a datetime string
-        pub fn get_date_time(&mut self) -> NaiveDateTime {
-            self.rtc.datetime().unwrap()
+        pub fn get_date_time(&mut self) -> Result<NaiveDateTime, Error> {
+            self.rtc.datetime()
+        }
+
+        /// The RTC's time in the configured timezone, read in one go: prefer it to assembling a
+        /// time from `get_year`, `get_hour` and friends, which can straddle a rollover.
+        pub fn now_local(&mut self) -> Result<DateTime<FixedOffset>, Error> {
+            // A fixed offset maps every local time to exactly one instant
+            Ok(self.get_date_time()?.and_local_timezone(self.timezone).unwrap())
+        }
+
+        /// `now_local` in UTC.
+        pub fn now_utc(&mut self) -> Result<DateTime<Utc>, Error> {
+            Ok(self.now_local()?.with_timezone(&Utc))
+        }
+
+        /// Set the RTC to `reference` (local time, e.g. from NTP) and return how many seconds
+        /// it was ahead of it (negative if behind).
+        pub fn correct_time(&mut self, reference: &NaiveDateTime) -> Result<i64, Error> {
+            let rtc = self.get_date_time()?;
+            self.set_date_time(reference)?;
+            Ok((rtc - *reference).num_seconds())
+        }
+
+        /// Whether the DS3231 oscillator has stopped since the flag was last cleared, which
+        /// happens when it loses both supplies. Its date and time are garbage until set again.
+        pub fn has_lost_power(&mut self) -> Result<bool, Error> {
+            self.rtc.has_been_stopped()
+        }
+
+        /// Clear the OSC_STOP flag once the time has been set again.
+        pub fn clear_power_loss_flag(&mut self) -> Result<(), Error> {
+            self.rtc.clear_has_been_stopped_flag()
+        }
+
+        /// Method to get the DS3231 aging offset
+        pub fn aging_offset(&mut self) -> Result<i8, Error> {
+            self.rtc.aging_offset()
+        }
+
+        /// Change the aging offset by `delta` LSBs (see `drift::aging_offset_correction`),
+        /// saturating at the register's range. Returns the new offset.
+        pub fn adjust_aging_offset(&mut self, delta: i8) -> Result<i8, Error> {
+            let offset = self.rtc.aging_offset()?.saturating_add(delta);
+            self.rtc.set_aging_offset(offset)?;
+            Ok(offset)
         }
 
         /// Method for returning a boolean for if it is after sunrsie today
-        pub fn after_sunrise(&mut self) -> bool {
-            if let Some(sunrise) = self.sunrise_times() {
-                let current_time: MappedLocalTime<DateTime<FixedOffset>> = self
-                    .get_date_time()
-                    .and_local_timezone(FixedOffset::west_opt(5 * 3600).unwrap());
-                // println!("{:?}", current_time);
-                current_time.single().unwrap() >= sunrise
-            } else {
-                false // Return false if sunrise is None
+        /// (always during a polar day, never during a polar night)
+        pub fn after_sunrise(&mut self) -> Result<bool, Error> {
+            match self.solar_day()? {
+                SolarDay::Normal { sunrise, .. } => {
+                    Ok(self.now_local()? >= sunrise)
+                }
+                SolarDay::PolarDay => Ok(true),
+                SolarDay::PolarNight => Ok(false),
             }
         }
 
         /// Method for returning a boolean for if it is after sunset today
-        pub fn after_sunset(&mut self) -> bool {
-            if let Some(sunset) = self.sunset_times() {
-                let current_time: MappedLocalTime<DateTime<FixedOffset>> = self
-                    .get_date_time()
-                    .and_local_timezone(FixedOffset::west_opt(5 * 3600).unwrap());
-                // println!("{:?}", current_time);
-                current_time.single().unwrap() >= sunset
-            } else {
-                false // Return false if sunset is None
+        /// (never during a polar day or night, as the sun doesn't set)
+        pub fn after_sunset(&mut self) -> Result<bool, Error> {
+            match self.solar_day()? {
+                SolarDay::Normal { sunset, .. } => {
+                    Ok(self.now_local()? >= sunset)
+                }
+                SolarDay::PolarDay | SolarDay::PolarNight => Ok(false),
             }
         }
 
+        /// Today's `SolarPhase` at the RTC's current time.
+        pub fn solar_phase(&mut self) -> Result<SolarPhase, Error> {
+            let day = self.solar_day()?;
+            Ok(solar_phase_at(self.now_local()?, day, self.hysteresis))
+        }
+
+        /// Whether to track, i.e. between sunrise and sunset today. Inside the hysteresis window
+        /// around either the previous answer stands; only right after boot do the exact times
+        /// decide there.
+        pub fn is_daytime(&mut self) -> Result<bool, Error> {
+            let daytime = match (self.solar_phase()?, self.daytime) {
+                (SolarPhase::Day, _) => true,
+                (SolarPhase::Night, _) => false,
+                (SolarPhase::PreSunrise | SolarPhase::PostSunset, Some(previous)) => previous,
+                (SolarPhase::PreSunrise | SolarPhase::PostSunset, None) => {
+                    self.after_sunrise()? && !self.after_sunset()?
+                }
+            };
+            self.daytime = Some(daytime);
+            Ok(daytime)
+        }
+
         ///Returns a unix timestamp based on the current date time provided
-        pub fn datetime_to_unix_timestamp(&mut self) -> i64 {
-            let current_time: MappedLocalTime<DateTime<FixedOffset>> = self
-                .get_date_time()
-                .and_local_timezone(FixedOffset::west_opt(5 * 3600).unwrap());
-            let unix_timestamp = current_time.single().unwrap().timestamp();
-            unix_timestamp
+        pub fn datetime_to_unix_timestamp(&mut self) -> Result<i64, Error> {
+            Ok(self.now_local()?.timestamp())
         }
     }
 }
 
-pub use clock::Clock;
+pub use clock::{
+    hours_to_h24, is_plausible_time, solar_day, solar_phase_at, sun_times_at, sun_times_in, Clock, SolarDay, SolarPhase,
+    DEFAULT_SUN_HYSTERESIS, MAX_PLAUSIBLE_YEAR, MIN_PLAUSIBLE_YEAR,
+};
+pub use drift::{DriftTracker, DEFAULT_RESYNC_INTERVAL};
+pub use sunrise::{CIVIL_TWILIGHT_DEG, STANDARD_DEPRESSION_DEG};
+pub use ds323x::Error as RtcError;
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        hours_to_h24, is_plausible_time, solar_day, solar_phase_at, sun_times_at, sun_times_in, Clock, RtcError, SolarDay, SolarPhase, CIVIL_TWILIGHT_DEG,
+        DEFAULT_SUN_HYSTERESIS, STANDARD_DEPRESSION_DEG,
+    };
+    use chrono::{Duration, FixedOffset, NaiveDate, Timelike};
+    use embedded_hal::i2c::{ErrorKind, ErrorType, I2c, Operation};
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    // A DS3231 register file on the bus: writes store from, and reads start at, the register
+    // addressed. Clones share the registers and the log of writes, so a test can look at them
+    // after handing the bus to a `Clock`.
+    #[derive(Default, Clone)]
+    struct MockI2c {
+        state: Rc<RefCell<MockState>>,
+    }
+
+    #[derive(Default)]
+    struct MockState {
+        registers: [u8; 0x13],
+        writes: Vec<Vec<u8>>,
+        // Every transaction fails, like a disconnected RTC
+        failing: bool,
+        // Writes are acknowledged but not stored, like an RTC that won't keep a new time
+        read_only: bool,
+    }
+
+    impl MockI2c {
+        fn failing() -> Self {
+            let bus = MockI2c::default();
+            bus.state.borrow_mut().failing = true;
+            bus
+        }
+
+        fn read_only(self) -> Self {
+            self.state.borrow_mut().read_only = true;
+            self
+        }
+
+        fn with_register(self, register: usize, value: u8) -> Self {
+            self.state.borrow_mut().registers[register] = value;
+            self
+        }
+
+        fn writes(&self) -> Vec<Vec<u8>> {
+            self.state.borrow().writes.clone()
+        }
+    }
+
+    impl ErrorType for MockI2c {
+        type Error = ErrorKind;
+    }
+
+    impl I2c for MockI2c {
+        fn transaction(&mut self, _address: u8, operations: &mut [Operation<'_>]) -> Result<(), Self::Error> {
+            let mut state = self.state.borrow_mut();
+            if state.failing {
+                return Err(ErrorKind::Other);
+            }
+            let mut register = 0;
+            for operation in operations {
+                match operation {
+                    Operation::Write(bytes) => {
+                        register = bytes[0] as usize;
+                        state.writes.push(bytes.to_vec());
+                        if !state.read_only {
+                            state.registers[register..register + bytes.len() - 1].copy_from_slice(&bytes[1..]);
+                        }
+                    }
+                    Operation::Read(buffer) => {
+                        let len = buffer.len();
+                        buffer.copy_from_slice(&state.registers[register..register + len]);
+                    }
+                }
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn wakeup_alarm_programs_alarm2_and_interrupt() {
+        let bus = MockI2c::default();
+        let mut clock = Clock::new(bus.clone(), 32.797868, -96.835597, 0.0, FixedOffset::west_opt(5 * 3600).unwrap());
+        clock.set_wakeup_alarm(6, 31).unwrap();
+
+        assert_eq!(
+            bus.writes(),
+            [
+                vec![0x0B, 0x31, 0x06, 0x81], // Alarm2: 06:31, day masked
+                vec![0x0F, 0x89],             // status: clear A2F, keep A1F
+                vec![0x0E, 0x1C],             // control: INTCN
+                vec![0x0E, 0x1E],             // control: INTCN | A2IE
+            ]
+        );
+    }
+
+    #[test]
+    fn wakeup_alarm_rejects_invalid_time() {
+        let bus = MockI2c::default();
+        let mut clock = Clock::new(bus.clone(), 32.797868, -96.835597, 0.0, FixedOffset::west_opt(5 * 3600).unwrap());
+        assert!(matches!(clock.set_wakeup_alarm(24, 0), Err(RtcError::InvalidInputData)));
+        assert!(bus.writes().is_empty());
+    }
+
+    #[test]
+    fn osc_stop_flag_means_power_was_lost() {
+        let bus = MockI2c::default().with_register(0x0F, 0x80);
+        let mut clock = Clock::new(bus.clone(), LAT, LON, 0.0, FixedOffset::west_opt(5 * 3600).unwrap());
+        assert!(clock.has_lost_power().unwrap());
+
+        clock.clear_power_loss_flag().unwrap();
+        let writes = bus.writes();
+        let status_write = writes.last().unwrap();
+        assert_eq!(0x0F, status_write[0]);
+        assert_eq!(0, status_write[1] & 0x80, "OSC_STOP still set");
+    }
+
+    #[test]
+    fn running_oscillator_has_not_lost_power() {
+        let bus = MockI2c::default().with_register(0x0F, 0x08);
+        let mut clock = Clock::new(bus, LAT, LON, 0.0, FixedOffset::west_opt(5 * 3600).unwrap());
+        assert!(!clock.has_lost_power().unwrap());
+        assert!(matches!(failing_clock().has_lost_power(), Err(RtcError::Comm)));
+    }
+
+    fn failing_clock() -> Clock<MockI2c> {
+        Clock::new(MockI2c::failing(), 32.797868, -96.835597, 0.0, FixedOffset::west_opt(5 * 3600).unwrap())
+    }
+
+    #[test]
+    fn rtc_errors_reach_the_caller() {
+        let mut clock = failing_clock();
+        assert!(matches!(clock.get_hour(), Err(RtcError::Comm)));
+        assert!(matches!(clock.get_date_time(), Err(RtcError::Comm)));
+        assert!(matches!(clock.sunrise_times(), Err(RtcError::Comm)));
+        assert!(matches!(clock.after_sunset(), Err(RtcError::Comm)));
+        assert!(matches!(clock.is_daytime(), Err(RtcError::Comm)));
+    }
+
+    const LAT: f64 = 32.797868;
+    const LON: f64 = -96.835597;
+
+    #[test]
+    fn sunrise_follows_timezone() {
+        let date = NaiveDate::from_ymd_opt(2024, 6, 21).unwrap();
+        let central = FixedOffset::west_opt(5 * 3600).unwrap();
+        let india = FixedOffset::east_opt(5 * 3600 + 30 * 60).unwrap();
+
+        let (sunrise_central, _) = sun_times_in(date, LAT, LON, 0.0, central).unwrap();
+        let (sunrise_india, _) = sun_times_in(date, LAT, LON, 0.0, india).unwrap();
+
+        // Same instant, wall clock 10h30 apart.
+        assert_eq!(sunrise_central, sunrise_india);
+        assert_eq!(
+            sunrise_india.naive_local() - sunrise_central.naive_local(),
+            Duration::minutes(10 * 60 + 30)
+        );
+        assert_eq!(sunrise_india.offset(), &india);
+    }
+
+    #[test]
+    fn dallas_sunrise_in_local_time() {
+        let date = NaiveDate::from_ymd_opt(2024, 6, 21).unwrap();
+        let central = FixedOffset::west_opt(5 * 3600).unwrap();
+
+        let (sunrise, sunset) = sun_times_in(date, LAT, LON, 0.0, central).unwrap();
+        assert_eq!(sunrise.hour(), 6);
+        assert_eq!(sunset.hour(), 20);
+    }
+
+    const TROMSO_LAT: f64 = 69.6496;
+    const TROMSO_LON: f64 = 18.9560;
+    const MCMURDO_LAT: f64 = -77.8419;
+    const MCMURDO_LON: f64 = 166.6863;
+
+    #[test]
+    fn normal_day_has_sunrise_and_sunset() {
+        let date = NaiveDate::from_ymd_opt(2024, 6, 21).unwrap();
+        let central = FixedOffset::west_opt(5 * 3600).unwrap();
+        let (sunrise, sunset) = sun_times_in(date, LAT, LON, 0.0, central).unwrap();
+        assert_eq!(SolarDay::Normal { sunrise, sunset }, solar_day(date, LAT, LON, 0.0, central, STANDARD_DEPRESSION_DEG));
+    }
+
+    #[test]
+    fn arctic_summer_is_a_polar_day() {
+        let date = NaiveDate::from_ymd_opt(2024, 6, 21).unwrap();
+        let utc = FixedOffset::east_opt(0).unwrap();
+        assert_eq!(SolarDay::PolarDay, solar_day(date, TROMSO_LAT, TROMSO_LON, 0.0, utc, STANDARD_DEPRESSION_DEG));
+        assert_eq!(SolarDay::PolarNight, solar_day(date, MCMURDO_LAT, MCMURDO_LON, 0.0, utc, STANDARD_DEPRESSION_DEG));
+    }
+
+    #[test]
+    fn arctic_winter_is_a_polar_night() {
+        let date = NaiveDate::from_ymd_opt(2024, 12, 21).unwrap();
+        let utc = FixedOffset::east_opt(0).unwrap();
+        assert_eq!(SolarDay::PolarNight, solar_day(date, TROMSO_LAT, TROMSO_LON, 0.0, utc, STANDARD_DEPRESSION_DEG));
+        assert_eq!(SolarDay::PolarDay, solar_day(date, MCMURDO_LAT, MCMURDO_LON, 0.0, utc, STANDARD_DEPRESSION_DEG));
+    }
+
+    #[test]
+    fn twilight_defaults_to_sunrise() {
+        let clock = failing_clock();
+        assert_eq!(STANDARD_DEPRESSION_DEG, clock.twilight_deg());
+        assert_eq!(CIVIL_TWILIGHT_DEG, clock.with_twilight(CIVIL_TWILIGHT_DEG).twilight_deg());
+    }
+
+    #[test]
+    fn civil_twilight_widens_the_day() {
+        let date = NaiveDate::from_ymd_opt(2024, 6, 21).unwrap();
+        let central = FixedOffset::west_opt(5 * 3600).unwrap();
+        let (sunrise, sunset) = sun_times_in(date, LAT, LON, 0.0, central).unwrap();
+        let (dawn, dusk) = sun_times_at(date, LAT, LON, 0.0, central, CIVIL_TWILIGHT_DEG).unwrap();
+        assert!(dawn < sunrise && sunset < dusk);
+    }
+
+    #[test]
+    fn deep_twilight_reaches_the_polar_winter() {
+        // The sun stays below the horizon at Tromsø in December but comes within 6° of it.
+        let date = NaiveDate::from_ymd_opt(2024, 12, 21).unwrap();
+        let utc = FixedOffset::east_opt(0).unwrap();
+        assert!(matches!(
+            solar_day(date, TROMSO_LAT, TROMSO_LON, 0.0, utc, CIVIL_TWILIGHT_DEG),
+            SolarDay::Normal { .. }
+        ));
+    }
+
+    #[test]
+    fn boundaries_get_a_hysteresis_window() {
+        let date = NaiveDate::from_ymd_opt(2024, 6, 21).unwrap();
+        let central = FixedOffset::west_opt(5 * 3600).unwrap();
+        let day = solar_day(date, LAT, LON, 0.0, central, STANDARD_DEPRESSION_DEG);
+        let SolarDay::Normal { sunrise, sunset } = day else {
+            panic!("Dallas has a sunrise in June");
+        };
+        let window = DEFAULT_SUN_HYSTERESIS;
+        let phase = |at| solar_phase_at(at, day, window);
+
+        assert_eq!(SolarPhase::Night, phase(sunrise - window - Duration::seconds(1)));
+        assert_eq!(SolarPhase::PreSunrise, phase(sunrise - Duration::seconds(1)));
+        assert_eq!(SolarPhase::PreSunrise, phase(sunrise + Duration::seconds(1)));
+        assert_eq!(SolarPhase::Day, phase(sunrise + window));
+        assert_eq!(SolarPhase::Day, phase(sunset - window));
+        assert_eq!(SolarPhase::PostSunset, phase(sunset));
+        assert_eq!(SolarPhase::Night, phase(sunset + window));
+    }
+
+    #[test]
+    fn polar_days_have_no_windows() {
+        let now = NaiveDate::from_ymd_opt(2024, 6, 21)
+            .unwrap()
+            .and_hms_opt(0, 0, 0)
+            .unwrap()
+            .and_utc()
+            .fixed_offset();
+        assert_eq!(SolarPhase::Day, solar_phase_at(now, SolarDay::PolarDay, DEFAULT_SUN_HYSTERESIS));
+        assert_eq!(SolarPhase::Night, solar_phase_at(now, SolarDay::PolarNight, DEFAULT_SUN_HYSTERESIS));
+    }
+
+    #[test]
+    fn power_loss_default_time_is_not_plausible() {
+        let power_loss = NaiveDate::from_ymd_opt(2000, 1, 1).unwrap().and_hms_opt(0, 0, 5).unwrap();
+        let real = NaiveDate::from_ymd_opt(2025, 3, 14).unwrap().and_hms_opt(9, 30, 0).unwrap();
+        assert!(!is_plausible_time(&power_loss));
+        assert!(is_plausible_time(&real));
+        let epoch = NaiveDate::from_ymd_opt(1970, 1, 1).unwrap().and_hms_opt(0, 0, 0).unwrap();
+        let far = NaiveDate::from_ymd_opt(2100, 1, 1).unwrap().and_hms_opt(0, 0, 0).unwrap();
+        assert!(!is_plausible_time(&epoch));
+        assert!(!is_plausible_time(&far));
+    }
+
+    // RTC registers reading 2025-06-21 with the given hours register, 30:05 past.
+    fn registers_at(hours_register: u8) -> MockI2c {
+        let bus = MockI2c::default();
+        bus.state.borrow_mut().registers[..7].copy_from_slice(&[0x05, 0x30, hours_register, 0x07, 0x21, 0x06, 0x25]);
+        bus
+    }
+
+    fn clock_at(hours_register: u8) -> Clock<MockI2c> {
+        Clock::new(registers_at(hours_register), 32.797868, -96.835597, 0.0, FixedOffset::west_opt(5 * 3600).unwrap())
+    }
+
+    #[test]
+    fn twelve_hour_clock_converts_to_24_hours() {
+        use ds323x::Hours::{AM, H24, PM};
+        assert_eq!(0, hours_to_h24(AM(12)));
+        assert_eq!(1, hours_to_h24(AM(1)));
+        assert_eq!(11, hours_to_h24(AM(11)));
+        assert_eq!(12, hours_to_h24(PM(12)));
+        assert_eq!(13, hours_to_h24(PM(1)));
+        assert_eq!(23, hours_to_h24(PM(11)));
+        assert_eq!(0, hours_to_h24(H24(0)));
+        assert_eq!(23, hours_to_h24(H24(23)));
+    }
+
+    #[test]
+    fn get_hour_reads_either_mode() {
+        // 12-hour mode (bit 6), PM (bit 5), 1 o'clock
+        assert_eq!(13, clock_at(0x40 | 0x20 | 0x01).get_hour().unwrap());
+        assert_eq!(0, clock_at(0x40 | 0x12).get_hour().unwrap());
+        assert_eq!(14, clock_at(0x14).get_hour().unwrap());
+    }
+
+    #[test]
+    fn midnight_and_noon_in_12_hour_mode() {
+        // 12 AM is the first hour of the day, 12 PM the first after noon
+        assert_eq!(0, clock_at(0x40 | 0x12).get_hour().unwrap());
+        assert_eq!(12, clock_at(0x40 | 0x20 | 0x12).get_hour().unwrap());
+        assert_eq!(11, clock_at(0x40 | 0x11).get_hour().unwrap());
+        assert_eq!(23, clock_at(0x40 | 0x20 | 0x11).get_hour().unwrap());
+        // The whole-datetime read agrees
+        assert_eq!(0, clock_at(0x40 | 0x12).now_local().unwrap().hour());
+        assert_eq!(12, clock_at(0x40 | 0x20 | 0x12).now_local().unwrap().hour());
+    }
+
+    #[test]
+    fn twelve_hour_rtc_is_switched_to_24_hours() {
+        let mut clock = clock_at(0x40 | 0x20 | 0x03);
+        assert!(clock.ensure_24h_mode().unwrap());
+        assert_eq!(15, clock.get_hour().unwrap());
+        // Now in 24-hour mode, so nothing more to do
+        assert!(!clock.ensure_24h_mode().unwrap());
+    }
+
+    #[test]
+    fn now_is_read_as_one_datetime() {
+        let mut clock = clock_at(0x14);
+        let local = clock.now_local().unwrap();
+        assert_eq!("2025-06-21T14:30:05-05:00", local.to_rfc3339());
+        assert_eq!("2025-06-21T19:30:05+00:00", clock.now_utc().unwrap().to_rfc3339());
+        assert_eq!(local.timestamp(), clock.datetime_to_unix_timestamp().unwrap());
+    }
+
+    #[test]
+    fn set_time_is_read_back() {
+        let mut clock = clock_at(0x40 | 0x20 | 0x01);
+        let time = NaiveDate::from_ymd_opt(2025, 11, 3).unwrap().and_hms_opt(7, 45, 0).unwrap();
+        clock.set_date_time(&time).unwrap();
+        assert_eq!(time, clock.get_date_time().unwrap());
+        // Written in 24-hour mode
+        assert!(!clock.ensure_24h_mode().unwrap());
+    }
+
+    #[test]
+    fn set_time_fails_when_the_rtc_does_not_keep_it() {
+        // An RTC that acknowledges writes but always reads back its old time
+        let rtc = registers_at(0x14).read_only();
+        let mut clock = Clock::new(rtc, 32.797868, -96.835597, 0.0, FixedOffset::west_opt(5 * 3600).unwrap());
+        let time = NaiveDate::from_ymd_opt(2025, 11, 3).unwrap().and_hms_opt(7, 45, 0).unwrap();
+        assert!(matches!(clock.set_date_time(&time), Err(RtcError::InvalidDeviceState)));
+    }
+}
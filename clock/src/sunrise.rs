@@ -0,0 +1,100 @@
+//! The sunrise equation (<https://en.wikipedia.org/wiki/Sunrise_equation>) with a configurable
+//! solar depression angle.
+//!
+//! This is the calculation of the `sun-times` crate, which fixes the sun's centre at 0.83°
+//! below the horizon (refraction plus the solar radius). A larger depression gives the start and
+//! end of twilight instead, e.g. `CIVIL_TWILIGHT_DEG`.
+
+use chrono::{DateTime, NaiveDate, TimeZone, Utc};
+
+/// Geometric sunrise/sunset, as used by `sun-times`.
+pub const STANDARD_DEPRESSION_DEG: f64 = 0.83;
+/// Civil twilight: the sun's centre 6° below the horizon.
+pub const CIVIL_TWILIGHT_DEG: f64 = 6.0;
+
+const UNIX_EPOCH_JD: f64 = 2440587.5;
+const JAN_2000_JD: f64 = 2451545.0;
+const LEAP_SECONDS_JD: f64 = 0.0008;
+const SECONDS_PER_DAY: f64 = 86400.0;
+const OBLIQUITY_OF_THE_ECLIPTIC: f64 = 23.44;
+const ARGUMENT_OF_PERIHELION: f64 = 102.9372;
+
+/// Times on `date` at which the sun's centre is `depression_deg` below the horizon, rising and
+/// setting. `None` if it never gets that low or that high (polar day/night).
+pub fn sun_times(
+    date: NaiveDate,
+    latitude: f64,
+    longitude: f64,
+    elevation: f64,
+    depression_deg: f64,
+) -> Option<(DateTime<Utc>, DateTime<Utc>)> {
+    let midnight = date.and_hms_opt(0, 0, 0)?.and_local_timezone(Utc).single()?;
+    let julian_date = midnight.timestamp() as f64 / SECONDS_PER_DAY + UNIX_EPOCH_JD;
+
+    // Negative elevations are treated as sea level, where refraction barely differs.
+    let elevation_correction = -2.076 * elevation.max(0.0).sqrt() / 60.0;
+
+    let days_since_2000 = (julian_date - JAN_2000_JD + LEAP_SECONDS_JD).ceil();
+    let mean_solar_time = days_since_2000 - longitude / 360.0;
+    let solar_mean_anomaly = (357.5291 + 0.98560028 * mean_solar_time).rem_euclid(360.0);
+    let center = 1.9148 * solar_mean_anomaly.to_radians().sin()
+        + 0.0200 * (2.0 * solar_mean_anomaly).to_radians().sin()
+        + 0.0003 * (3.0 * solar_mean_anomaly).to_radians().sin();
+    let ecliptic_longitude =
+        (solar_mean_anomaly + center + 180.0 + ARGUMENT_OF_PERIHELION).rem_euclid(360.0);
+
+    let declination =
+        (ecliptic_longitude.to_radians().sin() * OBLIQUITY_OF_THE_ECLIPTIC.to_radians().sin()).asin();
+    let event_hour_angle = (((-depression_deg + elevation_correction).to_radians().sin()
+        - latitude.to_radians().sin() * declination.sin())
+        / (latitude.to_radians().cos() * declination.cos()))
+    .acos()
+    .to_degrees();
+    if event_hour_angle.is_nan() {
+        return None;
+    }
+
+    let solar_transit = JAN_2000_JD + mean_solar_time + 0.0053 * solar_mean_anomaly.to_radians().sin()
+        - 0.0069 * (2.0 * ecliptic_longitude).to_radians().sin();
+    let to_datetime = |jd: f64| {
+        Utc.timestamp_opt(((jd - UNIX_EPOCH_JD) * SECONDS_PER_DAY).round() as i64, 0)
+            .single()
+    };
+    Some((
+        to_datetime(solar_transit - event_hour_angle / 360.0)?,
+        to_datetime(solar_transit + event_hour_angle / 360.0)?,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LAT: f64 = 32.797868;
+    const LON: f64 = -96.835597;
+
+    #[test]
+    fn standard_depression_matches_sun_times() {
+        for (month, day) in [(1, 1), (3, 20), (6, 21), (9, 22), (12, 21)] {
+            let date = NaiveDate::from_ymd_opt(2024, month, day).unwrap();
+            for elevation in [0.0, 150.0] {
+                assert_eq!(
+                    sun_times::sun_times(date, LAT, LON, elevation),
+                    sun_times(date, LAT, LON, elevation, STANDARD_DEPRESSION_DEG)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn civil_twilight_brackets_sunrise_and_sunset() {
+        let date = NaiveDate::from_ymd_opt(2024, 6, 21).unwrap();
+        let (sunrise, sunset) = sun_times(date, LAT, LON, 0.0, STANDARD_DEPRESSION_DEG).unwrap();
+        let (dawn, dusk) = sun_times(date, LAT, LON, 0.0, CIVIL_TWILIGHT_DEG).unwrap();
+        // Roughly half an hour of civil twilight at this latitude in June.
+        let morning = (sunrise - dawn).num_minutes();
+        let evening = (dusk - sunset).num_minutes();
+        assert!((20..40).contains(&morning), "dawn {} min before sunrise", morning);
+        assert!((20..40).contains(&evening), "dusk {} min after sunset", evening);
+    }
+}
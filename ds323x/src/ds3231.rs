@@ -1,8 +1,9 @@
 //! Functions exclusive of DS3231
 
-use crate::{BitFlags, Ds323x, Error, CONTROL_POR_VALUE, DEVICE_ADDRESS};
+use crate::{ic, interface::I2cInterface, BitFlags, Ds323x, CONTROL_POR_VALUE};
+use core::marker::PhantomData;
 
-impl<I2C> Ds323x<I2C>
+impl<I2C> Ds323x<I2cInterface<I2C>, ic::DS3231>
 where
     I2C: embedded_hal::i2c::I2c,
 {
@@ -10,40 +11,52 @@ where
     pub fn new_ds3231(i2c: I2C) -> Self {
         const STATUS_POR_VALUE: u8 = BitFlags::OSC_STOP | BitFlags::EN32KHZ;
         Ds323x {
-            i2c: i2c,
+            iface: I2cInterface { i2c },
             control: CONTROL_POR_VALUE,
             status: STATUS_POR_VALUE,
+            _ic: PhantomData,
         }
     }
 
-    pub fn write_register(&mut self, register: u8, data: u8) -> Result<(), Error> {
-        let payload: [u8; 2] = [register, data];
-        self.i2c
-            .write(DEVICE_ADDRESS, &payload)
-            .map_err(|_err| Error::Comm);
-        Ok(())
+    /// Destroy driver instance, return I²C bus instance.
+    pub fn destroy_ds3231(self) -> I2C {
+        self.iface.i2c
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{BitFlags, DateTimeAccess, Error, Register, Rtcc};
+    use crate::mock::MockI2c;
 
-    pub fn write_data(&mut self, payload: &mut [u8]) -> Result<(), Error> {
-        self.i2c
-            .write(DEVICE_ADDRESS, payload)
-            .map_err(|_err| Error::Comm);
-        Ok(())
+    #[test]
+    fn register_access_reports_bus_errors() {
+        let mut rtc = Ds323x::new_ds3231(MockI2c::failing());
+        assert!(matches!(rtc.write_register(0x00, 0x12), Err(Error::Comm)));
+        assert!(matches!(rtc.write_data(&mut [0x00, 0x12, 0x34]), Err(Error::Comm)));
+        assert!(matches!(rtc.read_register(0x00), Err(Error::Comm)));
+        assert!(matches!(rtc.read_data(&mut [0x00, 0, 0]), Err(Error::Comm)));
     }
 
-    pub fn read_register(&mut self, register: u8) -> Result<u8, Error> {
-        let mut data = [0];
-        self.i2c
-            .write_read(DEVICE_ADDRESS, &[register], &mut data)
-            .map_err(|_err| Error::Comm);
-        Ok(data[0])
+    #[test]
+    fn datetime_access_reports_bus_errors() {
+        let mut rtc = Ds323x::new_ds3231(MockI2c::failing());
+        assert!(matches!(rtc.datetime(), Err(Error::Comm)));
+        assert!(matches!(rtc.hours(), Err(Error::Comm)));
+        assert!(matches!(rtc.set_minutes(30), Err(Error::Comm)));
     }
 
-    pub fn read_data(&mut self, payload: &mut [u8]) -> Result<(), Error> {
-        let len = payload.len();
-        self.i2c
-            .write_read(DEVICE_ADDRESS, &[payload[0]], &mut payload[1..len])
-            .map_err(|_err| Error::Comm);
-        Ok(())
+    #[test]
+    fn status_flags_are_read_from_the_device() {
+        // A chip that ignores the clear still reports the flag: it is read back, not cached.
+        let i2c = MockI2c::default().with_register(Register::STATUS, BitFlags::OSC_STOP).read_only();
+        let mut rtc = Ds323x::new_ds3231(i2c);
+        rtc.clear_has_been_stopped_flag().unwrap();
+        assert!(rtc.has_been_stopped().unwrap());
+        let i2c = rtc.destroy_ds3231();
+        let write = &i2c.writes[0];
+        assert_eq!(Register::STATUS, write[0]);
+        assert_eq!(0, write[1] & BitFlags::OSC_STOP);
     }
 }
@@ -0,0 +1,84 @@
+//! Functions exclusive of DS3232
+
+use crate::{
+    check_sram_range, ic, interface::I2cInterface, BitFlags, Ds323x, Error, Register, CONTROL_POR_VALUE,
+    SRAM_BUFFER_LEN,
+};
+use core::marker::PhantomData;
+
+impl<I2C> Ds323x<I2cInterface<I2C>, ic::DS3232>
+where
+    I2C: embedded_hal::i2c::I2c,
+{
+    /// Create a new instance of the DS3232 device.
+    pub fn new_ds3232(i2c: I2C) -> Self {
+        const STATUS_POR_VALUE: u8 = BitFlags::OSC_STOP | BitFlags::BB32KHZ | BitFlags::EN32KHZ;
+        Ds323x {
+            iface: I2cInterface { i2c },
+            control: CONTROL_POR_VALUE,
+            status: STATUS_POR_VALUE,
+            _ic: PhantomData,
+        }
+    }
+
+    /// Destroy driver instance, return I²C bus instance.
+    pub fn destroy_ds3232(self) -> I2C {
+        self.iface.i2c
+    }
+
+    /// Read `buf.len()` bytes of user SRAM starting at `addr`.
+    ///
+    /// Returns `Error::InvalidInputData` if the read would run past the end of the SRAM.
+    pub fn read_sram(&mut self, addr: u8, buf: &mut [u8]) -> Result<(), Error> {
+        check_sram_range::<ic::DS3232>(addr, buf.len())?;
+        if buf.is_empty() {
+            return Ok(());
+        }
+        let mut payload = [0; SRAM_BUFFER_LEN];
+        let payload = &mut payload[..=buf.len()];
+        payload[0] = Register::SRAM_START + addr;
+        self.read_data(payload)?;
+        buf.copy_from_slice(&payload[1..]);
+        Ok(())
+    }
+
+    /// Write `data` to user SRAM starting at `addr`.
+    ///
+    /// Returns `Error::InvalidInputData` if the write would run past the end of the SRAM.
+    pub fn write_sram(&mut self, addr: u8, data: &[u8]) -> Result<(), Error> {
+        check_sram_range::<ic::DS3232>(addr, data.len())?;
+        if data.is_empty() {
+            return Ok(());
+        }
+        let mut payload = [0; SRAM_BUFFER_LEN];
+        let payload = &mut payload[..=data.len()];
+        payload[0] = Register::SRAM_START + addr;
+        payload[1..].copy_from_slice(data);
+        self.write_data(payload)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::MockI2c;
+
+    #[test]
+    fn sram_is_mapped_after_the_registers() {
+        let mut rtc = Ds323x::new_ds3232(MockI2c::default());
+        rtc.write_sram(0, &[0x5A, 0xA5]).unwrap();
+        let mut buf = [0; 2];
+        rtc.read_sram(0, &mut buf).unwrap();
+        assert_eq!([0x5A, 0xA5], buf);
+        assert_eq!([0x5A, 0xA5], rtc.destroy_ds3232().registers[0x14..0x16]);
+    }
+
+    #[test]
+    fn sram_holds_236_bytes() {
+        let mut rtc = Ds323x::new_ds3232(MockI2c::default());
+        rtc.write_sram(235, &[7]).unwrap();
+        assert!(matches!(rtc.write_sram(235, &[7, 8]), Err(Error::InvalidInputData)));
+        assert!(matches!(rtc.read_sram(236, &mut [0]), Err(Error::InvalidInputData)));
+        assert_eq!(7, rtc.destroy_ds3232().registers[0xFF]);
+    }
+}
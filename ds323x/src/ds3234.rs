@@ -0,0 +1,297 @@
+//! Functions exclusive of DS3234
+
+use crate::{
+    check_sram_range, ic, interface::SpiInterface, BitFlags, Ds323x, Error, Register, CONTROL_POR_VALUE,
+    SRAM_BUFFER_LEN,
+};
+use core::marker::PhantomData;
+use embedded_hal::{digital::OutputPin, spi::SpiBus};
+
+impl<SPI, CS> Ds323x<SpiInterface<SPI, CS>, ic::DS3234>
+where
+    SPI: SpiBus,
+    CS: OutputPin,
+{
+    /// Create a new instance of the DS3234 device on an SPI bus, with `cs` its chip select.
+    pub fn new_ds3234(spi: SPI, cs: CS) -> Self {
+        const STATUS_POR_VALUE: u8 = BitFlags::OSC_STOP | BitFlags::BB32KHZ | BitFlags::EN32KHZ;
+        Ds323x {
+            iface: SpiInterface { spi, cs },
+            control: CONTROL_POR_VALUE,
+            status: STATUS_POR_VALUE,
+            _ic: PhantomData,
+        }
+    }
+
+    /// Destroy driver instance, return SPI bus instance and chip select pin.
+    pub fn destroy_ds3234(self) -> (SPI, CS) {
+        (self.iface.spi, self.iface.cs)
+    }
+
+    /// Read `buf.len()` bytes of user SRAM starting at `addr`.
+    ///
+    /// Returns `Error::InvalidInputData` if the read would run past the end of the SRAM.
+    pub fn read_sram(&mut self, addr: u8, buf: &mut [u8]) -> Result<(), Error> {
+        check_sram_range::<ic::DS3234>(addr, buf.len())?;
+        if buf.is_empty() {
+            return Ok(());
+        }
+        // The SRAM address advances with every byte of a burst on the data register.
+        self.write_register(Register::SRAM_ADDRESS, addr)?;
+        let mut payload = [0; SRAM_BUFFER_LEN];
+        let payload = &mut payload[..=buf.len()];
+        payload[0] = Register::SRAM_DATA;
+        self.read_data(payload)?;
+        buf.copy_from_slice(&payload[1..]);
+        Ok(())
+    }
+
+    /// Write `data` to user SRAM starting at `addr`.
+    ///
+    /// Returns `Error::InvalidInputData` if the write would run past the end of the SRAM.
+    pub fn write_sram(&mut self, addr: u8, data: &[u8]) -> Result<(), Error> {
+        check_sram_range::<ic::DS3234>(addr, data.len())?;
+        if data.is_empty() {
+            return Ok(());
+        }
+        self.write_register(Register::SRAM_ADDRESS, addr)?;
+        let mut payload = [0; SRAM_BUFFER_LEN];
+        let payload = &mut payload[..=data.len()];
+        payload[0] = Register::SRAM_DATA;
+        payload[1..].copy_from_slice(data);
+        self.write_data(payload)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::{DateTimeAccess, Error, NaiveDate, Register};
+    use core::cell::{Cell, RefCell};
+    use core::convert::Infallible;
+    use embedded_hal::{digital, spi};
+    use std::rc::Rc;
+
+    type Registers = Rc<RefCell<[u8; 0x20]>>;
+    type MockRtc = Ds323x<SpiInterface<MockSpi, MockCs>, ic::DS3234>;
+
+    // A DS3234 register file. Every bus call is one chip-select frame: an address byte, then
+    // data written to or read from consecutive registers.
+    // Bursts on the SRAM data register go to consecutive SRAM bytes instead.
+    struct MockSpi {
+        registers: Registers,
+        sram: [u8; 256],
+        selected: Rc<Cell<bool>>,
+    }
+
+    impl Default for MockSpi {
+        fn default() -> Self {
+            MockSpi {
+                registers: Rc::default(),
+                sram: [0; 256],
+                selected: Rc::default(),
+            }
+        }
+    }
+
+    impl MockSpi {
+        fn frame(&mut self, bytes: &mut [u8]) {
+            assert!(self.selected.get(), "SPI access without chip select");
+            let mut registers = self.registers.borrow_mut();
+            let address = (bytes[0] & 0x7F) as usize;
+            if address == Register::SRAM_DATA as usize {
+                let write = bytes[0] & 0x80 != 0;
+                let sram_address = &mut registers[Register::SRAM_ADDRESS as usize];
+                for byte in &mut bytes[1..] {
+                    if write {
+                        self.sram[*sram_address as usize] = *byte;
+                    } else {
+                        *byte = self.sram[*sram_address as usize];
+                    }
+                    *sram_address = sram_address.wrapping_add(1);
+                }
+            } else if bytes[0] & 0x80 != 0 {
+                registers[address..address + bytes.len() - 1].copy_from_slice(&bytes[1..]);
+            } else {
+                let len = bytes.len() - 1;
+                bytes[1..].copy_from_slice(&registers[address..address + len]);
+            }
+        }
+    }
+
+    impl spi::ErrorType for MockSpi {
+        type Error = spi::ErrorKind;
+    }
+
+    impl SpiBus for MockSpi {
+        // The driver always sends an address first, so a bare read is a driver bug.
+        fn read(&mut self, _words: &mut [u8]) -> Result<(), Self::Error> {
+            Err(spi::ErrorKind::Other)
+        }
+
+        fn write(&mut self, words: &[u8]) -> Result<(), Self::Error> {
+            self.frame(&mut words.to_vec());
+            Ok(())
+        }
+
+        fn transfer(&mut self, read: &mut [u8], write: &[u8]) -> Result<(), Self::Error> {
+            let mut bytes = write.to_vec();
+            self.frame(&mut bytes);
+            read.copy_from_slice(&bytes);
+            Ok(())
+        }
+
+        fn transfer_in_place(&mut self, words: &mut [u8]) -> Result<(), Self::Error> {
+            self.frame(words);
+            Ok(())
+        }
+
+        fn flush(&mut self) -> Result<(), Self::Error> {
+            Ok(())
+        }
+    }
+
+    // Chip select, active low.
+    struct MockCs(Rc<Cell<bool>>);
+
+    impl digital::ErrorType for MockCs {
+        type Error = Infallible;
+    }
+
+    impl OutputPin for MockCs {
+        fn set_low(&mut self) -> Result<(), Self::Error> {
+            self.0.set(true);
+            Ok(())
+        }
+
+        fn set_high(&mut self) -> Result<(), Self::Error> {
+            self.0.set(false);
+            Ok(())
+        }
+    }
+
+    struct FailingSpi;
+
+    impl spi::ErrorType for FailingSpi {
+        type Error = spi::ErrorKind;
+    }
+
+    impl SpiBus for FailingSpi {
+        fn read(&mut self, _words: &mut [u8]) -> Result<(), Self::Error> {
+            Err(spi::ErrorKind::Other)
+        }
+
+        fn write(&mut self, _words: &[u8]) -> Result<(), Self::Error> {
+            Err(spi::ErrorKind::Other)
+        }
+
+        fn transfer(&mut self, _read: &mut [u8], _write: &[u8]) -> Result<(), Self::Error> {
+            Err(spi::ErrorKind::Other)
+        }
+
+        fn transfer_in_place(&mut self, _words: &mut [u8]) -> Result<(), Self::Error> {
+            Err(spi::ErrorKind::Other)
+        }
+
+        fn flush(&mut self) -> Result<(), Self::Error> {
+            Ok(())
+        }
+    }
+
+    struct FailingCs;
+
+    impl digital::ErrorType for FailingCs {
+        type Error = digital::ErrorKind;
+    }
+
+    impl OutputPin for FailingCs {
+        fn set_low(&mut self) -> Result<(), Self::Error> {
+            Err(digital::ErrorKind::Other)
+        }
+
+        fn set_high(&mut self) -> Result<(), Self::Error> {
+            Err(digital::ErrorKind::Other)
+        }
+    }
+
+    fn new_mock() -> (MockRtc, Registers, Rc<Cell<bool>>) {
+        let spi = MockSpi::default();
+        let registers = spi.registers.clone();
+        let selected = spi.selected.clone();
+        let cs = MockCs(selected.clone());
+        (Ds323x::new_ds3234(spi, cs), registers, selected)
+    }
+
+    #[test]
+    fn register_roundtrip() {
+        let (mut rtc, registers, selected) = new_mock();
+        rtc.write_register(Register::AGING_OFFSET, 0x12).unwrap();
+        assert_eq!(0x12, registers.borrow()[Register::AGING_OFFSET as usize]);
+        assert_eq!(0x12, rtc.read_register(Register::AGING_OFFSET).unwrap());
+        assert!(!selected.get(), "chip select left asserted");
+    }
+
+    #[test]
+    fn data_roundtrip() {
+        let (mut rtc, registers, _) = new_mock();
+        rtc.write_data(&mut [Register::ALARM2_MINUTES, 0x05, 0x19, 0x81]).unwrap();
+        assert_eq!([0x05, 0x19, 0x81], registers.borrow()[0x0B..0x0E]);
+
+        let mut data = [Register::ALARM2_MINUTES, 0, 0, 0];
+        rtc.read_data(&mut data).unwrap();
+        assert_eq!([0x05, 0x19, 0x81], data[1..]);
+    }
+
+    #[test]
+    fn datetime_roundtrip() {
+        let (mut rtc, _, _) = new_mock();
+        let datetime = NaiveDate::from_ymd_opt(2024, 6, 21)
+            .unwrap()
+            .and_hms_opt(13, 45, 30)
+            .unwrap();
+        rtc.set_datetime(&datetime).unwrap();
+        assert_eq!(datetime, rtc.datetime().unwrap());
+    }
+
+    #[test]
+    fn bus_errors_release_chip_select() {
+        let selected = Rc::new(Cell::new(false));
+        let mut rtc = Ds323x::new_ds3234(FailingSpi, MockCs(selected.clone()));
+        assert!(matches!(rtc.write_register(0x00, 0x12), Err(Error::Comm)));
+        assert!(matches!(rtc.read_register(0x00), Err(Error::Comm)));
+        assert!(!selected.get());
+    }
+
+    #[test]
+    fn pin_errors_are_reported() {
+        let mut rtc = Ds323x::new_ds3234(MockSpi::default(), FailingCs);
+        assert!(matches!(rtc.read_register(0x00), Err(Error::Pin)));
+    }
+
+    #[test]
+    fn sram_roundtrip() {
+        let (mut rtc, _, _) = new_mock();
+        rtc.write_sram(0x10, &[1, 2, 3, 4]).unwrap();
+        let mut buf = [0; 6];
+        rtc.read_sram(0x0F, &mut buf).unwrap();
+        assert_eq!([0, 1, 2, 3, 4, 0], buf);
+    }
+
+    #[test]
+    fn sram_covers_all_256_bytes() {
+        let (mut rtc, _, _) = new_mock();
+        rtc.write_sram(0xFF, &[0xAB]).unwrap();
+        let mut all = [0; 256];
+        rtc.read_sram(0, &mut all).unwrap();
+        assert_eq!(0xAB, all[255]);
+    }
+
+    #[test]
+    fn sram_access_past_the_end_is_rejected() {
+        let (mut rtc, _, _) = new_mock();
+        assert!(matches!(rtc.write_sram(0xFE, &[1, 2, 3]), Err(Error::InvalidInputData)));
+        assert!(matches!(rtc.read_sram(0x01, &mut [0; 256]), Err(Error::InvalidInputData)));
+    }
+}
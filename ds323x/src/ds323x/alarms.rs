@@ -1,6 +1,7 @@
 //! Alarm support
 
 use super::{decimal_to_packed_bcd, hours_to_register};
+use crate::interface::{ReadData, WriteData};
 use crate::{
     ds323x::{NaiveTime, Timelike},
     BitFlags, Ds323x, Error, Hours, Register,
@@ -149,9 +150,9 @@ fn amend_hour(hours: Hours) -> Hours {
     }
 }
 
-impl<I2C> Ds323x<I2C>
+impl<DI, IC> Ds323x<DI, IC>
 where
-    I2C: embedded_hal::i2c::I2c,
+    DI: ReadData + WriteData,
 {
     /// Set Alarm1 for day of the month.
     ///
@@ -337,3 +338,71 @@ where
         self.write_data(&mut data)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::{ic, interface::I2cInterface};
+    use embedded_hal::i2c::{ErrorKind, ErrorType, I2c, Operation};
+    use std::vec::Vec;
+
+    // Records every write so the register contents can be checked.
+    #[derive(Default)]
+    struct RecordingI2c {
+        writes: Vec<Vec<u8>>,
+    }
+
+    impl ErrorType for RecordingI2c {
+        type Error = ErrorKind;
+    }
+
+    impl I2c for RecordingI2c {
+        fn transaction(&mut self, _address: u8, operations: &mut [Operation<'_>]) -> Result<(), Self::Error> {
+            for operation in operations {
+                if let Operation::Write(bytes) = operation {
+                    self.writes.push(bytes.to_vec());
+                }
+            }
+            Ok(())
+        }
+    }
+
+    fn written(rtc: Ds323x<I2cInterface<RecordingI2c>, ic::DS3231>) -> Vec<Vec<u8>> {
+        rtc.destroy_ds3231().writes
+    }
+
+    #[test]
+    fn alarm1_hms_writes_time_and_masks_day() {
+        let mut rtc = Ds323x::new_ds3231(RecordingI2c::default());
+        rtc.set_alarm1_hms(NaiveTime::from_hms_opt(6, 42, 15).unwrap()).unwrap();
+        assert_eq!(written(rtc), [[Register::ALARM1_SECONDS, 0x15, 0x42, 0x06, 0x81]]);
+    }
+
+    #[test]
+    fn alarm2_hm_writes_time_and_masks_day() {
+        let mut rtc = Ds323x::new_ds3231(RecordingI2c::default());
+        rtc.set_alarm2_hm(NaiveTime::from_hms_opt(19, 5, 0).unwrap()).unwrap();
+        assert_eq!(written(rtc), [[Register::ALARM2_MINUTES, 0x05, 0x19, 0x81]]);
+    }
+
+    #[test]
+    fn alarm2_once_per_minute_masks_everything() {
+        let mut rtc = Ds323x::new_ds3231(RecordingI2c::default());
+        let when = DayAlarm2 { day: 1, hour: Hours::H24(0), minute: 0 };
+        rtc.set_alarm2_day(when, Alarm2Matching::OncePerMinute).unwrap();
+        assert_eq!(written(rtc), [[Register::ALARM2_MINUTES, 0x80, 0x80, 0x81]]);
+    }
+
+    #[test]
+    fn alarm1_rejects_invalid_time() {
+        let mut rtc = Ds323x::new_ds3231(RecordingI2c::default());
+        let when = DayAlarm1 { day: 1, hour: Hours::H24(24), minute: 0, second: 0 };
+        assert!(matches!(
+            rtc.set_alarm1_day(when, Alarm1Matching::HoursMinutesAndSecondsMatch),
+            Err(Error::InvalidInputData)
+        ));
+        assert!(written(rtc).is_empty());
+    }
+}
@@ -1,10 +1,11 @@
 //! Device configuration
 
+use crate::interface::{ReadData, WriteData};
 use crate::{BitFlags, Ds323x, Error, Register, SqWFreq};
 
-impl<I2C> Ds323x<I2C>
+impl<DI, IC> Ds323x<DI, IC>
 where
-    I2C: embedded_hal::i2c::I2c,
+    DI: ReadData + WriteData,
 {
     /// Enable the oscillator (set the clock running) (default).
     pub fn enable(&mut self) -> Result<(), Error> {
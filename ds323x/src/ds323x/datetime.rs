@@ -3,14 +3,15 @@
 use super::{
     decimal_to_packed_bcd, hours_to_register, packed_bcd_to_decimal, some_or_invalid_error,
 };
+use crate::interface::{ReadData, WriteData};
 use crate::{
     BitFlags, DateTimeAccess, Datelike, Ds323x, Error, Hours, NaiveDate, NaiveDateTime, NaiveTime,
     Register, Rtcc, Timelike,
 };
 
-impl<I2C> DateTimeAccess for Ds323x<I2C>
+impl<DI, IC> DateTimeAccess for Ds323x<DI, IC>
 where
-    I2C: embedded_hal::i2c::I2c,
+    DI: ReadData + WriteData,
 {
     type Error = Error;
 
@@ -53,9 +54,9 @@ where
     }
 }
 
-impl<I2C> Rtcc for Ds323x<I2C>
+impl<DI, IC> Rtcc for Ds323x<DI, IC>
 where
-    I2C: embedded_hal::i2c::I2c,
+    DI: ReadData + WriteData,
 {
     fn seconds(&mut self) -> Result<u8, Self::Error> {
         self.read_register_decimal(Register::SECONDS)
@@ -211,9 +212,9 @@ where
     }
 }
 
-impl<I2C> Ds323x<I2C>
+impl<DI, IC> Ds323x<DI, IC>
 where
-    I2C: embedded_hal::i2c::I2c,
+    DI: ReadData + WriteData,
 {
     fn read_register_decimal(&mut self, register: u8) -> Result<u8, Error> {
         let data = self.read_register(register)?;
@@ -272,7 +273,9 @@ fn is_am(hours_data: u8) -> bool {
 fn get_h24(hour: Hours) -> u8 {
     match hour {
         Hours::H24(h) => h,
+        Hours::AM(12) => 0,
         Hours::AM(h) => h,
+        Hours::PM(12) => 12,
         Hours::PM(h) => h + 12,
     }
 }
@@ -292,7 +295,8 @@ mod tests {
         assert_eq!(13, get_h24(Hours::PM(1)));
 
         assert_eq!(23, get_h24(Hours::H24(23)));
-        assert_eq!(12, get_h24(Hours::AM(12)));
+        assert_eq!(0, get_h24(Hours::AM(12)));
+        assert_eq!(12, get_h24(Hours::PM(12)));
         assert_eq!(23, get_h24(Hours::PM(11)));
     }
 }
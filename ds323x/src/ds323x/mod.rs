@@ -5,7 +5,29 @@ pub use self::alarms::{
     Alarm1Matching, Alarm2Matching, DayAlarm1, DayAlarm2, WeekdayAlarm1, WeekdayAlarm2,
 };
 mod datetime;
-use crate::{BitFlags, Error, Hours, NaiveTime, Timelike};
+use crate::interface::{ReadData, WriteData};
+use crate::{BitFlags, Ds323x, Error, Hours, NaiveTime, Timelike};
+
+impl<DI, IC> Ds323x<DI, IC>
+where
+    DI: ReadData + WriteData,
+{
+    pub fn write_register(&mut self, register: u8, data: u8) -> Result<(), Error> {
+        self.iface.write_register(register, data)
+    }
+
+    pub fn write_data(&mut self, payload: &mut [u8]) -> Result<(), Error> {
+        self.iface.write_data(payload)
+    }
+
+    pub fn read_register(&mut self, register: u8) -> Result<u8, Error> {
+        self.iface.read_register(register)
+    }
+
+    pub fn read_data(&mut self, payload: &mut [u8]) -> Result<(), Error> {
+        self.iface.read_data(payload)
+    }
+}
 
 // Transforms a decimal number to packed BCD format
 fn decimal_to_packed_bcd(dec: u8) -> u8 {
@@ -42,7 +64,7 @@ mod tests {
 
     #[test]
     fn if_some_then_get_inner() {
-        match some_or_invalid_error::<u8, (), ()>(Some(1)) {
+        match some_or_invalid_error::<u8>(Some(1)) {
             Ok(1) => (),
             _ => panic!(),
         }
@@ -50,7 +72,7 @@ mod tests {
 
     #[test]
     fn if_none_then_error() {
-        match some_or_invalid_error::<u8, (), ()>(None) {
+        match some_or_invalid_error::<u8>(None) {
             Err(Error::InvalidDeviceState) => (),
             _ => panic!(),
         }
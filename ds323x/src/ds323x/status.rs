@@ -1,10 +1,11 @@
 //! Device status
 
+use crate::interface::{ReadData, WriteData};
 use crate::{BitFlags, Ds323x, Error, Register};
 
-impl<I2C> Ds323x<I2C>
+impl<DI, IC> Ds323x<DI, IC>
 where
-    I2C: embedded_hal::i2c::I2c,
+    DI: ReadData + WriteData,
 {
     /// Read whether the oscillator is running
     pub fn running(&mut self) -> Result<bool, Error> {
@@ -80,13 +81,48 @@ where
     pub fn temperature(&mut self) -> Result<f32, Error> {
         let mut data = [Register::TEMP_MSB, 0, 0];
         self.read_data(&mut data)?;
-        let is_negative = (data[1] & 0b1000_0000) != 0;
-        let temp = (u16::from(data[1]) << 2) | u16::from(data[2] >> 6);
-        if is_negative {
-            let temp_sign_extended = temp | 0b1111_1100_0000_0000;
-            Ok(f32::from(temp_sign_extended as i16) * 0.25)
-        } else {
-            Ok(f32::from(temp) * 0.25)
-        }
+        Ok(temperature_from_registers(data[1], data[2]))
+    }
+}
+
+/// Decode the TEMP_MSB/TEMP_LSB pair: a 10-bit two's-complement value in 0.25°C steps,
+/// with the integer part in MSB and the fraction in the top two bits of LSB.
+fn temperature_from_registers(msb: u8, lsb: u8) -> f32 {
+    let is_negative = (msb & 0b1000_0000) != 0;
+    let temp = (u16::from(msb) << 2) | u16::from(lsb >> 6);
+    if is_negative {
+        let temp_sign_extended = temp | 0b1111_1100_0000_0000;
+        f32::from(temp_sign_extended as i16) * 0.25
+    } else {
+        f32::from(temp) * 0.25
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::temperature_from_registers;
+
+    #[test]
+    fn decodes_positive_temperatures() {
+        assert_eq!(0.0, temperature_from_registers(0b0000_0000, 0b0000_0000));
+        assert_eq!(25.0, temperature_from_registers(0b0001_1001, 0b0000_0000));
+        assert_eq!(127.75, temperature_from_registers(0b0111_1111, 0b1100_0000));
+    }
+
+    #[test]
+    fn decodes_quarter_degree_fractions() {
+        assert_eq!(21.25, temperature_from_registers(0b0001_0101, 0b0100_0000));
+        assert_eq!(21.5, temperature_from_registers(0b0001_0101, 0b1000_0000));
+        assert_eq!(21.75, temperature_from_registers(0b0001_0101, 0b1100_0000));
+        // The low six bits of LSB are unused.
+        assert_eq!(21.0, temperature_from_registers(0b0001_0101, 0b0011_1111));
+    }
+
+    #[test]
+    fn decodes_negative_temperatures() {
+        assert_eq!(-0.25, temperature_from_registers(0b1111_1111, 0b1100_0000));
+        assert_eq!(-1.0, temperature_from_registers(0b1111_1111, 0b0000_0000));
+        assert_eq!(-18.75, temperature_from_registers(0b1110_1101, 0b0100_0000));
+        assert_eq!(-128.0, temperature_from_registers(0b1000_0000, 0b0000_0000));
     }
 }
@@ -0,0 +1,128 @@
+//! I2C/SPI interfaces
+
+use crate::{private, Error, DEVICE_ADDRESS};
+use embedded_hal::{digital::OutputPin, spi::SpiBus};
+
+/// I2C interface (DS3231 and DS3232)
+#[derive(Debug, Default)]
+pub struct I2cInterface<I2C> {
+    pub(crate) i2c: I2C,
+}
+
+/// SPI interface (DS3234)
+///
+/// `CS` is driven low around every register access.
+#[derive(Debug, Default)]
+pub struct SpiInterface<SPI, CS> {
+    pub(crate) spi: SPI,
+    pub(crate) cs: CS,
+}
+
+// The DS3234 tells writes from reads by the MSB of the address byte.
+const SPI_WRITE_BIT: u8 = 0x80;
+
+/// Write data
+pub trait WriteData: private::Sealed {
+    /// Write to an u8 register
+    fn write_register(&mut self, register: u8, data: u8) -> Result<(), Error>;
+    /// Write data. The first element corresponds to the starting address.
+    fn write_data(&mut self, payload: &mut [u8]) -> Result<(), Error>;
+}
+
+/// Read data
+pub trait ReadData: private::Sealed {
+    /// Read an u8 register
+    fn read_register(&mut self, register: u8) -> Result<u8, Error>;
+    /// Read some data. The first element corresponds to the starting address.
+    fn read_data(&mut self, payload: &mut [u8]) -> Result<(), Error>;
+}
+
+impl<I2C> WriteData for I2cInterface<I2C>
+where
+    I2C: embedded_hal::i2c::I2c,
+{
+    fn write_register(&mut self, register: u8, data: u8) -> Result<(), Error> {
+        let payload: [u8; 2] = [register, data];
+        self.i2c
+            .write(DEVICE_ADDRESS, &payload)
+            .map_err(|_err| Error::Comm)
+    }
+
+    fn write_data(&mut self, payload: &mut [u8]) -> Result<(), Error> {
+        self.i2c
+            .write(DEVICE_ADDRESS, payload)
+            .map_err(|_err| Error::Comm)
+    }
+}
+
+impl<I2C> ReadData for I2cInterface<I2C>
+where
+    I2C: embedded_hal::i2c::I2c,
+{
+    fn read_register(&mut self, register: u8) -> Result<u8, Error> {
+        let mut data = [0];
+        self.i2c
+            .write_read(DEVICE_ADDRESS, &[register], &mut data)
+            .map_err(|_err| Error::Comm)?;
+        Ok(data[0])
+    }
+
+    fn read_data(&mut self, payload: &mut [u8]) -> Result<(), Error> {
+        let len = payload.len();
+        self.i2c
+            .write_read(DEVICE_ADDRESS, &[payload[0]], &mut payload[1..len])
+            .map_err(|_err| Error::Comm)
+    }
+}
+
+impl<SPI, CS> SpiInterface<SPI, CS>
+where
+    SPI: SpiBus,
+    CS: OutputPin,
+{
+    // Run `f` with the chip selected. CS is released even if the transfer fails.
+    fn selected<F>(&mut self, f: F) -> Result<(), Error>
+    where
+        F: FnOnce(&mut SPI) -> Result<(), SPI::Error>,
+    {
+        self.cs.set_low().map_err(|_err| Error::Pin)?;
+        let result = f(&mut self.spi)
+            .and_then(|_| self.spi.flush())
+            .map_err(|_err| Error::Comm);
+        self.cs.set_high().map_err(|_err| Error::Pin)?;
+        result
+    }
+}
+
+impl<SPI, CS> WriteData for SpiInterface<SPI, CS>
+where
+    SPI: SpiBus,
+    CS: OutputPin,
+{
+    fn write_register(&mut self, register: u8, data: u8) -> Result<(), Error> {
+        let payload: [u8; 2] = [register | SPI_WRITE_BIT, data];
+        self.selected(|spi| spi.write(&payload))
+    }
+
+    fn write_data(&mut self, payload: &mut [u8]) -> Result<(), Error> {
+        payload[0] |= SPI_WRITE_BIT;
+        self.selected(|spi| spi.write(payload))
+    }
+}
+
+impl<SPI, CS> ReadData for SpiInterface<SPI, CS>
+where
+    SPI: SpiBus,
+    CS: OutputPin,
+{
+    fn read_register(&mut self, register: u8) -> Result<u8, Error> {
+        let mut data = [register, 0];
+        self.selected(|spi| spi.transfer_in_place(&mut data))?;
+        Ok(data[1])
+    }
+
+    fn read_data(&mut self, payload: &mut [u8]) -> Result<(), Error> {
+        // The byte clocked in while the address goes out is garbage; callers skip payload[0].
+        self.selected(|spi| spi.transfer_in_place(payload))
+    }
+}
@@ -1,6 +1,8 @@
 #![deny(unsafe_code)]
 #![no_std]
 
+use core::marker::PhantomData;
+
 pub use rtcc::{
     DateTimeAccess, Datelike, Hours, NaiveDate, NaiveDateTime, NaiveTime, Rtcc, Timelike,
 };
@@ -66,6 +68,11 @@ impl Register {
     const AGING_OFFSET: u8 = 0x10;
     const TEMP_MSB: u8 = 0x11;
     const TEMP_CONV: u8 = 0x13;
+    // DS3232: user SRAM is mapped directly from here to 0xFF.
+    const SRAM_START: u8 = 0x14;
+    // DS3234: user SRAM is reached through an address and a data register.
+    const SRAM_ADDRESS: u8 = 0x18;
+    const SRAM_DATA: u8 = 0x19;
 }
 
 struct BitFlags;
@@ -108,19 +115,58 @@ pub mod ic {
     pub struct DS3234;
 }
 
+/// IC markers with battery-backed user SRAM (DS3232 and DS3234)
+///
+/// The SRAM keeps its contents on the backup battery, like the time, and takes any number of
+/// writes, so it suits data that changes too often for flash.
+pub trait Sram: private::Sealed {
+    /// User SRAM size in bytes
+    const SRAM_SIZE: usize;
+}
+
+impl Sram for ic::DS3232 {
+    const SRAM_SIZE: usize = 236;
+}
+
+impl Sram for ic::DS3234 {
+    const SRAM_SIZE: usize = 256;
+}
+
+// Largest SRAM access plus its address byte.
+const SRAM_BUFFER_LEN: usize = 257;
+
+// Reject SRAM accesses of `len` bytes from `addr` that run past the end of the SRAM.
+fn check_sram_range<IC: Sram>(addr: u8, len: usize) -> Result<(), Error> {
+    if addr as usize + len > IC::SRAM_SIZE {
+        Err(Error::InvalidInputData)
+    } else {
+        Ok(())
+    }
+}
+
 /// DS3231, DS3232 and DS3234 RTC driver
+///
+/// `DI` is the bus interface (`interface::I2cInterface` or `interface::SpiInterface`) and `IC`
+/// the device marker from `ic`.
 #[derive(Debug, Default)]
-pub struct Ds323x<I2C> {
-    i2c: I2C,
+pub struct Ds323x<DI, IC> {
+    iface: DI,
     control: u8,
     status: u8,
+    _ic: PhantomData<IC>,
 }
 
+pub mod interface;
+
 mod ds323x;
 pub use crate::ds323x::{
     Alarm1Matching, Alarm2Matching, DayAlarm1, DayAlarm2, WeekdayAlarm1, WeekdayAlarm2,
 };
 mod ds3231;
+mod ds3232;
+mod ds3234;
+#[cfg(test)]
+mod mock;
 
 mod private {
     use super::ic;
@@ -129,4 +175,6 @@ mod private {
     impl Sealed for ic::DS3231 {}
     impl Sealed for ic::DS3232 {}
     impl Sealed for ic::DS3234 {}
+    impl<I2C> Sealed for super::interface::I2cInterface<I2C> {}
+    impl<SPI, CS> Sealed for super::interface::SpiInterface<SPI, CS> {}
 }
@@ -0,0 +1,78 @@
+//! Register-file I2C bus shared by the DS3231 and DS3232 tests
+
+extern crate std;
+
+use embedded_hal::i2c::{ErrorKind, ErrorType, I2c, Operation};
+use std::vec::Vec;
+
+/// A DS323x register file. A write sets the register pointer from its first byte, then reads
+/// and writes go to consecutive registers. Every write is also kept in `writes`.
+pub(crate) struct MockI2c {
+    pub registers: [u8; 0x100],
+    pub writes: Vec<Vec<u8>>,
+    pointer: usize,
+    failing: bool,
+    read_only: bool,
+}
+
+impl Default for MockI2c {
+    fn default() -> Self {
+        MockI2c {
+            registers: [0; 0x100],
+            writes: Vec::new(),
+            pointer: 0,
+            failing: false,
+            read_only: false,
+        }
+    }
+}
+
+impl MockI2c {
+    /// A bus on which every transaction fails, like a disconnected or stuck RTC.
+    pub fn failing() -> Self {
+        MockI2c { failing: true, ..Default::default() }
+    }
+
+    /// Writes still move the register pointer and are logged, but leave the registers as they were.
+    pub fn read_only(self) -> Self {
+        MockI2c { read_only: true, ..self }
+    }
+
+    pub fn with_register(mut self, register: u8, value: u8) -> Self {
+        self.registers[register as usize] = value;
+        self
+    }
+}
+
+impl ErrorType for MockI2c {
+    type Error = ErrorKind;
+}
+
+impl I2c for MockI2c {
+    fn transaction(&mut self, _address: u8, operations: &mut [Operation<'_>]) -> Result<(), Self::Error> {
+        if self.failing {
+            return Err(ErrorKind::Other);
+        }
+        for operation in operations {
+            match operation {
+                Operation::Write(bytes) => {
+                    self.writes.push(bytes.to_vec());
+                    self.pointer = bytes[0] as usize;
+                    for byte in &bytes[1..] {
+                        if !self.read_only {
+                            self.registers[self.pointer] = *byte;
+                        }
+                        self.pointer += 1;
+                    }
+                }
+                Operation::Read(buffer) => {
+                    for byte in buffer.iter_mut() {
+                        *byte = self.registers[self.pointer];
+                        self.pointer += 1;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,260 @@
+//! The events themselves: a fixed-capacity ring and its NVS blob format.
+//!
+//! Blob layout, little-endian: format version (u8), events recorded since the log was created
+//! (u32), number of entries (u8), then per entry the Unix time in seconds (i64, 0 when the clock
+//! wasn't set), the kind (u8), the detail length (u8) and the detail's UTF-8 bytes.
+
+use serde::Serialize;
+use std::collections::VecDeque;
+
+/// Events kept; older ones are dropped as new ones arrive.
+pub const CAPACITY: usize = 32;
+/// Longer details are cut to this many bytes, so the blob stays under `MAX_BLOB_LEN`.
+pub const MAX_DETAIL_LEN: usize = 64;
+pub const MAX_BLOB_LEN: usize = HEADER_LEN + CAPACITY * (ENTRY_HEADER_LEN + MAX_DETAIL_LEN);
+
+const FORMAT_VERSION: u8 = 1;
+const HEADER_LEN: usize = 6;
+const ENTRY_HEADER_LEN: usize = 10;
+// Before this (2020-01-01) the system clock hasn't been set since boot.
+const MIN_VALID_UNIX_SECS: i64 = 1_577_836_800;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EventKind {
+    Boot,
+    Ota,
+    Stall,
+    HomingFailed,
+    Panic,
+    ModeChange,
+}
+
+impl EventKind {
+    // Stored in the blob; never renumber.
+    fn code(&self) -> u8 {
+        match self {
+            EventKind::Boot => 0,
+            EventKind::Ota => 1,
+            EventKind::Stall => 2,
+            EventKind::HomingFailed => 3,
+            EventKind::Panic => 4,
+            EventKind::ModeChange => 5,
+        }
+    }
+
+    fn from_code(code: u8) -> Option<EventKind> {
+        match code {
+            0 => Some(EventKind::Boot),
+            1 => Some(EventKind::Ota),
+            2 => Some(EventKind::Stall),
+            3 => Some(EventKind::HomingFailed),
+            4 => Some(EventKind::Panic),
+            5 => Some(EventKind::ModeChange),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Event {
+    /// Unix time in seconds, `None` if the clock wasn't set yet (early in boot, before NTP).
+    pub at: Option<i64>,
+    pub kind: EventKind,
+    pub detail: String,
+}
+
+impl Event {
+    /// An event at `unix_secs`, with `detail` cut to `MAX_DETAIL_LEN` bytes.
+    pub fn new(unix_secs: i64, kind: EventKind, detail: &str) -> Self {
+        let mut end = detail.len().min(MAX_DETAIL_LEN);
+        while !detail.is_char_boundary(end) {
+            end -= 1;
+        }
+        Event {
+            at: (unix_secs >= MIN_VALID_UNIX_SECS).then_some(unix_secs),
+            kind,
+            detail: detail[..end].to_string(),
+        }
+    }
+}
+
+/// Serializes as `{"recorded":<n>,"events":[...]}`, oldest event first.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Events {
+    #[serde(skip)]
+    capacity: usize,
+    // Everything ever recorded, so a dump shows how many were dropped.
+    recorded: u32,
+    #[serde(rename = "events")]
+    entries: VecDeque<Event>,
+}
+
+impl Events {
+    pub fn new(capacity: usize) -> Self {
+        Events {
+            capacity,
+            recorded: 0,
+            entries: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    /// Append an event, dropping the oldest one once the log is full.
+    pub fn push(&mut self, event: Event) {
+        self.recorded = self.recorded.wrapping_add(1);
+        if self.capacity == 0 {
+            return;
+        }
+        if self.entries.len() == self.capacity {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(event);
+    }
+
+    /// Events from oldest to newest.
+    pub fn iter(&self) -> impl Iterator<Item = &Event> {
+        self.entries.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Events recorded in total, including those since dropped.
+    pub fn recorded(&self) -> u32 {
+        self.recorded
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut blob = Vec::with_capacity(MAX_BLOB_LEN);
+        blob.push(FORMAT_VERSION);
+        blob.extend_from_slice(&self.recorded.to_le_bytes());
+        blob.push(self.entries.len() as u8);
+        for event in &self.entries {
+            blob.extend_from_slice(&event.at.unwrap_or(0).to_le_bytes());
+            blob.push(event.kind.code());
+            blob.push(event.detail.len() as u8);
+            blob.extend_from_slice(event.detail.as_bytes());
+        }
+        blob
+    }
+
+    /// The log stored by `encode`, keeping the newest `capacity` entries.
+    pub fn decode(blob: &[u8], capacity: usize) -> Result<Events, String> {
+        if blob.len() < HEADER_LEN {
+            return Err(format!("event log blob is {} bytes, shorter than its header", blob.len()));
+        }
+        if blob[0] != FORMAT_VERSION {
+            return Err(format!("unknown event log format {}", blob[0]));
+        }
+        let mut events = Events::new(capacity);
+        let count = blob[5] as usize;
+        let mut rest = &blob[HEADER_LEN..];
+        for i in 0..count {
+            if rest.len() < ENTRY_HEADER_LEN {
+                return Err(format!("event log blob ends inside entry {}", i));
+            }
+            let at = i64::from_le_bytes(rest[..8].try_into().unwrap());
+            let kind = EventKind::from_code(rest[8]).ok_or_else(|| format!("unknown event kind {}", rest[8]))?;
+            let len = rest[9] as usize;
+            rest = &rest[ENTRY_HEADER_LEN..];
+            if rest.len() < len {
+                return Err(format!("event log blob ends inside entry {}", i));
+            }
+            let detail = std::str::from_utf8(&rest[..len]).map_err(|e| format!("entry {}: {}", i, e))?;
+            events.push(Event::new(at, kind, detail));
+            rest = &rest[len..];
+        }
+        events.recorded = u32::from_le_bytes(blob[1..5].try_into().unwrap());
+        Ok(events)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 2025-06-21 14:30:05 -05:00
+    const T: i64 = 1_750_534_205;
+
+    fn log_of(count: usize, capacity: usize) -> Events {
+        let mut events = Events::new(capacity);
+        for i in 0..count {
+            events.push(Event::new(T + i as i64, EventKind::ModeChange, &format!("event {}", i)));
+        }
+        events
+    }
+
+    #[test]
+    fn wraps_around_dropping_the_oldest() {
+        let events = log_of(CAPACITY + 5, CAPACITY);
+        assert_eq!(CAPACITY, events.len());
+        assert_eq!(CAPACITY as u32 + 5, events.recorded());
+        assert_eq!("event 5", events.iter().next().unwrap().detail);
+        let newest = events.iter().last().unwrap();
+        assert_eq!(format!("event {}", CAPACITY + 4), newest.detail);
+    }
+
+    #[test]
+    fn survives_a_round_trip_after_wrapping() {
+        let events = log_of(CAPACITY * 2 + 3, CAPACITY);
+        let decoded = Events::decode(&events.encode(), CAPACITY).unwrap();
+        assert_eq!(events, decoded);
+    }
+
+    #[test]
+    fn a_smaller_capacity_keeps_the_newest() {
+        let decoded = Events::decode(&log_of(10, CAPACITY).encode(), 4).unwrap();
+        let details: Vec<&str> = decoded.iter().map(|e| e.detail.as_str()).collect();
+        assert_eq!(vec!["event 6", "event 7", "event 8", "event 9"], details);
+        assert_eq!(10, decoded.recorded());
+    }
+
+    #[test]
+    fn a_full_log_fits_the_blob() {
+        let mut events = Events::new(CAPACITY);
+        for _ in 0..CAPACITY {
+            events.push(Event::new(T, EventKind::Panic, &"x".repeat(200)));
+        }
+        assert_eq!(MAX_BLOB_LEN, events.encode().len());
+    }
+
+    #[test]
+    fn details_are_cut_on_a_char_boundary() {
+        // 'é' is two bytes, so byte MAX_DETAIL_LEN falls inside one
+        let detail = format!("x{}", "é".repeat(MAX_DETAIL_LEN));
+        let event = Event::new(T, EventKind::Stall, &detail);
+        assert_eq!(MAX_DETAIL_LEN - 1, event.detail.len());
+    }
+
+    #[test]
+    fn corrupt_blobs_are_rejected() {
+        let blob = log_of(3, CAPACITY).encode();
+        assert!(Events::decode(&blob[..blob.len() - 1], CAPACITY).is_err());
+        assert!(Events::decode(&blob[..3], CAPACITY).is_err());
+        let mut bad_kind = blob.clone();
+        bad_kind[HEADER_LEN + 8] = 99;
+        assert!(Events::decode(&bad_kind, CAPACITY).is_err());
+        let mut bad_version = blob;
+        bad_version[0] = 0;
+        assert!(Events::decode(&bad_version, CAPACITY).is_err());
+    }
+
+    #[test]
+    fn json_escapes_details_and_marks_an_unset_clock() {
+        let mut events = Events::new(CAPACITY);
+        events.push(Event::new(3, EventKind::Boot, "firmware 1.0.4"));
+        events.push(Event::new(T, EventKind::Panic, "Panic at src/main.rs:12: \"oops\"\n"));
+        assert_eq!(
+            concat!(
+                r#"{"recorded":2,"events":[{"at":null,"kind":"boot","detail":"firmware 1.0.4"},"#,
+                r#"{"at":1750534205,"kind":"panic","detail":"Panic at src/main.rs:12: \"oops\"\n"}]}"#
+            ),
+            serde_json::to_string(&events).unwrap()
+        );
+    }
+}
@@ -0,0 +1,96 @@
+//! Bounded event log kept in NVS for post-mortem analysis.
+//!
+//! The serial log is gone after a reboot. This keeps the last `CAPACITY` significant events
+//! (boots, OTA updates, stalls, failed homing, panics, mode changes) in a blob in its own NVS
+//! namespace, to be dumped over MQTT after the fact with the `event_log` command. Events are
+//! rare, so the whole blob is rewritten on each one.
+
+mod events;
+
+pub use events::{Event, EventKind, Events, CAPACITY, MAX_BLOB_LEN, MAX_DETAIL_LEN};
+
+use esp_idf_svc::nvs::{EspDefaultNvsPartition, EspNvs, NvsDefault};
+use esp_idf_svc::sys::EspError;
+use log::*;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub const NVS_NAMESPACE: &str = "event_log";
+const NVS_KEY_EVENTS: &str = "events";
+
+struct Inner {
+    events: Events,
+    nvs: EspNvs<NvsDefault>,
+}
+
+/// Shared handle to the log; clones record to the same one.
+#[derive(Clone)]
+pub struct EventLog {
+    inner: Arc<Mutex<Inner>>,
+}
+
+impl EventLog {
+    /// Open the log in `partition` with what earlier boots recorded. A blob that can't be read
+    /// starts an empty log, which overwrites it with the first event.
+    pub fn open(partition: EspDefaultNvsPartition) -> Result<EventLog, EspError> {
+        let nvs = EspNvs::new(partition, NVS_NAMESPACE, true)?;
+        let mut buf = vec![0u8; MAX_BLOB_LEN];
+        let events = match nvs.get_blob(NVS_KEY_EVENTS, &mut buf) {
+            Ok(Some(blob)) => Events::decode(blob, CAPACITY).unwrap_or_else(|e| {
+                warn!("Discarding unreadable event log: {}", e);
+                Events::new(CAPACITY)
+            }),
+            Ok(None) => Events::new(CAPACITY),
+            Err(e) => {
+                warn!("Failed to read event log from NVS: {:?}", e);
+                Events::new(CAPACITY)
+            }
+        };
+        info!("Event log has {} events ({} recorded)", events.len(), events.recorded());
+        Ok(EventLog {
+            inner: Arc::new(Mutex::new(Inner { events, nvs })),
+        })
+    }
+
+    /// Append an event stamped with the system time and store the log.
+    pub fn record(&self, kind: EventKind, detail: &str) {
+        let mut inner = self.inner.lock().unwrap_or_else(PoisonError::into_inner);
+        Self::append(&mut inner, kind, detail);
+    }
+
+    /// `record`, unless the log is in use. For the panic hook, where the panicking thread may
+    /// itself be holding the lock.
+    pub fn try_record(&self, kind: EventKind, detail: &str) -> bool {
+        match self.inner.try_lock() {
+            Ok(mut inner) => {
+                Self::append(&mut inner, kind, detail);
+                true
+            }
+            Err(_) => false,
+        }
+    }
+
+    fn append(inner: &mut Inner, kind: EventKind, detail: &str) {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs() as i64)
+            .unwrap_or(0);
+        inner.events.push(Event::new(now, kind, detail));
+        if let Err(e) = inner.nvs.set_blob(NVS_KEY_EVENTS, &inner.events.encode()) {
+            warn!("Failed to store event log in NVS: {:?}", e);
+        }
+    }
+
+    /// A copy of the events recorded so far, e.g. to publish with `Mqtt::publish_json`.
+    pub fn snapshot(&self) -> Events {
+        self.inner.lock().unwrap_or_else(PoisonError::into_inner).events.clone()
+    }
+
+    pub fn len(&self) -> usize {
+        self.inner.lock().unwrap_or_else(PoisonError::into_inner).events.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
@@ -2,6 +2,8 @@
 #![no_std]
 use embedded_hal::delay::DelayNs;
 const I2C_ADDRESS: u8 = 0x40;
+/// What `get_man_id` reads from a genuine HDC1080 (Texas Instruments).
+pub const MANUFACTURER_ID: u16 = 0x5449;
 
 /// Error.
 #[derive(Debug, Copy, Clone)]
@@ -137,6 +139,16 @@ where
         Ok(())
     }
 
+    /// Switch the on-chip heater, used to drive off condensation. It only runs while
+    /// measurements are being taken.
+    pub fn set_heater(&mut self, enabled: bool) -> Result<(), I2C::Error> {
+        if enabled {
+            self.set_config(self.config | ConfigBitFlags::HEAT)
+        } else {
+            self.set_config(self.config & !ConfigBitFlags::HEAT)
+        }
+    }
+
     /// Read config from register
     pub fn read_config(&mut self) -> Result<u16, I2C::Error> {
         let mut current_config: [u8; 2] = [0, 0];
@@ -230,13 +242,13 @@ where
         Ok(result)
     }
 
-    /// Manufacturer ID. Expect u16 0x5449
+    /// Manufacturer ID. Expect `MANUFACTURER_ID`. Unlike the readings, bus errors are returned,
+    /// so this tells whether the chip is there at all.
     pub fn get_man_id(&mut self) -> Result<u16, I2C::Error> {
         let mut buf = [0u8; 2];
         let result: u16;
         self.i2c
-            .write_read(I2C_ADDRESS, &[Register::MANUFACTURER], &mut buf)
-            .unwrap_or_default();
+            .write_read(I2C_ADDRESS, &[Register::MANUFACTURER], &mut buf)?;
         result = ((buf[0] as u16) << 8) | (buf[1] as u16);
         Ok(result)
     }
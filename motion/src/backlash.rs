@@ -0,0 +1,77 @@
+//! Gearbox backlash compensation.
+//!
+//! After the tower reverses, the first `ticks` encoder ticks of motion only take up the slack in
+//! the gearbox and don't turn the panel. `Backlash` remembers the last direction of travel and
+//! says how much extra travel a reversing move needs.
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Backlash {
+    ticks: i64,
+    // Sign of the last non-zero move; 0 until the first move, whose slack is unknown.
+    last_direction: i64,
+}
+
+impl Backlash {
+    pub fn new(ticks: i64) -> Self {
+        Backlash {
+            ticks: ticks.abs(),
+            last_direction: 0,
+        }
+    }
+
+    pub fn ticks(&self) -> i64 {
+        self.ticks
+    }
+
+    pub fn set_ticks(&mut self, ticks: i64) {
+        self.ticks = ticks.abs();
+    }
+
+    /// Extra encoder ticks (signed like `delta`) to add to a move of `delta`: the backlash
+    /// allowance if the move reverses the last direction, otherwise 0.
+    pub fn take_up(&mut self, delta: i64) -> i64 {
+        if delta == 0 {
+            return 0;
+        }
+        let direction = delta.signum();
+        let reversed = self.last_direction != 0 && direction != self.last_direction;
+        self.last_direction = direction;
+        if reversed {
+            direction * self.ticks
+        } else {
+            0
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reversal_adds_the_allowance_once() {
+        let mut backlash = Backlash::new(40);
+        assert_eq!(0, backlash.take_up(1000));
+        assert_eq!(0, backlash.take_up(500));
+        assert_eq!(-40, backlash.take_up(-300));
+        assert_eq!(0, backlash.take_up(-300));
+        assert_eq!(40, backlash.take_up(200));
+        assert_eq!(0, backlash.take_up(200));
+    }
+
+    #[test]
+    fn first_move_and_zero_moves_add_nothing() {
+        let mut backlash = Backlash::new(40);
+        assert_eq!(0, backlash.take_up(-100));
+        assert_eq!(0, backlash.take_up(0));
+        // A zero-length move doesn't count as a change of direction.
+        assert_eq!(0, backlash.take_up(-100));
+    }
+
+    #[test]
+    fn disabled_by_default() {
+        let mut backlash = Backlash::default();
+        backlash.take_up(100);
+        assert_eq!(0, backlash.take_up(-100));
+    }
+}
@@ -0,0 +1,128 @@
+//! Per-installation calibration that the `calibrate` command can change at runtime.
+//!
+//! Installers tune these on site, so they live in NVS rather than in the configuration. An
+//! update is checked as a whole and only then applied: one bad field rejects all of them.
+
+use crate::limits::SoftLimits;
+use network::command::CalibrationUpdate;
+
+// Soft limits may reach this far past a single turn either way, for cable wraps that allow it.
+pub const MIN_SOFT_LIMIT: f32 = -360.0;
+pub const MAX_SOFT_LIMIT: f32 = 720.0;
+// A panel further than this off the tower's heading is a mounting fault, not an offset.
+pub const MAX_MOUNTING_OFFSET_DEG: f32 = 45.0;
+// About 5 deg at the output shaft with the default `EncoderConfig`; more is a worn-out gearbox.
+pub const MAX_BACKLASH_TICKS: i64 = 4_800;
+// Same bounds as `homing.correction_factor` in the configuration.
+pub const MIN_CORRECTION_FACTOR: f64 = 0.5;
+pub const MAX_CORRECTION_FACTOR: f64 = 2.0;
+
+// NVS keys; angles and the correction factor are stored as their float bits.
+pub const NVS_KEY_CAL_SOFT_MIN: &str = "cal_soft_min";
+pub const NVS_KEY_CAL_SOFT_MAX: &str = "cal_soft_max";
+pub const NVS_KEY_CAL_MOUNT_OFFSET: &str = "cal_mount_off";
+pub const NVS_KEY_CAL_BACKLASH: &str = "cal_backlash";
+pub const NVS_KEY_CAL_CORRECTION: &str = "cal_correction";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Calibration {
+    pub soft_limits: SoftLimits,
+    /// Degrees added to the sun's azimuth to get the heading to track, for a panel that isn't
+    /// square to the tower.
+    pub mounting_offset: f32,
+    pub backlash_ticks: i64,
+    pub correction_factor: f64,
+}
+
+impl Calibration {
+    /// This calibration with `update` applied, or why it can't be.
+    pub fn updated(&self, update: &CalibrationUpdate) -> Result<Calibration, String> {
+        let min = update.soft_min.unwrap_or(self.soft_limits.min());
+        let max = update.soft_max.unwrap_or(self.soft_limits.max());
+        if !(MIN_SOFT_LIMIT..=MAX_SOFT_LIMIT).contains(&min) || !(MIN_SOFT_LIMIT..=MAX_SOFT_LIMIT).contains(&max) {
+            return Err(format!(
+                "soft limits {}..{} must be within {}..{}",
+                min, max, MIN_SOFT_LIMIT, MAX_SOFT_LIMIT
+            ));
+        }
+        if min >= max {
+            return Err(format!("soft_min {} must be below soft_max {}", min, max));
+        }
+
+        let mounting_offset = update.mounting_offset.unwrap_or(self.mounting_offset);
+        if mounting_offset.is_nan() || mounting_offset.abs() > MAX_MOUNTING_OFFSET_DEG {
+            return Err(format!(
+                "mounting_offset {} must be within ±{}",
+                mounting_offset, MAX_MOUNTING_OFFSET_DEG
+            ));
+        }
+
+        let backlash_ticks = update.backlash_ticks.unwrap_or(self.backlash_ticks);
+        if !(0..=MAX_BACKLASH_TICKS).contains(&backlash_ticks) {
+            return Err(format!("backlash {} must be in 0..={} ticks", backlash_ticks, MAX_BACKLASH_TICKS));
+        }
+
+        let correction_factor = update.correction_factor.unwrap_or(self.correction_factor);
+        if !(MIN_CORRECTION_FACTOR..=MAX_CORRECTION_FACTOR).contains(&correction_factor) {
+            return Err(format!(
+                "correction_factor {} must be in [{}, {}]",
+                correction_factor, MIN_CORRECTION_FACTOR, MAX_CORRECTION_FACTOR
+            ));
+        }
+
+        Ok(Calibration {
+            soft_limits: SoftLimits::new(min, max),
+            mounting_offset,
+            backlash_ticks,
+            correction_factor,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn current() -> Calibration {
+        Calibration {
+            soft_limits: SoftLimits::default(),
+            mounting_offset: 0.0,
+            backlash_ticks: 0,
+            correction_factor: 1.0,
+        }
+    }
+
+    #[test]
+    fn fields_left_out_are_kept() {
+        let update = CalibrationUpdate {
+            soft_max: Some(300.0),
+            backlash_ticks: Some(40),
+            ..Default::default()
+        };
+        let updated = current().updated(&update).unwrap();
+        assert_eq!(SoftLimits::new(0.0, 300.0), updated.soft_limits);
+        assert_eq!(40, updated.backlash_ticks);
+        assert_eq!(0.0, updated.mounting_offset);
+        assert_eq!(1.0, updated.correction_factor);
+    }
+
+    #[test]
+    fn rejects_out_of_range_values() {
+        let reject = |update: CalibrationUpdate, field: &str| {
+            let error = current().updated(&update).unwrap_err();
+            assert!(error.contains(field), "{}", error);
+        };
+        reject(CalibrationUpdate { soft_min: Some(-400.0), ..Default::default() }, "soft limits");
+        reject(CalibrationUpdate { mounting_offset: Some(60.0), ..Default::default() }, "mounting_offset");
+        reject(CalibrationUpdate { mounting_offset: Some(f32::NAN), ..Default::default() }, "mounting_offset");
+        reject(CalibrationUpdate { backlash_ticks: Some(-1), ..Default::default() }, "backlash");
+        reject(CalibrationUpdate { correction_factor: Some(3.0), ..Default::default() }, "correction_factor");
+    }
+
+    #[test]
+    fn soft_limits_must_not_cross() {
+        // Unlike `SoftLimits::new`, swapped bounds are an error: one of them is likely a typo
+        let update = CalibrationUpdate { soft_min: Some(400.0), ..Default::default() };
+        assert!(current().updated(&update).unwrap_err().contains("soft_min"));
+    }
+}
@@ -0,0 +1,9 @@
+//! Remote commands and where they are executed.
+//!
+//! `Motion` owns the stepper, relay and encoder and is not `Sync`, so only the main loop ever
+//! touches it. Commands received over MQTT arrive on `Mqtt::commands()`; the main loop drains
+//! them at safe points between tracking cycles and hands each command to `Motion::execute`. A
+//! command therefore never starts in the middle of a move, and a move it starts runs to
+//! completion before the next command or tracking cycle is looked at.
+
+pub use network::command::Command;
@@ -0,0 +1,44 @@
+//! Encoder-vs-stepper divergence check after each move.
+//!
+//! The stall detector only catches an encoder that stops moving. A stepper that skips some
+//! steps, or an encoder that drops some edges, still moves both, and the difference only shows
+//! as a growing pointing error over the day. After every completed move `Motion` compares the
+//! encoder travel with the travel the commanded steps should have produced and raises an alarm
+//! when they differ by more than a threshold.
+
+// About 0.2 deg at the output shaft with the default `EncoderConfig`.
+pub const DEFAULT_DIVERGENCE_THRESHOLD_TICKS: i64 = 200;
+
+/// Encoder ticks actually moved minus the ticks `steps` motor steps should have moved, at
+/// `ticks_per_step`. Positive when the encoder moved further than commanded.
+pub fn position_error_ticks(steps: i64, moved_ticks: i64, ticks_per_step: f64) -> i64 {
+    moved_ticks - (steps as f64 * ticks_per_step).round() as i64
+}
+
+/// Whether `error_ticks` is past `threshold_ticks` either way.
+pub fn is_divergent(error_ticks: i64, threshold_ticks: i64) -> bool {
+    error_ticks.abs() > threshold_ticks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Default `EncoderConfig`: 348_323 ticks per 107_520_000 steps.
+    const TICKS_PER_STEP: f64 = 348_323.0 / 107_520_000.0;
+
+    #[test]
+    fn matching_travel_has_no_error() {
+        // 10 degrees
+        assert_eq!(0, position_error_ticks(2_986_666, 9_676, TICKS_PER_STEP));
+        assert_eq!(0, position_error_ticks(-2_986_666, -9_676, TICKS_PER_STEP));
+    }
+
+    #[test]
+    fn slip_shows_as_a_shortfall() {
+        let error = position_error_ticks(2_986_666, 9_376, TICKS_PER_STEP);
+        assert_eq!(-300, error);
+        assert!(is_divergent(error, DEFAULT_DIVERGENCE_THRESHOLD_TICKS));
+        assert!(!is_divergent(-DEFAULT_DIVERGENCE_THRESHOLD_TICKS, DEFAULT_DIVERGENCE_THRESHOLD_TICKS));
+    }
+}
@@ -0,0 +1,177 @@
+//! Quadrature decoding of the tower encoder, either polled or driven by GPIO interrupts.
+//!
+//! Polled, counts are only taken while something calls `Encoder::poll`, which is the motor step
+//! loop; edges that happen while the CPU is elsewhere (the sleep between tracking cycles, MQTT,
+//! sensors) are lost. With `Encoder::enable_interrupts` every A or B edge updates the count from
+//! the ISR, and readers just load the atomic.
+
+use esp_idf_svc::hal::gpio::{Input, InputPin, InterruptType, PinDriver};
+use esp_idf_svc::sys::{gpio_get_level, gpio_intr_enable, EspError};
+use std::sync::atomic::{AtomicBool, AtomicI64, AtomicU8, Ordering};
+use std::sync::Arc;
+
+// Quarter-step change for each (previous AB << 2 | current AB), positive when A leads like the
+// `quadrature-encoder` decoder this replaced. Impossible transitions, where both channels
+// changed at once, count 0.
+const QUAD_TABLE: [i8; 16] = [0, -1, 1, 0, 1, 0, 0, -1, -1, 0, 0, 1, 0, 1, -1, 0];
+
+// Reported ticks are half steps, two per quadrature cycle, which is what
+// `EncoderConfig::counts_per_rev` was calibrated with.
+const QUARTERS_PER_TICK: i64 = 2;
+
+/// Count shared between the decoder and everyone reading the position.
+#[derive(Debug, Default)]
+pub struct QuadratureCount {
+    quarter_steps: AtomicI64,
+    last_ab: AtomicU8,
+}
+
+impl QuadratureCount {
+    pub fn new(a: bool, b: bool) -> Self {
+        QuadratureCount {
+            quarter_steps: AtomicI64::new(0),
+            last_ab: AtomicU8::new(ab(a, b)),
+        }
+    }
+
+    /// Feed the current pin levels. Safe to call from an ISR.
+    pub fn update(&self, a: bool, b: bool) {
+        let current = ab(a, b);
+        let previous = self.last_ab.swap(current, Ordering::AcqRel);
+        let delta = QUAD_TABLE[((previous << 2) | current) as usize];
+        if delta != 0 {
+            self.quarter_steps.fetch_add(delta as i64, Ordering::AcqRel);
+        }
+    }
+
+    /// Position in encoder ticks.
+    pub fn ticks(&self) -> i64 {
+        self.quarter_steps.load(Ordering::Acquire).div_euclid(QUARTERS_PER_TICK)
+    }
+}
+
+fn ab(a: bool, b: bool) -> u8 {
+    ((a as u8) << 1) | b as u8
+}
+
+pub struct Encoder<'d, A: InputPin, B: InputPin> {
+    a: PinDriver<'d, A, Input>,
+    b: PinDriver<'d, B, Input>,
+    count: Arc<QuadratureCount>,
+    interrupts: Arc<AtomicBool>,
+}
+
+impl<'d, A: InputPin, B: InputPin> Encoder<'d, A, B> {
+    /// A polled encoder on the A and B channel pins, counting from 0.
+    pub fn new(a: PinDriver<'d, A, Input>, b: PinDriver<'d, B, Input>) -> Self {
+        let count = Arc::new(QuadratureCount::new(a.is_high(), b.is_high()));
+        Encoder {
+            a,
+            b,
+            count,
+            interrupts: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// Decode from interrupts on both edges of both channels instead of `poll`.
+    pub fn enable_interrupts(&mut self) -> Result<(), EspError> {
+        let (a_pin, b_pin) = (self.a.pin(), self.b.pin());
+        self.a.set_interrupt_type(InterruptType::AnyEdge)?;
+        self.b.set_interrupt_type(InterruptType::AnyEdge)?;
+        let count = self.count.clone();
+        let isr = move |pin: i32| {
+            let count = count.clone();
+            move || {
+                // SAFETY: reading a level and setting the interrupt enable bit are register
+                // accesses, fine in an ISR. The driver disables the interrupt before calling us;
+                // `enable_interrupt` would re-register the handler, which can't be done here.
+                unsafe {
+                    count.update(gpio_get_level(a_pin) != 0, gpio_get_level(b_pin) != 0);
+                    gpio_intr_enable(pin);
+                }
+            }
+        };
+        // SAFETY: the callbacks only touch atomics and the GPIO registers.
+        unsafe {
+            self.a.subscribe(isr(a_pin))?;
+            self.b.subscribe(isr(b_pin))?;
+        }
+        // Resync with the pins before the first edge arrives.
+        self.count.update(self.a.is_high(), self.b.is_high());
+        self.a.enable_interrupt()?;
+        self.b.enable_interrupt()?;
+        self.interrupts.store(true, Ordering::Release);
+        Ok(())
+    }
+
+    pub fn interrupts_enabled(&self) -> bool {
+        self.interrupts.load(Ordering::Acquire)
+    }
+
+    /// Sample the pins. A no-op once interrupts are enabled, where sampling would race the ISR.
+    pub fn poll(&mut self) {
+        if !self.interrupts_enabled() {
+            self.count.update(self.a.is_high(), self.b.is_high());
+        }
+    }
+
+    /// Raw position in encoder ticks since start-up.
+    pub fn position(&self) -> i32 {
+        self.count.ticks() as i32
+    }
+
+    /// Current A and B levels.
+    pub fn levels(&self) -> (bool, bool) {
+        (self.a.is_high(), self.b.is_high())
+    }
+
+    /// The shared count, for reading the position from another thread.
+    pub fn count(&self) -> Arc<QuadratureCount> {
+        self.count.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A and B levels for one quadrature cycle each way, starting at 00. Forward, A leads.
+    const FORWARD: [(bool, bool); 4] = [(true, false), (true, true), (false, true), (false, false)];
+    const REVERSE: [(bool, bool); 4] = [(false, true), (true, true), (true, false), (false, false)];
+
+    #[test]
+    fn forward_cycle_is_two_ticks() {
+        let count = QuadratureCount::new(false, false);
+        for (a, b) in FORWARD {
+            count.update(a, b);
+        }
+        assert_eq!(2, count.ticks());
+    }
+
+    #[test]
+    fn reverse_cycle_is_minus_two_ticks() {
+        let count = QuadratureCount::new(false, false);
+        for (a, b) in REVERSE {
+            count.update(a, b);
+        }
+        assert_eq!(-2, count.ticks());
+    }
+
+    #[test]
+    fn jitter_on_one_edge_does_not_accumulate() {
+        let count = QuadratureCount::new(false, false);
+        for _ in 0..10 {
+            count.update(false, true);
+            count.update(false, false);
+        }
+        assert_eq!(0, count.ticks());
+    }
+
+    #[test]
+    fn repeated_and_impossible_samples_do_not_count() {
+        let count = QuadratureCount::new(false, false);
+        count.update(false, false);
+        count.update(true, true);
+        assert_eq!(0, count.ticks());
+    }
+}
@@ -0,0 +1,139 @@
+//! Time-limited capture of raw encoder A/B transitions for signal diagnostics.
+//!
+//! A marginal encoder connection shows up either as gaps (transitions missed, the decoder
+//! sees both channels change at once) or as noise (extra transitions bunched together).
+//! The capture keeps only aggregate statistics so it can run inside the step loop.
+
+use std::time::{Duration, Instant};
+
+// Number of equal time buckets the capture window is split into for the edge histogram.
+pub const TIMING_BUCKETS: usize = 20;
+
+pub struct EncoderTimingCapture {
+    started: Instant,
+    window: Duration,
+    last_a: Option<bool>,
+    last_b: Option<bool>,
+    last_edge: Option<Instant>,
+    a_edges: u32,
+    b_edges: u32,
+    invalid_transitions: u32,
+    min_interval: Option<Duration>,
+    max_interval: Duration,
+    total_interval: Duration,
+    intervals: u32,
+    buckets: [u32; TIMING_BUCKETS],
+}
+
+/// Summary of a finished capture, published to `<device>/debug/encoder_timing`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EncoderTimingReport {
+    pub window: Duration,
+    pub a_edges: u32,
+    pub b_edges: u32,
+    /// Both channels changed between two samples: at least one transition was missed.
+    pub invalid_transitions: u32,
+    pub min_interval: Duration,
+    pub max_interval: Duration,
+    pub mean_interval: Duration,
+    /// Edge count per `window / TIMING_BUCKETS` slice of the capture window.
+    pub buckets: [u32; TIMING_BUCKETS],
+}
+
+impl EncoderTimingCapture {
+    pub fn new(now: Instant, window: Duration) -> Self {
+        EncoderTimingCapture {
+            started: now,
+            window,
+            last_a: None,
+            last_b: None,
+            last_edge: None,
+            a_edges: 0,
+            b_edges: 0,
+            invalid_transitions: 0,
+            min_interval: None,
+            max_interval: Duration::ZERO,
+            total_interval: Duration::ZERO,
+            intervals: 0,
+            buckets: [0; TIMING_BUCKETS],
+        }
+    }
+
+    pub fn is_finished(&self, now: Instant) -> bool {
+        now.duration_since(self.started) >= self.window
+    }
+
+    /// Record one sample of the A and B levels.
+    pub fn record(&mut self, now: Instant, a: bool, b: bool) {
+        if self.is_finished(now) {
+            return;
+        }
+
+        let a_changed = self.last_a.map_or(false, |last| last != a);
+        let b_changed = self.last_b.map_or(false, |last| last != b);
+        self.last_a = Some(a);
+        self.last_b = Some(b);
+
+        if !a_changed && !b_changed {
+            return;
+        }
+        if a_changed {
+            self.a_edges += 1;
+        }
+        if b_changed {
+            self.b_edges += 1;
+        }
+        if a_changed && b_changed {
+            self.invalid_transitions += 1;
+        }
+
+        if let Some(last) = self.last_edge {
+            let interval = now.duration_since(last);
+            self.min_interval = Some(self.min_interval.map_or(interval, |min| min.min(interval)));
+            self.max_interval = self.max_interval.max(interval);
+            self.total_interval += interval;
+            self.intervals += 1;
+        }
+        self.last_edge = Some(now);
+
+        let bucket_len = self.window / TIMING_BUCKETS as u32;
+        if !bucket_len.is_zero() {
+            let index = (now.duration_since(self.started).as_nanos() / bucket_len.as_nanos()) as usize;
+            self.buckets[index.min(TIMING_BUCKETS - 1)] += 1;
+        }
+    }
+
+    pub fn report(&self) -> EncoderTimingReport {
+        EncoderTimingReport {
+            window: self.window,
+            a_edges: self.a_edges,
+            b_edges: self.b_edges,
+            invalid_transitions: self.invalid_transitions,
+            min_interval: self.min_interval.unwrap_or_default(),
+            max_interval: self.max_interval,
+            mean_interval: if self.intervals == 0 {
+                Duration::ZERO
+            } else {
+                self.total_interval / self.intervals
+            },
+            buckets: self.buckets,
+        }
+    }
+}
+
+impl EncoderTimingReport {
+    pub fn to_json(&self) -> String {
+        let buckets: Vec<String> = self.buckets.iter().map(|b| b.to_string()).collect();
+        format!(
+            "{{\"window_ms\":{},\"a_edges\":{},\"b_edges\":{},\"invalid_transitions\":{},\"min_interval_us\":{},\"max_interval_us\":{},\"mean_interval_us\":{},\"buckets\":[{}]}}",
+            self.window.as_millis(),
+            self.a_edges,
+            self.b_edges,
+            self.invalid_transitions,
+            self.min_interval.as_micros(),
+            self.max_interval.as_micros(),
+            self.mean_interval.as_micros(),
+            buckets.join(",")
+        )
+    }
+}
@@ -0,0 +1,95 @@
+//! The tower's best heading estimate, from the encoder or from the commanded steps.
+//!
+//! The encoder measures the output shaft, so it takes precedence as long as it can be trusted:
+//! it has been referenced to the limit switch (by homing, or a consistent snapshot restored at
+//! boot), the last move didn't stall, and the last move's encoder-vs-stepper divergence is
+//! within the alarm threshold. Otherwise the heading dead-reckoned from the commanded steps
+//! (`Motion::location`) is used; it keeps working without an encoder but can't see slip.
+
+use crate::divergence;
+use crate::snapshot::HOME_HEADING;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HeadingSource {
+    Encoder,
+    Stepper,
+}
+
+impl HeadingSource {
+    pub fn name(&self) -> &'static str {
+        match self {
+            HeadingSource::Encoder => "encoder",
+            HeadingSource::Stepper => "stepper",
+        }
+    }
+}
+
+/// What decides whether the encoder heading can be trusted.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EncoderHealth {
+    /// Zeroed at the limit switch, or restored from a consistent snapshot.
+    pub referenced: bool,
+    /// The last move ended `MoveOutcome::Stalled`.
+    pub stalled: bool,
+    /// Divergence of the last move and the alarm threshold (see `divergence`).
+    pub error_ticks: i64,
+    pub threshold_ticks: i64,
+}
+
+impl EncoderHealth {
+    pub fn is_healthy(&self) -> bool {
+        self.referenced && !self.stalled && !divergence::is_divergent(self.error_ticks, self.threshold_ticks)
+    }
+}
+
+/// Heading of `ticks` adjusted encoder ticks (0 at the limit switch).
+pub fn encoder_heading(ticks: i64, counts_per_rev: i64) -> f32 {
+    HOME_HEADING + (ticks as f64 * 360.0 / counts_per_rev as f64) as f32
+}
+
+/// The encoder heading while `health` says it is healthy, else the stepper heading.
+pub fn best_heading(stepper_heading: f32, encoder_heading: f32, health: &EncoderHealth) -> (f32, HeadingSource) {
+    if health.is_healthy() {
+        (encoder_heading, HeadingSource::Encoder)
+    } else {
+        (stepper_heading, HeadingSource::Stepper)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Default `EncoderConfig`
+    const COUNTS_PER_REV: i64 = 348_323;
+
+    const HEALTHY: EncoderHealth = EncoderHealth {
+        referenced: true,
+        stalled: false,
+        error_ticks: 20,
+        threshold_ticks: 200,
+    };
+
+    #[test]
+    fn encoder_ticks_count_from_home() {
+        assert_eq!(HOME_HEADING, encoder_heading(0, COUNTS_PER_REV));
+        assert!((encoder_heading(COUNTS_PER_REV / 4, COUNTS_PER_REV) - 180.0).abs() < 0.01);
+        assert!((encoder_heading(-COUNTS_PER_REV / 4, COUNTS_PER_REV) - 0.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn healthy_encoder_wins() {
+        assert_eq!((121.5, HeadingSource::Encoder), best_heading(120.0, 121.5, &HEALTHY));
+    }
+
+    #[test]
+    fn degraded_encoder_falls_back_to_the_stepper() {
+        let unreferenced = EncoderHealth { referenced: false, ..HEALTHY };
+        let stalled = EncoderHealth { stalled: true, ..HEALTHY };
+        let divergent = EncoderHealth { error_ticks: -500, ..HEALTHY };
+        for health in [unreferenced, stalled, divergent] {
+            assert!(!health.is_healthy());
+            assert_eq!((120.0, HeadingSource::Stepper), best_heading(120.0, 121.5, &health));
+        }
+    }
+}
@@ -1,168 +1,2073 @@
+pub mod backlash;
+pub mod calibration;
+pub mod command;
+pub mod divergence;
+pub mod encoder;
+pub mod encoder_timing;
+pub mod heading;
+pub mod history;
+pub mod limits;
+pub mod pid;
+pub mod progress;
+pub mod snapshot;
+pub mod stall;
+pub mod watchdog;
+
 pub mod motion {
+    use crate::backlash::Backlash;
+    use crate::calibration::{
+        Calibration, NVS_KEY_CAL_BACKLASH, NVS_KEY_CAL_CORRECTION, NVS_KEY_CAL_MOUNT_OFFSET, NVS_KEY_CAL_SOFT_MAX,
+        NVS_KEY_CAL_SOFT_MIN,
+    };
+    use crate::command::Command;
+    use crate::divergence::{self, DEFAULT_DIVERGENCE_THRESHOLD_TICKS};
+    use crate::encoder::Encoder;
+    use crate::encoder_timing::{EncoderTimingCapture, EncoderTimingReport};
+    use crate::heading::{self, EncoderHealth, HeadingSource};
+    use crate::history::History;
+    use crate::limits::SoftLimits;
+    use crate::pid::PidController;
+    use crate::progress::MoveProgress;
+    use crate::snapshot::{
+        PersistGuard, PositionSnapshot, ENC_SNAPSHOT_VERSION, HOME_HEADING, NVS_KEY_ENC_SNAPSHOT_VERSION,
+        NVS_KEY_ENC_TICKS_ADJ, NVS_KEY_LAST_HEADING,
+    };
+    use crate::stall::{StallConfig, StallDetector, StallVerdict, StallWatch};
+    use crate::watchdog;
     use accel_stepper::{Driver, OperatingSystemClock, StepAndDirection};
     use astronav::coords::noaa_sun::NOAASun;
-    use clock::Clock;
-    use std::time::{Duration, Instant};
+    use chrono::{DateTime, Datelike, FixedOffset, Timelike};
+    use clock::{Clock, RtcError, SolarDay};
+    use event_log::{EventKind, EventLog};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+    use esp_idf_svc::hal::delay::Ets;
     use esp_idf_svc::hal::gpio::{Gpio15, Gpio16, Gpio17, Gpio14, Gpio47, Gpio21, Input, Output, PinDriver};
-    use quadrature_encoder::{IncrementalEncoder, Rotary, HalfStep};
     use esp_idf_svc::nvs::*;
-    use network::mqtt::Mqtt;
-    use wifi::wifi::{Wifi, WifiState};
-    use ota::OtaUpdater;
-    use semver::Version;
+    use network::command::CalibrationUpdate;
+    use network::mqtt::{Mqtt, PanicReporter};
+    use network::http::HttpTelemetry;
+    use network::telemetry::{Telemetry, TelemetryTransport, TELEMETRY_TOPIC};
+    use ota::OtaScheduler;
     use std::{thread, panic};
 
-    #[derive(PartialEq)]
-    enum TrackingState {
-        L1,
-        L2,
-        L3,
-    }
+    // NVS key holding the reason of the last deliberate reboot (see `safe_restart`).
+    pub const NVS_KEY_REBOOT_REASON: &str = "reboot_reason";
+    // NVS keys for the stepper speed (f32 bits) and acceleration chosen at runtime.
+    pub const NVS_KEY_MOTOR_SPEED: &str = "motor_speed";
+    pub const NVS_KEY_MOTOR_ACCEL: &str = "motor_accel";
+
+    /// Fastest stepper speed `Motion::set_speed` accepts, in steps/s (about 0.17 deg/s at the
+    /// output shaft).
+    pub const MAX_SAFE_SPEED: f32 = 50_000.0;
+
+    /// Timing breakdown of the last completed move, collected by `Motion::run`.
+    ///
+    /// Counters are always kept; the two durations are only measured while move-performance
+    /// reporting is enabled (`Motion::set_move_perf_reporting`) to keep the step loop tight.
+    #[derive(Debug, Clone, Copy, Default, PartialEq)]
+    pub struct MovePerf {
+        pub total: Duration,
+        pub iterations: u64,
+        pub encoder_reads: u64,
+        pub encoder_changes: u64,
+        pub encoder_time: Duration,
+        pub motor_poll_time: Duration,
+    }
+
+    impl MovePerf {
+        /// Time spent reading the encoder for every unit of time spent polling the motor.
+        pub fn encoder_to_poll_ratio(&self) -> f64 {
+            if self.motor_poll_time.is_zero() {
+                return 0.0;
+            }
+            self.encoder_time.as_secs_f64() / self.motor_poll_time.as_secs_f64()
+        }
+
+        pub fn to_json(&self) -> String {
+            format!(
+                "{{\"total_ms\":{},\"iterations\":{},\"encoder_reads\":{},\"encoder_changes\":{},\"encoder_us\":{},\"motor_poll_us\":{},\"encoder_to_poll_ratio\":{:.3}}}",
+                self.total.as_millis(),
+                self.iterations,
+                self.encoder_reads,
+                self.encoder_changes,
+                self.encoder_time.as_micros(),
+                self.motor_poll_time.as_micros(),
+                self.encoder_to_poll_ratio()
+            )
+        }
+    }
+
+    // Number of limit-switch transitions kept for `publish_limit_switch_history`.
+    const LIMIT_SWITCH_HISTORY_LEN: usize = 32;
+
+    // How long `publish_divergence_alarm` waits for the broker to confirm the alarm.
+    const ALARM_ACK_TIMEOUT: Duration = Duration::from_secs(5);
+    // Least time between two watchdog feeds from the step loop.
+    const WATCHDOG_FEED_INTERVAL: Duration = Duration::from_secs(1);
+
+    /// One raw (pre-debounce) limit-switch transition.
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub struct LimitSwitchEvent {
+        /// Time since `Motion` was created.
+        pub at: Duration,
+        pub pressed: bool,
+        /// Adjusted encoder ticks at the time of the transition.
+        pub encoder_ticks: i32,
+    }
+
+    /// Where `Motion::set_progress_reporter` reports are published, under the device prefix.
+    pub const MOVE_PROGRESS_TOPIC: &str = "tower/progress";
+
+    // Closed-loop encoder moves give up after this many chunks, each at most this many ticks
+    // long (the stopping tolerance is `TrackingConfig::encoder_tolerance_ticks`).
+    const ENCODER_MOVE_MAX_CHUNKS: u32 = 50;
+    const ENCODER_MOVE_MAX_CHUNK_TICKS: f64 = 1000.0;
+
+    // L3 fine tracking: east/west LDR gaps (ADC counts) inside this band count as balanced.
+    const FINE_BALANCE_DEADBAND: i32 = 50;
+
+    /// When the tower moves. Larger values spare the motor, smaller ones follow the sun closer.
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub struct TrackingConfig {
+        /// Sun offset (degrees) above which a coarse L1 move is made; within it the tower
+        /// switches to LDR fine tracking.
+        pub move_threshold_deg: f64,
+        /// Closed-loop encoder moves stop once this close to the target.
+        pub encoder_tolerance_ticks: i64,
+        /// Size of one L3 fine-tracking nudge toward the brighter side.
+        pub fine_tolerance_deg: f64,
+        /// L2 counts the LDR balance as settled, and hands back to L1, once it changes by less
+        /// than this between two cycles.
+        pub l2_settle_balance: i32,
+    }
+
+    impl Default for TrackingConfig {
+        fn default() -> Self {
+            TrackingConfig {
+                move_threshold_deg: 5.0,
+                encoder_tolerance_ticks: 2,
+                fine_tolerance_deg: 0.1,
+                l2_settle_balance: 75,
+            }
+        }
+    }
+
+    impl TrackingConfig {
+        /// Whether a sun offset of `offset_deg` needs a coarse move.
+        pub fn needs_coarse_move(&self, offset_deg: f64) -> bool {
+            offset_deg.abs() > self.move_threshold_deg
+        }
+    }
+
+    // Wind stow: the 90 degree limit-switch position is the default safe heading, reached at this
+    // fraction of the normal stepper speed...
+    pub const DEFAULT_STOW_ANGLE: f32 = 90.0;
+    const STOW_SPEED_FRACTION: f32 = 0.25;
+    // ...whenever a reported wind speed (m/s) reaches this threshold.
+    pub const DEFAULT_STOW_WIND_SPEED: f32 = 15.0;
+
+    /// How a call to `Motion::run` ended.
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub enum MoveOutcome {
+        Completed,
+        /// Completed, but only after recovery wiggles freed it from at least one stall.
+        Recovered,
+        /// The encoder stopped following the stepper and recovery wiggles did not help.
+        Stalled,
+        /// `Motion::request_stop` was called while the move was running.
+        Aborted,
+        /// Refused before it started: the step count was beyond `MAX_MOVE_DEG`.
+        Rejected,
+    }
+
+    impl MoveOutcome {
+        /// Whether the move reached its target, with or without a stall recovery.
+        pub fn is_completed(&self) -> bool {
+            matches!(self, MoveOutcome::Completed | MoveOutcome::Recovered)
+        }
+    }
+
+    /// Who is moving the tower.
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub enum OperatingMode {
+        /// The tracking loop follows the sun.
+        Auto,
+        /// Tracking is suspended for servicing; only jogs and other commands move the tower.
+        Manual,
+        /// Parked against the wind (see `Motion::stow`); moves are refused until the stow is
+        /// cleared.
+        Stowed,
+    }
+
+    impl OperatingMode {
+        pub fn name(&self) -> &'static str {
+            match self {
+                OperatingMode::Auto => "auto",
+                OperatingMode::Manual => "manual",
+                OperatingMode::Stowed => "stowed",
+            }
+        }
+    }
+
+    /// Which way `find_limit_switch` searches for the home switch; it depends on which side of
+    /// the tower the site mounted it.
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub enum HomingDirection {
+        /// `find_limit_switch_cw`
+        Cw,
+        /// `find_limit_switch_ccw`
+        Ccw,
+    }
+
+    /// How far a limit switch search first turns away from the switch (CW for
+    /// `HomingDirection::Cw`, CCW for `Ccw`) before sweeping back for it.
+    pub const HOMING_NUDGE_DEG: f32 = 15.0;
+
+    /// Homing directions for the two operations that look for the switch: the morning verify
+    /// (homing at boot and re-homing, `find_limit_switch`) and the evening park (`park`).
+    /// Some sites home one way in the morning and park the other way in the evening.
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub struct HomingStrategy {
+        pub morning: HomingDirection,
+        pub evening: HomingDirection,
+    }
+
+    impl HomingStrategy {
+        /// The same direction for both operations.
+        pub fn uniform(direction: HomingDirection) -> Self {
+            HomingStrategy { morning: direction, evening: direction }
+        }
+
+        /// Check the strategy against the soft limits for a switch at `park_heading`: the park
+        /// must be inside the limits, and each search's first nudge away from it must stay inside
+        /// them too, as that is where a search starts when the tower is already parked.
+        pub fn check(&self, limits: SoftLimits, park_heading: f32) -> Result<(), String> {
+            if !limits.contains(park_heading) {
+                return Err(format!(
+                    "park heading {} is outside soft limits {}..{}",
+                    park_heading,
+                    limits.min(),
+                    limits.max()
+                ));
+            }
+            for (operation, direction) in [("morning", self.morning), ("evening", self.evening)] {
+                let nudged = match direction {
+                    HomingDirection::Cw => park_heading + HOMING_NUDGE_DEG,
+                    HomingDirection::Ccw => park_heading - HOMING_NUDGE_DEG,
+                };
+                if !limits.contains(nudged) {
+                    return Err(format!(
+                        "{} homing {:?} turns to {} first, outside soft limits {}..{}",
+                        operation,
+                        direction,
+                        nudged,
+                        limits.min(),
+                        limits.max()
+                    ));
+                }
+            }
+            Ok(())
+        }
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    enum TrackingState {
+        L1,
+        L2,
+        L3,
+    }
+
+    impl TrackingState {
+        fn name(&self) -> &'static str {
+            match self {
+                TrackingState::L1 => "L1",
+                TrackingState::L2 => "L2",
+                TrackingState::L3 => "L3",
+            }
+        }
+    }
+
+    // Number of tracking state transitions kept for `publish_state_history`.
+    const STATE_HISTORY_LEN: usize = 32;
+
+    #[derive(Debug, Clone, Copy)]
+    struct TrackingTransition {
+        // Time since `Motion` was created.
+        at: Duration,
+        from: TrackingState,
+        to: TrackingState,
+        reason: &'static str,
+    }
+
+    /// Encoder and drivetrain resolution used to convert between ticks, steps and degrees.
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub struct EncoderConfig {
+        /// Encoder ticks (HalfStep decoding) per output-shaft revolution.
+        pub counts_per_rev: i64,
+        /// Motor microsteps per output-shaft revolution.
+        pub motor_steps_per_rev: f64,
+    }
+
+    impl Default for EncoderConfig {
+        fn default() -> Self {
+            EncoderConfig {
+                counts_per_rev: 348_323,
+                motor_steps_per_rev: GEAR_CONSTANT,
+            }
+        }
+    }
+
+    /// Motor microsteps per tower revolution: 25600 microsteps x 50:1 gearbox x 84:1 slew drive.
+    ///
+    /// Every degree/step conversion goes through this constant (or `calculate_steps`).
+    pub const GEAR_CONSTANT: f64 = 25600.0 * 50.0 * 84.0;
+
+    /// Motor steps for a tower rotation of `offset` degrees, truncated toward zero.
+    ///
+    /// `calculate_steps(360.0) == 107_520_000`, `calculate_steps(-1.0) == -298_666`.
+    pub fn calculate_steps(offset: f32) -> i64 {
+        (offset as f64 / 360.0 * GEAR_CONSTANT) as i64
+    }
+
+    /// Largest single move `move_by` accepts: a full turn plus room for backlash and a site's
+    /// correction factor. Anything beyond is a step-math error, not a real target.
+    pub const MAX_MOVE_DEG: f64 = 405.0;
+
+    /// Whether `steps` is within `MAX_MOVE_DEG` either way.
+    pub fn move_in_range(steps: i64) -> bool {
+        steps.unsigned_abs() as f64 <= MAX_MOVE_DEG / 360.0 * GEAR_CONSTANT
+    }
+
+    /// Motor steps of a coarse (L1) move from heading `location` to `target`, scaled by the
+    /// site's correction factor and truncated toward zero.
+    pub fn coarse_move_steps(location: f32, target: f32, correction_factor: f64) -> i64 {
+        let offset = (target - location) as f64;
+        (offset / 360.0 * GEAR_CONSTANT * correction_factor) as i64
+    }
+
+    /// What a tracking cycle does before any fine (LDR) tracking, see `plan_cycle`.
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub enum CycleAction {
+        /// The sun is within the move threshold: leave it to fine tracking.
+        Hold,
+        /// Coarse (L1) move of `steps` to `target`, which is `requested` clamped to the soft
+        /// limits.
+        Move { requested: f32, target: f32, steps: i64 },
+        /// Night, away from the limit switch: park on it.
+        Park,
+        /// Night, parked: wait for sunrise.
+        Sleep,
+    }
+
+    /// The coarse decision of `Motion::set_tower_position` for a tower at `heading`, given the
+    /// sun's azimuth during the day and `None` at night.
+    pub fn plan_cycle(
+        heading: f32,
+        sun_azimuth: Option<f64>,
+        mounting_offset: f32,
+        config: &TrackingConfig,
+        limits: SoftLimits,
+        correction_factor: f64,
+    ) -> CycleAction {
+        let Some(azimuth) = sun_azimuth else {
+            return if heading == HOME_HEADING { CycleAction::Sleep } else { CycleAction::Park };
+        };
+        // Where the tower has to point for the panel to face the sun
+        let angle_offset = azimuth + mounting_offset as f64 - heading as f64;
+        if !config.needs_coarse_move(angle_offset) {
+            return CycleAction::Hold;
+        }
+        let requested = (heading as f64 + angle_offset) as f32;
+        let (target, _) = limits.clamp(requested);
+        CycleAction::Move {
+            requested,
+            target,
+            steps: coarse_move_steps(heading, target, correction_factor),
+        }
+    }
+
+    /// Sun position at `now`, local time at the given location.
+    pub fn noaa_sun_at(now: DateTime<FixedOffset>, latitude: f64, longitude: f64) -> NOAASun {
+        NOAASun {
+            year: now.year() as u16,
+            doy: now.ordinal() as u16,
+            long: longitude as f32,
+            lat: latitude as f32,
+            timezone: now.offset().local_minus_utc() as f32 / 3600.0,
+            hour: now.hour() as u8,
+            min: now.minute() as u8,
+            sec: now.second() as u8,
+        }
+    }
+
+    // Sun position at the RTC's current local time.
+    fn sun_now<I2C: embedded_hal::i2c::I2c>(clock: &mut Clock<I2C>) -> Result<NOAASun, RtcError> {
+        let now = clock.now_local()?;
+        Ok(noaa_sun_at(now, clock.get_latitude(), clock.get_longitude()))
+    }
+
+    // Bookkeeping for the move `Motion::tick` is stepping through.
+    struct ActiveMove {
+        started: Instant,
+        last_log: Instant,
+        timed: bool,
+        perf: MovePerf,
+        stall: StallWatch,
+        // Where the move started, for the divergence check: stepper position, raw encoder
+        // ticks (not moved by re-zeroing at the limit switch) and the backlash take-up.
+        start_steps: i64,
+        start_ticks: i32,
+        backlash_steps: i64,
+    }
+
+    pub struct Motion<'a> {
+        location: f32,
+        tracking_state: TrackingState,
+        speed: f32,
+        acceleration: u16,
+        motor: Driver,
+        motor_device:
+            StepAndDirection<PinDriver<'a, Gpio15, Output>, PinDriver<'a, Gpio16, Output>, Ets>,
+        motor_clock: OperatingSystemClock,
+        prev_balance: i32,
+        relay: PinDriver<'a, Gpio17, Output>,
+        lmsw: PinDriver<'a, Gpio14, Input>,
+        encoder: Encoder<'a, Gpio47, Gpio21>,
+        // Encoder "reset" is implemented as a software offset: displayed_position = raw - offset.
+        encoder_zero_offset: i32,
+        // Limit-switch edge detection / debounce state (active-low switch).
+        lmsw_last_state_pressed: bool,
+        lmsw_last_change: Instant,
+        lmsw_zeroed_this_press: bool,
+        move_perf_enabled: bool,
+        last_move_perf: MovePerf,
+        // Output-shaft speed cap in deg/s (see `set_max_angular_velocity`).
+        max_angular_velocity: Option<f32>,
+        // Armed A/B timing capture and the summary of the last finished one.
+        encoder_timing: Option<EncoderTimingCapture>,
+        encoder_timing_report: Option<EncoderTimingReport>,
+        created: Instant,
+        limit_switch_history: History<LimitSwitchEvent>,
+        stall_config: StallConfig,
+        homing: HomingStrategy,
+        // Scales the steps of coarse (L1) tracking moves, for drivetrains off the nominal ratio
+        correction_factor: f64,
+        state_history: History<TrackingTransition>,
+        encoder_config: EncoderConfig,
+        tracking_config: TrackingConfig,
+        pid: PidController,
+        active_move: Option<ActiveMove>,
+        last_move_outcome: MoveOutcome,
+        // Emergency stop, settable from any thread (see `request_stop`).
+        stop_requested: Arc<AtomicBool>,
+        soft_limits: SoftLimits,
+        backlash: Backlash,
+        // Motor steps `start_move_by` added to the current move to take up backlash.
+        backlash_steps: i64,
+        // Latest HDC1080 readings, reported with the telemetry (see `set_environment`).
+        temperature: Option<f32>,
+        humidity: Option<f32>,
+        // RTC drift found by the last NTP resync, reported with the telemetry.
+        rtc_drift_secs: Option<i64>,
+        // Where `publish_telemetry` sends each sample; MQTT stands in for a missing HTTP sink.
+        telemetry_transport: TelemetryTransport,
+        http_telemetry: Option<HttpTelemetry>,
+        // Wind stow settings; `stow` latches `mode` to Stowed until `clear_stow`.
+        stow_angle: f32,
+        stow_wind_speed: f32,
+        mode: OperatingMode,
+        // Stall description waiting for `publish_stall_alert`; moves have no MQTT handle.
+        stall_alert: Option<String>,
+        // Last watchdog feed from `tick`, see `WATCHDOG_FEED_INTERVAL`.
+        last_watchdog_feed: Instant,
+        // Rate limit and write count for `save_position`.
+        position_persist: PersistGuard,
+        // Where position snapshots are kept (see `set_position_store`).
+        position_store: Option<EspNvs<NvsDefault>>,
+        // NVS may hold a snapshot, which the next move has to remove first. Assumed at boot.
+        position_stored: bool,
+        // Speed or acceleration changed since the last `save_motor_tuning`.
+        tuning_changed: bool,
+        // Encoder-vs-stepper divergence of the last completed move, the alarm threshold, the
+        // alarm waiting for `publish_divergence_alarm` and whether that re-homes.
+        position_error_ticks: i64,
+        divergence_threshold_ticks: i64,
+        divergence_alarm: Option<String>,
+        rehome_on_divergence: bool,
+        // Keep the relay energized between daytime tracking moves (see `set_hold_between_moves`).
+        hold_between_moves: bool,
+        // Added to the sun's azimuth to get the tracking heading (see `Calibration`).
+        mounting_offset: f32,
+        // Calibration changed since the last `save_calibration`.
+        calibration_changed: bool,
+        // Publishes long closed-loop moves' progress (see `set_progress_reporter`).
+        progress_reporter: Option<PanicReporter>,
+        // `encoder_zero_offset` puts 0 at the limit switch, so the encoder gives a heading.
+        encoder_referenced: bool,
+        // Stalls, failed homing and mode changes are recorded here (see `set_event_log`).
+        event_log: Option<EventLog>,
+    }
+
+    // CW: direction
+    // CCW: step
+    impl Motion<'_> {
+        pub fn new<'a>(p10: Gpio15, p11: Gpio16, p7: Gpio17, p6: Gpio14, p47: Gpio47, p21: Gpio21) -> Motion<'a> {
+            Motion::with_config(p10, p11, p7, p6, p47, p21, EncoderConfig::default())
+        }
+
+        pub fn with_config<'a>(
+            p10: Gpio15,
+            p11: Gpio16,
+            p7: Gpio17,
+            p6: Gpio14,
+            p47: Gpio47,
+            p21: Gpio21,
+            encoder_config: EncoderConfig,
+        ) -> Motion<'a> {
+            let step = PinDriver::output(p10).unwrap();
+            let direction = PinDriver::output(p11).unwrap();
+            let relay = PinDriver::output(p7).unwrap();
+            let mut lmsw = PinDriver::input(p6).unwrap();
+            let encoderA = PinDriver::input(p47).unwrap();
+            let encoderB = PinDriver::input(p21).unwrap();
+            lmsw.set_pull(esp_idf_svc::hal::gpio::Pull::Down)
+                .unwrap_or_default();
+
+            // Interrupt-driven so edges aren't missed outside the step loop; polled as a fallback
+            let mut encoder = Encoder::new(encoderA, encoderB);
+            if let Err(e) = encoder.enable_interrupts() {
+                log::warn!("Encoder interrupts unavailable, polling in the step loop: {:?}", e);
+            }
+
+            let now = Instant::now();
+            Motion {
+                location: 0.0,
+                tracking_state: TrackingState::L1,
+                speed: 43000.0,
+                acceleration: 20000,
+                motor: Driver::new(),
+                motor_device: StepAndDirection::new(step, direction, Ets),
+                motor_clock: OperatingSystemClock::new(),
+                prev_balance: 0,
+                relay,
+                lmsw,
+                encoder,
+                encoder_zero_offset: 0,
+                lmsw_last_state_pressed: false,
+                lmsw_last_change: now,
+                lmsw_zeroed_this_press: false,
+                move_perf_enabled: false,
+                last_move_perf: MovePerf::default(),
+                max_angular_velocity: None,
+                encoder_timing: None,
+                encoder_timing_report: None,
+                created: now,
+                limit_switch_history: History::new(LIMIT_SWITCH_HISTORY_LEN),
+                stall_config: StallConfig::default(),
+                homing: HomingStrategy::uniform(HomingDirection::Cw),
+                correction_factor: 1.0,
+                state_history: History::new(STATE_HISTORY_LEN),
+                encoder_config,
+                tracking_config: TrackingConfig::default(),
+                pid: PidController::default(),
+                active_move: None,
+                last_move_outcome: MoveOutcome::Completed,
+                stop_requested: Arc::new(AtomicBool::new(false)),
+                soft_limits: SoftLimits::default(),
+                backlash: Backlash::default(),
+                backlash_steps: 0,
+                temperature: None,
+                humidity: None,
+                rtc_drift_secs: None,
+                telemetry_transport: TelemetryTransport::Mqtt,
+                http_telemetry: None,
+                stow_angle: DEFAULT_STOW_ANGLE,
+                stow_wind_speed: DEFAULT_STOW_WIND_SPEED,
+                mode: OperatingMode::Auto,
+                stall_alert: None,
+                last_watchdog_feed: Instant::now(),
+                position_persist: PersistGuard::default(),
+                position_store: None,
+                position_stored: true,
+                tuning_changed: false,
+                position_error_ticks: 0,
+                divergence_threshold_ticks: DEFAULT_DIVERGENCE_THRESHOLD_TICKS,
+                divergence_alarm: None,
+                rehome_on_divergence: false,
+                hold_between_moves: false,
+                mounting_offset: 0.0,
+                calibration_changed: false,
+                progress_reporter: None,
+                encoder_referenced: false,
+                event_log: None,
+            }
+        }
+
+        pub fn update_position(&mut self, location: f32) {
+            self.location = location;
+        }
+
+        pub fn location(&mut self) -> f32 {
+            self.location
+        }
+
+        /// The best estimate of the current heading, and the authoritative one for commands and
+        /// telemetry: the encoder's while it is healthy, else the heading dead-reckoned from the
+        /// commanded steps (`location`). See `heading` for what counts as healthy.
+        pub fn heading_deg(&self) -> f32 {
+            self.best_heading().0
+        }
+
+        /// Which of the two `heading_deg` is using.
+        pub fn heading_source(&self) -> HeadingSource {
+            self.best_heading().1
+        }
+
+        fn best_heading(&self) -> (f32, HeadingSource) {
+            let health = EncoderHealth {
+                referenced: self.encoder_referenced,
+                stalled: self.last_move_outcome == MoveOutcome::Stalled,
+                error_ticks: self.position_error_ticks,
+                threshold_ticks: self.divergence_threshold_ticks,
+            };
+            let encoder = heading::encoder_heading(self.encoder_ticks_adjusted() as i64, self.encoder_config.counts_per_rev);
+            heading::best_heading(self.location, encoder, &health)
+        }
+
+        /// Raw stepper position (steps since boot) and encoder count (before the home offset),
+        /// for diagnostics.
+        pub fn raw_positions(&self) -> (i64, i64) {
+            (self.motor.current_position(), self.encoder.position() as i64)
+        }
+
+        /// Current tracking level: "L1", "L2" or "L3".
+        pub fn tracking_state_name(&self) -> &'static str {
+            self.tracking_state.name()
+        }
+
+        pub fn switch_pressed(&mut self) -> bool {
+            self.lmsw.is_low()
+        }
+
+        // Stage 1: single definition of "adjusted encoder ticks".
+        // Convention: CW is positive; 0 ticks corresponds to the limit switch (home) after zeroing.
+        pub fn encoder_ticks_adjusted(&self) -> i32 {
+            self.encoder.position() - self.encoder_zero_offset
+        }
+
+        pub fn encoder_config(&self) -> EncoderConfig {
+            self.encoder_config
+        }
+
+        /// Replace the move thresholds and tolerances (see `TrackingConfig`).
+        pub fn set_tracking_config(&mut self, config: TrackingConfig) {
+            log::info!("Tracking config: {:?}", config);
+            self.tracking_config = config;
+        }
+
+        pub fn tracking_config(&self) -> TrackingConfig {
+            self.tracking_config
+        }
+
+        pub fn encoder_ticks_to_motor_steps(&self, ticks: i64) -> i64 {
+            (ticks as f64 * self.encoder_config.motor_steps_per_rev / self.encoder_config.counts_per_rev as f64)
+                .round() as i64
+        }
+
+        pub fn angle_to_encoder_ticks(&self, degrees: f64) -> i64 {
+            (degrees / 360.0 * self.encoder_config.counts_per_rev as f64).round() as i64
+        }
+
+        /// Tower angle from home (the limit switch) according to the encoder.
+        pub fn encoder_degrees(&self) -> f64 {
+            self.encoder_ticks_adjusted() as f64 * 360.0 / self.encoder_config.counts_per_rev as f64
+        }
+
+        /// Enable timing of every move and publishing of the breakdown to `<device>/perf/move`.
+        pub fn set_move_perf_reporting(&mut self, enabled: bool) {
+            self.move_perf_enabled = enabled;
+        }
+
+        /// Breakdown of the last move that actually stepped the motor.
+        pub fn last_move_perf(&self) -> MovePerf {
+            self.last_move_perf
+        }
+
+        /// Publish the last move's breakdown if reporting is enabled.
+        pub fn publish_move_perf(&self, mqtt: &mut Mqtt) {
+            if !self.move_perf_enabled {
+                return;
+            }
+            let payload = self.last_move_perf.to_json();
+            match mqtt.publish(&mqtt.topic("perf/move"), payload.as_bytes()) {
+                Ok(_) => log::info!("Published move performance report"),
+                Err(e) => log::error!("Failed to publish move performance report: {:?}", e),
+            }
+        }
+
+        /// Arm a time-limited capture of raw encoder A/B transitions.
+        ///
+        /// Transitions are sampled inside the step loop, so the window should cover a move.
+        /// The summary is published by `publish_encoder_timing` once the window has elapsed.
+        pub fn start_encoder_timing_capture(&mut self, window: Duration) {
+            log::info!("Encoder timing capture armed for {:?}", window);
+            self.encoder_timing = Some(EncoderTimingCapture::new(Instant::now(), window));
+            self.encoder_timing_report = None;
+        }
+
+        // Turn a finished capture into a report.
+        fn finish_encoder_timing_capture(&mut self) {
+            let finished = self
+                .encoder_timing
+                .as_ref()
+                .map_or(false, |capture| capture.is_finished(Instant::now()));
+            if finished {
+                if let Some(capture) = self.encoder_timing.take() {
+                    let report = capture.report();
+                    log::info!("Encoder timing capture finished: {}", report.to_json());
+                    self.encoder_timing_report = Some(report);
+                }
+            }
+        }
+
+        /// Publish the summary of a finished encoder timing capture, if one is pending.
+        pub fn publish_encoder_timing(&mut self, mqtt: &mut Mqtt) {
+            self.finish_encoder_timing_capture();
+            if let Some(report) = self.encoder_timing_report.take() {
+                match mqtt.publish(&mqtt.topic("debug/encoder_timing"), report.to_json().as_bytes()) {
+                    Ok(_) => log::info!("Published encoder timing report"),
+                    Err(e) => log::error!("Failed to publish encoder timing report: {:?}", e),
+                }
+            }
+        }
+
+        /// Raw limit-switch transitions seen while moving, oldest first.
+        pub fn limit_switch_history(&self) -> impl Iterator<Item = &LimitSwitchEvent> {
+            self.limit_switch_history.iter()
+        }
+
+        /// Dump the limit-switch transition history to `<device>/debug/limit_sw`.
+        ///
+        /// Bursts of transitions a few ms apart point at bouncing (debounce tuning), no
+        /// transitions during a homing sweep at a switch that never triggers.
+        pub fn publish_limit_switch_history(&self, mqtt: &mut Mqtt) {
+            let events: Vec<String> = self
+                .limit_switch_history
+                .iter()
+                .map(|e| {
+                    format!(
+                        "{{\"t_ms\":{},\"pressed\":{},\"encoder_ticks\":{}}}",
+                        e.at.as_millis(),
+                        e.pressed,
+                        e.encoder_ticks
+                    )
+                })
+                .collect();
+            let payload = format!("[{}]", events.join(","));
+            match mqtt.publish(&mqtt.topic("debug/limit_sw"), payload.as_bytes()) {
+                Ok(_) => log::info!("Published limit switch history ({} events)", events.len()),
+                Err(e) => log::error!("Failed to publish limit switch history: {:?}", e),
+            }
+        }
+
+        // Change the tracking state, recording real transitions for `publish_state_history`.
+        fn set_tracking_state(&mut self, state: TrackingState, reason: &'static str) {
+            if self.tracking_state == state {
+                return;
+            }
+            log::info!("Tracking state {} -> {} ({})", self.tracking_state.name(), state.name(), reason);
+            self.state_history.push(TrackingTransition {
+                at: self.created.elapsed(),
+                from: self.tracking_state,
+                to: state,
+                reason,
+            });
+            self.tracking_state = state;
+        }
+
+        /// Dump the tracking state transition history to `<device>/debug/state_history`.
+        ///
+        /// A tower flapping between L1 and L2 shows up as alternating entries a tracking
+        /// cycle apart.
+        pub fn publish_state_history(&self, mqtt: &mut Mqtt) {
+            let transitions: Vec<String> = self
+                .state_history
+                .iter()
+                .map(|t| {
+                    format!(
+                        "{{\"t_ms\":{},\"from\":\"{}\",\"to\":\"{}\",\"reason\":\"{}\"}}",
+                        t.at.as_millis(),
+                        t.from.name(),
+                        t.to.name(),
+                        t.reason
+                    )
+                })
+                .collect();
+            let payload = format!("[{}]", transitions.join(","));
+            match mqtt.publish(&mqtt.topic("debug/state_history"), payload.as_bytes()) {
+                Ok(_) => log::info!("Published tracking state history ({} transitions)", transitions.len()),
+                Err(e) => log::error!("Failed to publish tracking state history: {:?}", e),
+            }
+        }
+
+        pub fn init(&mut self) {
+            let max_speed = self.effective_max_speed();
+            self.motor.set_max_speed(max_speed);
+            self.motor.set_speed(max_speed);
+            self.motor.set_acceleration(self.acceleration.into());
+        }
+
+        /// Cap how fast the tower's output shaft may rotate, in degrees per second.
+        ///
+        /// One tower revolution takes 25600 microsteps x 50:1 gearbox x 84:1 slew drive
+        /// = 107_520_000 steps (the same kinematics as `calculate_steps`), so
+        /// `steps/s = deg/s / 360 * 107_520_000`. The stepper max speed is clamped to that figure;
+        /// the configured stepper speed is kept so the cap can be raised again later.
+        pub fn set_max_angular_velocity(&mut self, deg_per_sec: f32) {
+            if !(deg_per_sec > 0.0) || !deg_per_sec.is_finite() {
+                log::error!("Rejected max angular velocity {} deg/s, must be positive", deg_per_sec);
+                return;
+            }
+            self.max_angular_velocity = Some(deg_per_sec);
+            let max_speed = self.effective_max_speed();
+            self.motor.set_max_speed(max_speed);
+            self.motor.set_speed(max_speed);
+            log::info!(
+                "Max angular velocity set to {} deg/s, stepper max speed now {} steps/s",
+                deg_per_sec,
+                max_speed
+            );
+        }
+
+        pub fn max_angular_velocity(&self) -> Option<f32> {
+            self.max_angular_velocity
+        }
+
+        /// Stepper speed in steps/s, at most `MAX_SAFE_SPEED` and still subject to
+        /// `set_max_angular_velocity`. Applies to the next move; returns false, changing nothing,
+        /// for an out-of-range value.
+        pub fn set_speed(&mut self, speed: f32) -> bool {
+            if !(speed > 0.0 && speed <= MAX_SAFE_SPEED) {
+                log::error!("Rejected stepper speed {} steps/s, must be in (0, {}]", speed, MAX_SAFE_SPEED);
+                return false;
+            }
+            self.speed = speed;
+            let max_speed = self.effective_max_speed();
+            self.motor.set_max_speed(max_speed);
+            self.motor.set_speed(max_speed);
+            self.tuning_changed = true;
+            log::info!("Stepper speed set to {} steps/s, max speed now {} steps/s", speed, max_speed);
+            true
+        }
+
+        pub fn speed(&self) -> f32 {
+            self.speed
+        }
+
+        /// Stepper acceleration in steps/s². Returns false, changing nothing, for 0.
+        pub fn set_acceleration(&mut self, acceleration: u16) -> bool {
+            if acceleration == 0 {
+                log::error!("Rejected stepper acceleration 0, must be positive");
+                return false;
+            }
+            self.acceleration = acceleration;
+            self.motor.set_acceleration(acceleration.into());
+            self.tuning_changed = true;
+            log::info!("Stepper acceleration set to {} steps/s²", acceleration);
+            true
+        }
+
+        pub fn acceleration(&self) -> u16 {
+            self.acceleration
+        }
+
+        /// Store the speed and acceleration for `load_motor_tuning` if either changed since the
+        /// last save.
+        pub fn save_motor_tuning<T: NvsPartitionId>(&mut self, nvs: &mut EspNvs<T>) {
+            if !self.tuning_changed {
+                return;
+            }
+            self.tuning_changed = false;
+            let saved = nvs
+                .set_u32(NVS_KEY_MOTOR_SPEED, self.speed.to_bits())
+                .and_then(|_| nvs.set_u16(NVS_KEY_MOTOR_ACCEL, self.acceleration));
+            match saved {
+                Ok(_) => log::info!("Stored stepper speed {} and acceleration {} in NVS", self.speed, self.acceleration),
+                Err(e) => log::warn!("Failed to store stepper tuning in NVS: {:?}", e),
+            }
+        }
+
+        /// Apply the speed and acceleration stored by `save_motor_tuning`. Missing or
+        /// out-of-range values leave the defaults in place. Call after `init`.
+        pub fn load_motor_tuning<T: NvsPartitionId>(&mut self, nvs: &mut EspNvs<T>) {
+            if let Some(speed) = nvs.get_u32(NVS_KEY_MOTOR_SPEED).ok().flatten().map(f32::from_bits) {
+                self.set_speed(speed);
+            }
+            if let Some(acceleration) = nvs.get_u16(NVS_KEY_MOTOR_ACCEL).ok().flatten() {
+                self.set_acceleration(acceleration);
+            }
+            // Already in NVS
+            self.tuning_changed = false;
+        }
+
+        // Stepper speed after applying the angular velocity cap, if any.
+        fn effective_max_speed(&self) -> f32 {
+            match self.max_angular_velocity {
+                Some(deg_per_sec) => self.speed.min(calculate_steps(deg_per_sec) as f32),
+                None => self.speed,
+            }
+        }
+
+
+        pub fn move_by(&mut self, location: i64) -> MoveOutcome {
+            if !self.start_move_by(location) {
+                return MoveOutcome::Rejected;
+            }
+            self.run()
+        }
+
+        pub fn move_by_ticks(&mut self, ticks: i64) -> MoveOutcome {
+            if !self.start_move_by(self.encoder_ticks_to_motor_steps(ticks)) {
+                return MoveOutcome::Rejected;
+            }
+            self.run()
+        }
+
+        /// Start moving by `steps` without waiting for the move; drive it with `tick`.
+        /// A move that reverses the last direction is lengthened by the backlash allowance.
+        /// Returns false, starting nothing, when `steps` is beyond `MAX_MOVE_DEG`.
+        pub fn start_move_by(&mut self, steps: i64) -> bool {
+            if !move_in_range(steps) {
+                log::error!(
+                    "Refusing a move of {} steps ({:.1} degrees), beyond the {} degree bound",
+                    steps,
+                    steps as f64 / GEAR_CONSTANT * 360.0,
+                    MAX_MOVE_DEG
+                );
+                self.last_move_outcome = MoveOutcome::Rejected;
+                return false;
+            }
+            let slack = self.backlash.take_up(steps);
+            self.backlash_steps = self.encoder_ticks_to_motor_steps(slack);
+            self.motor.move_by(steps + self.backlash_steps);
+            // A new target gets a fresh stall baseline and performance report.
+            if let Some(active) = self.active_move.take() {
+                self.finish_move(active, MoveOutcome::Completed);
+            }
+            true
+        }
+
+        /// Step the current move to completion, watching the encoder for stalls.
+        ///
+        /// On a stall up to `StallConfig::recovery_attempts` wiggles (see `recovery_wiggle`) are
+        /// tried before the move is abandoned and `MoveOutcome::Stalled` is returned; a move one
+        /// of them freed ends `MoveOutcome::Recovered`.
+        pub fn run(&mut self) -> MoveOutcome {
+            self.last_move_outcome = MoveOutcome::Completed;
+            while self.tick() {}
+            self.last_move_outcome
+        }
+
+        /// One iteration of the current move: motor poll, encoder read, limit switch and stall
+        /// check (see `run`). Returns whether the motor is still running; once it returns false
+        /// `last_move_outcome` says how the move ended.
+        pub fn tick(&mut self) -> bool {
+            // A limit switch search or slow stow can outlast the watchdog timeout; only a step
+            // loop that stops coming round should trip it. Once a second is plenty, and keeps
+            // the feed's critical section out of nearly every motor poll.
+            let now = Instant::now();
+            if now.duration_since(self.last_watchdog_feed) >= WATCHDOG_FEED_INTERVAL {
+                watchdog::feed();
+                self.last_watchdog_feed = now;
+            }
+            if !self.motor.is_running() {
+                if let Some(active) = self.active_move.take() {
+                    self.finish_move(active, MoveOutcome::Completed);
+                }
+                return false;
+            }
+
+            // Consumed here, so a stop only ever aborts the move it interrupted.
+            if self.stop_requested.swap(false, Ordering::SeqCst) {
+                log::warn!("Stop requested at step {}, aborting move", self.motor.current_position());
+                self.emergency_stop();
+                let active = self.active_move.take().unwrap_or_else(|| self.begin_move());
+                self.finish_move(active, MoveOutcome::Aborted);
+                return false;
+            }
+
+            let mut active = match self.active_move.take() {
+                Some(active) => active,
+                None => self.begin_move(),
+            };
+            self.poll_once(&mut active.perf, active.timed);
+
+            let now = Instant::now();
+            match active.stall.update(now, self.encoder_ticks_adjusted(), self.motor.current_position()) {
+                StallVerdict::Moving => {}
+                StallVerdict::Recover => {
+                    log::warn!(
+                        "Stall detected at step {}, attempting recovery wiggle ({} attempts left)",
+                        self.motor.current_position(),
+                        active.stall.attempts_left()
+                    );
+                    self.recovery_wiggle(&mut active.perf, active.timed);
+                    active.stall.reset(Instant::now(), self.encoder_ticks_adjusted(), self.motor.current_position());
+                }
+                StallVerdict::GiveUp => {
+                    self.raise_stall_alert(format!(
+                        "Stall detected at step {} (encoder ticks {}), aborting move after {} recovery attempts",
+                        self.motor.current_position(),
+                        self.encoder_ticks_adjusted(),
+                        active.stall.recoveries()
+                    ));
+                    self.halt();
+                    self.finish_move(active, MoveOutcome::Stalled);
+                    return false;
+                }
+            }
+
+            if active.last_log.elapsed() >= Duration::from_millis(100) {
+                let position = self.encoder_ticks_adjusted();
+                let step_pos = self.motor.current_position();
+                let step_rem = self.motor.distance_to_go();
+                log::info!(
+                    "Encoder Ticks: {}, Step Position: {}, Step Remaining: {}",
+                    position,
+                    step_pos,
+                    step_rem
+                );
+                active.last_log = Instant::now();
+            }
+
+            if self.motor.is_running() {
+                self.active_move = Some(active);
+                true
+            } else {
+                self.finish_move(active, MoveOutcome::Completed);
+                false
+            }
+        }
+
+        /// Gearbox backlash in encoder ticks, added to every move that reverses direction (0
+        /// disables).
+        pub fn set_backlash(&mut self, ticks: i64) {
+            self.backlash.set_ticks(ticks);
+        }
+
+        /// Commanded headings (tracking, Goto, Jog) are clamped to `min..=max` degrees. Homing to
+        /// the limit switch is not limited.
+        pub fn set_soft_limits(&mut self, min: f32, max: f32) {
+            self.soft_limits = SoftLimits::new(min, max);
+        }
+
+        pub fn soft_limits(&self) -> SoftLimits {
+            self.soft_limits
+        }
+
+        // `target` clamped to the soft limits, warning over MQTT when that changed it.
+        fn limit_target(&mut self, target: f32, mqtt: &mut Mqtt) -> f32 {
+            let (limited, _) = self.soft_limits.clamp(target);
+            self.warn_if_clamped(target, limited, mqtt);
+            limited
+        }
+
+        // Warn over MQTT when the soft limits moved `target` to `limited`.
+        fn warn_if_clamped(&self, target: f32, limited: f32, mqtt: &mut Mqtt) {
+            if limited != target {
+                let warning = format!(
+                    "Soft limit: target {} clamped to {} (limits {}..{})",
+                    target,
+                    limited,
+                    self.soft_limits.min(),
+                    self.soft_limits.max()
+                );
+                log::warn!("{}", warning);
+                if let Err(e) = mqtt.publish_retained(&mqtt.topic("tower/status"), warning.as_bytes()) {
+                    log::error!("Failed to publish soft limit warning: {:?}", e);
+                }
+            }
+        }
+
+        /// Heading `stow` drives to (clamped to the soft limits) and the wind speed in m/s at
+        /// which `report_wind` triggers it.
+        pub fn set_stow(&mut self, angle: f32, wind_speed: f32) {
+            self.stow_angle = angle;
+            self.stow_wind_speed = wind_speed;
+        }
+
+        pub fn is_stowed(&self) -> bool {
+            self.mode == OperatingMode::Stowed
+        }
+
+        pub fn mode(&self) -> OperatingMode {
+            self.mode
+        }
+
+        /// Switch the operating mode and publish it to `tower/mode`. In `Manual`,
+        /// `set_tower_position` leaves the tower where it is.
+        pub fn set_mode(&mut self, mode: OperatingMode, mqtt: &mut Mqtt) {
+            if mode == self.mode {
+                return;
+            }
+            log::info!("Operating mode {} -> {}", self.mode.name(), mode.name());
+            self.record_event(EventKind::ModeChange, &format!("{} -> {}", self.mode.name(), mode.name()));
+            self.mode = mode;
+            // Holding is for automatic tracking only
+            self.release_relay();
+            self.publish_mode(mqtt);
+        }
+
+        /// Keep the driver energized between tracking moves so its holding torque, not just the
+        /// gearbox, resists wind back-driving the tower (and corrupting the encoder-tracked
+        /// heading). Only in `Auto` during the day: parking for the night, stowing and leaving
+        /// `Auto` all de-energize. Off by default, as the driver draws current all day.
+        pub fn set_hold_between_moves(&mut self, hold: bool) {
+            self.hold_between_moves = hold;
+            self.release_relay();
+        }
+
+        /// Whether the relay is being kept energized between moves right now.
+        pub fn is_holding(&self) -> bool {
+            self.hold_between_moves && self.mode == OperatingMode::Auto && self.relay.is_set_high()
+        }
+
+        // End of a move: de-energize the relay, unless holding between tracking moves.
+        fn release_relay(&mut self) {
+            if !(self.hold_between_moves && self.mode == OperatingMode::Auto) {
+                self.relay.set_low().unwrap_or_default();
+            }
+        }
+
+        /// Publish the current mode to `tower/mode` (retained), e.g. at boot.
+        pub fn publish_mode(&self, mqtt: &mut Mqtt) {
+            if let Err(e) = mqtt.publish_retained(&mqtt.topic("tower/mode"), self.mode.name().as_bytes()) {
+                log::error!("Failed to publish operating mode: {:?}", e);
+            }
+        }
+
+        /// Drive to the stow angle at reduced speed and latch into the stowed state, in which
+        /// Goto, Jog, Park and automatic tracking are refused until `clear_stow`. The latch is set
+        /// even if the move stalls or is stopped: the wind that caused it hasn't gone away.
+        pub fn stow(&mut self, mqtt: &mut Mqtt) -> MoveOutcome {
+            let target = self.limit_target(self.stow_angle, mqtt);
+            log::warn!("Stowing at {} degrees (from {})", target, self.location);
+            let max_speed = self.effective_max_speed();
+            self.motor.set_max_speed(max_speed * STOW_SPEED_FRACTION);
+            let outcome = self.turn_by(target - self.location);
+            self.motor.set_max_speed(max_speed);
+            if !outcome.is_completed() {
+                log::error!("Stow move ended {:?} at heading {}", outcome, self.location);
+            }
+            self.set_mode(OperatingMode::Stowed, mqtt);
+            self.save_moved_position();
+            if let Err(e) = mqtt.publish_retained(&mqtt.topic("tower/status"), b"stowed") {
+                log::error!("Failed to publish stow status: {:?}", e);
+            }
+            outcome
+        }
+
+        /// Leave the stowed state so commands and tracking are accepted again.
+        pub fn clear_stow(&mut self, mqtt: &mut Mqtt) {
+            if self.is_stowed() {
+                log::info!("Stow cleared at heading {}", self.location);
+                self.set_mode(OperatingMode::Auto, mqtt);
+            }
+        }
+
+        /// Feed a wind speed reading in m/s; stows the tower once it reaches the threshold set
+        /// by `set_stow`. Returns whether the tower is stowed afterwards.
+        pub fn report_wind(&mut self, wind_speed: f32, mqtt: &mut Mqtt) -> bool {
+            if !self.is_stowed() && wind_speed >= self.stow_wind_speed {
+                log::warn!(
+                    "Wind {} m/s at or above the stow threshold of {} m/s",
+                    wind_speed,
+                    self.stow_wind_speed
+                );
+                self.stow(mqtt);
+            }
+            self.is_stowed()
+        }
+
+        /// Abort the running move within one `tick`: the stepper target is dropped and the relay
+        /// de-energized. Safe to call from any thread through `stop_flag`. The request is
+        /// consumed by the move it aborts, and cleared before every `execute`d command.
+        pub fn request_stop(&self) {
+            self.stop_requested.store(true, Ordering::SeqCst);
+        }
+
+        /// The flag behind `request_stop`, for threads that can't borrow `Motion`.
+        pub fn stop_flag(&self) -> Arc<AtomicBool> {
+            self.stop_requested.clone()
+        }
+
+        // Stop dead rather than decelerating with `Driver::stop`: the relay cuts the driver anyway.
+        fn emergency_stop(&mut self) {
+            self.halt();
+            self.relay.set_low().unwrap_or_default();
+        }
+
+        /// How the last move driven by `tick`/`run` ended.
+        pub fn last_move_outcome(&self) -> MoveOutcome {
+            self.last_move_outcome
+        }
+
+        fn begin_move(&mut self) -> ActiveMove {
+            self.invalidate_position();
+            let now = Instant::now();
+            ActiveMove {
+                started: now,
+                last_log: now,
+                timed: self.move_perf_enabled,
+                perf: MovePerf::default(),
+                stall: StallWatch::new(&self.stall_config, now, self.encoder_ticks_adjusted(), self.motor.current_position()),
+                start_steps: self.motor.current_position(),
+                start_ticks: self.encoder.position(),
+                backlash_steps: self.backlash_steps,
+            }
+        }
+
+        fn finish_move(&mut self, mut active: ActiveMove, outcome: MoveOutcome) {
+            let outcome = match outcome {
+                MoveOutcome::Completed if active.stall.recoveries() > 0 => {
+                    log::info!("Move completed after {} stall recoveries", active.stall.recoveries());
+                    MoveOutcome::Recovered
+                }
+                outcome => outcome,
+            };
+            self.last_move_outcome = outcome;
+            // Stalls and aborts are reported on their own
+            if outcome.is_completed() {
+                self.check_divergence(&active);
+            }
+            // `run()` is often called again right after `move_by`; keep the report of the real move.
+            if active.perf.iterations > 0 {
+                active.perf.total = active.started.elapsed();
+                self.last_move_perf = active.perf;
+                log::info!("Move performance: {}", active.perf.to_json());
+            }
+        }
+
+        // Compare the encoder travel of a completed move with what its steps should have done.
+        fn check_divergence(&mut self, active: &ActiveMove) {
+            // Steps spent taking up backlash don't reach the encoder
+            let steps = self.motor.current_position() - active.start_steps - active.backlash_steps;
+            let moved = (self.encoder.position() - active.start_ticks) as i64;
+            let ticks_per_step = self.encoder_config.counts_per_rev as f64 / self.encoder_config.motor_steps_per_rev;
+            let error = divergence::position_error_ticks(steps, moved, ticks_per_step);
+            self.position_error_ticks = error;
+            if divergence::is_divergent(error, self.divergence_threshold_ticks) && self.divergence_alarm.is_none() {
+                let alarm = format!(
+                    "Encoder diverged from stepper by {} ticks (threshold {}): {} steps commanded, {} ticks measured",
+                    error, self.divergence_threshold_ticks, steps, moved
+                );
+                log::error!("{}", alarm);
+                self.divergence_alarm = Some(alarm);
+            }
+        }
+
+        /// Encoder ticks measured minus ticks expected from the commanded steps, for the last
+        /// completed move. Positive when the encoder moved further.
+        pub fn position_error_ticks(&self) -> i64 {
+            self.position_error_ticks
+        }
+
+        /// Divergence (in encoder ticks, either way) above which a move raises an alarm, and
+        /// whether `publish_divergence_alarm` then re-homes the tower.
+        pub fn set_divergence_alarm(&mut self, threshold_ticks: i64, rehome: bool) {
+            self.divergence_threshold_ticks = threshold_ticks.abs();
+            self.rehome_on_divergence = rehome;
+        }
+
+        /// Publish a pending divergence alarm to `tower/alarm` and, if enabled with
+        /// `set_divergence_alarm`, re-home to clear the accumulated error.
+        pub fn publish_divergence_alarm(&mut self, mqtt: &mut Mqtt) {
+            let Some(alarm) = self.divergence_alarm.take() else {
+                return;
+            };
+            let topic = mqtt.topic("tower/alarm");
+            if let Err(e) = mqtt.publish_confirmed(&topic, alarm.as_bytes(), ALARM_ACK_TIMEOUT) {
+                if mqtt.is_connected() {
+                    // It went out at QoS 1, so the client keeps retransmitting it until acked
+                    log::warn!("Divergence alarm not confirmed yet, left to QoS 1 retransmission: {:?}", e);
+                } else {
+                    // Never sent; queued instead, delivered once the broker is back
+                    log::warn!("Divergence alarm not sent while disconnected: {:?}", e);
+                    if let Err(e) = mqtt.publish(&topic, alarm.as_bytes()) {
+                        log::error!("Failed to publish divergence alarm: {:?}", e);
+                    }
+                }
+            }
+            if !self.rehome_on_divergence {
+                return;
+            }
+            let status: &[u8] = if self.rehome() {
+                b"Re-homed after encoder divergence, heading 90"
+            } else {
+                self.publish_limit_switch_history(mqtt);
+                b"Re-home after encoder divergence failed: limit switch not found"
+            };
+            // The search moves of a failing encoder would only raise the same alarm again
+            self.divergence_alarm = None;
+            if let Err(e) = mqtt.publish_retained(&mqtt.topic("tower/status"), status) {
+                log::error!("Failed to publish re-home status: {:?}", e);
+            }
+        }
+
+        fn raise_stall_alert(&mut self, alert: String) {
+            log::error!("{}", alert);
+            self.record_event(EventKind::Stall, &alert);
+            self.stall_alert = Some(alert);
+        }
+
+        /// Publish the alert of the last stalled move, if it hasn't been yet, to `tower/status`.
+        pub fn publish_stall_alert(&mut self, mqtt: &mut Mqtt) {
+            if let Some(alert) = self.stall_alert.take() {
+                if let Err(e) = mqtt.publish_retained(&mqtt.topic("tower/status"), alert.as_bytes()) {
+                    log::error!("Failed to publish stall alert: {:?}", e);
+                }
+            }
+        }
+
+        /// Move by `ticks` encoder ticks, correcting with the PID controller until the encoder
+        /// agrees with the target (see `set_pid_gains`).
+        ///
+        /// Each chunk is watched for stalls by `tick`; chunks too short for that are covered by
+        /// a second detector spanning the whole closed-loop move.
+        pub fn move_by_encoder_ticks(&mut self, ticks: i64) -> MoveOutcome {
+            let target = self.encoder_ticks_adjusted() as i64 + ticks;
+            self.pid.reset();
+            let mut last = Instant::now();
+            let mut progress = MoveProgress::new(self.encoder_ticks_adjusted() as i64, target, last);
+            let mut stall = StallDetector::with_config(
+                &self.stall_config,
+                last,
+                self.encoder_ticks_adjusted(),
+                self.motor.current_position(),
+            );
+            // Any chunk freed by a recovery wiggle makes the whole move a recovered one
+            let mut recovered = false;
+            for _ in 0..ENCODER_MOVE_MAX_CHUNKS {
+                let error = target - self.encoder_ticks_adjusted() as i64;
+                if error.abs() <= self.tracking_config.encoder_tolerance_ticks {
+                    return if recovered { MoveOutcome::Recovered } else { MoveOutcome::Completed };
+                }
+
+                let dt = last.elapsed().as_secs_f64();
+                last = Instant::now();
+                let output = self
+                    .pid
+                    .compute(error as f64, dt)
+                    .clamp(-ENCODER_MOVE_MAX_CHUNK_TICKS, ENCODER_MOVE_MAX_CHUNK_TICKS);
+                let steps = self.encoder_ticks_to_motor_steps(output.round() as i64);
+                if steps == 0 {
+                    break;
+                }
+                let outcome = self.move_by(steps);
+                if !outcome.is_completed() {
+                    return outcome;
+                }
+                recovered |= outcome == MoveOutcome::Recovered;
+                if let Some(report) = progress
+                    .as_mut()
+                    .and_then(|progress| progress.update(self.encoder_ticks_adjusted() as i64, Instant::now()))
+                {
+                    log::info!(
+                        "Encoder move {:.0}% ({} of {} ticks), ETA {:?}",
+                        report.percent,
+                        report.done_ticks,
+                        report.total_ticks,
+                        report.eta
+                    );
+                    if let (Some(reporter), Ok(payload)) = (&self.progress_reporter, serde_json::to_vec(&report)) {
+                        reporter.publish(&payload);
+                    }
+                }
+                if stall.update(Instant::now(), self.encoder_ticks_adjusted(), self.motor.current_position()) {
+                    self.raise_stall_alert(format!(
+                        "Stall detected in encoder move: target {} ticks, stuck at {} ticks",
+                        target,
+                        self.encoder_ticks_adjusted()
+                    ));
+                    self.emergency_stop();
+                    self.last_move_outcome = MoveOutcome::Stalled;
+                    return MoveOutcome::Stalled;
+                }
+            }
+            log::warn!(
+                "Encoder move did not settle: target {} ticks, at {} ticks",
+                target,
+                self.encoder_ticks_adjusted()
+            );
+            if recovered { MoveOutcome::Recovered } else { MoveOutcome::Completed }
+        }
+
+        /// Publish the progress of long closed-loop moves (see `progress`) through `reporter`,
+        /// e.g. one for `MOVE_PROGRESS_TOPIC` from `Mqtt::panic_reporter`, which works mid-move
+        /// without `&mut Mqtt`. Progress is logged either way.
+        pub fn set_progress_reporter(&mut self, reporter: Option<PanicReporter>) {
+            self.progress_reporter = reporter;
+        }
+
+        /// Record stalls, failed homing and mode changes in `event_log`, and dump it for the
+        /// `event_log` command.
+        pub fn set_event_log(&mut self, event_log: Option<EventLog>) {
+            self.event_log = event_log;
+        }
+
+        /// Record an event in the log from `set_event_log`, if there is one.
+        pub fn record_event(&self, kind: EventKind, detail: &str) {
+            if let Some(event_log) = &self.event_log {
+                event_log.record(kind, detail);
+            }
+        }
+
+        /// Dump the event log to `<device>/debug/event_log`. Returns the number of events
+        /// published, `None` without a log or when the publish failed.
+        pub fn publish_event_log(&self, mqtt: &mut Mqtt) -> Option<usize> {
+            let events = self.event_log.as_ref()?.snapshot();
+            match mqtt.publish_json(&mqtt.topic("debug/event_log"), &events) {
+                Ok(_) => Some(events.len()),
+                Err(e) => {
+                    log::error!("Failed to publish event log: {:?}", e);
+                    None
+                }
+            }
+        }
+
+        /// Enclosure temperature (C) and humidity (%) to report with the next telemetry;
+        /// `None` for a reading that failed.
+        pub fn set_environment(&mut self, temperature: Option<f32>, humidity: Option<f32>) {
+            self.temperature = temperature;
+            self.humidity = humidity;
+        }
+
+        /// Seconds the RTC was ahead of NTP at the last resync, to report with the telemetry.
+        pub fn set_rtc_drift(&mut self, drift_secs: i64) {
+            self.rtc_drift_secs = Some(drift_secs);
+        }
+
+        /// Send telemetry over `transport`. Without an `http` sink it goes to MQTT whatever
+        /// `transport` says, so a sink that failed to start doesn't silence the tower.
+        pub fn set_telemetry_transport(&mut self, transport: TelemetryTransport, http: Option<HttpTelemetry>) {
+            if transport.uses_http() && http.is_none() {
+                log::warn!("No HTTP telemetry sink, publishing telemetry over MQTT");
+            }
+            self.telemetry_transport = transport;
+            self.http_telemetry = http;
+        }
+
+        fn publish_telemetry(&mut self, mqtt: &mut Mqtt, sun_azimuth: f64, twilight_deg: f64) {
+            let telemetry = Telemetry {
+                timestamp: SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map(|d| d.as_secs())
+                    .unwrap_or_default(),
+                tracking_state: self.tracking_state.name(),
+                location: self.heading_deg(),
+                angle_offset: sun_azimuth - self.heading_deg() as f64,
+                sun_azimuth,
+                encoder_count: self.encoder_ticks_adjusted(),
+                encoder_degrees: self.encoder_degrees(),
+                temperature_c: self.temperature,
+                humidity: self.humidity,
+                rtc_drift_secs: self.rtc_drift_secs,
+                twilight_deg,
+                nvs_position_writes: self.position_writes(),
+                holding: self.is_holding(),
+                heading_source: self.heading_source().name(),
+            };
+            let http = if self.telemetry_transport.uses_http() { self.http_telemetry.as_mut() } else { None };
+            let via_mqtt = self.telemetry_transport.uses_mqtt() || http.is_none();
+            if let Some(http) = http {
+                // Failed samples stay queued in the sink and go out ahead of the next one
+                match http.post_json(&telemetry) {
+                    Ok(_) => log::info!("Posted telemetry to {}", http.url()),
+                    Err(e) => log::error!("Failed to post telemetry: {:?}", e),
+                }
+            }
+            if via_mqtt {
+                match mqtt.publish_json(&mqtt.topic(TELEMETRY_TOPIC), &telemetry) {
+                    Ok(_) => log::info!("Published telemetry successfully"),
+                    Err(e) => log::error!("Failed to publish telemetry: {:?}", e),
+                }
+            }
+        }
+
+        /// One L3 nudge toward equal light on both LDRs. `balance` is `Sensors::balance_gap()`
+        /// (east minus west); the tower turns `TrackingConfig::fine_tolerance_deg` toward the brighter side
+        /// unless the gap is inside `FINE_BALANCE_DEADBAND`. A gap that changes sign since the
+        /// previous nudge means the last one overshot, so that also counts as balanced rather
+        /// than hunting back and forth. Returns whether the tower moved.
+        pub fn fine_balance_step(&mut self, balance: i32) -> bool {
+            let overshot = self.prev_balance.signum() * balance.signum() < 0;
+            if balance.abs() <= FINE_BALANCE_DEADBAND || overshot {
+                self.prev_balance = 0;
+                return false;
+            }
+            self.prev_balance = balance;
 
-    pub fn calculate_steps(offset: f32) -> i64 {
-        return ((offset / 360.0) * (25600.0 * 50.0 * 84.0)) as i64;
-    }
+            let step = self.tracking_config.fine_tolerance_deg;
+            let degrees = if balance > 0 { step } else { -step };
+            if !self.soft_limits.contains(self.location + degrees as f32) {
+                log::warn!("Fine balance step from {} would leave the soft limits", self.location);
+                return false;
+            }
+            let start = self.location;
+            let outcome = self.turn_by_encoder(degrees);
+            log::info!(
+                "Fine balance {}: moved {:.3} deg ({:?})",
+                balance,
+                self.location - start,
+                outcome
+            );
+            true
+        }
 
-    pub struct Motion<'a> {
-        location: f32,
-        tracking_state: TrackingState,
-        speed: f32,
-        acceleration: u16,
-        motor: Driver,
-        motor_device:
-            StepAndDirection<PinDriver<'a, Gpio15, Output>, PinDriver<'a, Gpio16, Output>>,
-        motor_clock: OperatingSystemClock,
-        prev_balance: i32,
-        relay: PinDriver<'a, Gpio17, Output>,
-        lmsw: PinDriver<'a, Gpio14, Input>,
-        encoder: IncrementalEncoder<Rotary, PinDriver<'a, Gpio47, Input>, PinDriver<'a, Gpio21, Input>, HalfStep>,
-        // Encoder "reset" is implemented as a software offset: displayed_position = raw - offset.
-        encoder_zero_offset: i32,
-        // Limit-switch edge detection / debounce state (active-low switch).
-        lmsw_last_state_pressed: bool,
-        lmsw_last_change: Instant,
-        lmsw_zeroed_this_press: bool,
-    }
+        /// Turn to the compass heading `target` the short way round, or the long way when the
+        /// short way would cross a soft limit. The move is closed-loop on the encoder. Returns
+        /// `None`, without moving, if `target` can't be reached inside the soft limits.
+        pub fn move_to_angle_shortest(&mut self, target: f32) -> Option<MoveOutcome> {
+            let Some(delta) = self.soft_limits.shortest_delta(self.location, target) else {
+                log::warn!(
+                    "Heading {} unreachable from {} within soft limits {}..{}",
+                    target,
+                    self.location,
+                    self.soft_limits.min(),
+                    self.soft_limits.max()
+                );
+                return None;
+            };
+            log::info!("Moving {} -> {} by {} deg", self.location, target, delta);
+            Some(self.turn_by_encoder(delta as f64))
+        }
 
-    // CW: direction
-    // CCW: step
-    impl Motion<'_> {
-        pub fn new<'a>(p10: Gpio15, p11: Gpio16, p7: Gpio17, p6: Gpio14, p47: Gpio47, p21: Gpio21) -> Motion<'a> {
-            let step = PinDriver::output(p10).unwrap();
-            let direction = PinDriver::output(p11).unwrap();
-            let relay = PinDriver::output(p7).unwrap();
-            let mut lmsw = PinDriver::input(p6).unwrap();
-            let encoderA = PinDriver::input(p47).unwrap();
-            let encoderB = PinDriver::input(p21).unwrap();
-            lmsw.set_pull(esp_idf_svc::hal::gpio::Pull::Down)
-                .unwrap_or_default();
+        // Powered closed-loop relative move; `location` follows what the encoder measured.
+        fn turn_by_encoder(&mut self, degrees: f64) -> MoveOutcome {
+            let start = self.encoder_degrees();
+            self.relay.set_high().unwrap_or_default();
+            let outcome = self.move_by_encoder_ticks(self.angle_to_encoder_ticks(degrees));
+            self.release_relay();
+            let moved = self.encoder_degrees() - start;
+            self.update_position(self.location + moved as f32);
+            outcome
+        }
 
-            let encoder = IncrementalEncoder::<Rotary, _, _, HalfStep>::new(encoderA, encoderB);
+        /// Gains of the PID controller used by `move_by_encoder_ticks` (error in encoder ticks).
+        pub fn set_pid_gains(&mut self, kp: f64, ki: f64, kd: f64) {
+            self.pid.set_gains(kp, ki, kd);
+        }
+
+        // One iteration of the step loop: motor, encoder, timing capture and limit switch.
+        fn poll_once(&mut self, perf: &mut MovePerf, timed: bool) {
+            perf.iterations += 1;
+
+            let poll_start = timed.then(Instant::now);
+            let _ = self.motor.poll(&mut self.motor_device, &self.motor_clock);
+            if let Some(t) = poll_start {
+                perf.motor_poll_time += t.elapsed();
+            }
+
+            let before = self.encoder.position();
+            let read_start = timed.then(Instant::now);
+            self.encoder.poll();
+            if let Some(t) = read_start {
+                perf.encoder_time += t.elapsed();
+            }
+            perf.encoder_reads += 1;
+            if self.encoder.position() != before {
+                perf.encoder_changes += 1;
+            }
+
+            if let Some(capture) = self.encoder_timing.as_mut() {
+                let (a, b) = self.encoder.levels();
+                capture.record(Instant::now(), a, b);
+            }
 
+            // Reset encoder count to 0 when the limit switch is pressed (edge-triggered + debounced).
+            //
+            // The switch is active-low in this codebase (pressed => is_low()).
+            let pressed = self.lmsw.is_low();
             let now = Instant::now();
-            Motion {
-                location: 0.0,
-                tracking_state: TrackingState::L1,
-                speed: 43000.0,
-                acceleration: 20000,
-                motor: Driver::new(),
-                motor_device: StepAndDirection::new(step, direction),
-                motor_clock: OperatingSystemClock::new(),
-                prev_balance: 0,
-                relay,
-                lmsw,
-                encoder,
-                encoder_zero_offset: 0,
-                lmsw_last_state_pressed: false,
-                lmsw_last_change: now,
-                lmsw_zeroed_this_press: false,
+            if pressed != self.lmsw_last_state_pressed {
+                self.lmsw_last_state_pressed = pressed;
+                self.lmsw_last_change = now;
+                self.limit_switch_history.push(LimitSwitchEvent {
+                    at: now.duration_since(self.created),
+                    pressed,
+                    encoder_ticks: self.encoder_ticks_adjusted(),
+                });
+                // Allow re-zeroing after a release.
+                if !pressed {
+                    self.lmsw_zeroed_this_press = false;
+                }
+            }
+
+            // Simple time-based debounce: require stable pressed state for 30ms.
+            if pressed
+                && !self.lmsw_zeroed_this_press
+                && self.lmsw_last_change.elapsed() >= Duration::from_millis(30)
+            {
+                self.encoder_zero_offset = self.encoder.position();
+                self.encoder_referenced = true;
+                self.lmsw_zeroed_this_press = true;
+                log::info!("Limit switch pressed: encoder zeroed (offset={})", self.encoder_zero_offset);
             }
         }
 
-        pub fn update_position(&mut self, location: f32) {
-            self.location = location;
+        // Drop the pending target so the motor stops where it is.
+        fn halt(&mut self) {
+            let position = self.motor.current_position();
+            self.motor.set_current_position(position);
         }
 
-        pub fn location(&mut self) -> f32 {
-            self.location
+        // Back off a little against the direction of travel, then re-target the original
+        // destination. Often enough to break the tower free of ice or debris.
+        fn recovery_wiggle(&mut self, perf: &mut MovePerf, timed: bool) {
+            let remaining = self.motor.distance_to_go();
+            let back_off_steps = calculate_steps(self.stall_config.back_off_deg);
+            let back_off = if remaining >= 0 { -back_off_steps } else { back_off_steps };
+
+            self.halt();
+            self.motor.move_by(back_off);
+            while self.motor.is_running() && !self.stop_requested.load(Ordering::SeqCst) {
+                self.poll_once(perf, timed);
+            }
+            self.motor.move_by(remaining - back_off);
         }
 
-        pub fn switch_pressed(&mut self) -> bool {
-            self.lmsw.is_low()
+        /// Number of recovery wiggles tried on a stall before a move is abandoned (0 disables).
+        pub fn set_stall_recovery_attempts(&mut self, attempts: u32) {
+            self.stall_config.recovery_attempts = attempts;
         }
 
-        // Stage 1: single definition of "adjusted encoder ticks".
-        // Convention: CW is positive; 0 ticks corresponds to the limit switch (home) after zeroing.
-        pub fn encoder_ticks_adjusted(&self) -> i32 {
-            self.encoder.position() - self.encoder_zero_offset
+        pub fn stall_recovery_attempts(&self) -> u32 {
+            self.stall_config.recovery_attempts
         }
 
-        pub fn init(&mut self) {
-            self.motor.set_max_speed(self.speed);
-            self.motor.set_speed(self.speed);
-            self.motor.set_acceleration(self.acceleration.into());
+        /// Stall detection window and thresholds, and the recovery budget and back-off, for
+        /// moves started from now on.
+        pub fn set_stall_config(&mut self, config: StallConfig) {
+            self.stall_config = config;
         }
 
+        pub fn stall_config(&self) -> StallConfig {
+            self.stall_config
+        }
 
-        pub fn move_by(&mut self, location: i64) {
-            self.motor.move_by(location);
-            self.run();
+        /// Site setup: the side the home switch is on, for both the morning verify and the
+        /// evening park (see `set_homing_strategy` to split them), and the scale applied to
+        /// coarse tracking moves (1.0 for the nominal `GEAR_CONSTANT`).
+        pub fn set_site(&mut self, homing_direction: HomingDirection, correction_factor: f64) {
+            self.homing = HomingStrategy::uniform(homing_direction);
+            self.correction_factor = correction_factor;
         }
 
-        pub fn move_by_ticks(&mut self, location: i64) {
-            self.motor.move_by(location);
-            self.run();
+        /// Home one way for the morning verify and the other for the evening park. Rejected,
+        /// keeping the current strategy, if `HomingStrategy::check` fails against the soft limits.
+        pub fn set_homing_strategy(&mut self, strategy: HomingStrategy) -> Result<(), String> {
+            strategy.check(self.soft_limits, HOME_HEADING)?;
+            log::info!("Homing strategy: {:?}", strategy);
+            self.homing = strategy;
+            Ok(())
         }
-        
 
+        pub fn homing_strategy(&self) -> HomingStrategy {
+            self.homing
+        }
 
-        pub fn run(&mut self) {
-            let mut t0 = Instant::now();
-            loop {
-                if self.motor.is_running() {
-                    let _ = self.motor.poll(&mut self.motor_device, &self.motor_clock);
-                    self.encoder.poll();
+        /// Direction of the morning verify.
+        pub fn homing_direction(&self) -> HomingDirection {
+            self.homing.morning
+        }
 
-                    // Reset encoder count to 0 when the limit switch is pressed (edge-triggered + debounced).
-                    //
-                    // The switch is active-low in this codebase (pressed => is_low()).
-                    let pressed = self.lmsw.is_low();
-                    let now = Instant::now();
-                    if pressed != self.lmsw_last_state_pressed {
-                        self.lmsw_last_state_pressed = pressed;
-                        self.lmsw_last_change = now;
-                        // Allow re-zeroing after a release.
-                        if !pressed {
-                            self.lmsw_zeroed_this_press = false;
-                        }
-                    }
+        /// Soft limits, mounting offset, backlash and correction factor in effect.
+        pub fn calibration(&self) -> Calibration {
+            Calibration {
+                soft_limits: self.soft_limits,
+                mounting_offset: self.mounting_offset,
+                backlash_ticks: self.backlash.ticks(),
+                correction_factor: self.correction_factor,
+            }
+        }
+
+        fn apply_calibration(&mut self, calibration: Calibration) {
+            self.soft_limits = calibration.soft_limits;
+            self.mounting_offset = calibration.mounting_offset;
+            self.backlash.set_ticks(calibration.backlash_ticks);
+            self.correction_factor = calibration.correction_factor;
+        }
+
+        /// Apply a `calibrate` command, all of it or, when any field is out of range, none of it.
+        /// New soft limits must contain the current heading: move inside them first. Saved by
+        /// the next `save_calibration`. Returns the applied calibration or why it was rejected.
+        pub fn calibrate(&mut self, update: &CalibrationUpdate) -> Result<Calibration, String> {
+            let calibration = self.calibration().updated(update)?;
+            self.homing.check(calibration.soft_limits, HOME_HEADING)?;
+            if !calibration.soft_limits.contains(self.location) {
+                return Err(format!(
+                    "heading {} is outside soft limits {}..{}, move inside them first",
+                    self.location,
+                    calibration.soft_limits.min(),
+                    calibration.soft_limits.max()
+                ));
+            }
+            self.apply_calibration(calibration);
+            self.calibration_changed = true;
+            log::info!("Calibration set to {:?}", calibration);
+            Ok(calibration)
+        }
+
+        /// Store the calibration for `load_calibration` if a `calibrate` command changed it.
+        pub fn save_calibration<T: NvsPartitionId>(&mut self, nvs: &mut EspNvs<T>) {
+            if !self.calibration_changed {
+                return;
+            }
+            self.calibration_changed = false;
+            let calibration = self.calibration();
+            let saved = nvs
+                .set_u32(NVS_KEY_CAL_SOFT_MIN, calibration.soft_limits.min().to_bits())
+                .and_then(|_| nvs.set_u32(NVS_KEY_CAL_SOFT_MAX, calibration.soft_limits.max().to_bits()))
+                .and_then(|_| nvs.set_u32(NVS_KEY_CAL_MOUNT_OFFSET, calibration.mounting_offset.to_bits()))
+                .and_then(|_| nvs.set_i64(NVS_KEY_CAL_BACKLASH, calibration.backlash_ticks))
+                .and_then(|_| nvs.set_u64(NVS_KEY_CAL_CORRECTION, calibration.correction_factor.to_bits()));
+            match saved {
+                Ok(_) => log::info!("Stored calibration in NVS: {:?}", calibration),
+                Err(e) => log::warn!("Failed to store calibration in NVS: {:?}", e),
+            }
+        }
+
+        /// Apply the calibration stored by `save_calibration` over the configured one. Missing
+        /// fields keep their configured value; an out-of-range set is ignored as a whole.
+        /// Call after `set_site`.
+        pub fn load_calibration<T: NvsPartitionId>(&mut self, nvs: &mut EspNvs<T>) {
+            let update = CalibrationUpdate {
+                soft_min: nvs.get_u32(NVS_KEY_CAL_SOFT_MIN).ok().flatten().map(f32::from_bits),
+                soft_max: nvs.get_u32(NVS_KEY_CAL_SOFT_MAX).ok().flatten().map(f32::from_bits),
+                mounting_offset: nvs.get_u32(NVS_KEY_CAL_MOUNT_OFFSET).ok().flatten().map(f32::from_bits),
+                backlash_ticks: nvs.get_i64(NVS_KEY_CAL_BACKLASH).ok().flatten(),
+                correction_factor: nvs.get_u64(NVS_KEY_CAL_CORRECTION).ok().flatten().map(f64::from_bits),
+            };
+            if update.is_empty() {
+                return;
+            }
+            match self.calibration().updated(&update) {
+                Ok(calibration) => {
+                    self.apply_calibration(calibration);
+                    log::info!("Restored calibration from NVS: {:?}", calibration);
+                }
+                Err(e) => log::warn!("Ignoring calibration stored in NVS: {}", e),
+            }
+        }
+
+        /// Keep position snapshots in `nvs`. Without a store the position isn't saved, and every
+        /// boot homes.
+        pub fn set_position_store(&mut self, nvs: EspNvs<NvsDefault>) {
+            self.position_store = Some(nvs);
+        }
+
+        /// Save the heading and encoder ticks for `restore_position`. Only call this once a move
+        /// has finished, so the snapshot matches where the tower is.
+        ///
+        /// Skipped, to spare the flash, when nothing changed since the last save or it was less
+        /// than the persist interval ago (see `set_persist_interval`).
+        pub fn save_position(&mut self) {
+            let snapshot = self.position_snapshot();
+            if self.position_persist.should_write(Instant::now(), &snapshot) {
+                self.write_position(snapshot);
+            }
+        }
+
+        /// `save_position` without the rate limit, for clean shutdown points such as reaching the
+        /// sleep position.
+        pub fn save_position_now(&mut self) {
+            let snapshot = self.position_snapshot();
+            self.write_position(snapshot);
+        }
+
+        /// `save_position_now` if a move removed the snapshot since the last save. For the end of
+        /// commands, stows and maintenance jogs, which are rare next to tracking moves.
+        pub fn save_moved_position(&mut self) {
+            if !self.position_stored {
+                self.save_position_now();
+            }
+        }
+
+        // Remove the snapshot before the tower moves, so power lost mid-move leaves none behind.
+        fn invalidate_position(&mut self) {
+            if !self.position_stored {
+                return;
+            }
+            let Some(nvs) = self.position_store.as_mut() else {
+                return;
+            };
+            match nvs.remove(NVS_KEY_ENC_SNAPSHOT_VERSION) {
+                Ok(_) => log::debug!("Removed position snapshot from NVS ahead of a move"),
+                Err(e) => log::warn!("Failed to remove position snapshot from NVS: {:?}", e),
+            }
+            self.position_persist.invalidate();
+            self.position_stored = false;
+        }
+
+        /// Least time between two `save_position` writes.
+        pub fn set_persist_interval(&mut self, interval: Duration) {
+            self.position_persist.set_interval(interval);
+        }
+
+        /// Position snapshots written to NVS since boot.
+        pub fn position_writes(&self) -> u32 {
+            self.position_persist.writes()
+        }
 
-                    // Simple time-based debounce: require stable pressed state for 30ms.
-                    if pressed
-                        && !self.lmsw_zeroed_this_press
-                        && self.lmsw_last_change.elapsed() >= Duration::from_millis(30)
-                    {
-                        self.encoder_zero_offset = self.encoder.position();
-                        self.lmsw_zeroed_this_press = true;
-                        log::info!("Limit switch pressed: encoder zeroed (offset={})", self.encoder_zero_offset);
+        fn position_snapshot(&self) -> PositionSnapshot {
+            PositionSnapshot {
+                heading: self.location,
+                encoder_ticks: self.encoder_ticks_adjusted(),
+            }
+        }
+
+        fn write_position(&mut self, snapshot: PositionSnapshot) {
+            let Some(nvs) = self.position_store.as_mut() else {
+                return;
+            };
+            let ticks = snapshot.encoder_ticks;
+            // The version goes last: it is what marks the snapshot complete
+            let saved = nvs
+                .set_i32(NVS_KEY_ENC_TICKS_ADJ, ticks)
+                .and_then(|_| nvs.set_u32(NVS_KEY_LAST_HEADING, snapshot.heading.to_bits()))
+                .and_then(|_| nvs.set_u32(NVS_KEY_ENC_SNAPSHOT_VERSION, ENC_SNAPSHOT_VERSION));
+            // Count attempts too: a failed write may still have worn the flash
+            self.position_persist.record_write(Instant::now(), snapshot);
+            self.position_stored = saved.is_ok();
+            match saved {
+                Ok(_) => log::info!(
+                    "Stored position in NVS: heading {}, encoder ticks {} (v={})",
+                    snapshot.heading,
+                    ticks,
+                    ENC_SNAPSHOT_VERSION
+                ),
+                Err(e) => log::warn!("Failed to store position in NVS: {:?}", e),
+            }
+        }
+
+        /// Resume from the position saved by `save_position` instead of homing. Returns false,
+        /// leaving the position untouched, when there is no snapshot of this version or its
+        /// heading and encoder ticks disagree; the tower must then be homed.
+        pub fn restore_position(&mut self) -> bool {
+            let Some(nvs) = self.position_store.as_ref() else {
+                log::info!("No position store, homing");
+                return false;
+            };
+            let version = nvs.get_u32(NVS_KEY_ENC_SNAPSHOT_VERSION).ok().flatten();
+            if version != Some(ENC_SNAPSHOT_VERSION) {
+                log::info!("No usable position snapshot in NVS (version {:?})", version);
+                return false;
+            }
+            let ticks = nvs.get_i32(NVS_KEY_ENC_TICKS_ADJ).ok().flatten();
+            let heading = nvs.get_u32(NVS_KEY_LAST_HEADING).ok().flatten().map(f32::from_bits);
+            let (Some(encoder_ticks), Some(heading)) = (ticks, heading) else {
+                log::info!("Incomplete position snapshot in NVS: ticks {:?}, heading {:?}", ticks, heading);
+                return false;
+            };
+            let snapshot = PositionSnapshot { heading, encoder_ticks };
+            if !snapshot.is_consistent(self.encoder_config.counts_per_rev) {
+                log::warn!(
+                    "Stored heading {} disagrees with the encoder's {} ({} ticks), re-homing",
+                    heading,
+                    snapshot.encoder_heading(self.encoder_config.counts_per_rev),
+                    encoder_ticks
+                );
+                return false;
+            }
+            self.encoder_zero_offset = self.encoder.position() - encoder_ticks;
+            self.encoder_referenced = true;
+            self.update_position(heading);
+            log::info!("Restored position from NVS: heading {}, encoder ticks {}", heading, encoder_ticks);
+            true
+        }
+
+        /// Put the tower in a safe state ahead of a deliberate reboot.
+        ///
+        /// Halts the stepper, de-energizes the relay, records `reason` in NVS and publishes a
+        /// "rebooting" status. Use this directly only when something other than `restart()`
+        /// performs the reboot (e.g. an OTA rollback); otherwise call `safe_restart`.
+        pub fn prepare_for_restart<T: NvsPartitionId>(
+            &mut self,
+            reason: &str,
+            nvs: &mut EspNvs<T>,
+            mqtt: &mut Mqtt,
+        ) {
+            log::warn!("Preparing for restart: {}", reason);
+
+            // Drop any pending target so nothing keeps stepping, then cut motor power.
+            self.halt();
+            self.relay.set_low().unwrap_or_default();
+
+            // EspNvs commits on every set, so the reason is on flash once this returns.
+            match nvs.set_str(NVS_KEY_REBOOT_REASON, reason) {
+                Ok(_) => log::info!("Stored reboot reason in NVS"),
+                Err(e) => log::error!("Failed to store reboot reason in NVS: {:?}", e),
+            }
+
+            let payload = format!("Rebooting: {}", reason);
+            if let Err(e) = mqtt.publish_retained(&mqtt.topic("tower/status"), payload.as_bytes()) {
+                log::error!("Failed to publish reboot status: {:?}", e);
+            }
+
+            // Give the MQTT client a moment to push the status out before the radio goes down.
+            thread::sleep(Duration::from_secs(1));
+        }
+
+        /// Bring the tower to a safe state (see `prepare_for_restart`) and restart the chip.
+        ///
+        /// All deliberate reboots should go through here so the tower is never left powered
+        /// or in motion across a restart.
+        pub fn safe_restart<T: NvsPartitionId>(
+            &mut self,
+            reason: &str,
+            nvs: &mut EspNvs<T>,
+            mqtt: &mut Mqtt,
+        ) -> ! {
+            self.prepare_for_restart(reason, nvs, mqtt);
+            log::info!("Restarting now");
+            esp_idf_svc::hal::reset::restart();
+        }
+
+        /// Carry out a queued remote command. Only call this between tracking cycles (see
+        /// `crate::command`); it blocks until any move it starts has finished.
+        pub fn execute(&mut self, command: Command, mqtt: &mut Mqtt) {
+            log::info!("Executing command: {:?}", command);
+            // A stop that arrived while idle must not abort this command's own move.
+            self.stop_requested.store(false, Ordering::SeqCst);
+            // Moves start from the best heading estimate, not just the dead-reckoned one
+            if self.heading_source() == HeadingSource::Encoder {
+                self.update_position(self.heading_deg());
+            }
+            let status = match command {
+                Command::Goto { .. }
+                | Command::Jog { .. }
+                | Command::Park
+                | Command::Rehome
+                | Command::Manual
+                | Command::Auto
+                    if self.is_stowed() =>
+                {
+                    format!("{:?} refused: tower stowed, clear the stow first", command)
+                }
+                Command::Goto { heading } => {
+                    let target = self.limit_target(heading, mqtt);
+                    let outcome = self.turn_by(target - self.location);
+                    format!("Goto {}: {:?}, heading {}", heading, outcome, self.heading_deg())
+                }
+                Command::Jog { degrees } => {
+                    let outcome = self.jog(degrees, mqtt);
+                    format!("Jog {}: {:?}, heading {}", degrees, outcome, self.heading_deg())
+                }
+                Command::Park => {
+                    if self.park() {
+                        format!("Park: reached limit switch, heading {}", self.heading_deg())
+                    } else if self.last_move_outcome == MoveOutcome::Aborted {
+                        "Park: stopped on request".to_string()
+                    } else {
+                        self.publish_limit_switch_history(mqtt);
+                        "Park: limit switch not found".to_string()
                     }
-                    
-                    if t0.elapsed() >= Duration::from_millis(100) {
-                        let position = self.encoder_ticks_adjusted();
-                        let step_pos = self.motor.current_position();
-                        let step_rem = self.motor.distance_to_go();
-                        log::info!(
-                            "Encoder Ticks: {}, Step Position: {}, Step Remaining: {}",
-                            position,
-                            step_pos,
-                            step_rem
-                        );
-                        t0 = Instant::now();
+                }
+                Command::Rehome => {
+                    if self.rehome() {
+                        "Re-homed: encoder zeroed at the limit switch, heading 90".to_string()
+                    } else if self.last_move_outcome == MoveOutcome::Aborted {
+                        "Re-home: stopped on request".to_string()
+                    } else {
+                        self.publish_limit_switch_history(mqtt);
+                        "Re-home failed: limit switch not found".to_string()
+                    }
+                }
+                Command::Stop => {
+                    self.emergency_stop();
+                    format!("Stop: tower idle, heading {}", self.heading_deg())
+                }
+                Command::Stow => {
+                    let outcome = self.stow(mqtt);
+                    format!("Stow: {:?}, heading {}", outcome, self.heading_deg())
+                }
+                Command::Manual => {
+                    self.set_mode(OperatingMode::Manual, mqtt);
+                    format!("Manual mode: automatic tracking suspended, heading {}", self.heading_deg())
+                }
+                Command::Auto => {
+                    self.set_mode(OperatingMode::Auto, mqtt);
+                    format!("Auto mode: tracking resumed, heading {}", self.heading_deg())
+                }
+                Command::ClearStow => {
+                    self.clear_stow(mqtt);
+                    format!("Stow cleared, heading {}", self.heading_deg())
+                }
+                Command::Wind { speed } => {
+                    let stowed = self.report_wind(speed, mqtt);
+                    format!("Wind {} m/s: {}", speed, if stowed { "stowed" } else { "tracking" })
+                }
+                Command::SetSpeed { speed } => {
+                    if self.set_speed(speed) {
+                        format!("Speed set to {} steps/s", speed)
+                    } else {
+                        format!("Speed {} refused: must be in (0, {}] steps/s", speed, MAX_SAFE_SPEED)
+                    }
+                }
+                Command::SetAcceleration { acceleration } => {
+                    if self.set_acceleration(acceleration) {
+                        format!("Acceleration set to {} steps/s²", acceleration)
+                    } else {
+                        "Acceleration 0 refused: must be positive".to_string()
                     }
-                } else {
-                    break;
                 }
+                // The RTC belongs to the main loop, which sets it before commands get here
+                Command::SetTime { time } => format!("Set time {} ignored: no RTC to set", time),
+                // As is the configuration, which only the main loop holds
+                Command::SetLocation(update) => format!("Set location {:?} ignored: no configuration to save", update),
+                Command::Calibrate(update) => match self.calibrate(&update) {
+                    Ok(calibration) => format!("Calibration set: {:?}", calibration),
+                    Err(e) => format!("Calibration refused: {}", e),
+                },
+                Command::EventLog => match self.publish_event_log(mqtt) {
+                    Some(count) => format!("Event log: published {} events", count),
+                    None => "Event log unavailable".to_string(),
+                },
+                Command::EncoderTiming { secs } => {
+                    self.start_encoder_timing_capture(Duration::from_secs(secs.into()));
+                    format!("Encoder timing capture armed for {} s", secs)
+                }
+            };
+            log::info!("{}", status);
+            self.save_moved_position();
+            if let Err(e) = mqtt.publish_retained(&mqtt.topic("tower/status"), status.as_bytes()) {
+                log::error!("Failed to publish command status: {:?}", e);
             }
+            self.publish_stall_alert(mqtt);
+            self.publish_divergence_alarm(mqtt);
+            self.publish_encoder_timing(mqtt);
+        }
+
+        /// Nudge the tower by `degrees` (positive is CW) within the soft limits, powering the
+        /// relay for the move. Used for remote jogs and the maintenance-mode jog buttons.
+        pub fn jog(&mut self, degrees: f32, mqtt: &mut Mqtt) -> MoveOutcome {
+            let target = self.limit_target(self.location + degrees, mqtt);
+            self.turn_by(target - self.location)
+        }
+
+        // Powered relative move that keeps `location` in step with the tower.
+        fn turn_by(&mut self, degrees: f32) -> MoveOutcome {
+            self.relay.set_high().unwrap_or_default();
+            let start = self.motor.current_position();
+            let outcome = self.move_by(calculate_steps(degrees));
+            // Steps spent taking up backlash didn't turn the tower.
+            let stepped = self.motor.current_position() - start - self.backlash_steps;
+            let moved = stepped as f64 / GEAR_CONSTANT * 360.0;
+            self.release_relay();
+            self.update_position(self.location + moved as f32);
+            outcome
         }
 
         pub fn flip_relay(&mut self) {
@@ -181,11 +2086,13 @@ pub mod motion {
             self.relay.set_high().unwrap_or_default();
 
             let correction_factor = 1.231;
-            let steps = (15.0 / 360.0) * (25600.0 * 50.0 * 84.0);
+            let steps = (HOMING_NUDGE_DEG as f64 / 360.0) * GEAR_CONSTANT;
             log::info!("Steps Needed: {}", steps);
             log::info!("Steps Needed: {}", steps as i64);
-            self.move_by(steps as i64);
-            self.run();    // Blocking 
+            if self.move_by(steps as i64) == MoveOutcome::Aborted {
+                log::warn!("Limit switch search aborted by stop request");
+                return false;
+            }
             log::info!("Done moving 15 Degress clockwise");
             
             log::info!("Now, looking for the limit switch");
@@ -193,7 +2100,10 @@ pub mod motion {
             let mut max_steps = calculate_steps(-360.0);
             while (max_steps < 0 && self.lmsw.is_high()) {
                 let step_movement = calculate_steps(-1.0);
-                self.move_by(step_movement);
+                if self.move_by(step_movement) == MoveOutcome::Aborted {
+                    log::warn!("Limit switch search aborted by stop request");
+                    return false;
+                }
                 max_steps -= step_movement;
             }
 
@@ -209,6 +2119,50 @@ pub mod motion {
         }
 
 
+        /// Home in the morning `HomingStrategy` direction. Returns false when the switch isn't
+        /// found.
+        pub fn find_limit_switch(&mut self) -> bool {
+            self.find_limit_switch_toward(self.homing.morning)
+        }
+
+        /// Return to the switch for the night in the evening `HomingStrategy` direction. Returns
+        /// false when the switch isn't found.
+        pub fn park(&mut self) -> bool {
+            self.find_limit_switch_toward(self.homing.evening)
+        }
+
+        fn find_limit_switch_toward(&mut self, direction: HomingDirection) -> bool {
+            let found = match direction {
+                HomingDirection::Cw => self.find_limit_switch_cw(),
+                HomingDirection::Ccw => self.find_limit_switch_ccw(),
+            };
+            if !found {
+                self.record_event(
+                    EventKind::HomingFailed,
+                    &format!("{:?} search from heading {}", direction, self.location),
+                );
+            }
+            found
+        }
+
+        /// Re-home mid-day, e.g. when the encoder is suspected to have drifted: search for the
+        /// limit switch like `find_limit_switch`, then zero the encoder there and set the
+        /// heading to 90. Returns false, leaving both alone, when the switch isn't found.
+        pub fn rehome(&mut self) -> bool {
+            let heading = self.location;
+            if !self.find_limit_switch() {
+                return false;
+            }
+            // The debounce in the step loop may not have zeroed it, e.g. when the tower was
+            // already on the switch and didn't move
+            self.encoder_zero_offset = self.encoder.position();
+            self.encoder_referenced = true;
+            self.lmsw_zeroed_this_press = true;
+            self.update_position(90.0);
+            log::info!("Re-homed from heading {}: encoder zeroed (offset={})", heading, self.encoder_zero_offset);
+            true
+        }
+
         pub fn find_limit_switch_ccw(&mut self) -> bool {
             
             if self.lmsw.is_low() {
@@ -220,18 +2174,23 @@ pub mod motion {
             self.relay.set_high().unwrap_or_default();
 
             let correction_factor = 1.231;
-            let steps = (15.0 / -360.0) * (25600.0 * 50.0 * 84.0);
+            let steps = (HOMING_NUDGE_DEG as f64 / -360.0) * GEAR_CONSTANT;
             log::info!("Steps Needed: {}", steps);
             log::info!("Steps Needed: {}", steps as i64);
-            self.move_by(steps as i64);
-            self.run();    // Blocking 
+            if self.move_by(steps as i64) == MoveOutcome::Aborted {
+                log::warn!("Limit switch search aborted by stop request");
+                return false;
+            }
             log::info!("Done moving 15 Degress clockwise");
             log::info!("Now, looking for the limit switch");
 
             let mut max_steps = calculate_steps(360.0); // full CW
             while (max_steps > 0 && self.lmsw.is_high()) {
                 let step_movement = calculate_steps(1.0); // Move 1 deg at a time
-                self.move_by(step_movement);
+                if self.move_by(step_movement) == MoveOutcome::Aborted {
+                    log::warn!("Limit switch search aborted by stop request");
+                    return false;
+                }
                 max_steps -= step_movement;
             }
 
@@ -247,143 +2206,186 @@ pub mod motion {
 
 
 
-        pub fn set_tower_position<I2C: embedded_hal::i2c::I2c, T: NvsPartitionId>(
+        pub fn set_tower_position<I2C: embedded_hal::i2c::I2c>(
             &mut self,
             clock: &mut Clock<I2C>,
             location: f32,
             balance: i32,
             mqtt: &mut Mqtt,
-            current_version: Version,
-            nvs: &mut EspNvs<T>,
-            wifi: &mut Wifi<'_>,
+            ota_schedule: &OtaScheduler,
             formatted_time: String,
         ) -> bool {
             self.update_position(location);
-            log::info!("{},", clock.after_sunrise());
-            if clock.after_sunrise() && !clock.after_sunset() {
-                let sun = NOAASun {
-                    year: clock.get_year(),
-                    doy: clock.get_day() as u16,
-                    long: clock.get_longitude() as f32,
-                    lat: clock.get_latitude() as f32,
-                    timezone: -5.0,
-                    hour: clock.get_hour(),
-                    min: clock.get_minutes(),
-                    sec: clock.get_seconds(),
+            // Manual moves stay put, and so does the sunset park
+            if self.mode == OperatingMode::Manual {
+                log::info!("Manual mode, tracking suspended at heading {}", location);
+                return true;
+            }
+            // Without a readable RTC there is no sun position to track; try again next cycle.
+            // Within a few minutes of sunrise or sunset, `is_daytime` keeps its last answer.
+            let (phase, daytime) = match clock.solar_phase().and_then(|phase| Ok((phase, clock.is_daytime()?))) {
+                Ok(result) => result,
+                Err(e) => {
+                    log::error!("RTC read failed, skipping tracking cycle: {:?}", e);
+                    return true;
+                }
+            };
+            log::info!("Solar phase {:?}, daytime: {}", phase, daytime);
+            // Polar days count as daytime and polar nights as night (see `Clock::is_daytime`)
+            match clock.solar_day() {
+                Ok(SolarDay::PolarDay) => log::info!("Polar day: the sun doesn't set, tracking around the clock"),
+                Ok(SolarDay::PolarNight) => log::info!("Polar night: the sun doesn't rise, staying parked"),
+                _ => {}
+            }
+            if daytime {
+                let sun = match sun_now(clock) {
+                    Ok(sun) => sun,
+                    Err(e) => {
+                        log::error!("RTC read failed, skipping tracking cycle: {:?}", e);
+                        return true;
+                    }
                 };
                 log::info!("Tracking in progress");
-                let angle_offset = sun.azimuth_in_deg() - (location as f64);
+                let action = plan_cycle(
+                    location,
+                    Some(sun.azimuth_in_deg()),
+                    self.mounting_offset,
+                    &self.tracking_config,
+                    self.soft_limits,
+                    self.correction_factor,
+                );
                 log::info!("Actual Location: {}", location);
-                log::info!("Angle Offset: {}", angle_offset);
                 log::info!("Sun Angle: {}", sun.azimuth_in_deg());
-                if angle_offset.abs() > 5.0 {
+                log::info!("Cycle action: {:?}", action);
+                let coarse = matches!(action, CycleAction::Move { .. });
+                if coarse {
                     self.relay.set_high().unwrap_or_default();
-                    self.tracking_state = TrackingState::L1;
+                    self.set_tracking_state(TrackingState::L1, "offset above move threshold");
                 }
-                if angle_offset.abs() <= 5.0 && self.tracking_state == TrackingState::L1 {
-                    let _ = self.relay.set_low().unwrap_or_default();
+                if !coarse && self.tracking_state == TrackingState::L1 {
+                    self.release_relay();
+                    self.prev_balance = 0;
+                    self.set_tracking_state(TrackingState::L3, "offset within move threshold");
                     return true; // New line
                     //self.tracking_state = TrackingState::L2;
                 }
                 match self.tracking_state {
                     TrackingState::L1 => {
-                        let correction_factor = 1.3;
                         log::info!("Tracking state L1");
-                        let steps = (angle_offset / 360.0) * (25600.0 * 50.0 * 84.0); // * correction_factor; // Change to -360 for waco 
-                        log::info!("Steps Needed: {}", steps as i64);
-                        self.move_by(steps as i64);
+                        // L1 is only left in place with a coarse move to make (see above)
+                        let CycleAction::Move { requested, target, steps } = action else {
+                            return true;
+                        };
+                        self.warn_if_clamped(requested, target, mqtt);
+                        log::info!("Steps Needed: {}", steps);
+                        if self.move_by(steps) == MoveOutcome::Rejected {
+                            // Nothing moved, so the heading still holds
+                            self.release_relay();
+                            return true;
+                        }
                         self.run();    // Blocking 
                         // log::info!("Angle Offset: {}", angle_offset);
-                        self.update_position((location as f64 + angle_offset) as f32);
+                        self.update_position(target);
                         log::info!("Exiting Tracking state L1");
-                        self.relay.set_low().unwrap_or_default(); // New line
+                        self.release_relay();
+                        self.publish_move_perf(mqtt);
+                        self.publish_encoder_timing(mqtt);
 
-                        //Publish message
-                        let payload = format!(
+                        log::info!(
                             "Current datetime: {}, and current tower angle: {}",
-                            formatted_time, 
-                            location as f64 + angle_offset
+                            formatted_time,
+                            self.location
                         );
-                        match mqtt.publish("device1A/data", payload.as_bytes()){
-                            Ok(_) => log::info!("Published data payload successfully"),
-                            Err(e) => log::error!("Failed to publish data payload: {:?}", e),
-                        }
+                        self.publish_telemetry(mqtt, sun.azimuth_in_deg(), clock.twilight_deg());
                         return false;
                     }
                     TrackingState::L2 => {
                         log::info!("Tracking state L2");
-                        if angle_offset.abs() > 5.0 {
+                        if coarse {
                             self.prev_balance = 0;
-                            self.tracking_state = TrackingState::L1;
+                            self.set_tracking_state(TrackingState::L1, "L2 offset above move threshold");
                             return false;
                         }
-                        if (balance - self.prev_balance).abs() < 75 {
+                        if (balance - self.prev_balance).abs() < self.tracking_config.l2_settle_balance {
                             self.prev_balance = 0;
-                            self.tracking_state = TrackingState::L1;
+                            self.set_tracking_state(TrackingState::L1, "balance settled");
                             return true;
                         } else {
                             self.prev_balance = balance;
                         }
                         if balance <= -10 {
-                            let steps = (-0.5 / 360.0) * (25600.0 * 50.0 * 84.0);
-                            self.move_by(steps as i64);
-                            self.run();
-                            self.update_position(location - 0.5);
+                            let target = self.limit_target(location - 0.5, mqtt);
+                            let ticks = self.angle_to_encoder_ticks((target - location) as f64);
+                            self.move_by_encoder_ticks(ticks);
+                            self.update_position(target);
                             return false;
                         } else if balance >= 10 {
-                            let steps = (0.5 / 360.0) * (25600.0 * 50.0 * 84.0);
-                            self.move_by(steps as i64);
-                            self.run();
-                            self.update_position(location + 0.5);
+                            let target = self.limit_target(location + 0.5, mqtt);
+                            let ticks = self.angle_to_encoder_ticks((target - location) as f64);
+                            self.move_by_encoder_ticks(ticks);
+                            self.update_position(target);
                             return false;
                         } else {
                             self.prev_balance = 0;
-                            self.tracking_state = TrackingState::L1;
+                            self.set_tracking_state(TrackingState::L1, "balance within deadband");
+                            return true;
+                        }
+                    }
+                    TrackingState::L3 => {
+                        log::info!("Tracking state L3");
+                        if !self.fine_balance_step(balance) {
                             return true;
                         }
+                        self.publish_telemetry(mqtt, sun.azimuth_in_deg(), clock.twilight_deg());
+                        return false;
                     }
-                    TrackingState::L3 => (), // Future tracking 
                 }
             } 
             else {// Sunset Operation 
-                if location == 90.0 {
+                // Nothing to hold against overnight, even when holding between moves
+                self.relay.set_low().unwrap_or_default();
+                let action = plan_cycle(
+                    location,
+                    None,
+                    self.mounting_offset,
+                    &self.tracking_config,
+                    self.soft_limits,
+                    self.correction_factor,
+                );
+                if action == CycleAction::Sleep {
                     log::info!("Already reached sleep position");
 
-                    // Track start time
-                    let mut last_check = Instant::now();
-                    let check_interval = Duration::from_secs(2 * 60 * 60); // 2 hours
-
                     // Wait here until sunrise
-                    while clock.after_sunset() || !clock.after_sunrise() {
-                        if clock.after_sunrise() && !clock.after_sunset() {
-                            log::info!("Sunrise detected, exiting sleep loop");
+                    // Arm the RTC alarm for sunrise. INT/SQW is not wired to a wake-capable GPIO
+                    // on this board yet, so the flag is polled; once it is, the sleep below can
+                    // become a CPU sleep woken by the alarm.
+                    match clock.set_sunrise_alarm() {
+                        Ok(Some(time)) => log::info!("Wakeup alarm set for sunrise at {}", time),
+                        Ok(None) => log::warn!("No sunrise today, wakeup alarm not set"),
+                        Err(e) => log::warn!("Failed to set wakeup alarm: {:?}", e),
+                    }
+
+                    loop {
+                        watchdog::feed();
+                        if let Ok(true) = clock.wakeup_alarm_fired() {
+                            log::info!("Sunrise alarm fired, exiting sleep loop");
+                            if let Err(e) = clock.clear_wakeup_alarm() {
+                                log::warn!("Failed to clear wakeup alarm: {:?}", e);
+                            }
                             break;
                         }
-                        if last_check.elapsed() >= check_interval {
-                            log::info!("2 hours elapsed, checking for OTA");
-
-                            // Check to see if wifi is disconnected before OTA try
-                            log::info!("Current wifi state: {:?}", wifi.state());
-                            if wifi.state() == WifiState::Disconnected{
-                                wifi.reconnect_if_disconnected();
+                        match clock.is_daytime() {
+                            Ok(true) => {
+                                log::info!("Sunrise detected, exiting sleep loop");
+                                break;
                             }
-
-                            // Creates an instance of OTA crate and runs version compare
-                            thread::sleep(Duration::from_secs(3));
-                            let mut updater = OtaUpdater::new_ota(current_version.clone(), mqtt, Some("device1A"), Some("device1A")).expect("Failed to create OTA udater instance");
-
-                            thread::sleep(Duration::from_secs(3));
-                            let run_compare = updater.run_version_compare(nvs);
-
-                            match run_compare {
-                                Ok(_) => log::info!("Version compare succeeded"),
-                                Err(e) => {
-                                    log::error!("Version compare failed: {:?}", e);
-                                }
-                            } 
-
-                            last_check = Instant::now(); // reset the timer
-                            //break;
+                            Ok(false) => {}
+                            Err(e) => log::warn!("RTC read failed while waiting for sunrise: {:?}", e),
+                        }
+                        // The main loop runs OTA checks; hand back to it when one is due
+                        if ota_schedule.is_due(Instant::now()) {
+                            log::info!("OTA check due, leaving the sleep loop");
+                            break;
                         }
                         log::info!("Still waiting for sunrise...");
                         std::thread::sleep(std::time::Duration::from_secs(600)); // Prevent busy waiting
@@ -392,13 +2394,21 @@ pub mod motion {
                     return true;
                 } else {
                     log::info!("Moving to sleep position...");
-                    let limit_sw_status = self.find_limit_switch_cw(); // change to ccw for waco
+                    self.publish_state_history(mqtt);
+                    let limit_sw_status = self.park();
                     match limit_sw_status{
                         true => log::info!("Limit switch has returned true"),
+                        false if self.last_move_outcome == MoveOutcome::Aborted => {
+                            log::warn!("Move to sleep position stopped on request, retrying next cycle");
+                            return true;
+                        }
                         false => {
                             log::error!("Limit switch has returned false, limit switch could not be found");
+                            self.publish_limit_switch_history(mqtt);
                             loop{
-                                if let Err(e) = mqtt.publish("device1A/tower/status", b"Critical failure: Limit switch failure!") {
+                                // Halted on purpose; a watchdog reboot would only retry homing
+                                watchdog::feed();
+                                if let Err(e) = mqtt.publish_retained(&mqtt.topic("tower/status"), b"Critical failure: Limit switch failure!") {
                                     log::error!("Failed to publish critical error message: {:?}", e);
                                 }
                                 thread::sleep(Duration::from_secs(900));// Loop every 15 minutes
@@ -406,6 +2416,7 @@ pub mod motion {
                         }
                     }
                     log::info!("Tower has reached sleep position");
+                    self.save_position_now();
                     return false;
                 }
             }
@@ -434,4 +2445,148 @@ pub mod motion {
     }
 }
 
-pub use motion::Motion;
+pub use calibration::Calibration;
+pub use heading::HeadingSource;
+pub use command::Command;
+pub use limits::SoftLimits;
+pub use stall::{StallConfig, StallVerdict, StallWatch};
+pub use motion::{
+    calculate_steps, coarse_move_steps, move_in_range, noaa_sun_at, plan_cycle, CycleAction, EncoderConfig, HomingDirection, HomingStrategy, LimitSwitchEvent,
+    Motion, MoveOutcome, MovePerf, OperatingMode, TrackingConfig, GEAR_CONSTANT, MAX_MOVE_DEG, MAX_SAFE_SPEED,
+    MOVE_PROGRESS_TOPIC,
+};
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        calculate_steps, move_in_range, noaa_sun_at, plan_cycle, CycleAction, HomingDirection, HomingStrategy,
+        SoftLimits, TrackingConfig, GEAR_CONSTANT,
+    };
+    use chrono::{FixedOffset, NaiveDate, TimeZone};
+    use clock::{solar_day, solar_phase_at, SolarDay, SolarPhase, DEFAULT_SUN_HYSTERESIS, STANDARD_DEPRESSION_DEG};
+
+    #[test]
+    fn gear_constant_matches_drivetrain() {
+        assert_eq!(GEAR_CONSTANT, 107_520_000.0);
+    }
+
+    #[test]
+    fn full_revolution() {
+        assert_eq!(calculate_steps(360.0), 107_520_000);
+        assert_eq!(calculate_steps(-360.0), -107_520_000);
+    }
+
+    #[test]
+    fn single_degree() {
+        assert_eq!(calculate_steps(1.0), 298_666);
+        assert_eq!(calculate_steps(-1.0), -298_666);
+    }
+
+    #[test]
+    fn fractional_offsets() {
+        assert_eq!(calculate_steps(0.5), 149_333);
+        assert_eq!(calculate_steps(-0.5), -149_333);
+        assert_eq!(calculate_steps(15.0), 4_480_000);
+        assert_eq!(calculate_steps(0.0), 0);
+    }
+
+    #[test]
+    fn over_range_moves_are_rejected() {
+        assert!(move_in_range(calculate_steps(360.0)));
+        assert!(move_in_range(calculate_steps(-400.0)));
+        assert!(!move_in_range(calculate_steps(410.0)));
+        // calculate_steps off by a factor of ten
+        assert!(!move_in_range(calculate_steps(-90.0) * 10));
+        assert!(!move_in_range(i64::MIN));
+    }
+
+    #[test]
+    fn coarse_moves_only_beyond_the_threshold() {
+        let config = TrackingConfig::default();
+        assert!(!config.needs_coarse_move(5.0));
+        assert!(!config.needs_coarse_move(-4.9));
+        assert!(config.needs_coarse_move(-5.1));
+
+        let eager = TrackingConfig { move_threshold_deg: 1.0, ..config };
+        assert!(eager.needs_coarse_move(1.5));
+    }
+
+    #[test]
+    fn homing_strategy_must_fit_the_soft_limits() {
+        let split = HomingStrategy {
+            morning: HomingDirection::Cw,
+            evening: HomingDirection::Ccw,
+        };
+        split.check(SoftLimits::default(), 90.0).unwrap();
+        // No room CCW of the switch for the evening search's first nudge
+        let error = split.check(SoftLimits::new(80.0, 300.0), 90.0).unwrap_err();
+        assert!(error.contains("evening"), "{}", error);
+        HomingStrategy::uniform(HomingDirection::Cw)
+            .check(SoftLimits::new(80.0, 300.0), 90.0)
+            .unwrap();
+        // The switch itself outside the limits
+        let error = split.check(SoftLimits::new(120.0, 300.0), 90.0).unwrap_err();
+        assert!(error.contains("park heading"), "{}", error);
+    }
+
+    // A day of five-minute tracking cycles at the Dallas site on the summer solstice, acting on
+    // `plan_cycle` as `set_tower_position` does: move to the sun's NOAA azimuth once it is more
+    // than the threshold away, and park on the limit switch once the sun has set. Guards the
+    // solar math and the move arithmetic together.
+    #[test]
+    fn coarse_tracking_follows_the_sun_over_a_simulated_day() {
+        let (latitude, longitude) = (32.797868, -96.835597);
+        let central = FixedOffset::east_opt(-5 * 3600).unwrap();
+        let date = NaiveDate::from_ymd_opt(2025, 6, 21).unwrap();
+        let day = solar_day(date, latitude, longitude, 0.0, central, STANDARD_DEPRESSION_DEG);
+        let SolarDay::Normal { sunset, .. } = day else {
+            panic!("no sunrise in Dallas: {:?}", day);
+        };
+        let config = TrackingConfig::default();
+        let limits = SoftLimits::default();
+
+        // Homed on the limit switch overnight
+        let mut heading = 90.0_f32;
+        let (mut moves, mut parks, mut tracked, mut daytime) = (0, 0, false, false);
+        let mut now = central.from_local_datetime(&date.and_hms_opt(0, 0, 0).unwrap()).unwrap();
+        while now.date_naive() == date {
+            let phase = solar_phase_at(now, day, DEFAULT_SUN_HYSTERESIS);
+            // Like `Clock::is_daytime`, the windows around sunrise and sunset keep the last answer
+            daytime = match phase {
+                SolarPhase::Day => true,
+                SolarPhase::Night => false,
+                _ => daytime,
+            };
+            let azimuth = daytime.then(|| noaa_sun_at(now, latitude, longitude).azimuth_in_deg());
+            match plan_cycle(heading, azimuth, 0.0, &config, limits, 1.0) {
+                CycleAction::Move { requested, target, steps } => {
+                    assert_eq!(requested, target, "clamped at {}", now);
+                    assert!(move_in_range(steps), "{} steps at {}", steps, now);
+                    heading += (steps as f64 / GEAR_CONSTANT * 360.0) as f32;
+                    moves += 1;
+                }
+                CycleAction::Park => {
+                    assert!(now > sunset, "parked at {}, before sunset {}", now, sunset);
+                    heading = 90.0;
+                    parks += 1;
+                }
+                CycleAction::Sleep => assert_ne!(SolarPhase::Day, phase, "idle at {}", now),
+                CycleAction::Hold => {}
+            }
+            if let Some(azimuth) = azimuth {
+                // Truncation to whole steps costs well under a thousandth of a degree
+                let error = (azimuth - heading as f64).abs();
+                assert!(error <= config.move_threshold_deg + 0.001, "{} degrees off at {}", error, now);
+                tracked = true;
+            }
+            now += chrono::Duration::minutes(5);
+        }
+
+        // Morning sun in the east, evening sun in the west: a sweep of some 240 degrees, far
+        // more moves than one but not one every cycle
+        assert!(tracked);
+        assert!((20..100).contains(&moves), "{} coarse moves", moves);
+        assert_eq!(1, parks);
+        assert_eq!(90.0, heading);
+    }
+}
@@ -0,0 +1,129 @@
+//! Soft angle limits that keep commanded targets inside the tower's safe mechanical range.
+
+// The sun's azimuth sweeps roughly 60-300 degrees over a summer day at our sites, so the default
+// only stops the tower winding its cables past a full turn. Tighten it per installation.
+pub const DEFAULT_MIN_ANGLE: f32 = 0.0;
+pub const DEFAULT_MAX_ANGLE: f32 = 360.0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SoftLimits {
+    min: f32,
+    max: f32,
+}
+
+impl SoftLimits {
+    /// Limits from `min` to `max` degrees; the bounds are swapped if given in the wrong order.
+    pub fn new(min: f32, max: f32) -> Self {
+        if min <= max {
+            SoftLimits { min, max }
+        } else {
+            SoftLimits { min: max, max: min }
+        }
+    }
+
+    pub fn min(&self) -> f32 {
+        self.min
+    }
+
+    pub fn max(&self) -> f32 {
+        self.max
+    }
+
+    pub fn contains(&self, angle: f32) -> bool {
+        (self.min..=self.max).contains(&angle)
+    }
+
+    /// `target` brought inside the limits, and whether it had to be moved.
+    pub fn clamp(&self, target: f32) -> (f32, bool) {
+        let clamped = target.clamp(self.min, self.max);
+        (clamped, clamped != target)
+    }
+
+    /// Smallest signed turn (degrees) from `from` that ends at the compass heading `to` without
+    /// leaving the limits. The short way round is preferred; the long way is used when the short
+    /// way would cross a limit. `None` if neither way ends inside the limits.
+    pub fn shortest_delta(&self, from: f32, to: f32) -> Option<f32> {
+        let short = (to - from + 540.0).rem_euclid(360.0) - 180.0;
+        let long = if short >= 0.0 { short - 360.0 } else { short + 360.0 };
+        [short, long]
+            .into_iter()
+            .find(|delta| self.contains(from + delta))
+    }
+}
+
+impl Default for SoftLimits {
+    fn default() -> Self {
+        SoftLimits::new(DEFAULT_MIN_ANGLE, DEFAULT_MAX_ANGLE)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn move_past_max_is_clamped() {
+        let limits = SoftLimits::new(0.0, 180.0);
+        assert_eq!((180.0, true), limits.clamp(250.0));
+    }
+
+    #[test]
+    fn move_past_min_is_clamped() {
+        let limits = SoftLimits::new(30.0, 180.0);
+        assert_eq!((30.0, true), limits.clamp(-10.0));
+    }
+
+    #[test]
+    fn targets_inside_are_untouched() {
+        let limits = SoftLimits::new(0.0, 180.0);
+        assert_eq!((0.0, false), limits.clamp(0.0));
+        assert_eq!((120.5, false), limits.clamp(120.5));
+        assert_eq!((180.0, false), limits.clamp(180.0));
+    }
+
+    fn assert_close(expected: f32, actual: Option<f32>) {
+        let actual = actual.expect("no path");
+        assert!((expected - actual).abs() < 1e-3, "expected {}, got {}", expected, actual);
+    }
+
+    #[test]
+    fn shortest_path_wraps_forward() {
+        let limits = SoftLimits::new(-360.0, 720.0);
+        assert_close(20.0, limits.shortest_delta(350.0, 10.0));
+    }
+
+    #[test]
+    fn shortest_path_wraps_backward() {
+        let limits = SoftLimits::new(-360.0, 720.0);
+        assert_close(-20.0, limits.shortest_delta(10.0, 350.0));
+    }
+
+    #[test]
+    fn shortest_path_without_wrap() {
+        let limits = SoftLimits::default();
+        assert_close(30.0, limits.shortest_delta(90.0, 120.0));
+        assert_close(-30.0, limits.shortest_delta(120.0, 90.0));
+        assert_close(0.0, limits.shortest_delta(90.0, 450.0));
+    }
+
+    #[test]
+    fn long_way_round_when_short_path_crosses_a_limit() {
+        // 350 -> 370 would pass 360.
+        let limits = SoftLimits::default();
+        assert_close(-340.0, limits.shortest_delta(350.0, 10.0));
+        assert_close(340.0, limits.shortest_delta(10.0, 350.0));
+    }
+
+    #[test]
+    fn unreachable_target_has_no_path() {
+        let limits = SoftLimits::new(60.0, 300.0);
+        assert_eq!(None, limits.shortest_delta(90.0, 0.0));
+    }
+
+    #[test]
+    fn reversed_bounds_are_swapped() {
+        let limits = SoftLimits::new(180.0, 0.0);
+        assert_eq!(0.0, limits.min());
+        assert_eq!(180.0, limits.max());
+    }
+}
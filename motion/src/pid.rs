@@ -0,0 +1,168 @@
+//! PID controller for closed-loop encoder moves (`Motion::move_by_encoder_ticks`).
+//!
+//! Error and output share the same unit (encoder ticks): the output is the size of the next
+//! step chunk to command, which the caller converts to motor steps and clamps.
+
+// Default gains, tuned for a tower whose stepper follows each chunk almost exactly.
+pub const DEFAULT_KP: f64 = 0.6;
+pub const DEFAULT_KI: f64 = 0.05;
+pub const DEFAULT_KD: f64 = 0.0;
+// Bound on `ki * integral`, in output units, so a long stall can't wind the integrator up.
+pub const DEFAULT_INTEGRAL_LIMIT: f64 = 50.0;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PidController {
+    kp: f64,
+    ki: f64,
+    kd: f64,
+    integral_limit: f64,
+    integral: f64,
+    prev_error: Option<f64>,
+}
+
+impl Default for PidController {
+    fn default() -> Self {
+        PidController::new(DEFAULT_KP, DEFAULT_KI, DEFAULT_KD)
+    }
+}
+
+impl PidController {
+    pub fn new(kp: f64, ki: f64, kd: f64) -> Self {
+        PidController {
+            kp,
+            ki,
+            kd,
+            integral_limit: DEFAULT_INTEGRAL_LIMIT,
+            integral: 0.0,
+            prev_error: None,
+        }
+    }
+
+    pub fn gains(&self) -> (f64, f64, f64) {
+        (self.kp, self.ki, self.kd)
+    }
+
+    /// Change the gains; the accumulated state is cleared as it is meaningless under new gains.
+    pub fn set_gains(&mut self, kp: f64, ki: f64, kd: f64) {
+        self.kp = kp;
+        self.ki = ki;
+        self.kd = kd;
+        self.reset();
+    }
+
+    /// Clamp the integral contribution to `±limit` output units.
+    pub fn set_integral_limit(&mut self, limit: f64) {
+        self.integral_limit = limit.abs();
+        self.clamp_integral();
+    }
+
+    /// Forget the integral and derivative history, e.g. before a new move.
+    pub fn reset(&mut self) {
+        self.integral = 0.0;
+        self.prev_error = None;
+    }
+
+    /// Controller output for `error` (target - measured) after `dt` seconds.
+    pub fn compute(&mut self, error: f64, dt: f64) -> f64 {
+        if dt <= 0.0 {
+            return self.kp * error + self.ki * self.integral;
+        }
+
+        self.integral += error * dt;
+        self.clamp_integral();
+
+        let derivative = match self.prev_error {
+            Some(prev) => (error - prev) / dt,
+            None => 0.0,
+        };
+        self.prev_error = Some(error);
+
+        self.kp * error + self.ki * self.integral + self.kd * derivative
+    }
+
+    fn clamp_integral(&mut self) {
+        if self.ki != 0.0 {
+            let max = self.integral_limit / self.ki.abs();
+            self.integral = self.integral.clamp(-max, max);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // First-order plant: y' = (gain * u - y) / tau, integrated with explicit Euler.
+    struct FirstOrderPlant {
+        y: f64,
+        gain: f64,
+        tau: f64,
+    }
+
+    impl FirstOrderPlant {
+        fn step(&mut self, u: f64, dt: f64) -> f64 {
+            self.y += (self.gain * u - self.y) / self.tau * dt;
+            self.y
+        }
+    }
+
+    // Drive the plant to `target`; returns (final value, peak value).
+    fn simulate(pid: &mut PidController, target: f64, steps: usize, dt: f64) -> (f64, f64) {
+        let mut plant = FirstOrderPlant { y: 0.0, gain: 1.0, tau: 0.5 };
+        let mut peak = f64::MIN;
+        for _ in 0..steps {
+            let u = pid.compute(target - plant.y, dt);
+            let y = plant.step(u, dt);
+            peak = peak.max(y);
+        }
+        (plant.y, peak)
+    }
+
+    #[test]
+    fn settles_without_overshoot() {
+        let target = 100.0;
+        let tolerance = 0.5;
+        let max_overshoot = 0.02 * target;
+
+        let mut pid = PidController::new(2.0, 2.0, 0.0);
+        pid.set_integral_limit(1000.0);
+        let (settled, peak) = simulate(&mut pid, target, 2000, 0.01);
+
+        assert!((settled - target).abs() <= tolerance, "settled at {}", settled);
+        assert!(peak - target <= max_overshoot, "overshot to {}", peak);
+    }
+
+    #[test]
+    fn proportional_only_leaves_steady_state_error() {
+        let mut pid = PidController::new(2.0, 0.0, 0.0);
+        let (settled, _) = simulate(&mut pid, 100.0, 2000, 0.01);
+
+        // y = kp / (1 + kp) * target for a unity-gain plant.
+        assert!((settled - 100.0 * 2.0 / 3.0).abs() < 0.5, "settled at {}", settled);
+    }
+
+    #[test]
+    fn integral_is_clamped() {
+        let mut pid = PidController::new(0.0, 1.0, 0.0);
+        pid.set_integral_limit(10.0);
+        for _ in 0..1000 {
+            pid.compute(100.0, 0.1);
+        }
+        assert_eq!(pid.compute(100.0, 0.1), 10.0);
+    }
+
+    #[test]
+    fn derivative_ignores_first_sample() {
+        let mut pid = PidController::new(0.0, 0.0, 1.0);
+        assert_eq!(pid.compute(50.0, 0.1), 0.0);
+        assert!((pid.compute(40.0, 0.1) + 100.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn set_gains_resets_state() {
+        let mut pid = PidController::new(1.0, 1.0, 1.0);
+        pid.compute(10.0, 0.1);
+        pid.set_gains(1.0, 1.0, 1.0);
+        assert_eq!(pid, PidController::new(1.0, 1.0, 1.0));
+    }
+}
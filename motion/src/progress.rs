@@ -0,0 +1,161 @@
+//! Progress and time remaining of a long closed-loop move.
+//!
+//! Measured on the encoder rather than the commanded steps, so slip shows as slower progress
+//! instead of being hidden. The ETA comes from the tick rate over the last few seconds, which
+//! follows the move's acceleration and any slowdown better than the average since the start.
+
+use serde::{Serialize, Serializer};
+use std::time::{Duration, Instant};
+
+// Moves shorter than this (about 3 deg with the default `EncoderConfig`) finish before a
+// progress report would be of any use.
+pub const MIN_PROGRESS_TICKS: i64 = 3_000;
+// Least time between two reports of the same move.
+pub const PROGRESS_INTERVAL: Duration = Duration::from_secs(5);
+// The ETA uses the tick rate over at least this long.
+const RATE_WINDOW: Duration = Duration::from_secs(2);
+
+/// One report, see `MoveProgress::update`.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Progress {
+    /// Ticks moved toward the target, and the ticks to the target at the start.
+    pub done_ticks: i64,
+    pub total_ticks: i64,
+    /// `done_ticks / total_ticks * 100`, kept within 0..=100.
+    pub percent: f32,
+    /// `None` until the rate is known, or while the tower isn't moving toward the target.
+    #[serde(rename = "eta_secs", serialize_with = "serialize_secs")]
+    pub eta: Option<Duration>,
+}
+
+// Whole seconds are plenty for an ETA.
+fn serialize_secs<S: Serializer>(duration: &Option<Duration>, serializer: S) -> Result<S::Ok, S::Error> {
+    duration.map(|d| d.as_secs()).serialize(serializer)
+}
+
+#[derive(Debug, Clone)]
+pub struct MoveProgress {
+    start_ticks: i64,
+    total_ticks: i64,
+    // Start of the current rate window, and the rate (ticks/s toward the target) over the last one.
+    window: (Instant, i64),
+    rate: Option<f64>,
+    last_report: Instant,
+}
+
+impl MoveProgress {
+    /// Track a move from `start_ticks` to `target_ticks` starting at `now`; `None` for a move
+    /// too short to report on.
+    pub fn new(start_ticks: i64, target_ticks: i64, now: Instant) -> Option<Self> {
+        let total_ticks = target_ticks - start_ticks;
+        if total_ticks.abs() < MIN_PROGRESS_TICKS {
+            return None;
+        }
+        Some(MoveProgress {
+            start_ticks,
+            total_ticks,
+            window: (now, start_ticks),
+            rate: None,
+            last_report: now,
+        })
+    }
+
+    // Ticks moved toward the target, negative if the tower went the other way.
+    fn done(&self, ticks: i64) -> i64 {
+        (ticks - self.start_ticks) * self.total_ticks.signum()
+    }
+
+    /// Feed the encoder position at `now`. Returns a report once `PROGRESS_INTERVAL` has passed
+    /// since the last one (or the start).
+    pub fn update(&mut self, ticks: i64, now: Instant) -> Option<Progress> {
+        let (window_start, window_ticks) = self.window;
+        let elapsed = now.saturating_duration_since(window_start);
+        if elapsed >= RATE_WINDOW {
+            let moved = self.done(ticks) - self.done(window_ticks);
+            self.rate = Some(moved as f64 / elapsed.as_secs_f64());
+            self.window = (now, ticks);
+        }
+        if now.saturating_duration_since(self.last_report) < PROGRESS_INTERVAL {
+            return None;
+        }
+        self.last_report = now;
+        Some(self.progress(ticks))
+    }
+
+    /// Where the move stands at `ticks`, unthrottled.
+    pub fn progress(&self, ticks: i64) -> Progress {
+        let total = self.total_ticks.abs();
+        let done = self.done(ticks);
+        let remaining = (total - done).max(0);
+        let eta = match self.rate {
+            Some(rate) if rate > 0.0 => Some(Duration::from_secs_f64(remaining as f64 / rate)),
+            _ => None,
+        };
+        Progress {
+            done_ticks: done,
+            total_ticks: total,
+            percent: (done as f32 / total as f32 * 100.0).clamp(0.0, 100.0),
+            eta,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn short_moves_are_not_tracked() {
+        let now = Instant::now();
+        assert!(MoveProgress::new(0, MIN_PROGRESS_TICKS - 1, now).is_none());
+        assert!(MoveProgress::new(0, -MIN_PROGRESS_TICKS, now).is_some());
+    }
+
+    #[test]
+    fn percent_follows_the_encoder_either_way() {
+        let start = Instant::now();
+        let cw = MoveProgress::new(1_000, 11_000, start).unwrap();
+        assert_eq!(25.0, cw.progress(3_500).percent);
+        let ccw = MoveProgress::new(1_000, -9_000, start).unwrap();
+        let progress = ccw.progress(-1_500);
+        assert_eq!(25.0, progress.percent);
+        assert_eq!((2_500, 10_000), (progress.done_ticks, progress.total_ticks));
+        // Backwards or past the target stays within 0..=100
+        assert_eq!(0.0, cw.progress(500).percent);
+        assert_eq!(100.0, cw.progress(11_200).percent);
+    }
+
+    #[test]
+    fn eta_comes_from_the_recent_rate() {
+        let start = Instant::now();
+        let mut progress = MoveProgress::new(0, 10_000, start).unwrap();
+        // Slow start, then 1000 ticks/s
+        assert_eq!(None, progress.update(500, start + Duration::from_secs(2)));
+        let report = progress.update(4_500, start + Duration::from_secs(6)).unwrap();
+        assert_eq!(45.0, report.percent);
+        assert_eq!(Some(Duration::from_secs_f64(5.5)), report.eta);
+    }
+
+    #[test]
+    fn reports_are_throttled() {
+        let start = Instant::now();
+        let mut progress = MoveProgress::new(0, 10_000, start).unwrap();
+        assert!(progress.update(1_000, start + Duration::from_secs(1)).is_none());
+        assert!(progress.update(5_000, start + PROGRESS_INTERVAL).is_some());
+        assert!(progress.update(6_000, start + PROGRESS_INTERVAL + Duration::from_secs(1)).is_none());
+        assert!(progress.update(9_000, start + PROGRESS_INTERVAL * 2).is_some());
+    }
+
+    #[test]
+    fn stalled_tower_has_no_eta() {
+        let start = Instant::now();
+        let mut progress = MoveProgress::new(0, 10_000, start).unwrap();
+        progress.update(2_000, start + Duration::from_secs(2));
+        let report = progress.update(2_000, start + Duration::from_secs(5)).unwrap();
+        assert_eq!(None, report.eta);
+        assert_eq!(
+            serde_json::json!({"done_ticks": 2000, "total_ticks": 10000, "percent": 20.0, "eta_secs": null}),
+            serde_json::to_value(report).unwrap()
+        );
+    }
+}
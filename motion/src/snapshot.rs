@@ -0,0 +1,173 @@
+//! The tower position saved to NVS so a warm reboot can resume tracking without re-homing.
+//!
+//! The tower is non-backdrivable, so the heading and encoder ticks saved after a finished move
+//! still hold at the next boot. Both are saved; a snapshot whose two halves disagree means
+//! something moved or counted wrong, and the tower has to be homed instead.
+//!
+//! A snapshot only holds while the tower stands still, so it is removed before every move and
+//! saved again once the move is over; power lost mid-move leaves none, and the next boot homes.
+//!
+//! Every save is a flash write, so `PersistGuard` skips saves that come too soon after the last
+//! one or wouldn't change what is stored.
+
+use std::time::{Duration, Instant};
+
+// If you ever change meanings / units, bump this version and ignore old snapshots on boot.
+pub const ENC_SNAPSHOT_VERSION: u32 = 1;
+
+pub const NVS_KEY_ENC_SNAPSHOT_VERSION: &str = "enc_snapshot_v";
+// Adjusted ticks: 0 at the limit switch, CW positive.
+pub const NVS_KEY_ENC_TICKS_ADJ: &str = "enc_ticks_adj";
+// `Motion::location`, stored as its f32 bits.
+pub const NVS_KEY_LAST_HEADING: &str = "last_heading";
+
+// Heading of the limit switch, where the adjusted encoder ticks are 0.
+pub const HOME_HEADING: f32 = 90.0;
+// Largest gap (degrees) between the saved heading and the encoder's heading that is trusted.
+pub const MAX_HEADING_DISAGREEMENT_DEG: f32 = 2.0;
+
+// Least time between two rate-limited saves: every third 5-minute tracking move. A reboot after
+// a skipped save finds no snapshot and homes.
+pub const DEFAULT_PERSIST_INTERVAL: Duration = Duration::from_secs(15 * 60);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PositionSnapshot {
+    pub heading: f32,
+    pub encoder_ticks: i32,
+}
+
+impl PositionSnapshot {
+    /// Heading implied by the saved encoder ticks alone.
+    pub fn encoder_heading(&self, counts_per_rev: i64) -> f32 {
+        HOME_HEADING + (self.encoder_ticks as f64 * 360.0 / counts_per_rev as f64) as f32
+    }
+
+    /// Whether the saved heading and encoder ticks agree to `MAX_HEADING_DISAGREEMENT_DEG`.
+    pub fn is_consistent(&self, counts_per_rev: i64) -> bool {
+        (self.encoder_heading(counts_per_rev) - self.heading).abs() <= MAX_HEADING_DISAGREEMENT_DEG
+    }
+}
+
+/// Decides which position saves reach NVS and counts the writes for flash-wear telemetry.
+#[derive(Debug, Clone)]
+pub struct PersistGuard {
+    interval: Duration,
+    last_write: Option<Instant>,
+    // What NVS holds, `None` after `invalidate`.
+    stored: Option<PositionSnapshot>,
+    writes: u32,
+}
+
+impl PersistGuard {
+    pub fn new(interval: Duration) -> Self {
+        PersistGuard {
+            interval,
+            last_write: None,
+            stored: None,
+            writes: 0,
+        }
+    }
+
+    pub fn set_interval(&mut self, interval: Duration) {
+        self.interval = interval;
+    }
+
+    /// Whether `snapshot` is worth writing at `now`: it differs from the last one written, and
+    /// the interval has passed since then.
+    pub fn should_write(&self, now: Instant, snapshot: &PositionSnapshot) -> bool {
+        if self.stored.as_ref() == Some(snapshot) {
+            return false;
+        }
+        match self.last_write {
+            None => true,
+            Some(at) => now.duration_since(at) >= self.interval,
+        }
+    }
+
+    pub fn record_write(&mut self, now: Instant, snapshot: PositionSnapshot) {
+        self.last_write = Some(now);
+        self.stored = Some(snapshot);
+        self.writes = self.writes.saturating_add(1);
+    }
+
+    /// The snapshot was removed from NVS (itself a write), so the next save is never skipped
+    /// as unchanged.
+    pub fn invalidate(&mut self) {
+        self.stored = None;
+        self.writes = self.writes.saturating_add(1);
+    }
+
+    /// NVS writes since boot.
+    pub fn writes(&self) -> u32 {
+        self.writes
+    }
+}
+
+impl Default for PersistGuard {
+    fn default() -> Self {
+        PersistGuard::new(DEFAULT_PERSIST_INTERVAL)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const COUNTS_PER_REV: i64 = 348_323;
+
+    #[test]
+    fn encoder_heading_is_relative_to_home() {
+        let at_home = PositionSnapshot { heading: 90.0, encoder_ticks: 0 };
+        assert_eq!(90.0, at_home.encoder_heading(COUNTS_PER_REV));
+        let quarter_turn = PositionSnapshot { heading: 180.0, encoder_ticks: 87_081 };
+        assert!((quarter_turn.encoder_heading(COUNTS_PER_REV) - 180.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn matching_snapshot_is_trusted() {
+        let snapshot = PositionSnapshot { heading: 181.5, encoder_ticks: 87_081 };
+        assert!(snapshot.is_consistent(COUNTS_PER_REV));
+    }
+
+    #[test]
+    fn disagreeing_snapshot_forces_a_rehome() {
+        let snapshot = PositionSnapshot { heading: 185.0, encoder_ticks: 87_081 };
+        assert!(!snapshot.is_consistent(COUNTS_PER_REV));
+        let unhomed = PositionSnapshot { heading: 150.0, encoder_ticks: 0 };
+        assert!(!unhomed.is_consistent(COUNTS_PER_REV));
+    }
+
+    #[test]
+    fn unchanged_position_is_not_rewritten() {
+        let mut guard = PersistGuard::default();
+        let now = Instant::now();
+        let snapshot = PositionSnapshot { heading: 120.0, encoder_ticks: 29_027 };
+        assert!(guard.should_write(now, &snapshot));
+        guard.record_write(now, snapshot);
+        assert!(!guard.should_write(now + Duration::from_secs(60), &snapshot));
+        assert_eq!(1, guard.writes());
+    }
+
+    #[test]
+    fn changes_wait_out_the_interval() {
+        let mut guard = PersistGuard::new(Duration::from_secs(10));
+        let now = Instant::now();
+        guard.record_write(now, PositionSnapshot { heading: 120.0, encoder_ticks: 29_027 });
+        let moved = PositionSnapshot { heading: 121.0, encoder_ticks: 29_995 };
+        assert!(!guard.should_write(now + Duration::from_secs(9), &moved));
+        assert!(guard.should_write(now + Duration::from_secs(10), &moved));
+    }
+
+    #[test]
+    fn invalidated_snapshot_is_saved_again_even_if_unchanged() {
+        let mut guard = PersistGuard::new(Duration::from_secs(10));
+        let now = Instant::now();
+        let snapshot = PositionSnapshot { heading: 120.0, encoder_ticks: 29_027 };
+        guard.record_write(now, snapshot);
+        guard.invalidate();
+        // A move that ended where it started still has to restore the snapshot
+        assert!(!guard.should_write(now + Duration::from_secs(5), &snapshot));
+        assert!(guard.should_write(now + Duration::from_secs(10), &snapshot));
+        assert_eq!(2, guard.writes());
+    }
+}
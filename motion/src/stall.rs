@@ -0,0 +1,239 @@
+//! Encoder-based stall detection for moves driven by `Motion::run` and the closed-loop
+//! `Motion::move_by_encoder_ticks`, fed from the quadrature encoder.
+//!
+//! The stepper is open loop, so a bound or iced tower keeps "moving" as far as the step
+//! counter is concerned. A stall is declared when the motor has been commanded a meaningful
+//! number of steps over a time window without the encoder advancing. `StallWatch` adds the
+//! recovery budget: a stall is often a momentary catch that backing off frees.
+
+use std::time::{Duration, Instant};
+
+// Encoder must advance by at least this many ticks...
+pub const STALL_MIN_DENC: i32 = 1;
+// ...within this window...
+pub const STALL_TIME_MS: u64 = 250;
+// ...once at least this many steps were commanded (one encoder tick is ~300 motor steps,
+// so short moves and the start of a ramp never look like a stall).
+pub const STALL_MIN_STEPS: i64 = 1000;
+// Back-offs tried on a stall before a move is abandoned...
+pub const DEFAULT_STALL_RECOVERY_ATTEMPTS: u32 = 2;
+// ...each reversing this far, in output-shaft degrees.
+pub const DEFAULT_STALL_BACK_OFF_DEG: f32 = 0.2;
+
+/// Stall detection and recovery tunables, see `Motion::set_stall_config`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StallConfig {
+    /// The encoder must advance by at least `min_delta_ticks`...
+    pub min_delta_ticks: i32,
+    /// ...within `window`...
+    pub window: Duration,
+    /// ...once at least `min_steps` were commanded.
+    pub min_steps: i64,
+    /// Back-offs tried before a stalled move is abandoned; 0 gives up on the first stall.
+    pub recovery_attempts: u32,
+    /// How far each back-off reverses against the direction of travel, in degrees.
+    pub back_off_deg: f32,
+}
+
+impl Default for StallConfig {
+    fn default() -> Self {
+        StallConfig {
+            min_delta_ticks: STALL_MIN_DENC,
+            window: Duration::from_millis(STALL_TIME_MS),
+            min_steps: STALL_MIN_STEPS,
+            recovery_attempts: DEFAULT_STALL_RECOVERY_ATTEMPTS,
+            back_off_deg: DEFAULT_STALL_BACK_OFF_DEG,
+        }
+    }
+}
+
+pub struct StallDetector {
+    window: Duration,
+    min_delta_ticks: i32,
+    min_steps: i64,
+    progress_at: Instant,
+    progress_ticks: i32,
+    progress_steps: i64,
+}
+
+impl StallDetector {
+    pub fn new(now: Instant, ticks: i32, steps: i64) -> Self {
+        StallDetector::with_config(&StallConfig::default(), now, ticks, steps)
+    }
+
+    pub fn with_config(config: &StallConfig, now: Instant, ticks: i32, steps: i64) -> Self {
+        StallDetector {
+            window: config.window,
+            min_delta_ticks: config.min_delta_ticks,
+            min_steps: config.min_steps,
+            progress_at: now,
+            progress_ticks: ticks,
+            progress_steps: steps,
+        }
+    }
+
+    /// Forget past progress, e.g. after a recovery move.
+    pub fn reset(&mut self, now: Instant, ticks: i32, steps: i64) {
+        self.progress_at = now;
+        self.progress_ticks = ticks;
+        self.progress_steps = steps;
+    }
+
+    /// Feed the current encoder ticks and stepper position; returns true on stall.
+    pub fn update(&mut self, now: Instant, ticks: i32, steps: i64) -> bool {
+        if (ticks - self.progress_ticks).abs() >= self.min_delta_ticks {
+            self.reset(now, ticks, steps);
+            return false;
+        }
+        now.duration_since(self.progress_at) >= self.window
+            && (steps - self.progress_steps).abs() >= self.min_steps
+    }
+}
+
+/// What a move should do after a `StallWatch::update`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StallVerdict {
+    Moving,
+    /// Stalled with attempts left: back off, then `StallWatch::reset` and carry on.
+    Recover,
+    /// Stalled with no attempts left: abandon the move.
+    GiveUp,
+}
+
+/// A move's stall detector together with what is left of its recovery budget.
+pub struct StallWatch {
+    detector: StallDetector,
+    attempts_left: u32,
+    recoveries: u32,
+}
+
+impl StallWatch {
+    pub fn new(config: &StallConfig, now: Instant, ticks: i32, steps: i64) -> Self {
+        StallWatch {
+            detector: StallDetector::with_config(config, now, ticks, steps),
+            attempts_left: config.recovery_attempts,
+            recoveries: 0,
+        }
+    }
+
+    pub fn update(&mut self, now: Instant, ticks: i32, steps: i64) -> StallVerdict {
+        if !self.detector.update(now, ticks, steps) {
+            return StallVerdict::Moving;
+        }
+        if self.attempts_left == 0 {
+            return StallVerdict::GiveUp;
+        }
+        self.attempts_left -= 1;
+        self.recoveries += 1;
+        StallVerdict::Recover
+    }
+
+    /// Start a fresh window after the back-off.
+    pub fn reset(&mut self, now: Instant, ticks: i32, steps: i64) {
+        self.detector.reset(now, ticks, steps);
+    }
+
+    pub fn attempts_left(&self) -> u32 {
+        self.attempts_left
+    }
+
+    /// Back-offs tried so far in this move.
+    pub fn recoveries(&self) -> u32 {
+        self.recoveries
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn static_encoder_while_stepping_stalls_after_the_window() {
+        let start = Instant::now();
+        let mut detector = StallDetector::new(start, 500, 0);
+        let mut steps = 0;
+        for ms in (10..STALL_TIME_MS).step_by(10) {
+            steps += 2000;
+            assert!(!detector.update(start + Duration::from_millis(ms), 500, steps));
+        }
+        assert!(detector.update(start + Duration::from_millis(STALL_TIME_MS), 500, steps + 2000));
+    }
+
+    #[test]
+    fn advancing_encoder_never_stalls() {
+        let start = Instant::now();
+        let mut detector = StallDetector::new(start, 0, 0);
+        for i in 1..100 {
+            let now = start + Duration::from_millis(i * 100);
+            assert!(!detector.update(now, i as i32, i as i64 * 2000));
+        }
+    }
+
+    #[test]
+    fn idle_motor_is_not_a_stall() {
+        let start = Instant::now();
+        let mut detector = StallDetector::new(start, 500, 0);
+        let later = start + Duration::from_millis(10 * STALL_TIME_MS);
+        assert!(!detector.update(later, 500, STALL_MIN_STEPS - 1));
+    }
+
+    #[test]
+    fn reset_restarts_the_window() {
+        let start = Instant::now();
+        let mut detector = StallDetector::new(start, 500, 0);
+        let reset_at = start + Duration::from_millis(STALL_TIME_MS - 10);
+        detector.reset(reset_at, 500, 5000);
+        assert!(!detector.update(start + Duration::from_millis(STALL_TIME_MS), 500, 10_000));
+        assert!(detector.update(reset_at + Duration::from_millis(STALL_TIME_MS), 500, 10_000));
+    }
+
+    #[test]
+    fn transient_stall_recovers_after_one_back_off() {
+        let config = StallConfig {
+            recovery_attempts: 1,
+            ..StallConfig::default()
+        };
+        let start = Instant::now();
+        let mut watch = StallWatch::new(&config, start, 500, 0);
+
+        // Caught: the motor steps on but the encoder doesn't move
+        let caught = start + config.window;
+        assert_eq!(StallVerdict::Recover, watch.update(caught, 500, 5000));
+        assert_eq!(0, watch.attempts_left());
+
+        // Backed off and freed: the encoder follows the retried move
+        watch.reset(caught, 498, 4000);
+        for i in 1..20 {
+            let now = caught + Duration::from_millis(i * 100);
+            assert_eq!(StallVerdict::Moving, watch.update(now, 498 + i as i32, 4000 + i as i64 * 2000));
+        }
+        assert_eq!(1, watch.recoveries());
+    }
+
+    #[test]
+    fn persistent_stall_gives_up_once_attempts_run_out() {
+        let config = StallConfig {
+            recovery_attempts: 1,
+            ..StallConfig::default()
+        };
+        let start = Instant::now();
+        let mut watch = StallWatch::new(&config, start, 500, 0);
+        assert_eq!(StallVerdict::Recover, watch.update(start + config.window, 500, 5000));
+        watch.reset(start + config.window, 500, 5000);
+        assert_eq!(StallVerdict::GiveUp, watch.update(start + config.window * 2, 500, 10_000));
+    }
+
+    #[test]
+    fn configured_window_and_threshold_apply() {
+        let config = StallConfig {
+            min_delta_ticks: 5,
+            window: Duration::from_millis(1000),
+            ..StallConfig::default()
+        };
+        let start = Instant::now();
+        let mut detector = StallDetector::with_config(&config, start, 0, 0);
+        // Creeping 4 ticks is not progress, but the longer window hasn't run out yet
+        assert!(!detector.update(start + Duration::from_millis(500), 4, 5000));
+        assert!(detector.update(start + Duration::from_millis(1000), 4, 10_000));
+    }
+}
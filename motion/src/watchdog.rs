@@ -0,0 +1,47 @@
+//! ESP-IDF task watchdog (TWDT) for the main task.
+//!
+//! Once `subscribe` has been called from the tracking loop's task, that task has to `feed` at
+//! least once per `timeout` or the TWDT panics, and the panic handler reboots. Everything that
+//! can keep the loop away for long (the between-cycle wait, the overnight wait for sunrise, and
+//! a move's step loop, which `Motion::tick` feeds at most once a second) feeds as it goes, so
+//! only a genuine hang, such as a step loop or publish that never returns, lets the timeout
+//! expire.
+//!
+//! The feeds are free functions because the loop's callees live in several crates; feeding
+//! from a task that isn't subscribed does nothing.
+
+use esp_idf_svc::sys::{
+    esp, esp_task_wdt_add, esp_task_wdt_config_t, esp_task_wdt_init, esp_task_wdt_reconfigure,
+    esp_task_wdt_reset, EspError, ESP_ERR_INVALID_STATE,
+};
+use std::ptr;
+use std::time::Duration;
+
+/// Start the TWDT with `timeout`, rebooting when it expires. The idle tasks aren't watched.
+pub fn init(timeout: Duration) -> Result<(), EspError> {
+    let config = esp_task_wdt_config_t {
+        timeout_ms: timeout.as_millis() as u32,
+        idle_core_mask: 0,
+        trigger_panic: true,
+    };
+    // SAFETY: `config` outlives the calls, which copy it.
+    unsafe {
+        match esp!(esp_task_wdt_init(&config)) {
+            // Already started by ESP-IDF at boot (CONFIG_ESP_TASK_WDT_INIT)
+            Err(e) if e.code() == ESP_ERR_INVALID_STATE => esp!(esp_task_wdt_reconfigure(&config)),
+            result => result,
+        }
+    }
+}
+
+/// Watch the calling task. From now on it has to `feed` within the timeout.
+pub fn subscribe() -> Result<(), EspError> {
+    // SAFETY: a null handle means the calling task.
+    esp!(unsafe { esp_task_wdt_add(ptr::null_mut()) })
+}
+
+/// Reset the calling task's watchdog timer.
+pub fn feed() {
+    // SAFETY: no arguments; fails harmlessly (ESP_ERR_NOT_FOUND) when the task isn't subscribed.
+    let _ = unsafe { esp_task_wdt_reset() };
+}
@@ -0,0 +1,132 @@
+//! Broker acknowledgements of QoS 1 publishes, for `Mqtt::publish_confirmed`.
+//!
+//! The MQTT event thread reports every `Published(id)` through `acknowledge`; a publisher that
+//! needs delivery confirmation registers the id it got back with `expect` and waits on the
+//! receiver. The ack can beat the registration (the client returns the id after the message is
+//! already on its way), so recent unclaimed acks are kept for a late `expect` to find.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::sync::{Arc, Mutex};
+
+/// Id ESP-MQTT assigns to a publish and reports back in `EventPayload::Published`.
+pub type MessageId = u32;
+
+// Unclaimed acks remembered for a late `expect`. Fire-and-forget publishes are acknowledged
+// too, so this is bounded; a waiter only needs the few that arrived since its publish.
+const RECENT_ACKS: usize = 16;
+
+#[derive(Default)]
+struct Acks {
+    waiting: HashMap<MessageId, SyncSender<()>>,
+    recent: VecDeque<MessageId>,
+}
+
+/// Shared between the event thread and publishers; clones refer to the same table.
+#[derive(Clone, Default)]
+pub struct PublishAcks {
+    inner: Arc<Mutex<Acks>>,
+}
+
+impl PublishAcks {
+    pub fn new() -> Self {
+        PublishAcks::default()
+    }
+
+    /// Wait for the ack of `id`: the receiver gets one message when it arrives, straight away
+    /// if it already has.
+    pub fn expect(&self, id: MessageId) -> Receiver<()> {
+        let (tx, rx) = mpsc::sync_channel(1);
+        let mut acks = self.inner.lock().unwrap();
+        if let Some(index) = acks.recent.iter().position(|&acked| acked == id) {
+            acks.recent.remove(index);
+            let _ = tx.send(());
+        } else {
+            acks.waiting.insert(id, tx);
+        }
+        rx
+    }
+
+    /// Record the broker's ack of `id`. Returns true if a publisher was waiting for it.
+    /// Never blocks, so it is safe on the MQTT event thread.
+    pub fn acknowledge(&self, id: MessageId) -> bool {
+        let mut acks = self.inner.lock().unwrap();
+        match acks.waiting.remove(&id) {
+            Some(tx) => {
+                let _ = tx.try_send(());
+                true
+            }
+            None => {
+                if acks.recent.len() >= RECENT_ACKS {
+                    acks.recent.pop_front();
+                }
+                acks.recent.push_back(id);
+                false
+            }
+        }
+    }
+
+    /// Stop waiting for `id`, e.g. after a timeout.
+    pub fn forget(&self, id: MessageId) {
+        self.inner.lock().unwrap().waiting.remove(&id);
+    }
+
+    /// Publishers still waiting for an ack.
+    pub fn pending(&self) -> usize {
+        self.inner.lock().unwrap().waiting.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn waiter_is_signalled_by_its_ack() {
+        let acks = PublishAcks::new();
+        let rx = acks.expect(7);
+        assert_eq!(1, acks.pending());
+
+        // The event thread sees the broker's PUBACK
+        let events = acks.clone();
+        thread::spawn(move || {
+            assert!(!events.acknowledge(6));
+            assert!(events.acknowledge(7));
+        })
+        .join()
+        .unwrap();
+
+        assert!(rx.recv_timeout(Duration::from_secs(1)).is_ok());
+        assert_eq!(0, acks.pending());
+    }
+
+    #[test]
+    fn ack_arriving_before_expect_is_not_lost() {
+        let acks = PublishAcks::new();
+        assert!(!acks.acknowledge(3));
+        assert!(acks.expect(3).try_recv().is_ok());
+        // Claimed once only
+        assert!(acks.expect(3).try_recv().is_err());
+    }
+
+    #[test]
+    fn missing_ack_times_out_and_can_be_forgotten() {
+        let acks = PublishAcks::new();
+        let rx = acks.expect(9);
+        assert!(rx.recv_timeout(Duration::from_millis(10)).is_err());
+        acks.forget(9);
+        assert_eq!(0, acks.pending());
+    }
+
+    #[test]
+    fn unclaimed_acks_are_bounded() {
+        let acks = PublishAcks::new();
+        for id in 0..100 {
+            acks.acknowledge(id);
+        }
+        assert!(acks.expect(0).try_recv().is_err());
+        assert!(acks.expect(99).try_recv().is_ok());
+    }
+}
@@ -0,0 +1,324 @@
+//! Remote tower commands and their MQTT wire format.
+//!
+//! Payloads on the device's `COMMAND_TOPIC` are JSON objects selected by their `action` field:
+//! `{"action":"move_to","angle":120}`, `{"action":"jog","degrees":-2.5}`, `{"action":"home"}`,
+//! `{"action":"rehome"}`, `{"action":"stop"}`, `{"action":"stow"}`, `{"action":"clear_stow"}`,
+//! `{"action":"manual"}`, `{"action":"auto"}`,
+//! `{"action":"wind","speed":17.5}`, `{"action":"set_speed","speed":30000}`,
+//! `{"action":"set_acceleration","acceleration":15000}`,
+//! `{"action":"set_time","iso8601":"2025-06-21T14:30:05-05:00"}`,
+//! `{"action":"calibrate","soft_min":20,"soft_max":340,"mounting_offset":-1.5,"backlash":40,"correction_factor":1.02}`
+//! (any subset of the calibration fields), `{"action":"event_log"}`,
+//! `{"action":"encoder_timing","secs":60}` and
+//! `{"action":"set_location","latitude":32.7767,"longitude":-96.797,"altitude":139,"timezone_offset_hours":-6}`
+//! (any subset of the location fields).
+
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, FixedOffset};
+use serde_json::Value;
+
+/// Relative to the device prefix (see `DeviceId::topic`).
+pub const COMMAND_TOPIC: &str = "cmd";
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    /// Turn to an absolute heading, in degrees.
+    Goto { heading: f32 },
+    /// Turn by a relative amount, in degrees (positive is CW).
+    Jog { degrees: f32 },
+    /// Return to the limit switch (the 90 degree sleep position).
+    Park,
+    /// Find the limit switch and zero the encoder there, for when the encoder has drifted.
+    Rehome,
+    /// Stop the motor and de-energize the relay.
+    Stop,
+    /// Drive to the stow angle and refuse moves until `ClearStow`.
+    Stow,
+    ClearStow,
+    /// Suspend automatic tracking so remote jogs aren't undone by the next cycle.
+    Manual,
+    /// Resume automatic tracking after `Manual`.
+    Auto,
+    /// A wind speed reading in m/s, stowing the tower above the configured threshold.
+    Wind { speed: f32 },
+    /// Stepper speed in steps/s, kept across reboots.
+    SetSpeed { speed: f32 },
+    /// Stepper acceleration in steps/s², kept across reboots.
+    SetAcceleration { acceleration: u16 },
+    /// Set the RTC, for when it has drifted and NTP is unreachable. The offset is required so
+    /// the time can't be read in the wrong timezone.
+    SetTime { time: DateTime<FixedOffset> },
+    /// Change calibration without reflashing, kept across reboots.
+    Calibrate(CalibrationUpdate),
+    /// Publish the event log kept across reboots.
+    EventLog,
+    /// Capture raw encoder transitions for the next `secs` seconds of moves.
+    EncoderTiming { secs: u32 },
+    /// Change the site location in config.toml, applied from the next boot.
+    SetLocation(LocationUpdate),
+}
+
+/// The fields of a `calibrate` command; those left out keep their current value.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct CalibrationUpdate {
+    /// Soft limits, in degrees.
+    pub soft_min: Option<f32>,
+    pub soft_max: Option<f32>,
+    /// Degrees added to the sun's azimuth to get the tracking heading.
+    pub mounting_offset: Option<f32>,
+    /// Backlash allowance in encoder ticks.
+    pub backlash_ticks: Option<i64>,
+    /// Scale of coarse tracking moves.
+    pub correction_factor: Option<f64>,
+}
+
+impl CalibrationUpdate {
+    pub fn is_empty(&self) -> bool {
+        *self == CalibrationUpdate::default()
+    }
+}
+
+/// The fields of a `set_location` command; those left out keep their configured value.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct LocationUpdate {
+    /// Degrees, north and east positive.
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+    /// Metres above sea level.
+    pub altitude: Option<f64>,
+    pub timezone_offset_hours: Option<i32>,
+}
+
+impl LocationUpdate {
+    pub fn is_empty(&self) -> bool {
+        *self == LocationUpdate::default()
+    }
+}
+
+impl Command {
+    /// Parse a `COMMAND_TOPIC` payload.
+    pub fn from_json(payload: &[u8]) -> Result<Command> {
+        let value: Value = serde_json::from_slice(payload)?;
+        let action = value
+            .get("action")
+            .and_then(Value::as_str)
+            .ok_or_else(|| anyhow!("Command has no \"action\""))?;
+        match action {
+            "move_to" => Ok(Command::Goto {
+                heading: number_field(&value, action, "angle")?,
+            }),
+            "jog" => Ok(Command::Jog {
+                degrees: number_field(&value, action, "degrees")?,
+            }),
+            "home" => Ok(Command::Park),
+            "rehome" => Ok(Command::Rehome),
+            "stop" => Ok(Command::Stop),
+            "stow" => Ok(Command::Stow),
+            "clear_stow" => Ok(Command::ClearStow),
+            "manual" => Ok(Command::Manual),
+            "auto" => Ok(Command::Auto),
+            "wind" => Ok(Command::Wind {
+                speed: number_field(&value, action, "speed")?,
+            }),
+            "set_speed" => Ok(Command::SetSpeed {
+                speed: number_field(&value, action, "speed")?,
+            }),
+            "set_acceleration" => Ok(Command::SetAcceleration {
+                acceleration: value
+                    .get("acceleration")
+                    .and_then(Value::as_u64)
+                    .and_then(|n| u16::try_from(n).ok())
+                    .ok_or_else(|| anyhow!("{:?} needs an integer \"acceleration\" up to {}", action, u16::MAX))?,
+            }),
+            "set_time" => {
+                let iso8601 = value
+                    .get("iso8601")
+                    .and_then(Value::as_str)
+                    .ok_or_else(|| anyhow!("{:?} needs a string \"iso8601\"", action))?;
+                let time = DateTime::parse_from_rfc3339(iso8601)
+                    .map_err(|e| anyhow!("{:?}: bad \"iso8601\" {:?}: {}", action, iso8601, e))?;
+                Ok(Command::SetTime { time })
+            }
+            "calibrate" => {
+                let update = CalibrationUpdate {
+                    soft_min: optional_number(&value, action, "soft_min")?.map(|n| n as f32),
+                    soft_max: optional_number(&value, action, "soft_max")?.map(|n| n as f32),
+                    mounting_offset: optional_number(&value, action, "mounting_offset")?.map(|n| n as f32),
+                    backlash_ticks: match value.get("backlash") {
+                        None => None,
+                        Some(n) => Some(
+                            n.as_i64()
+                                .ok_or_else(|| anyhow!("{:?} needs an integer \"backlash\"", action))?,
+                        ),
+                    },
+                    correction_factor: optional_number(&value, action, "correction_factor")?,
+                };
+                if update.is_empty() {
+                    return Err(anyhow!(
+                        "{:?} needs soft_min, soft_max, mounting_offset, backlash or correction_factor",
+                        action
+                    ));
+                }
+                Ok(Command::Calibrate(update))
+            }
+            "event_log" => Ok(Command::EventLog),
+            "encoder_timing" => Ok(Command::EncoderTiming {
+                secs: value
+                    .get("secs")
+                    .and_then(Value::as_u64)
+                    .and_then(|n| u32::try_from(n).ok())
+                    .filter(|&n| n > 0)
+                    .ok_or_else(|| anyhow!("{:?} needs a positive integer \"secs\"", action))?,
+            }),
+            "set_location" => {
+                let update = LocationUpdate {
+                    latitude: optional_number(&value, action, "latitude")?,
+                    longitude: optional_number(&value, action, "longitude")?,
+                    altitude: optional_number(&value, action, "altitude")?,
+                    timezone_offset_hours: match value.get("timezone_offset_hours") {
+                        None => None,
+                        Some(n) => Some(
+                            n.as_i64()
+                                .and_then(|n| i32::try_from(n).ok())
+                                .ok_or_else(|| anyhow!("{:?} needs an integer \"timezone_offset_hours\"", action))?,
+                        ),
+                    },
+                };
+                if update.is_empty() {
+                    return Err(anyhow!(
+                        "{:?} needs latitude, longitude, altitude or timezone_offset_hours",
+                        action
+                    ));
+                }
+                Ok(Command::SetLocation(update))
+            }
+            other => Err(anyhow!("Unknown command action {:?}", other)),
+        }
+    }
+}
+
+fn number_field(value: &Value, action: &str, field: &str) -> Result<f32> {
+    value
+        .get(field)
+        .and_then(Value::as_f64)
+        .map(|n| n as f32)
+        .ok_or_else(|| anyhow!("{:?} needs a numeric {:?}", action, field))
+}
+
+// `field` if present, which must then be a number.
+fn optional_number(value: &Value, action: &str, field: &str) -> Result<Option<f64>> {
+    match value.get(field) {
+        None => Ok(None),
+        Some(n) => n
+            .as_f64()
+            .map(Some)
+            .ok_or_else(|| anyhow!("{:?} needs a numeric {:?}", action, field)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_every_action() {
+        assert_eq!(
+            Command::Goto { heading: 120.0 },
+            Command::from_json(br#"{"action":"move_to","angle":120}"#).unwrap()
+        );
+        assert_eq!(
+            Command::Jog { degrees: -2.5 },
+            Command::from_json(br#"{"action":"jog","degrees":-2.5}"#).unwrap()
+        );
+        assert_eq!(Command::Park, Command::from_json(br#"{"action":"home"}"#).unwrap());
+        assert_eq!(Command::Rehome, Command::from_json(br#"{"action":"rehome"}"#).unwrap());
+        assert_eq!(Command::Stop, Command::from_json(br#"{"action":"stop"}"#).unwrap());
+        assert_eq!(Command::Stow, Command::from_json(br#"{"action":"stow"}"#).unwrap());
+        assert_eq!(
+            Command::ClearStow,
+            Command::from_json(br#"{"action":"clear_stow"}"#).unwrap()
+        );
+        assert_eq!(Command::Manual, Command::from_json(br#"{"action":"manual"}"#).unwrap());
+        assert_eq!(Command::Auto, Command::from_json(br#"{"action":"auto"}"#).unwrap());
+        assert_eq!(Command::EventLog, Command::from_json(br#"{"action":"event_log"}"#).unwrap());
+        assert_eq!(
+            Command::EncoderTiming { secs: 60 },
+            Command::from_json(br#"{"action":"encoder_timing","secs":60}"#).unwrap()
+        );
+        assert_eq!(
+            Command::Wind { speed: 17.5 },
+            Command::from_json(br#"{"action":"wind","speed":17.5}"#).unwrap()
+        );
+        assert_eq!(
+            Command::SetSpeed { speed: 30000.0 },
+            Command::from_json(br#"{"action":"set_speed","speed":30000}"#).unwrap()
+        );
+        assert_eq!(
+            Command::SetAcceleration { acceleration: 15000 },
+            Command::from_json(br#"{"action":"set_acceleration","acceleration":15000}"#).unwrap()
+        );
+        assert_eq!(
+            Command::SetTime {
+                time: DateTime::parse_from_rfc3339("2025-06-21T14:30:05-05:00").unwrap()
+            },
+            Command::from_json(br#"{"action":"set_time","iso8601":"2025-06-21T14:30:05-05:00"}"#).unwrap()
+        );
+        assert_eq!(
+            Command::Calibrate(CalibrationUpdate {
+                soft_min: Some(20.0),
+                soft_max: Some(340.0),
+                mounting_offset: Some(-1.5),
+                backlash_ticks: Some(40),
+                correction_factor: Some(1.02),
+            }),
+            Command::from_json(
+                br#"{"action":"calibrate","soft_min":20,"soft_max":340,"mounting_offset":-1.5,"backlash":40,"correction_factor":1.02}"#
+            )
+            .unwrap()
+        );
+        assert_eq!(
+            Command::SetLocation(LocationUpdate {
+                latitude: Some(32.7767),
+                longitude: Some(-96.797),
+                altitude: Some(139.0),
+                timezone_offset_hours: Some(-6),
+            }),
+            Command::from_json(
+                br#"{"action":"set_location","latitude":32.7767,"longitude":-96.797,"altitude":139,"timezone_offset_hours":-6}"#
+            )
+            .unwrap()
+        );
+    }
+
+    #[test]
+    fn calibrate_takes_any_subset() {
+        assert_eq!(
+            Command::Calibrate(CalibrationUpdate {
+                backlash_ticks: Some(0),
+                ..Default::default()
+            }),
+            Command::from_json(br#"{"action":"calibrate","backlash":0}"#).unwrap()
+        );
+        assert!(Command::from_json(br#"{"action":"calibrate"}"#).is_err());
+        assert!(Command::from_json(br#"{"action":"calibrate","backlash":12.5}"#).is_err());
+        assert!(Command::from_json(br#"{"action":"calibrate","soft_min":"20"}"#).is_err());
+    }
+
+    #[test]
+    fn rejects_malformed_payloads() {
+        assert!(Command::from_json(b"move_to 120").is_err());
+        assert!(Command::from_json(br#"{"angle":120}"#).is_err());
+        assert!(Command::from_json(br#"{"action":"dance"}"#).is_err());
+        assert!(Command::from_json(br#"{"action":"move_to"}"#).is_err());
+        assert!(Command::from_json(br#"{"action":"move_to","angle":"120"}"#).is_err());
+        assert!(Command::from_json(br#"{"action":"set_acceleration","acceleration":70000}"#).is_err());
+        assert!(Command::from_json(br#"{"action":"set_acceleration","acceleration":1.5}"#).is_err());
+        assert!(Command::from_json(br#"{"action":"set_time"}"#).is_err());
+        assert!(Command::from_json(br#"{"action":"encoder_timing"}"#).is_err());
+        assert!(Command::from_json(br#"{"action":"encoder_timing","secs":0}"#).is_err());
+        assert!(Command::from_json(br#"{"action":"set_location"}"#).is_err());
+        assert!(Command::from_json(br#"{"action":"set_location","timezone_offset_hours":-5.5}"#).is_err());
+        // No offset, so no way to tell which timezone is meant
+        assert!(Command::from_json(br#"{"action":"set_time","iso8601":"2025-06-21T14:30:05"}"#).is_err());
+    }
+}
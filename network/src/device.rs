@@ -0,0 +1,47 @@
+//! Per-tower identity: the MQTT client id and the prefix of every topic the tower uses.
+
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeviceId(String);
+
+impl DeviceId {
+    pub fn new(name: &str) -> Self {
+        DeviceId(name.to_string())
+    }
+
+    /// The naming used across the fleet: tower 1 is `device1A`.
+    pub fn from_tower_id(tower_id: u32) -> Self {
+        DeviceId(format!("device{}A", tower_id))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// Full topic for `suffix`, e.g. `tower/status` -> `device1A/tower/status`.
+    pub fn topic(&self, suffix: &str) -> String {
+        format!("{}/{}", self.0, suffix)
+    }
+}
+
+impl fmt::Display for DeviceId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn topics_follow_the_tower_id() {
+        assert_eq!("device1A/data", DeviceId::from_tower_id(1).topic("data"));
+        assert_eq!(
+            "device7A/firmware/status",
+            DeviceId::from_tower_id(7).topic("firmware/status")
+        );
+        assert_eq!("dallas-west/cmd", DeviceId::new("dallas-west").topic("cmd"));
+    }
+}
@@ -0,0 +1,60 @@
+//! Per-cycle vitals published as JSON to `HEARTBEAT_TOPIC`, one topic to watch fleet health on.
+
+use serde::Serialize;
+
+/// Relative to the device prefix (see `DeviceId::topic`).
+pub const HEARTBEAT_TOPIC: &str = "heartbeat";
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Heartbeat {
+    /// Seconds since boot.
+    pub uptime_secs: u64,
+    pub free_heap_bytes: u32,
+    /// Lowest free heap since boot.
+    pub min_free_heap_bytes: u32,
+    /// Main task stack that has never been used.
+    pub stack_high_water_bytes: u32,
+    /// Signal strength of the current AP in dBm; `null` when not associated.
+    pub wifi_rssi: Option<i8>,
+    pub mqtt_connected: bool,
+    /// Heading in degrees.
+    pub location: f32,
+    pub tracking_state: &'static str,
+    /// `OperatingMode::name`: auto, manual or stowed.
+    pub mode: &'static str,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn serializes_every_field() {
+        let heartbeat = Heartbeat {
+            uptime_secs: 3_600,
+            free_heap_bytes: 187_000,
+            min_free_heap_bytes: 150_000,
+            stack_high_water_bytes: 4_096,
+            wifi_rssi: None,
+            mqtt_connected: true,
+            location: 135.5,
+            tracking_state: "L2",
+            mode: "auto",
+        };
+        let json: serde_json::Value = serde_json::to_value(&heartbeat).unwrap();
+        assert_eq!(
+            serde_json::json!({
+                "uptime_secs": 3_600,
+                "free_heap_bytes": 187_000,
+                "min_free_heap_bytes": 150_000,
+                "stack_high_water_bytes": 4_096,
+                "wifi_rssi": null,
+                "mqtt_connected": true,
+                "location": 135.5,
+                "tracking_state": "L2",
+                "mode": "auto",
+            }),
+            json
+        );
+    }
+}
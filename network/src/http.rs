@@ -0,0 +1,151 @@
+//! HTTPS client pieces shared with the OTA updater, and `HttpTelemetry`, a telemetry sink for
+//! sites whose firewall blocks the MQTT broker but lets HTTPS out.
+
+use crate::queue::OutboundQueue;
+use anyhow::Result;
+use base64::{engine::general_purpose, Engine as _};
+use embedded_svc::http::client::Client as HttpClient;
+use embedded_svc::mqtt::client::QoS;
+use esp_idf_svc::http::client::{Configuration as HttpConfiguration, EspHttpConnection};
+use esp_idf_svc::io::{Read, Write};
+use esp_idf_svc::sys::{esp_crt_bundle_attach, EspError};
+use log::*;
+use serde::Serialize;
+use std::time::Duration;
+
+/// HTTPS client checking servers against the ESP-IDF certificate bundle.
+pub fn new_client(timeout: Duration) -> Result<HttpClient<EspHttpConnection>, EspError> {
+    let connection = EspHttpConnection::new(&HttpConfiguration {
+        buffer_size: Some(1024),
+        timeout: Some(timeout),
+        crt_bundle_attach: Some(esp_crt_bundle_attach),
+        use_global_ca_store: true,
+        ..Default::default()
+    })?;
+    Ok(HttpClient::wrap(connection))
+}
+
+/// Basic-Auth `authorization` header, encoded once so every request can borrow it.
+pub struct AuthHeader(Option<String>);
+
+impl AuthHeader {
+    /// No header unless both are given.
+    pub fn new(username: Option<&str>, password: Option<&str>) -> Self {
+        AuthHeader(match (username, password) {
+            (Some(u), Some(p)) => {
+                let credentials = format!("{}:{}", u, p);
+                Some(format!("Basic {}", general_purpose::STANDARD.encode(credentials.as_bytes())))
+            }
+            _ => None,
+        })
+    }
+
+    pub fn header(&self) -> Option<(&str, &str)> {
+        self.0.as_deref().map(|value| ("authorization", value))
+    }
+}
+
+// A POST that doesn't answer within this is retried with the next sample.
+const POST_TIMEOUT: Duration = Duration::from_secs(15);
+// Every queued sample goes to the same URL, so the queue's topic is only a label.
+const QUEUE_LABEL: &str = "http telemetry";
+
+/// POSTs JSON telemetry to a fixed URL. Samples that can't be delivered are queued like MQTT
+/// messages (see `OutboundQueue`) and sent, oldest first, ahead of the next one.
+pub struct HttpTelemetry {
+    client: HttpClient<EspHttpConnection>,
+    url: String,
+    auth_header: AuthHeader,
+    queue: OutboundQueue,
+}
+
+impl HttpTelemetry {
+    pub fn new(url: &str, username: Option<&str>, password: Option<&str>) -> Result<Self> {
+        Ok(HttpTelemetry {
+            client: new_client(POST_TIMEOUT)?,
+            url: url.to_string(),
+            auth_header: AuthHeader::new(username, password),
+            queue: OutboundQueue::default(),
+        })
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// Samples waiting for the endpoint to come back.
+    pub fn queued(&self) -> usize {
+        self.queue.len()
+    }
+
+    /// POST `value` as JSON, or queue it if the endpoint can't be reached.
+    pub fn post_json<T: Serialize>(&mut self, value: &T) -> Result<()> {
+        self.post(&serde_json::to_vec(value)?)
+    }
+
+    /// POST `payload`, after anything still queued; on failure it is queued too.
+    pub fn post(&mut self, payload: &[u8]) -> Result<()> {
+        let result = self
+            .flush_queue()
+            .and_then(|()| post_to(&mut self.client, &self.url, &self.auth_header, payload));
+        if let Err(e) = &result {
+            if self.queue.push(QUEUE_LABEL, payload, QoS::AtLeastOnce, false) {
+                warn!("HTTP telemetry queue full, dropped oldest sample ({} dropped so far)", self.queue.dropped());
+            }
+            info!("HTTP telemetry failed, queued sample ({} queued): {:?}", self.queue.len(), e);
+        }
+        result
+    }
+
+    fn flush_queue(&mut self) -> Result<()> {
+        if self.queue.is_empty() {
+            return Ok(());
+        }
+        // Split the borrow: the queue hands messages to a closure that needs the client
+        let HttpTelemetry {
+            client,
+            url,
+            auth_header,
+            queue,
+        } = self;
+        let sent = queue.flush(|message| post_to(client, url, auth_header, &message.payload))?;
+        info!("Flushed {} queued telemetry samples to {}", sent, url);
+        Ok(())
+    }
+}
+
+fn post_to(client: &mut HttpClient<EspHttpConnection>, url: &str, auth_header: &AuthHeader, payload: &[u8]) -> Result<()> {
+    let length = payload.len().to_string();
+    let mut headers = vec![("content-type", "application/json"), ("content-length", length.as_str())];
+    headers.extend(auth_header.header());
+
+    let mut request = client.post(url, &headers)?;
+    request.write_all(payload)?;
+    request.flush()?;
+    let mut response = request.submit()?;
+    let status = response.status();
+    // Drain the (small) reply so the connection can be reused
+    let mut buf = [0u8; 64];
+    while response.read(&mut buf)? > 0 {}
+    if !(200..300).contains(&status) {
+        anyhow::bail!("{} answered HTTP {}", url, status);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn auth_header_is_encoded_once_and_borrowed() {
+        let auth = AuthHeader::new(Some("user"), Some("pass"));
+        let (key, first) = auth.header().unwrap();
+        let (_, second) = auth.header().unwrap();
+        assert_eq!("authorization", key);
+        assert_eq!("Basic dXNlcjpwYXNz", first);
+        // Every call hands out the same stored string rather than a fresh (or leaked) one
+        assert!(std::ptr::eq(first, second));
+        assert!(AuthHeader::new(Some("user"), None).header().is_none());
+    }
+}
@@ -1 +1,9 @@
+pub mod ack;
+pub mod command;
+pub mod device;
+pub mod heartbeat;
+pub mod http;
 pub mod mqtt;
+pub mod queue;
+pub mod selftest;
+pub mod telemetry;
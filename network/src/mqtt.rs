@@ -1,33 +1,171 @@
 use anyhow::Result;
 use log::*;
+use serde::Serialize;
 use esp_idf_svc::{
     mqtt::client::{
-    EspMqttClient, EventPayload, MqttClientConfiguration, QoS},
+    Details, EspMqttClient, EventPayload, LwtConfiguration, MqttClientConfiguration, QoS},
+    handle::RawHandle,
+    sys::{esp_mqtt_client_handle_t, esp_mqtt_client_publish},
     tls::X509,
 };
-use std::{sync::{atomic::{AtomicBool, Ordering}, Arc}, thread};
-use std::ffi::CStr;
+use std::{sync::{atomic::{AtomicBool, Ordering}, Arc, Mutex}, thread};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::ffi::{CStr, CString};
 use std::time::Duration;
-use std::collections::VecDeque;
+
+use crate::ack::PublishAcks;
+use crate::command::{Command, COMMAND_TOPIC};
+use crate::device::DeviceId;
+use crate::queue::OutboundQueue;
+
+// Retained presence topic (under the device prefix): the broker publishes `STATUS_OFFLINE` there
+// (our Last Will) when the connection drops without a clean disconnect, and we overwrite it with
+// `STATUS_ONLINE` on connect.
+pub const STATUS_TOPIC: &str = "status";
+pub const STATUS_ONLINE: &[u8] = b"online";
+pub const STATUS_OFFLINE: &[u8] = b"offline";
+
+pub const DEFAULT_BROKER_URL: &str = "mqtts://mqtt.jantaus.com:9443";
+
+/// Where and as whom to connect; see `Mqtt::new_mqtt`.
+#[derive(Debug, Clone)]
+pub struct MqttSettings {
+    pub broker_url: String,
+    /// Defaults to `<device id>_pub`.
+    pub client_id: Option<String>,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// Root the broker certificate must chain to. Defaults to the compiled-in `fullchain.pem`
+    /// (`DEFAULT_CA_CERT`).
+    pub ca_cert: Option<X509<'static>>,
+    /// Certificate and private key for brokers that authenticate clients by mutual TLS; set
+    /// both or neither. ESP-MQTT keeps using them for the life of the client, hence `'static`.
+    pub client_cert: Option<X509<'static>>,
+    pub private_key: Option<X509<'static>>,
+}
+
+impl Default for MqttSettings {
+    fn default() -> Self {
+        MqttSettings {
+            broker_url: DEFAULT_BROKER_URL.to_string(),
+            client_id: None,
+            username: None,
+            password: None,
+            ca_cert: None,
+            client_cert: None,
+            private_key: None,
+        }
+    }
+}
+
+/// Reject broker URLs ESP-MQTT can't connect to: the scheme must be `mqtt://` or `mqtts://`
+/// (any case) and a host must follow.
+pub fn check_broker_url(url: &str) -> Result<()> {
+    let (scheme, rest) = url
+        .split_once("://")
+        .ok_or_else(|| anyhow::anyhow!("broker URL {:?} has no scheme", url))?;
+    if !scheme.eq_ignore_ascii_case("mqtt") && !scheme.eq_ignore_ascii_case("mqtts") {
+        anyhow::bail!("broker URL {:?} must start with mqtt:// or mqtts://", url);
+    }
+    if rest.is_empty() || rest.starts_with(':') || rest.starts_with('/') {
+        anyhow::bail!("broker URL {:?} has no host", url);
+    }
+    Ok(())
+}
+
 pub struct Mqtt {
     client: EspMqttClient<'static>,
+    device_id: DeviceId,
     connected: Arc<AtomicBool>,
+    // Set by the event thread on every (re)connect; `service` then renews the subscriptions,
+    // which the broker forgets with the clean session.
+    session_pending: Arc<AtomicBool>,
+    subscriptions: Vec<(String, QoS)>,
+    command_tx: Arc<Mutex<Option<Sender<Command>>>>,
+    // Messages on every other subscribed topic, see `received`
+    message_tx: Arc<Mutex<Option<Sender<(String, Vec<u8>)>>>>,
+    // Raised straight from the event thread on a `Stop` command, so it reaches a move in progress
+    stop_flag: Arc<Mutex<Option<Arc<AtomicBool>>>>,
+    // Messages published while disconnected, sent in order once the broker is back
+    queue: Mutex<OutboundQueue>,
+    status_topic: String,
+    // Fed by the event thread's `Published` events, see `publish_confirmed`
+    acks: PublishAcks,
 }
 
-const CA_CERT: &CStr = unsafe{
+/// Publishes to one topic from any thread without `&mut Mqtt`, for the panic hook, where the
+/// `Mqtt` may be borrowed by the code that panicked. See `Mqtt::panic_reporter`.
+pub struct PanicReporter {
+    client: esp_mqtt_client_handle_t,
+    topic: CString,
+    connected: Arc<AtomicBool>,
+}
+
+// SAFETY: the ESP-MQTT client API is thread-safe; the handle is only passed to it.
+unsafe impl Send for PanicReporter {}
+unsafe impl Sync for PanicReporter {}
+
+impl PanicReporter {
+    /// Best-effort QoS 1 publish: returns false when disconnected or the client refused it.
+    /// Blocks while the client sends, so don't call it from the MQTT event thread.
+    pub fn publish(&self, payload: &[u8]) -> bool {
+        if !self.connected.load(Ordering::SeqCst) {
+            return false;
+        }
+        // SAFETY: the client outlives the reporter (see `Mqtt::panic_reporter`), and the
+        // pointers are valid for the length given.
+        let id = unsafe {
+            esp_mqtt_client_publish(
+                self.client,
+                self.topic.as_ptr(),
+                payload.as_ptr() as _,
+                payload.len() as _,
+                QoS::AtLeastOnce as _,
+                0,
+            )
+        };
+        id >= 0
+    }
+}
+
+/// Root certificate of the production broker.
+pub const DEFAULT_CA_CERT: &CStr = unsafe{
     CStr::from_bytes_with_nul_unchecked(concat!(include_str!("../fullchain.pem"), "\0").as_bytes())
 };
 impl Mqtt {
-    /// Create a new TLS-secured MQTT client
-    pub fn new_mqtt(broker_url: &str, client_id: &str, user: &str, pass: &str) -> Result<Self> {
-
+    /// Create a new TLS-secured MQTT client for `device_id`, connecting as `settings` says. The
+    /// broker publishes `offline_payload` (retained) to `status_topic` if the tower drops off;
+    /// `STATUS_ONLINE` replaces it on every connect.
+    pub fn new_mqtt(
+        settings: &MqttSettings,
+        device_id: DeviceId,
+        status_topic: &str,
+        offline_payload: &[u8],
+    ) -> Result<Self> {
+        check_broker_url(&settings.broker_url)?;
+        if settings.client_cert.is_some() != settings.private_key.is_some() {
+            anyhow::bail!("MQTT client certificate and private key must be given together");
+        }
+        let broker_url = settings.broker_url.as_str();
 
+        let client_id = settings
+            .client_id
+            .clone()
+            .unwrap_or_else(|| format!("{}_pub", device_id));
         let mqtt_config = MqttClientConfiguration {
-            client_id: Some(client_id),
-            username: Some(user),
-            password: Some(pass),
-            server_certificate: Some(X509::pem(CA_CERT)),
+            client_id: Some(&client_id),
+            username: settings.username.as_deref(),
+            password: settings.password.as_deref(),
+            server_certificate: Some(settings.ca_cert.unwrap_or(X509::pem(DEFAULT_CA_CERT))),
+            client_certificate: settings.client_cert,
+            private_key: settings.private_key,
             keep_alive_interval: Some(Duration::from_secs(60)),
+            lwt: Some(LwtConfiguration {
+                topic: status_topic,
+                payload: offline_payload,
+                qos: QoS::AtLeastOnce,
+                retain: true,
+            }),
             ..Default::default()
         };
 
@@ -36,8 +174,20 @@ impl Mqtt {
 
         let connected = Arc::new(AtomicBool::new(false));
         let connected_clone = connected.clone();
+        let session_pending = Arc::new(AtomicBool::new(false));
+        let session_pending_clone = session_pending.clone();
+        let command_tx: Arc<Mutex<Option<Sender<Command>>>> = Arc::new(Mutex::new(None));
+        let command_tx_clone = command_tx.clone();
+        let message_tx: Arc<Mutex<Option<Sender<(String, Vec<u8>)>>>> = Arc::new(Mutex::new(None));
+        let message_tx_clone = message_tx.clone();
+        let stop_flag: Arc<Mutex<Option<Arc<AtomicBool>>>> = Arc::new(Mutex::new(None));
+        let stop_flag_clone = stop_flag.clone();
+        let command_topic = device_id.topic(COMMAND_TOPIC);
+        let command_topic_clone = command_topic.clone();
+        let acks = PublishAcks::new();
+        let acks_clone = acks.clone();
 
-        let (mut client, mut connection) = EspMqttClient::new(
+        let (client, mut connection) = EspMqttClient::new(
             broker_url,
             &mqtt_config,
         )?;
@@ -45,28 +195,16 @@ impl Mqtt {
         info!("MQTT client created successfully!");
 
         thread::spawn(move || {
-            let mut message_queue: VecDeque<(String, Vec<u8>)> = VecDeque::new();
-
             while let Ok(event) = connection.next() {
                 match event.payload() {
                     EventPayload::Connected(_) => {
                         info!("MQTT Connected");
                         connected_clone.store(true, Ordering::SeqCst);
+                        session_pending_clone.store(true, Ordering::SeqCst);
 
-                        // publish inside thread if needed
-
-                        /* // Flush queued messages
-                        while let Some((topic, payload)) = message_queue.pop_front() {
-                            match client.publish(&topic, QoS::AtLeastOnce, false, &payload) {
-                                Ok(_) => info!("Queued message published successfully"),
-                                Err(e) => {
-                                    warn!("Failed to publish queued message: {:?}, putting back in queue", e);
-                                    message_queue.push_front((topic, payload));
-                                    break; // stop flushing for now
-                                }
-                            }
-
-                        } */
+                        // The queue is flushed from the main thread by `service`/`publish`: calling
+                        // the client here, while the MQTT task waits for this event to be
+                        // released, would deadlock on the client's API lock.
                     }
                     EventPayload::Disconnected => {
                         warn!("MQTT Disconnected, will queue messages temporarily...");
@@ -74,14 +212,64 @@ impl Mqtt {
                         connected_clone.store(false, Ordering::SeqCst);
                         // trigger reconnect
                     }
-                    EventPayload::Published(id) => info!("MQTT Publish Message {} confirmed", id),
+                    EventPayload::Received { topic, data, details, .. } => {
+                        if details != Details::Complete {
+                            warn!("Ignoring fragmented message on {:?} ({} bytes)", topic, data.len());
+                            continue;
+                        }
+                        if topic != Some(command_topic_clone.as_str()) {
+                            if let (Some(topic), Some(tx)) = (topic, message_tx_clone.lock().unwrap().as_ref()) {
+                                let _ = tx.send((topic.to_string(), data.to_vec()));
+                            }
+                            continue;
+                        }
+                        match Command::from_json(data) {
+                            Ok(command) => {
+                                info!("Received command: {:?}", command);
+                                if command == Command::Stop {
+                                    if let Some(flag) = stop_flag_clone.lock().unwrap().as_ref() {
+                                        flag.store(true, Ordering::SeqCst);
+                                    }
+                                }
+                                if let Some(tx) = command_tx_clone.lock().unwrap().as_ref() {
+                                    let _ = tx.send(command);
+                                }
+                            }
+                            Err(e) => warn!("Ignoring malformed command {:?}: {}", String::from_utf8_lossy(data), e),
+                        }
+                    }
+                    EventPayload::Published(id) => {
+                        info!("MQTT Publish Message {} confirmed", id);
+                        acks_clone.acknowledge(id);
+                    }
                     EventPayload::Error(e) => error!("MQTT error: {:?}", e),
                     _ => {}
                 }
             }
         });
 
-        Ok(Self {client, connected})
+        Ok(Self {
+            client,
+            device_id,
+            connected,
+            session_pending,
+            subscriptions: vec![(command_topic, QoS::AtMostOnce)],
+            command_tx,
+            message_tx,
+            stop_flag,
+            queue: Mutex::new(OutboundQueue::default()),
+            status_topic: status_topic.to_string(),
+            acks,
+        })
+    }
+
+    pub fn device_id(&self) -> &DeviceId {
+        &self.device_id
+    }
+
+    /// Full topic for `suffix` under this device's prefix.
+    pub fn topic(&self, suffix: &str) -> String {
+        self.device_id.topic(suffix)
     }
 
     // Expose the flag safely
@@ -89,15 +277,184 @@ impl Mqtt {
         self.connected.load(Ordering::SeqCst)
     }
 
+    /// A reporter publishing to `topic` on this client. The `Mqtt` must outlive it, which holds
+    /// for the firmware's single client kept for the lifetime of `main`.
+    pub fn panic_reporter(&self, topic: &str) -> Result<PanicReporter> {
+        Ok(PanicReporter {
+            client: self.client.handle(),
+            topic: CString::new(topic)?,
+            connected: self.connected.clone(),
+        })
+    }
+
+    /// Publish `payload` at QoS 1, not retained, or queue it until the broker is reachable
+    /// again. See `publish_with`.
     pub fn publish(&mut self, topic: &str, payload: &[u8]) -> Result<()> {
+        self.publish_with(topic, payload, QoS::AtLeastOnce, false)
+    }
+
+    /// Publish `payload` retained at QoS 1, for state such as status and firmware version that
+    /// a dashboard subscribing later should see straight away.
+    pub fn publish_retained(&mut self, topic: &str, payload: &[u8]) -> Result<()> {
+        self.publish_with(topic, payload, QoS::AtLeastOnce, true)
+    }
+
+    /// Publish `payload`, or queue it until the broker is reachable again.
+    ///
+    /// `qos` is the delivery guarantee between us and the broker: `AtMostOnce` (0) may be lost,
+    /// `AtLeastOnce` (1) is resent until acknowledged and may arrive twice, `ExactlyOnce` (2)
+    /// arrives once at the cost of a second handshake. With `retain` the broker keeps the last
+    /// message on `topic` and hands it to every new subscriber.
+    pub fn publish_with(&mut self, topic: &str, payload: &[u8], qos: QoS, retain: bool) -> Result<()> {
+        self.service();
+        // Anything still queued goes first so the broker sees messages in publish order
+        if !self.is_connected() || self.flush_queue().is_err() {
+            let mut queue = self.queue.lock().unwrap();
+            if queue.push(topic, payload, qos, retain) {
+                warn!("MQTT queue full, dropped oldest message ({} dropped so far)", queue.dropped());
+            }
+            info!("MQTT unavailable, queued message for {} ({} queued)", topic, queue.len());
+            return Ok(());
+        }
         info!("Attempting to publish message to topic...");
-        self.client.publish(topic, QoS::AtLeastOnce, false, payload)?;
+        self.client.publish(topic, qos, retain, payload)?;
         info!("Initial message published successfully!");
         Ok(())
     }
 
+    /// Publish `payload` at QoS 1 and wait up to `timeout` for the broker to acknowledge it,
+    /// for messages whose delivery matters (alarms, OTA status). Unlike `publish` nothing is
+    /// queued: while disconnected, or when the ack doesn't come, this fails and the caller
+    /// decides what to do.
+    pub fn publish_confirmed(&mut self, topic: &str, payload: &[u8], timeout: Duration) -> Result<()> {
+        self.service();
+        if !self.is_connected() {
+            anyhow::bail!("MQTT not connected, cannot confirm delivery to {}", topic);
+        }
+        self.flush_queue()?;
+        let id = self.client.publish(topic, QoS::AtLeastOnce, false, payload)?;
+        let acked = self.acks.expect(id);
+        if acked.recv_timeout(timeout).is_err() {
+            self.acks.forget(id);
+            anyhow::bail!("Broker did not acknowledge message {} to {} within {:?}", id, topic, timeout);
+        }
+        info!("Delivery of message {} to {} confirmed", id, topic);
+        Ok(())
+    }
+
+    /// Publish `value` serialized as JSON.
+    pub fn publish_json<T: Serialize>(&mut self, topic: &str, value: &T) -> Result<()> {
+        let payload = serde_json::to_vec(value)?;
+        self.publish(topic, &payload)
+    }
+
+    fn flush_queue(&mut self) -> Result<()> {
+        let client = &mut self.client;
+        let mut queue = self.queue.lock().unwrap();
+        if queue.is_empty() {
+            return Ok(());
+        }
+        let sent = queue.flush(|message| {
+            client
+                .publish(&message.topic, message.qos, message.retain, &message.payload)
+                .map(|_| ())
+        });
+        match sent {
+            Ok(sent) => {
+                info!("Flushed {} queued MQTT messages", sent);
+                Ok(())
+            }
+            Err(e) => {
+                warn!("Failed to flush queued messages, {} still queued: {:?}", queue.len(), e);
+                Err(e.into())
+            }
+        }
+    }
+
+    /// Subscribe to `topic` at QoS 0, now if connected and again after every reconnect.
     pub fn subscribe(&mut self, topic: &str) -> Result<()> {
-        self.client.subscribe(topic, QoS::AtMostOnce)?;
+        self.subscribe_with(topic, QoS::AtMostOnce)
+    }
+
+    /// Subscribe to `topic`, capping at `qos` the guarantee the broker delivers with (see
+    /// `publish_with`).
+    pub fn subscribe_with(&mut self, topic: &str, qos: QoS) -> Result<()> {
+        match self.subscriptions.iter_mut().find(|(t, _)| t == topic) {
+            Some(subscription) => subscription.1 = qos,
+            None => self.subscriptions.push((topic.to_string(), qos)),
+        }
+        if self.is_connected() {
+            self.client.subscribe(topic, qos)?;
+        }
         Ok(())
     }
+
+    /// Commands received on this device's `COMMAND_TOPIC`. Only the most recently returned receiver is fed.
+    pub fn commands(&mut self) -> Receiver<Command> {
+        let (tx, rx) = mpsc::channel();
+        *self.command_tx.lock().unwrap() = Some(tx);
+        rx
+    }
+
+    /// `(topic, payload)` of messages received on topics added with `subscribe`; commands go to
+    /// `commands` instead. Only the most recently returned receiver is fed. Subscriptions are
+    /// renewed by `service` after every reconnect, so keep calling it.
+    pub fn received(&mut self) -> Receiver<(String, Vec<u8>)> {
+        let (tx, rx) = mpsc::channel();
+        *self.message_tx.lock().unwrap() = Some(tx);
+        rx
+    }
+
+    /// Flag to raise as soon as a `Stop` command arrives (e.g. `Motion::stop_flag`); the command
+    /// is still delivered through `commands` as well.
+    pub fn set_stop_flag(&mut self, flag: Arc<AtomicBool>) {
+        *self.stop_flag.lock().unwrap() = Some(flag);
+    }
+
+    /// Announce presence, renew the subscriptions and flush queued messages after a
+    /// (re)connect. Cheap; call it from the main loop's poll.
+    pub fn service(&mut self) {
+        if !self.session_pending.swap(false, Ordering::SeqCst) {
+            return;
+        }
+        match self.client.publish(&self.status_topic, QoS::AtLeastOnce, true, STATUS_ONLINE) {
+            Ok(_) => info!("Published online status to {}", self.status_topic),
+            Err(e) => {
+                error!("Failed to publish online status: {:?}", e);
+                self.session_pending.store(true, Ordering::SeqCst);
+            }
+        }
+        for (topic, qos) in &self.subscriptions {
+            match self.client.subscribe(topic, *qos) {
+                Ok(_) => info!("Subscribed to {}", topic),
+                Err(e) => {
+                    error!("Failed to subscribe to {}: {:?}", topic, e);
+                    self.session_pending.store(true, Ordering::SeqCst);
+                }
+            }
+        }
+        if self.flush_queue().is_err() {
+            self.session_pending.store(true, Ordering::SeqCst);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accepts_mqtt_schemes() {
+        assert!(check_broker_url(DEFAULT_BROKER_URL).is_ok());
+        assert!(check_broker_url("mqtt://192.168.1.10:1883").is_ok());
+        assert!(check_broker_url("mqttS://mqtt.jantaus.com:9443").is_ok());
+    }
+
+    #[test]
+    fn rejects_other_schemes_and_missing_hosts() {
+        assert!(check_broker_url("https://mqtt.jantaus.com").is_err());
+        assert!(check_broker_url("mqtt.jantaus.com:9443").is_err());
+        assert!(check_broker_url("mqtts://:9443").is_err());
+        assert!(check_broker_url("mqtts://").is_err());
+    }
 }
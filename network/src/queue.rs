@@ -0,0 +1,171 @@
+//! Bounded store-and-forward buffer for messages published while the broker is unreachable.
+
+use embedded_svc::mqtt::client::QoS;
+use std::collections::VecDeque;
+
+// Roughly a night's worth of 5-minute telemetry; older messages are dropped first.
+pub const DEFAULT_QUEUE_CAPACITY: usize = 64;
+
+// A message waiting to be published, with the options it was published with.
+pub struct QueuedMessage {
+    pub topic: String,
+    pub payload: Vec<u8>,
+    pub qos: QoS,
+    pub retain: bool,
+}
+
+pub struct OutboundQueue {
+    messages: VecDeque<QueuedMessage>,
+    capacity: usize,
+    dropped: u64,
+}
+
+impl OutboundQueue {
+    pub fn new(capacity: usize) -> Self {
+        OutboundQueue {
+            messages: VecDeque::with_capacity(capacity),
+            capacity: capacity.max(1),
+            dropped: 0,
+        }
+    }
+
+    /// Queue a message; when full the oldest one is dropped and true is returned.
+    pub fn push(&mut self, topic: &str, payload: &[u8], qos: QoS, retain: bool) -> bool {
+        let overflow = self.messages.len() >= self.capacity;
+        if overflow {
+            self.messages.pop_front();
+            self.dropped += 1;
+        }
+        self.messages.push_back(QueuedMessage {
+            topic: topic.to_string(),
+            payload: payload.to_vec(),
+            qos,
+            retain,
+        });
+        overflow
+    }
+
+    /// Hand the queued messages to `send`, oldest first. A message is only removed once `send`
+    /// accepts it, so on error it and everything after it stay queued for the next flush.
+    pub fn flush<E, F>(&mut self, mut send: F) -> Result<usize, E>
+    where
+        F: FnMut(&QueuedMessage) -> Result<(), E>,
+    {
+        let mut sent = 0;
+        while let Some(message) = self.messages.front() {
+            send(message)?;
+            self.messages.pop_front();
+            sent += 1;
+        }
+        Ok(sent)
+    }
+
+    pub fn len(&self) -> usize {
+        self.messages.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.messages.is_empty()
+    }
+
+    /// Messages lost to overflow since the queue was created.
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+}
+
+impl Default for OutboundQueue {
+    fn default() -> Self {
+        OutboundQueue::new(DEFAULT_QUEUE_CAPACITY)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn drain(queue: &mut OutboundQueue) -> Vec<(String, Vec<u8>)> {
+        let mut sent = Vec::new();
+        queue
+            .flush(|message| {
+                sent.push((message.topic.clone(), message.payload.clone()));
+                Ok::<(), ()>(())
+            })
+            .unwrap();
+        sent
+    }
+
+    fn push(queue: &mut OutboundQueue, topic: &str, payload: &[u8]) -> bool {
+        queue.push(topic, payload, QoS::AtLeastOnce, false)
+    }
+
+    #[test]
+    fn flushes_in_publish_order() {
+        let mut queue = OutboundQueue::new(8);
+        push(&mut queue, "device1A/data", b"one");
+        push(&mut queue, "device1A/tower/status", b"two");
+        push(&mut queue, "device1A/data", b"three");
+
+        let sent = drain(&mut queue);
+        assert_eq!(
+            vec![
+                ("device1A/data".to_string(), b"one".to_vec()),
+                ("device1A/tower/status".to_string(), b"two".to_vec()),
+                ("device1A/data".to_string(), b"three".to_vec()),
+            ],
+            sent
+        );
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn overflow_drops_the_oldest() {
+        let mut queue = OutboundQueue::new(2);
+        assert!(!push(&mut queue, "t", b"1"));
+        assert!(!push(&mut queue, "t", b"2"));
+        assert!(push(&mut queue, "t", b"3"));
+        assert_eq!(1, queue.dropped());
+
+        let payloads: Vec<Vec<u8>> = drain(&mut queue).into_iter().map(|(_, p)| p).collect();
+        assert_eq!(vec![b"2".to_vec(), b"3".to_vec()], payloads);
+    }
+
+    #[test]
+    fn failed_flush_keeps_the_rest_queued() {
+        let mut queue = OutboundQueue::new(8);
+        push(&mut queue, "t", b"1");
+        push(&mut queue, "t", b"2");
+        push(&mut queue, "t", b"3");
+
+        let mut attempts = 0;
+        let result = queue.flush(|_| {
+            attempts += 1;
+            if attempts == 2 {
+                Err("disconnected")
+            } else {
+                Ok(())
+            }
+        });
+        assert_eq!(Err("disconnected"), result);
+        assert_eq!(2, queue.len());
+
+        let payloads: Vec<Vec<u8>> = drain(&mut queue).into_iter().map(|(_, p)| p).collect();
+        assert_eq!(vec![b"2".to_vec(), b"3".to_vec()], payloads);
+    }
+
+    #[test]
+    fn keeps_publish_options() {
+        let mut queue = OutboundQueue::new(8);
+        queue.push("device1A/tower/status", b"online", QoS::AtLeastOnce, true);
+        queue.push("device1A/data", b"{}", QoS::AtMostOnce, false);
+
+        let mut options = Vec::new();
+        queue
+            .flush(|message| {
+                options.push((message.qos, message.retain));
+                Ok::<(), ()>(())
+            })
+            .unwrap();
+        assert_eq!(vec![(QoS::AtLeastOnce, true), (QoS::AtMostOnce, false)], options);
+    }
+}
@@ -0,0 +1,106 @@
+//! Boot self-test report published as JSON to `SELFTEST_TOPIC`.
+
+use serde::Serialize;
+
+/// Relative to the device prefix (see `DeviceId::topic`).
+pub const SELFTEST_TOPIC: &str = "selftest";
+
+/// Outcome of one subsystem check; `detail` says what was wrong.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Check {
+    pub ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+impl Check {
+    pub fn pass() -> Self {
+        Check { ok: true, detail: None }
+    }
+
+    pub fn fail(detail: impl Into<String>) -> Self {
+        Check {
+            ok: false,
+            detail: Some(detail.into()),
+        }
+    }
+}
+
+/// Every subsystem checked at boot, each reported on its own so a failure names the part.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SelfTestReport {
+    pub wifi: Check,
+    pub mqtt: Check,
+    /// The DS3231 answers and holds a plausible time.
+    pub rtc: Check,
+    pub hdc1080: Check,
+    /// Both LDR channels return samples.
+    pub adc: Check,
+    pub limit_switch: Check,
+}
+
+impl SelfTestReport {
+    fn checks(&self) -> [(&'static str, &Check); 6] {
+        [
+            ("wifi", &self.wifi),
+            ("mqtt", &self.mqtt),
+            ("rtc", &self.rtc),
+            ("hdc1080", &self.hdc1080),
+            ("adc", &self.adc),
+            ("limit_switch", &self.limit_switch),
+        ]
+    }
+
+    pub fn passed(&self) -> bool {
+        self.checks().iter().all(|(_, check)| check.ok)
+    }
+
+    /// Names of the failed checks, in report order.
+    pub fn failures(&self) -> Vec<&'static str> {
+        self.checks()
+            .into_iter()
+            .filter(|(_, check)| !check.ok)
+            .map(|(name, _)| name)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn all_passing() -> SelfTestReport {
+        SelfTestReport {
+            wifi: Check::pass(),
+            mqtt: Check::pass(),
+            rtc: Check::pass(),
+            hdc1080: Check::pass(),
+            adc: Check::pass(),
+            limit_switch: Check::pass(),
+        }
+    }
+
+    #[test]
+    fn reports_each_failure_by_name() {
+        let mut report = all_passing();
+        assert!(report.passed());
+        assert!(report.failures().is_empty());
+
+        report.rtc = Check::fail("RTC lost power");
+        report.adc = Check::fail("no samples");
+        assert!(!report.passed());
+        assert_eq!(vec!["rtc", "adc"], report.failures());
+    }
+
+    #[test]
+    fn serializes_details_of_failed_checks_only() {
+        let mut report = all_passing();
+        report.limit_switch = Check::fail("reads are unstable");
+        let json: serde_json::Value = serde_json::to_value(&report).unwrap();
+        assert_eq!(serde_json::json!({ "ok": true }), json["wifi"]);
+        assert_eq!(
+            serde_json::json!({ "ok": false, "detail": "reads are unstable" }),
+            json["limit_switch"]
+        );
+    }
+}
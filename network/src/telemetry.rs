@@ -0,0 +1,104 @@
+//! Tracking telemetry published as JSON to `TELEMETRY_TOPIC`.
+
+use serde::Serialize;
+
+/// Relative to the device prefix (see `DeviceId::topic`).
+pub const TELEMETRY_TOPIC: &str = "data";
+
+/// Where telemetry goes: the broker, an HTTP endpoint (see `http::HttpTelemetry`), or both.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum TelemetryTransport {
+    #[default]
+    Mqtt,
+    Http,
+    Both,
+}
+
+impl TelemetryTransport {
+    pub fn uses_mqtt(&self) -> bool {
+        matches!(self, TelemetryTransport::Mqtt | TelemetryTransport::Both)
+    }
+
+    pub fn uses_http(&self) -> bool {
+        matches!(self, TelemetryTransport::Http | TelemetryTransport::Both)
+    }
+}
+
+/// One tracking-cycle sample. Angles are in degrees; `timestamp` is Unix seconds (UTC).
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Telemetry {
+    pub timestamp: u64,
+    pub tracking_state: &'static str,
+    /// Best estimate of the heading; `heading_source` says whether from the encoder or stepper.
+    pub location: f32,
+    pub angle_offset: f64,
+    pub sun_azimuth: f64,
+    pub encoder_count: i32,
+    pub encoder_degrees: f64,
+    /// Degrees Celsius; `null` if the HDC1080 could not be read.
+    pub temperature_c: Option<f32>,
+    /// Percent relative humidity; `null` if the HDC1080 could not be read.
+    pub humidity: Option<f32>,
+    /// Seconds the RTC was ahead of NTP at the last resync; `null` before the first one.
+    pub rtc_drift_secs: Option<i64>,
+    /// Depression angle below the horizon that counts as sunrise/sunset (`Clock::twilight_deg`).
+    pub twilight_deg: f64,
+    /// Position snapshots written to NVS since boot, to watch flash wear.
+    pub nvs_position_writes: u32,
+    /// Whether the driver is energized between moves, holding the tower against back-drive.
+    pub holding: bool,
+    /// `"encoder"` or `"stepper"`, see `location`.
+    pub heading_source: &'static str,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn transports() {
+        assert!(TelemetryTransport::Mqtt.uses_mqtt() && !TelemetryTransport::Mqtt.uses_http());
+        assert!(!TelemetryTransport::Http.uses_mqtt() && TelemetryTransport::Http.uses_http());
+        assert!(TelemetryTransport::Both.uses_mqtt() && TelemetryTransport::Both.uses_http());
+    }
+
+    #[test]
+    fn serializes_every_field() {
+        let telemetry = Telemetry {
+            timestamp: 1_760_000_000,
+            tracking_state: "L1",
+            location: 120.5,
+            angle_offset: -0.25,
+            sun_azimuth: 120.25,
+            encoder_count: 1234,
+            encoder_degrees: 1.25,
+            temperature_c: Some(21.5),
+            humidity: None,
+            rtc_drift_secs: Some(-1),
+            twilight_deg: 0.83,
+            nvs_position_writes: 3,
+            holding: true,
+            heading_source: "encoder",
+        };
+        let json: serde_json::Value = serde_json::to_value(&telemetry).unwrap();
+        assert_eq!(
+            serde_json::json!({
+                "timestamp": 1_760_000_000u64,
+                "tracking_state": "L1",
+                "location": 120.5,
+                "angle_offset": -0.25,
+                "sun_azimuth": 120.25,
+                "encoder_count": 1234,
+                "encoder_degrees": 1.25,
+                "temperature_c": 21.5,
+                "humidity": null,
+                "rtc_drift_secs": -1,
+                "twilight_deg": 0.83,
+                "nvs_position_writes": 3,
+                "holding": true,
+                "heading_source": "encoder",
+            }),
+            json
+        );
+    }
+}
@@ -4,10 +4,9 @@ use esp_idf_svc::{
     ota::EspOta,
     nvs::*,
 };
-use esp_idf_svc::http::client::{EspHttpConnection, Configuration as HttpConfiguration};
+use esp_idf_svc::http::client::EspHttpConnection;
 use semver::Version;
 use serde_json::Value;
-use esp_idf_svc::sys::esp_crt_bundle_attach;
 use embedded_svc::{
     http::client::{
         Client as HttpClient,
@@ -20,57 +19,533 @@ use esp_idf_svc::io::{
     Write,
 };
 use esp_idf_svc::io::EspIOError;
-use base64::{engine::general_purpose, Engine as _}; // for Basic Auth header
+use esp_idf_svc::sys::{
+    esp_ota_get_next_update_partition, esp_reset_reason, esp_reset_reason_t, esp_reset_reason_t_ESP_RST_BROWNOUT,
+    esp_reset_reason_t_ESP_RST_INT_WDT, esp_reset_reason_t_ESP_RST_PANIC, esp_reset_reason_t_ESP_RST_SW,
+    esp_reset_reason_t_ESP_RST_TASK_WDT, esp_reset_reason_t_ESP_RST_WDT, EspError, ESP_ERR_NOT_FOUND,
+};
+use base64::{engine::general_purpose, Engine as _};
 // use ota::OtaPartition; // hypothetical struct from ota crate
 use anyhow::Result;
 use log::*;
+use network::http::{self, AuthHeader};
 use network::mqtt::Mqtt;
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use std::result::Result::Ok;
 use esp_idf_svc::io::Error; 
 use sha2::{Sha256, Digest};
+use ed25519_dalek::{Signature, VerifyingKey};
+use std::fmt;
+
+/// Result of a successful `run_version_compare`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum OtaOutcome {
+    /// The running firmware is already the latest available.
+    UpToDate,
+    /// A new image was written and validated; the caller must restart to boot into it.
+    Installed(Version),
+    /// OTA is disabled on this device (see `set_ota_disabled`); the server was not contacted.
+    Disabled,
+}
+
+/// Why an update check or install failed. Lets the caller tell a server that is down or
+/// misconfigured (worth retrying later) from a bad or tampered image (worth alerting on).
+#[derive(Debug)]
+pub enum OtaError {
+    /// The server answered with a non-success status.
+    HttpStatus(u16),
+    /// The server could not be reached, or the transfer kept failing, after all retries.
+    NetworkTimeout,
+    /// A request or read failed at the transport level.
+    Network(String),
+    /// metadata.json is not valid JSON, or a field is missing or malformed.
+    MetadataParse(String),
+    /// The downloaded image does not hash to the metadata `sha256`.
+    ChecksumMismatch,
+    /// The image signature is missing, malformed or does not verify.
+    SignatureInvalid(String),
+    /// The configured firmware public key is not a valid Ed25519 key.
+    InvalidPublicKey,
+    /// The metadata `size` is larger than the partition the update would be written to.
+    ImageTooLarge { size: u64, capacity: u64 },
+    /// The server sent a different number of bytes than the metadata `size`.
+    SizeMismatch { expected: u64, written: u64 },
+    /// An ESP-IDF call failed: HTTP client setup, the OTA partitions or NVS.
+    Esp(EspError),
+}
+
+impl fmt::Display for OtaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OtaError::HttpStatus(status) => write!(f, "Non-success HTTP status: {}", status),
+            OtaError::NetworkTimeout => write!(f, "Firmware server unreachable after retries"),
+            OtaError::Network(e) => write!(f, "Network error: {}", e),
+            OtaError::MetadataParse(e) => write!(f, "Invalid firmware metadata: {}", e),
+            OtaError::ChecksumMismatch => write!(f, "SHA256 mismatch"),
+            OtaError::SignatureInvalid(e) => write!(f, "Firmware signature invalid: {}", e),
+            OtaError::InvalidPublicKey => write!(f, "Invalid firmware public key"),
+            OtaError::ImageTooLarge { size, capacity } => {
+                write!(f, "Firmware image of {} bytes does not fit the {} byte update partition", size, capacity)
+            }
+            OtaError::SizeMismatch { expected, written } => {
+                write!(f, "Firmware download was {} bytes, metadata says {}", written, expected)
+            }
+            OtaError::Esp(e) => write!(f, "ESP-IDF error: {}", e),
+        }
+    }
+}
+
+impl OtaError {
+    /// Whether the failure is on the way to the server, so a later check may well succeed.
+    /// The rest means the server, its metadata or the image needs fixing.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            OtaError::NetworkTimeout | OtaError::Network(_) => true,
+            OtaError::HttpStatus(status) => *status >= 500,
+            _ => false,
+        }
+    }
+}
+
+impl std::error::Error for OtaError {}
+
+impl From<EspError> for OtaError {
+    fn from(e: EspError) -> Self {
+        OtaError::Esp(e)
+    }
+}
+
+impl From<EspIOError> for OtaError {
+    fn from(e: EspIOError) -> Self {
+        OtaError::Network(e.to_string())
+    }
+}
+
+// NVS flag (u8, 1 = disabled) that freezes the firmware on devices under validation.
+pub const NVS_KEY_OTA_DISABLED: &str = "ota_disabled";
+
+/// Whether OTA has been disabled on this device. A missing key means OTA is enabled.
+pub fn is_ota_disabled<T: NvsPartitionId>(nvs: &EspNvs<T>) -> bool {
+    match nvs.get_u8(NVS_KEY_OTA_DISABLED) {
+        Ok(Some(flag)) => flag != 0,
+        Ok(None) => false,
+        Err(e) => {
+            warn!("Failed to read {} from NVS, assuming OTA enabled: {:?}", NVS_KEY_OTA_DISABLED, e);
+            false
+        }
+    }
+}
+
+/// Persist the OTA disabled flag.
+pub fn set_ota_disabled<T: NvsPartitionId>(nvs: &mut EspNvs<T>, disabled: bool) -> Result<()> {
+    nvs.set_u8(NVS_KEY_OTA_DISABLED, disabled as u8)?;
+    info!("OTA {} on this device", if disabled { "disabled" } else { "enabled" });
+    Ok(())
+}
+
+// NVS string holding the last flashed firmware version, written by `run_version_compare` after
+// an update and seeded by `load_or_seed_version` on the first boot. Bookkeeping only: the
+// running version is the one the binary was built with.
+pub const NVS_KEY_VERSION: &str = "version";
+
+/// The last flashed firmware version from NVS. On the first boot nothing is stored yet, so
+/// `build_version` is stored and returned instead; later boots keep whatever is stored.
+pub fn load_or_seed_version<T: NvsPartitionId>(nvs: &mut EspNvs<T>, build_version: &Version) -> Result<Version> {
+    let mut buf = [0u8; 32];
+    let stored = nvs.get_str(NVS_KEY_VERSION, &mut buf)?.map(str::to_owned);
+    let (version, seed) = stored_or_seed(stored.as_deref(), build_version)?;
+    if seed {
+        record_flashed_version(nvs, &version)?;
+        info!("No firmware version in NVS, seeded with {}", version);
+    }
+    Ok(version)
+}
+
+/// Store `version` as the last flashed firmware.
+pub fn record_flashed_version<T: NvsPartitionId>(nvs: &mut EspNvs<T>, version: &Version) -> Result<(), EspError> {
+    nvs.set_str(NVS_KEY_VERSION, &version.to_string())
+}
+
+// The version to use and whether it still has to be stored.
+fn stored_or_seed(stored: Option<&str>, build_version: &Version) -> Result<(Version, bool)> {
+    match stored {
+        Some(stored) => Ok((stored.trim().parse()?, false)),
+        None => Ok((build_version.clone(), true)),
+    }
+}
+
+// NVS counter (u8) of boots in a row that crashed before `STABLE_RUN_TIME`, plus the running one.
+pub const NVS_KEY_UNSTABLE_BOOTS: &str = "unstable_boots";
+/// Unstable boots in a row tolerated before the running image is rolled back.
+pub const MAX_UNSTABLE_BOOTS: u8 = 3;
+/// Uptime after which a boot counts as stable and the counter is cleared.
+pub const STABLE_RUN_TIME: Duration = Duration::from_secs(10 * 60);
+
+// NVS flag (u8) set by the panic hook right before it restarts, see `record_panic`.
+pub const NVS_KEY_PANICKED: &str = "panicked";
+
+/// Whether the last boot ended in a crash: a panic, a watchdog or a brownout. Power-on, the
+/// reset button and deliberate restarts (`safe_restart`, a new OTA image) are not crashes.
+/// A Rust panic reboots through `restart()`, a software reset like the deliberate ones, so
+/// it only counts when `panicked` says the panic hook flagged it.
+pub fn is_crash_reset(reason: esp_reset_reason_t, panicked: bool) -> bool {
+    match reason {
+        esp_reset_reason_t_ESP_RST_PANIC
+        | esp_reset_reason_t_ESP_RST_INT_WDT
+        | esp_reset_reason_t_ESP_RST_TASK_WDT
+        | esp_reset_reason_t_ESP_RST_WDT
+        | esp_reset_reason_t_ESP_RST_BROWNOUT => true,
+        esp_reset_reason_t_ESP_RST_SW => panicked,
+        _ => false,
+    }
+}
+
+/// Flag the coming software reset as a crash for `record_boot`. For the panic hook, right
+/// before it restarts.
+pub fn record_panic<T: NvsPartitionId>(nvs: &mut EspNvs<T>) -> Result<(), EspError> {
+    nvs.set_u8(NVS_KEY_PANICKED, 1)
+}
+
+// Read and clear the flag `record_panic` left, so it only ever explains one reset.
+fn take_panic_flag<T: NvsPartitionId>(nvs: &mut EspNvs<T>) -> bool {
+    match nvs.get_u8(NVS_KEY_PANICKED) {
+        Ok(Some(flag)) => {
+            if let Err(e) = nvs.remove(NVS_KEY_PANICKED) {
+                warn!("Failed to clear {} in NVS: {:?}", NVS_KEY_PANICKED, e);
+            }
+            flag != 0
+        }
+        Ok(None) => false,
+        Err(e) => {
+            warn!("Failed to read {} from NVS: {:?}", NVS_KEY_PANICKED, e);
+            false
+        }
+    }
+}
+
+// The running boot counts as unstable; earlier ones only while they kept crashing.
+fn unstable_boots(stored: u8, crash_reset: bool) -> u8 {
+    if crash_reset {
+        stored.saturating_add(1)
+    } else {
+        1
+    }
+}
+
+/// Count this boot as unstable until `clear_unstable_boots`, and return how many boots in a row
+/// have been unstable, this one included. Only a crash reset (see `is_crash_reset`) continues
+/// the run, so power cycles and deliberate restarts never lead to a rollback. Call it early,
+/// before anything that could crash, with the namespace `record_panic` writes to.
+pub fn record_boot<T: NvsPartitionId>(nvs: &mut EspNvs<T>) -> u8 {
+    // SAFETY: no arguments; only reads the reason the ROM recorded.
+    let reason = unsafe { esp_reset_reason() };
+    let crash_reset = is_crash_reset(reason, take_panic_flag(nvs));
+    if crash_reset {
+        warn!("Reset after a crash (reason {})", reason);
+    }
+    let boots = match nvs.get_u8(NVS_KEY_UNSTABLE_BOOTS) {
+        Ok(boots) => unstable_boots(boots.unwrap_or(0), crash_reset),
+        Err(e) => {
+            warn!("Failed to read {} from NVS: {:?}", NVS_KEY_UNSTABLE_BOOTS, e);
+            1
+        }
+    };
+    if let Err(e) = nvs.set_u8(NVS_KEY_UNSTABLE_BOOTS, boots) {
+        warn!("Failed to store {} in NVS: {:?}", NVS_KEY_UNSTABLE_BOOTS, e);
+    }
+    boots
+}
+
+/// Mark the running boot as stable.
+pub fn clear_unstable_boots<T: NvsPartitionId>(nvs: &mut EspNvs<T>) -> Result<()> {
+    nvs.set_u8(NVS_KEY_UNSTABLE_BOOTS, 0)?;
+    Ok(())
+}
+
+// Upper bound for metadata.json; a bigger body is not a metadata document.
+const MAX_METADATA_SIZE: usize = 16 * 1024;
+
+// Read `reader` to EOF. A body can arrive over several reads, so a single `read` is not enough.
+fn read_body<R: Read>(reader: &mut R, max_len: usize) -> Result<Vec<u8>, OtaError> {
+    let mut body = Vec::new();
+    let mut buf = [0u8; 512];
+    loop {
+        let n = match reader.read(&mut buf) {
+            Ok(0) => return Ok(body),
+            Ok(n) => n,
+            Err(e) if e.kind() == esp_idf_svc::io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(OtaError::Network(format!("Read error: {:?}", e.kind()))),
+        };
+        if body.len() + n > max_len {
+            return Err(OtaError::MetadataParse(format!("Body exceeds {} bytes", max_len)));
+        }
+        body.extend_from_slice(&buf[..n]);
+    }
+}
+
+// Where production towers look for new firmware.
+pub const DEFAULT_METADATA_URL: &str = "https://firmware.jantaus.com/firmware/test2/metadata.json";
+
+/// Default `OtaSettings::check_interval`.
+pub const DEFAULT_CHECK_INTERVAL: Duration = Duration::from_secs(2 * 60 * 60);
+
+/// Firmware server a tower checks for updates, the Basic auth credentials for it, and how
+/// often to check after boot (see `OtaScheduler`).
+#[derive(Debug, Clone, PartialEq)]
+pub struct OtaSettings {
+    pub metadata_url: String,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub check_interval: Duration,
+    /// When set, only images signed with this Ed25519 key are installed.
+    pub public_key: Option<[u8; 32]>,
+}
+
+impl Default for OtaSettings {
+    fn default() -> Self {
+        OtaSettings {
+            metadata_url: DEFAULT_METADATA_URL.to_string(),
+            username: None,
+            password: None,
+            check_interval: DEFAULT_CHECK_INTERVAL,
+            public_key: None,
+        }
+    }
+}
+
+/// Parse a firmware public key written as 64 hex digits, checking it is a usable Ed25519 key.
+pub fn parse_public_key(hex_key: &str) -> Result<[u8; 32], OtaError> {
+    let bytes: [u8; 32] = hex::decode(hex_key.trim())
+        .ok()
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or(OtaError::InvalidPublicKey)?;
+    VerifyingKey::from_bytes(&bytes).map_err(|_| OtaError::InvalidPublicKey)?;
+    Ok(bytes)
+}
+
+/// When the next `run_version_compare` is due: every `interval`, day or night. The caller
+/// consults `is_due` each cycle and calls `mark_checked` once the server was asked.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OtaScheduler {
+    interval: Duration,
+    last_check: Option<Instant>,
+}
+
+impl OtaScheduler {
+    /// Due straight away, until the first `mark_checked`.
+    pub fn new(interval: Duration) -> Self {
+        OtaScheduler {
+            interval,
+            last_check: None,
+        }
+    }
+
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    pub fn is_due(&self, now: Instant) -> bool {
+        self.time_until_due(now).is_zero()
+    }
+
+    /// Zero once the check is due.
+    pub fn time_until_due(&self, now: Instant) -> Duration {
+        self.last_check
+            .map_or(Duration::ZERO, |last| self.interval.saturating_sub(now.saturating_duration_since(last)))
+    }
+
+    pub fn mark_checked(&mut self, now: Instant) {
+        self.last_check = Some(now);
+    }
+}
+
+// Size in bytes of the partition the next update is written to.
+fn update_partition_size() -> Result<u64, OtaError> {
+    // SAFETY: null means the slot after the running one; the result points into the partition
+    // table, which lives for the whole program.
+    let partition = unsafe { esp_ota_get_next_update_partition(std::ptr::null()) };
+    if partition.is_null() {
+        return Err(OtaError::Esp(EspError::from_infallible::<ESP_ERR_NOT_FOUND>()));
+    }
+    Ok(unsafe { (*partition).size } as u64)
+}
+
+// Refuse an image the update partition can't hold before any of it is downloaded.
+fn check_image_fits(size: u64, capacity: u64) -> Result<(), OtaError> {
+    if size > capacity {
+        return Err(OtaError::ImageTooLarge { size, capacity });
+    }
+    Ok(())
+}
+
+// The image is complete only if exactly `expected` bytes were written.
+fn check_written(expected: u64, written: u64) -> Result<(), OtaError> {
+    if written != expected {
+        return Err(OtaError::SizeMismatch { expected, written });
+    }
+    Ok(())
+}
+
+/// Check the metadata `signature` (base64 Ed25519 over the SHA-256 digest of the image).
+///
+/// Without a configured key there is nothing to check. With one, a missing or bad signature
+/// is an error: an attacker able to edit metadata.json could otherwise just drop the field.
+fn verify_signature(public_key: Option<&VerifyingKey>, signature: Option<&str>, digest: &[u8]) -> Result<(), OtaError> {
+    let Some(key) = public_key else {
+        return Ok(());
+    };
+    let signature = signature.ok_or_else(|| OtaError::SignatureInvalid("missing 'signature' field".into()))?;
+    let bytes = general_purpose::STANDARD
+        .decode(signature.trim())
+        .map_err(|e| OtaError::SignatureInvalid(format!("not valid base64: {e}")))?;
+    let signature = Signature::from_slice(&bytes)
+        .map_err(|e| OtaError::SignatureInvalid(format!("not an Ed25519 signature: {e}")))?;
+    key.verify_strict(digest, &signature)
+        .map_err(|e| OtaError::SignatureInvalid(e.to_string()))
+}
+
+/// Fields of metadata.json describing the latest image.
+#[derive(Debug, Clone, PartialEq)]
+struct FirmwareMetadata {
+    version: Version,
+    size: u64,
+    download_url: String,
+    sha256: String,
+    signature: Option<String>,
+}
+
+// Extract and check the metadata fields; `signed` makes `signature` mandatory.
+fn parse_metadata(json: &Value, signed: bool) -> Result<FirmwareMetadata, OtaError> {
+    let field = |name: &str| {
+        json.get(name)
+            .and_then(|v| v.as_str())
+            .map(str::trim)
+            .ok_or_else(|| OtaError::MetadataParse(format!("Missing or invalid '{}' field", name)))
+    };
+
+    let version: Version = field("version")?
+        .parse()
+        .map_err(|e| OtaError::MetadataParse(format!("'version': {e}")))?;
+
+    let size = json
+        .get("size")
+        .and_then(|s| s.as_u64())
+        .ok_or_else(|| OtaError::MetadataParse("Missing or invalid 'size' field".into()))?;
+    if size == 0 {
+        return Err(OtaError::MetadataParse("'size' field is zero".into()));
+    }
+
+    let download_url = field("download_url")?.to_string();
+    if download_url.is_empty() {
+        return Err(OtaError::MetadataParse("'download_url' field is empty".into()));
+    }
+
+    // 64 hex chars → 32 bytes
+    let sha256 = field("sha256")?.to_string();
+    if sha256.len() != 64 {
+        return Err(OtaError::MetadataParse("'sha256' must be exactly 64 hex characters".into()));
+    }
+    if hex::decode(&sha256).is_err() {
+        return Err(OtaError::MetadataParse("'sha256' is not valid hex".into()));
+    }
+
+    // Optional Ed25519 signature; mandatory once a public key is configured
+    let signature = json
+        .get("signature")
+        .and_then(|s| s.as_str())
+        .map(|s| s.trim().to_string());
+    if signed && signature.is_none() {
+        return Err(OtaError::SignatureInvalid("missing 'signature' field".into()));
+    }
+
+    Ok(FirmwareMetadata { version, size, download_url, sha256, signature })
+}
+
+// Range re-requests of the image after transient read errors, per download
+const MAX_RESUMES: u32 = 5;
+const RESUME_DELAY: Duration = Duration::from_secs(3);
+
+// Download progress goes to <device>/firmware/progress every PROGRESS_STEP_PERCENT, or after
+// PROGRESS_INTERVAL on a slow link
+const PROGRESS_STEP_PERCENT: f64 = 5.0;
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(5);
+
+fn progress_json(current: &Version, target: &Version, written: u64, total: u64) -> String {
+    let percent = if total == 0 { 0.0 } else { written as f64 / total as f64 * 100.0 };
+    format!(
+        "{{\"current_version\":\"{}\",\"target_version\":\"{}\",\"bytes_written\":{},\"bytes_total\":{},\"percent\":{:.1}}}",
+        current, target, written, total, percent
+    )
+}
+
+fn publish_progress(mqtt: &mut Mqtt, current: &Version, target: &Version, written: u64, total: u64) {
+    let payload = progress_json(current, target, written, total);
+    if let Err(e) = mqtt.publish(&mqtt.topic("firmware/progress"), payload.as_bytes()) {
+        warn!("Failed to publish OTA progress: {:?}", e);
+    }
+}
 
 pub struct OtaUpdater<'a> {
     current_version: Version, 
     mqtt_client: &'a mut Mqtt,
     client: HttpClient<EspHttpConnection>,
-    username: Option<String>, 
-    password: Option<String>, 
+    auth_header: AuthHeader,
     default_headers: Vec<(&'static str, &'static str)>,
+    // When set, every image must carry a valid signature from this key.
+    public_key: Option<VerifyingKey>,
+    metadata_url: String,
 }
 
 impl<'a> OtaUpdater<'a> {
-    pub fn new_ota(current_version: Version, mqtt_client: &'a mut Mqtt, username: Option<&str>, password: Option<&str> ) -> Result<Self> {
-        let config = EspHttpConnection::new(&HttpConfiguration {
-            buffer_size: Some(1024),
-            timeout: Some(Duration::from_secs(60)),
-            crt_bundle_attach: Some(esp_crt_bundle_attach),
-            use_global_ca_store: true, 
-            ..Default::default()
-        })?;
-
-        let client = HttpClient::wrap(config);
+    pub fn new_ota(current_version: Version, mqtt_client: &'a mut Mqtt, username: Option<&str>, password: Option<&str> ) -> Result<Self, OtaError> {
+        let client = http::new_client(Duration::from_secs(60))?;
 
         Ok( Self { 
             current_version, 
             mqtt_client, 
             client,
-            username: username.map(|s| s.to_string()),
-            password: password.map(|s| s.to_string()),
+            auth_header: AuthHeader::new(username, password),
             default_headers: vec![("User-Agent", "ESP32-Rust-Client/1.0")],
+            public_key: None,
+            metadata_url: DEFAULT_METADATA_URL.to_string(),
         })
     }
 
-    // Build authorization header if username/password provided
-    fn build_auth_header(&self) -> Option<(String, String)> {
-        if let (Some(u), Some(p)) = (&self.username, &self.password) {
-            let credentials = format!("{}:{}", u, p);
-            let encoded = general_purpose::STANDARD.encode(credentials.as_bytes());
-            Some(("authorization".into(), format!("Basic {}", encoded)))
-        } else {
-            None
-        }
+    /// `new_ota` with the server, credentials and public key taken from `settings`.
+    pub fn from_settings(current_version: Version, mqtt_client: &'a mut Mqtt, settings: &OtaSettings) -> Result<Self, OtaError> {
+        let username = settings.username.as_deref();
+        let password = settings.password.as_deref();
+        let updater = match &settings.public_key {
+            Some(key) => Self::with_public_key(current_version, mqtt_client, username, password, key)?,
+            None => Self::new_ota(current_version, mqtt_client, username, password)?,
+        };
+        Ok(updater.with_metadata_url(&settings.metadata_url))
+    }
+
+    /// Check `url` instead of `DEFAULT_METADATA_URL` (e.g. a staging server).
+    pub fn with_metadata_url(mut self, url: &str) -> Self {
+        self.metadata_url = url.to_string();
+        self
+    }
+
+    pub fn metadata_url(&self) -> &str {
+        &self.metadata_url
+    }
+
+    /// Like `new_ota`, but only installs images signed with `public_key` (raw 32-byte
+    /// Ed25519 key, normally compiled into the firmware).
+    pub fn with_public_key(
+        current_version: Version,
+        mqtt_client: &'a mut Mqtt,
+        username: Option<&str>,
+        password: Option<&str>,
+        public_key: &[u8; 32],
+    ) -> Result<Self, OtaError> {
+        let key = VerifyingKey::from_bytes(public_key).map_err(|_| OtaError::InvalidPublicKey)?;
+        let mut updater = Self::new_ota(current_version, mqtt_client, username, password)?;
+        updater.public_key = Some(key);
+        Ok(updater)
     }
 
     // Creates a new http client
@@ -86,125 +561,78 @@ impl<'a> OtaUpdater<'a> {
     }  */
 
     // Function for requesting the version text file from the server
-    fn get_remote_version(& mut self, url: &str) -> Result<Value> {
-        const MAX_RETRIES: usize = 3; 
-        const RETRY_DELAY: Duration = Duration::from_secs(2); 
-
-        for attempt in 1..=MAX_RETRIES { 
-            info!("Attempt {} to fetch remote version...", attempt);            
-            
-            // Recreate the HTTP client for each attempt
-            let mut headers = vec![("accept", "application/json")];
-            if let Some((key, value)) = self.build_auth_header() {
-                // Leak strings into static refs (safe in embedded static context)
-                headers.push((
-                    Box::leak(key.into_boxed_str()),
-                    Box::leak(value.into_boxed_str()),
-                ));
-            }
-
-            // Build GET request using existing client
-            let request = match self.client.request(Method::Get, url, &headers) {
-                Ok(r) => r,
-                Err(e) => {
-                    warn!("Failed to build GET request: {:?}", e);
-                    thread::sleep(RETRY_DELAY);
-                    continue;
-                }
-            };
-
-            // Create a new request
-            match request.submit(){
-                Ok(mut response) => {
-                    let status = response.status();
-                    info!("HTTP status: {}", status);
-                    if !(200..300).contains(&status) {
-                        warn!("Non-success HTTP status: {}", status);
-                        thread::sleep(RETRY_DELAY);
-                        continue;
-                    }
-
-                    // Read response in a loop (streaming)
-                    let mut buf = [0u8; 512];
-                    let bytes_read = match response.read(&mut buf) {
-                        Ok(n) => n,
-                        Err(e) => {
-                            warn!("Failed reading body: {:?}", e);
-                            thread::sleep(RETRY_DELAY);
-                            continue;
-                        }
-                    };
-                    info!("Read {} bytes", bytes_read);
+    fn get_remote_version(& mut self, url: &str) -> Result<Value, OtaError> {
+        const MAX_ATTEMPTS: u32 = 3;
+        const RETRY_DELAY: Duration = Duration::from_secs(2);
 
-                    let body_str = std::str::from_utf8(&buf[..bytes_read])
-                        .map_err(|e| anyhow::anyhow!("UTF-8 decode error: {e}"))?;
+        // Transport failures and error statuses are retried; a body that isn't metadata is not
+        let body = util::retry::with_backoff(MAX_ATTEMPTS, RETRY_DELAY, |attempt| {
+            info!("Attempt {} to fetch remote version...", attempt);
 
-                    let json: Value = serde_json::from_str(body_str)
-                        .map_err(|e| anyhow::anyhow!("JSON parse error: {e}"))?;
+            let mut headers = vec![("accept", "application/json")];
+            // Borrows the header built in `new_ota`; nothing is allocated per request
+            headers.extend(self.auth_header.header());
 
-                    return Ok(json);
-                }
-                Err(e) => {
-                    warn!("Request failed: {:?}", e);
-                    thread::sleep(RETRY_DELAY);
-                }
+            let request = self.client.request(Method::Get, url, &headers).map_err(|e| {
+                warn!("Failed to build GET request: {:?}", e);
+                OtaError::NetworkTimeout
+            })?;
+            let mut response = request.submit().map_err(|e| {
+                warn!("Request failed: {:?}", e);
+                OtaError::NetworkTimeout
+            })?;
+            let status = response.status();
+            info!("HTTP status: {}", status);
+            if !(200..300).contains(&status) {
+                warn!("Non-success HTTP status: {}", status);
+                // A server that keeps answering with an error status is reported as such
+                return Err(OtaError::HttpStatus(status));
             }
-        }
-        return Err(anyhow::anyhow!("Failed to fetch remote version after {} attempts", MAX_RETRIES))
-    }
 
-    pub fn run_version_compare<T: NvsPartitionId>(&mut self, nvs: &mut EspNvs<T>) -> Result<()> {
-
-        // Retrieve remote version
-        let remote_json = self.get_remote_version("https://firmware.jantaus.com/firmware/test2/metadata.json")?;
+            // Read response in a loop (streaming)
+            read_body(&mut response, MAX_METADATA_SIZE).inspect_err(|e| warn!("Failed reading body: {:?}", e))
+        })
+        .inspect_err(|_| warn!("Failed to fetch remote version after {} attempts", MAX_ATTEMPTS))?;
+        info!("Read {} bytes", body.len());
 
-        // Extact the "version" field from JSON and verify its not empty
-        let remote_version: Version = remote_json
-            .get("version")
-            .and_then(|v| v.as_str())
-            .ok_or_else(|| anyhow::anyhow!("Missing or invalid 'version' field in remote JSON"))?
-            .trim()
-            .parse()?;
-
-        // Extact the "size" field from JSON and verify its not empty
-        let remote_size = remote_json
-            .get("size")
-            .and_then(|s| s.as_u64())
-            .ok_or_else(|| anyhow::anyhow!("Missing or invalid 'size' field in remote JSON"))?;
-
-        if remote_size == 0 {
-            return Err(anyhow::anyhow!("'size' field is zero"));
-        }
+        let body_str = std::str::from_utf8(&body)
+            .map_err(|e| OtaError::MetadataParse(format!("UTF-8 decode error: {e}")))?;
 
-        // Extract download_url
-        let remote_url = remote_json
-            .get("download_url")
-            .and_then(|u| u.as_str())
-            .ok_or_else(|| anyhow::anyhow!("Missing or invalid 'download_url' field in remote JSON"))?
-            .trim()
-            .to_string();
+        serde_json::from_str(body_str).map_err(|e| OtaError::MetadataParse(format!("JSON parse error: {e}")))
+    }
 
-        if remote_url.is_empty() {
-            return Err(anyhow::anyhow!("'download_url' field is empty"));
+    // Status updates are informational: failing to queue one doesn't fail the update.
+    fn publish_status(&mut self, status: &[u8]) {
+        if let Err(e) = self.mqtt_client.publish_retained(&self.mqtt_client.topic("firmware/status"), status) {
+            warn!("Failed to publish OTA status: {:?}", e);
         }
+    }
 
-        // Extract sha256 and validate sha256 length (must be 64 hex chars → 32 bytes)
-        let remote_sha256 = remote_json
-            .get("sha256")
-            .and_then(|h| h.as_str())
-            .ok_or_else(|| anyhow::anyhow!("Missing or invalid 'sha256' field in remote JSON"))?
-            .trim()
-            .to_string();
+    /// Check the server for a newer firmware and install it if found.
+    ///
+    /// This never reboots by itself: on `OtaOutcome::Installed` the caller is expected to
+    /// put the tower in a safe state and restart. A failed check or download is returned as
+    /// an `OtaError`; the running firmware is left untouched either way.
+    pub fn run_version_compare<T: NvsPartitionId>(&mut self, nvs: &mut EspNvs<T>) -> Result<OtaOutcome, OtaError> {
 
-        if remote_sha256.len() != 64 {
-            return Err(anyhow::anyhow!("'sha256' must be exactly 64 hex characters"));
+        // Devices under validation must never change firmware on their own
+        if is_ota_disabled(nvs) {
+            info!("OTA disabled on this device, skipping version check");
+            self.publish_status(b"OTA disabled on this device");
+            return Ok(OtaOutcome::Disabled);
         }
 
-        if hex::decode(&remote_sha256).is_err() {
-            return Err(anyhow::anyhow!("'sha256' is not valid hex"));
-        }
+        // Retrieve remote version
+        let metadata_url = self.metadata_url.clone();
+        let remote_json = self.get_remote_version(&metadata_url)?;
 
-        // TODO: Verify digital signature if present (strongly recommended!)
+        let FirmwareMetadata {
+            version: remote_version,
+            size: remote_size,
+            download_url: remote_url,
+            sha256: remote_sha256,
+            signature: remote_signature,
+        } = parse_metadata(&remote_json, self.public_key.is_some())?;
 
         info!("Here is the current remote version: {remote_version}");
         info!("Here is the current firmware version: {}", self.current_version);
@@ -219,51 +647,52 @@ impl<'a> OtaUpdater<'a> {
             // Run firmware update
             info!("Waiting 5 seconds before running firmware download...");
             thread::sleep(Duration::from_secs(5)); 
-            let flash_download = self.run_update(remote_url, remote_version.clone(), remote_sha256, remote_size);
+            let flash_download = self.run_update(remote_url, remote_version.clone(), remote_sha256, remote_signature, remote_size);
 
             match flash_download {
                 Ok(_) => {
                     // Saving new version to NVS
                     info!("Saving new version to nvs!");
-                    nvs.set_str("version", &remote_version.to_string())?; 
+                    record_flashed_version(nvs, &remote_version)?;
                     nvs.set_u8("first_boot", 1)?; 
 
-                    self.mqtt_client.publish("device1A/firmware/status", b"OTA firmware downloaded, preparing esp restart!")?;
+                    self.publish_status(b"OTA firmware downloaded, preparing esp restart!");
 
-                    // Reboot into new firmware
-                    info!("Reebooting firmware in 3 seconds...");
-                    thread::sleep(Duration::from_secs(3));
-                    esp_idf_svc::hal::reset::restart();                     
+                    // Rebooting is left to the caller so it can de-energize the tower first
+                    return Ok(OtaOutcome::Installed(remote_version));
                 }
                 Err(e) => {
-                    info!("Firmware download failed: {:?}", e);
-                    self.mqtt_client.publish("device1A/firmware/status", b"OTA update failed!")?; 
+                    info!("Firmware download failed: {}", e);
+                    self.publish_status(b"OTA update failed!");
+                    return Err(e);
                 }
             }
         }
         else {
             info!("Firmware already up to date: {}", self.current_version );
         }
-        Ok(())
+        Ok(OtaOutcome::UpToDate)
     }
 
     // Function for downloading the binary file
-    fn run_update(&mut self, remote_url: String, remote_version: Version, remote_sha256: String, remote_size: u64) -> Result<()> {
+    fn run_update(&mut self, remote_url: String, remote_version: Version, remote_sha256: String, remote_signature: Option<String>, remote_size: u64) -> Result<(), OtaError> {
         info!("Attempting to download and installing new version {}", remote_version);
 
+        let capacity = update_partition_size()?;
+        check_image_fits(remote_size, capacity)?;
+        info!("Image of {} bytes fits the {} byte update partition", remote_size, capacity);
+
         //let mut response = self.get_firmware(&remote_url)?;
         // Stream firmware directly using existing client
-        let mut headers = vec![("accept", "application/octet-stream")];
-        if let Some((key, value)) = self.build_auth_header() {
-            headers.push((Box::leak(key.into_boxed_str()), Box::leak(value.into_boxed_str())));
-        }
+        let mut base_headers = vec![("accept", "application/octet-stream")];
+        base_headers.extend(self.auth_header.header());
 
-        let request = self.client.request(Method::Get, &remote_url, &headers)?;
+        let request = self.client.request(Method::Get, &remote_url, &base_headers)?;
         let mut response = request.submit()?;
         let status = response.status();
         info!("HTTP status: {}", status);
         if !(200..300).contains(&status) {
-            return Err(anyhow::anyhow!("Non-success HTTP status: {}", status));
+            return Err(OtaError::HttpStatus(status));
         }
         
         // Gets an instance of OTA
@@ -286,55 +715,157 @@ impl<'a> OtaUpdater<'a> {
         // Read and write chunks to flash
         let mut buf = [0u8; 4096]; 
         
-        // Setting progress variable
-        let mut progress: f64 = 0.0;
-        
+        // Bytes of the image written to flash (and fed to the hasher) so far
+        let mut written: u64 = 0;
+        // Bytes of the current response body to drop before writing (see below)
+        let mut skip: u64 = 0;
+        let mut resumes: u32 = 0;
+
+        // Last progress report sent over MQTT
+        let mut reported_progress: f64 = 0.0;
+        let mut reported_at = Instant::now();
+        publish_progress(self.mqtt_client, &self.current_version, &remote_version, 0, remote_size);
+
+        // Set when the current response failed and the rest of the image must be re-requested
+        let mut interrupted: Option<EspIOError> = None;
+
         loop {
+            if let Some(e) = interrupted.take() {
+                // Transient error: ask for the rest of the image and keep feeding the same
+                // update handle and hasher
+                if resumes >= MAX_RESUMES {
+                    if let Some(u) = update.take() {
+                        u.abort()?;
+                    }
+                    warn!("Firmware download failed after {} resumes: {:?}", resumes, e);
+                    return Err(OtaError::NetworkTimeout);
+                }
+                resumes += 1;
+                warn!("Download interrupted after {} bytes: {:?}, resuming ({}/{})", written, e, resumes, MAX_RESUMES);
+                thread::sleep(RESUME_DELAY);
+
+                let range = format!("bytes={}-", written);
+                let mut headers: Vec<(&str, &str)> = base_headers.clone();
+                headers.push(("range", range.as_str()));
+                response = match self.client.request(Method::Get, &remote_url, &headers).and_then(|r| r.submit()) {
+                    Ok(r) => r,
+                    Err(e) => {
+                        interrupted = Some(e);
+                        continue;
+                    }
+                };
+
+                match response.status() {
+                    206 => {
+                        info!("Resuming download at byte {}", written);
+                        skip = 0;
+                    }
+                    // The server ignored Range and sends the whole image again: restart the
+                    // transfer from byte 0, skipping what is already written
+                    200 => {
+                        warn!("Server ignored Range request, restarting download from the beginning");
+                        skip = written;
+                    }
+                    status => {
+                        if let Some(u) = update.take() {
+                            u.abort()?;
+                        }
+                        warn!("Non-success HTTP status on resume: {}", status);
+                        return Err(OtaError::HttpStatus(status));
+                    }
+                }
+            }
+
             // Read from the ESP-IDF specific reader
             let bytes_read = match response.read(&mut buf) {
                 Ok(0) => break, // Reached the end of the response body
                 Ok(n) => n,
                 Err(e) if e.kind() == esp_idf_svc::io::ErrorKind::Interrupted => continue,
-                Err(e) => return Err(e.into()), // Propagate the error
-            }; 
-            info!("Writing {} bytes to flash", bytes_read);
+                Err(e) => {
+                    interrupted = Some(e);
+                    continue;
+                }
+            };
+
+            // Part of the image that is already on flash
+            let dropped = skip.min(bytes_read as u64) as usize;
+            skip -= dropped as u64;
+            let chunk = &buf[dropped..bytes_read];
+            if chunk.is_empty() {
+                continue;
+            }
+            // More than the metadata promised: stop before it runs off the partition
+            if written + chunk.len() as u64 > remote_size {
+                if let Some(u) = update.take() {
+                    u.abort()?;
+                }
+                return Err(OtaError::SizeMismatch {
+                    expected: remote_size,
+                    written: written + chunk.len() as u64,
+                });
+            }
+            info!("Writing {} bytes to flash", chunk.len());
 
             // Write chunk to OTA partition
             if let Some(u) = update.as_mut() {
-                u.write(&buf[..bytes_read])?; // <-- use as_mut() and unwrap Option
+                u.write(chunk)?; // <-- use as_mut() and unwrap Option
             }
 
             //update.write(&buf[..bytes_read])?;            GPT SUGGEST1
 
             // Update SHA256
-            hasher.update(&buf[..bytes_read]);
+            hasher.update(chunk);
+            written += chunk.len() as u64;
 
             // Progress info
-            progress += (bytes_read as f64/remote_size as f64) * 100.0;
+            let progress = written as f64 / remote_size as f64 * 100.0;
             info!("Progress: {:.2}%", progress);
-
+            if progress - reported_progress >= PROGRESS_STEP_PERCENT || reported_at.elapsed() >= PROGRESS_INTERVAL {
+                publish_progress(self.mqtt_client, &self.current_version, &remote_version, written, remote_size);
+                reported_progress = progress;
+                reported_at = Instant::now();
+            }
         };
-        info!("OTA update written, verifying checksum…");
+
+        info!("OTA update written ({} bytes, {} resumes), verifying checksum…", written, resumes);
+        publish_progress(self.mqtt_client, &self.current_version, &remote_version, written, remote_size);
+
+        // A body cut short would otherwise only show up as a checksum mismatch
+        if let Err(e) = check_written(remote_size, written) {
+            error!("{}, aborting update", e);
+            if let Some(u) = update.take() {
+                u.abort()?;
+            }
+            return Err(e);
+        }
 
         // Finalize hash and compare with expected
         let calculated_sha = hasher.finalize().to_vec();
 
         // Convert the hex string from manifest into raw bytes
         let expected_sha = hex::decode(&remote_sha256)
-            .map_err(|_| anyhow::anyhow!("Invalid SHA256 hex string in manifest"))?;
+            .map_err(|_| OtaError::MetadataParse("Invalid SHA256 hex string in manifest".into()))?;
 
         if calculated_sha != expected_sha {
 
             if let Some(u) = update.take() {
                 u.abort()?; // explicitly end OTA
             }
-            return Err(anyhow::anyhow!("SHA256 mismatch"));
+            return Err(OtaError::ChecksumMismatch);
 
             /* error!("SHA256 mismatch, aborting update");
             update.abort()?; // discard bad image               GPT SUGGEST1
             return Err(anyhow::anyhow!("SHA256 mismatch")); */
         }
 
+        if let Err(e) = verify_signature(self.public_key.as_ref(), remote_signature.as_deref(), &calculated_sha) {
+            error!("{}, aborting update", e);
+            if let Some(u) = update.take() {
+                u.abort()?; // discard unsigned image
+            }
+            return Err(e);
+        }
+
         info!("Firmware checksum validated successfully & OTA complete, rebooting...");
 
         // Finish writing OTA image
@@ -349,4 +880,239 @@ impl<'a> OtaUpdater<'a> {
 }
 
 /* info!("Starting http run...");
-    let mut client = Box::new(HttpsClient::new_https(Some("device1A"), Some("device1A"))?); */
\ No newline at end of file
+    let mut client = Box::new(HttpsClient::new_https(Some("device1A"), Some("device1A"))?); */
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use esp_idf_svc::io::{ErrorKind, ErrorType};
+
+    // Hands out `data` at most `chunk` bytes per read, like a body split over TCP segments.
+    struct ChunkedReader {
+        data: Vec<u8>,
+        pos: usize,
+        chunk: usize,
+        fail_at: Option<usize>,
+    }
+
+    impl ErrorType for ChunkedReader {
+        type Error = ErrorKind;
+    }
+
+    impl Read for ChunkedReader {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+            if self.fail_at == Some(self.pos) {
+                return Err(ErrorKind::Other);
+            }
+            let n = self.chunk.min(buf.len()).min(self.data.len() - self.pos);
+            buf[..n].copy_from_slice(&self.data[self.pos..self.pos + n]);
+            self.pos += n;
+            Ok(n)
+        }
+    }
+
+    fn metadata(padding: usize) -> String {
+        format!(
+            "{{\"version\":\"1.2.3\",\"size\":1024,\"notes\":\"{}\"}}",
+            "x".repeat(padding)
+        )
+    }
+
+    #[test]
+    fn only_crashes_continue_the_unstable_run() {
+        assert_eq!(3, unstable_boots(2, true));
+        assert_eq!(1, unstable_boots(2, false));
+        assert_eq!(u8::MAX, unstable_boots(u8::MAX, true));
+        assert!(is_crash_reset(esp_reset_reason_t_ESP_RST_TASK_WDT, false));
+        assert!(!is_crash_reset(esp_idf_svc::sys::esp_reset_reason_t_ESP_RST_POWERON, false));
+        assert!(!is_crash_reset(esp_reset_reason_t_ESP_RST_SW, false));
+    }
+
+    #[test]
+    fn panic_restarts_count_as_crashes() {
+        // The panic hook flags NVS, then reboots through restart(), a software reset
+        let mut boots = 1;
+        for _ in 0..MAX_UNSTABLE_BOOTS {
+            boots = unstable_boots(boots, is_crash_reset(esp_reset_reason_t_ESP_RST_SW, true));
+        }
+        assert!(boots > MAX_UNSTABLE_BOOTS);
+        // The flag is only set on the panic path, so a power-on still starts afresh
+        assert!(!is_crash_reset(esp_idf_svc::sys::esp_reset_reason_t_ESP_RST_POWERON, true));
+    }
+
+    #[test]
+    fn assembles_body_from_small_chunks() {
+        let doc = metadata(2000);
+        let mut reader = ChunkedReader { data: doc.clone().into_bytes(), pos: 0, chunk: 7, fail_at: None };
+
+        let body = read_body(&mut reader, MAX_METADATA_SIZE).unwrap();
+        assert_eq!(body, doc.as_bytes());
+
+        let json: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(json["version"], "1.2.3");
+        assert_eq!(json["notes"].as_str().unwrap().len(), 2000);
+    }
+
+    #[test]
+    fn rejects_oversized_body() {
+        let mut reader = ChunkedReader { data: vec![b'x'; 100], pos: 0, chunk: 30, fail_at: None };
+        assert!(read_body(&mut reader, 64).is_err());
+    }
+
+    #[test]
+    fn propagates_read_errors() {
+        let mut reader = ChunkedReader { data: metadata(0).into_bytes(), pos: 0, chunk: 10, fail_at: Some(20) };
+        assert!(read_body(&mut reader, MAX_METADATA_SIZE).is_err());
+    }
+
+    fn signed(digest: &[u8]) -> (VerifyingKey, String) {
+        use ed25519_dalek::{Signer, SigningKey};
+        let signing_key = SigningKey::from_bytes(&[7u8; 32]);
+        let signature = signing_key.sign(digest);
+        (signing_key.verifying_key(), general_purpose::STANDARD.encode(signature.to_bytes()))
+    }
+
+    #[test]
+    fn accepts_valid_signature() {
+        let digest = Sha256::digest(b"firmware image").to_vec();
+        let (key, signature) = signed(&digest);
+        assert!(verify_signature(Some(&key), Some(&signature), &digest).is_ok());
+    }
+
+    #[test]
+    fn rejects_signature_over_other_image() {
+        let digest = Sha256::digest(b"firmware image").to_vec();
+        let (key, signature) = signed(&digest);
+        let tampered = Sha256::digest(b"tampered image").to_vec();
+        assert!(matches!(
+            verify_signature(Some(&key), Some(&signature), &tampered),
+            Err(OtaError::SignatureInvalid(_))
+        ));
+    }
+
+    #[test]
+    fn rejects_malformed_signature() {
+        let digest = Sha256::digest(b"firmware image").to_vec();
+        let (key, _) = signed(&digest);
+        assert!(verify_signature(Some(&key), Some("not base64!"), &digest).is_err());
+        assert!(verify_signature(Some(&key), Some("AAAA"), &digest).is_err());
+    }
+
+    #[test]
+    fn missing_signature_requires_no_key() {
+        let digest = Sha256::digest(b"firmware image").to_vec();
+        let (key, _) = signed(&digest);
+        assert!(verify_signature(Some(&key), None, &digest).is_err());
+        assert!(verify_signature(None, None, &digest).is_ok());
+    }
+
+    #[test]
+    fn parses_hex_public_key() {
+        let (key, _) = signed(b"");
+        let hex_key = hex::encode(key.to_bytes());
+        assert_eq!(key.to_bytes(), parse_public_key(&hex_key).unwrap());
+        assert!(matches!(parse_public_key(&hex_key[..62]), Err(OtaError::InvalidPublicKey)));
+        assert!(matches!(parse_public_key(&"zz".repeat(32)), Err(OtaError::InvalidPublicKey)));
+    }
+
+    fn firmware_json() -> Value {
+        serde_json::json!({
+            "version": " 1.1.0 ",
+            "size": 2048,
+            "download_url": "https://firmware.example.com/tower.bin",
+            "sha256": "ab".repeat(32),
+        })
+    }
+
+    #[test]
+    fn parses_metadata_fields() {
+        let metadata = parse_metadata(&firmware_json(), false).unwrap();
+        assert_eq!(Version::new(1, 1, 0), metadata.version);
+        assert_eq!(2048, metadata.size);
+        assert_eq!("https://firmware.example.com/tower.bin", metadata.download_url);
+        assert_eq!(None, metadata.signature);
+    }
+
+    #[test]
+    fn malformed_metadata_is_a_parse_error() {
+        let broken = [("version", serde_json::json!("1.x")), ("size", serde_json::json!(0)), ("sha256", serde_json::json!("abcd"))];
+        for (key, value) in broken {
+            let mut json = firmware_json();
+            json[key] = value;
+            assert!(matches!(parse_metadata(&json, false), Err(OtaError::MetadataParse(_))), "{key}");
+        }
+        let mut json = firmware_json();
+        json.as_object_mut().unwrap().remove("download_url");
+        assert!(matches!(parse_metadata(&json, false), Err(OtaError::MetadataParse(_))));
+    }
+
+    #[test]
+    fn signed_metadata_requires_a_signature() {
+        assert!(matches!(parse_metadata(&firmware_json(), true), Err(OtaError::SignatureInvalid(_))));
+        let mut json = firmware_json();
+        json["signature"] = serde_json::json!("c2ln");
+        assert_eq!(Some("c2ln".to_string()), parse_metadata(&json, true).unwrap().signature);
+    }
+
+    #[test]
+    fn progress_payload() {
+        let current = Version::new(1, 0, 4);
+        let target = Version::new(1, 1, 0);
+        assert_eq!(
+            progress_json(&current, &target, 512, 2048),
+            "{\"current_version\":\"1.0.4\",\"target_version\":\"1.1.0\",\"bytes_written\":512,\"bytes_total\":2048,\"percent\":25.0}"
+        );
+        assert!(progress_json(&current, &target, 2048, 2048).ends_with("\"percent\":100.0}"));
+    }
+
+    #[test]
+    fn first_boot_seeds_the_build_version() {
+        let build = Version::new(1, 0, 4);
+        let (version, seed) = stored_or_seed(None, &build).unwrap();
+        assert_eq!(build, version);
+        assert!(seed);
+    }
+
+    #[test]
+    fn later_boots_keep_the_stored_version() {
+        let build = Version::new(1, 0, 4);
+        let (version, seed) = stored_or_seed(Some("1.2.0 "), &build).unwrap();
+        assert_eq!(Version::new(1, 2, 0), version);
+        assert!(!seed);
+        assert!(stored_or_seed(Some("not a version"), &build).is_err());
+    }
+
+    #[test]
+    fn scheduler_is_due_every_interval() {
+        let start = Instant::now();
+        let mut scheduler = OtaScheduler::new(Duration::from_secs(3600));
+        assert!(scheduler.is_due(start));
+
+        scheduler.mark_checked(start);
+        assert!(!scheduler.is_due(start + Duration::from_secs(3599)));
+        assert_eq!(Duration::from_secs(600), scheduler.time_until_due(start + Duration::from_secs(3000)));
+        assert!(scheduler.is_due(start + Duration::from_secs(3600)));
+
+        scheduler.mark_checked(start + Duration::from_secs(4000));
+        assert!(!scheduler.is_due(start + Duration::from_secs(7000)));
+        assert!(scheduler.is_due(start + Duration::from_secs(7600)));
+    }
+
+    #[test]
+    fn images_larger_than_the_partition_are_refused() {
+        assert!(check_image_fits(0x1E_0000, 0x1E_0000).is_ok());
+        assert!(matches!(
+            check_image_fits(0x1E_0001, 0x1E_0000),
+            Err(OtaError::ImageTooLarge { size: 0x1E_0001, capacity: 0x1E_0000 })
+        ));
+    }
+
+    #[test]
+    fn written_size_must_match_the_metadata() {
+        assert!(check_written(4096, 4096).is_ok());
+        assert!(matches!(
+            check_written(4096, 4000),
+            Err(OtaError::SizeMismatch { expected: 4096, written: 4000 })
+        ));
+    }
+}
@@ -1,26 +1,190 @@
 pub mod sensors {
+    use embedded_hal::i2c::Error as _;
     use esp_idf_svc::hal::adc::{
         AdcContConfig, AdcContDriver, AdcMeasurement, Attenuated, EmptyAdcChannels, ADC1,
     };
     use esp_idf_svc::hal::delay::Ets;
-    use esp_idf_svc::hal::gpio::{Gpio2, Gpio3};
-    use hdc1080::Hdc1080;
+    use esp_idf_svc::hal::gpio::{ADCPin, Gpio1, Gpio2, Gpio3};
+    use esp_idf_svc::sys::{adc_channel_t, EspError};
+    use hdc1080::{HResolution, Hdc1080, TResolution};
+    use std::fmt;
+
+    #[derive(Debug)]
+    pub enum SensorError {
+        /// Reading the LDR channels from the continuous ADC driver failed.
+        Adc(EspError),
+        /// The captured window held no samples for the requested channel.
+        NoSamples,
+        /// The HDC1080 temperature/humidity sensor did not answer on the I2C bus.
+        Humidity(embedded_hal::i2c::ErrorKind),
+        /// The HDC1080 has no such measurement resolution (see `Sensors::set_resolution`).
+        Resolution { temp_bits: u8, humidity_bits: u8 },
+    }
+
+    impl fmt::Display for SensorError {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            match self {
+                SensorError::Adc(e) => write!(f, "LDR ADC read failed: {}", e),
+                SensorError::NoSamples => write!(f, "LDR ADC returned no samples for the channel"),
+                SensorError::Humidity(kind) => write!(f, "HDC1080 read failed: {:?}", kind),
+                SensorError::Resolution { temp_bits, humidity_bits } => write!(
+                    f,
+                    "HDC1080 has no {}-bit temperature / {}-bit humidity resolution",
+                    temp_bits, humidity_bits
+                ),
+            }
+        }
+    }
+
+    impl std::error::Error for SensorError {}
 
     pub struct Sensors<'a, I2C> {
         humidity_sensor: Hdc1080<I2C, Ets>,
         light_sensor: AdcContDriver<'a>,
+        east_channel: adc_channel_t,
+        west_channel: adc_channel_t,
+        wind_channel: adc_channel_t,
+        wind_calibration: WindCalibration,
+        lux_calibration: LuxCalibration,
+    }
+
+    // Raw reading at full scale and the voltage it stands for at 11 dB attenuation.
+    const ADC_MAX_RAW: f32 = 4095.0;
+    const ADC_FULL_SCALE_VOLTS: f32 = 3.1;
+
+    /// Anemometer output voltage for a raw ADC reading.
+    pub fn raw_to_volts(raw: i32) -> f32 {
+        raw as f32 / ADC_MAX_RAW * ADC_FULL_SCALE_VOLTS
+    }
+
+    /// Linear anemometer calibration: `m/s = slope * volts + offset`, never below 0.
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub struct WindCalibration {
+        pub slope: f32,
+        pub offset: f32,
+    }
+
+    impl Default for WindCalibration {
+        // 0.4 V at rest to 2.0 V at 32.4 m/s, the usual voltage-output cup anemometer.
+        fn default() -> Self {
+            WindCalibration {
+                slope: 20.25,
+                offset: -8.1,
+            }
+        }
+    }
+
+    impl WindCalibration {
+        /// Wind speed in m/s for a raw ADC reading.
+        pub fn wind_speed(&self, raw: i32) -> f32 {
+            (self.slope * raw_to_volts(raw) + self.offset).max(0.0)
+        }
+    }
+
+    /// Piecewise-linear map from raw LDR counts to approximate lux, clamped to the end points.
+    ///
+    /// The LDR response is close to logarithmic, so put a point every decade or so of lux.
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct LuxCalibration {
+        points: Vec<(i32, f32)>,
+    }
+
+    impl LuxCalibration {
+        /// `(raw, lux)` points in increasing raw order. None with fewer than two points or
+        /// when raw doesn't strictly increase.
+        pub fn new(points: Vec<(i32, f32)>) -> Option<Self> {
+            let increasing = points.windows(2).all(|pair| pair[0].0 < pair[1].0);
+            (points.len() >= 2 && increasing).then_some(LuxCalibration { points })
+        }
+
+        pub fn points(&self) -> &[(i32, f32)] {
+            &self.points
+        }
+
+        /// Approximate lux for a raw LDR reading.
+        pub fn lux(&self, raw: i32) -> f32 {
+            let (first, last) = (self.points[0], self.points[self.points.len() - 1]);
+            if raw <= first.0 {
+                return first.1;
+            }
+            if raw >= last.0 {
+                return last.1;
+            }
+            let above = self.points.iter().position(|&(r, _)| r > raw).unwrap();
+            let ((r0, l0), (r1, l1)) = (self.points[above - 1], self.points[above]);
+            l0 + (l1 - l0) * (raw - r0) as f32 / (r1 - r0) as f32
+        }
+    }
+
+    impl Default for LuxCalibration {
+        // A GL5528 (10k at 10 lux) from 3.3 V over a 1k resistor to ground
+        fn default() -> Self {
+            LuxCalibration {
+                points: vec![
+                    (0, 0.0),
+                    (85, 1.0),
+                    (396, 10.0),
+                    (1454, 100.0),
+                    (3116, 1_000.0),
+                    (4040, 10_000.0),
+                ],
+            }
+        }
+    }
+
+    // Samples captured per read; continuous mode interleaves both channels into this window.
+    const WINDOW_LEN: usize = 128;
+
+    pub fn celsius_to_fahrenheit(celsius: f32) -> f32 {
+        (celsius * 9.0 / 5.0) + 32.0
+    }
+
+    /// HDC1080 resolutions for `temp_bits` (11 or 14) and `humidity_bits` (8, 11 or 14).
+    pub(crate) fn resolution(temp_bits: u8, humidity_bits: u8) -> Option<(TResolution, HResolution)> {
+        let temp = match temp_bits {
+            11 => TResolution::_11,
+            14 => TResolution::_14,
+            _ => return None,
+        };
+        let humidity = match humidity_bits {
+            8 => HResolution::_8,
+            11 => HResolution::_11,
+            14 => HResolution::_14,
+            _ => return None,
+        };
+        Some((temp, humidity))
+    }
+
+    /// Mean of the `channel` readings in an interleaved `(channel, data)` stream.
+    pub(crate) fn average_channel<I>(readings: I, channel: adc_channel_t) -> Option<i32>
+    where
+        I: IntoIterator<Item = (adc_channel_t, u16)>,
+    {
+        let (sum, count) = readings
+            .into_iter()
+            .filter(|&(ch, _)| ch == channel)
+            .fold((0i64, 0i64), |(sum, count), (_, data)| (sum + data as i64, count + 1));
+        if count == 0 {
+            None
+        } else {
+            Some((sum / count) as i32)
+        }
     }
 
     impl<I2C> Sensors<'_, I2C>
     where
         I2C: embedded_hal::i2c::I2c,
     {
-        pub fn new<'a>(bus: I2C, adc: ADC1, ldr_e: Gpio2, ldr_w: Gpio3) -> Sensors<'a, I2C> {
+        pub fn new<'a>(bus: I2C, adc: ADC1, ldr_e: Gpio2, ldr_w: Gpio3, anemometer: Gpio1) -> Sensors<'a, I2C> {
+            let east_channel = ldr_e.adc_channel();
+            let west_channel = ldr_w.adc_channel();
+            let wind_channel = anemometer.adc_channel();
             let att_e = Attenuated::db11(ldr_e);
             let att_w = Attenuated::db11(ldr_w);
+            let att_wind = Attenuated::db11(anemometer);
 
             let temp_channel = EmptyAdcChannels::chain(att_e);
-            let adc_channels = temp_channel.chain(att_w);
+            let adc_channels = temp_channel.chain(att_w).chain(att_wind);
 
             let adc_config = AdcContConfig::default();
             let mut driver = AdcContDriver::new(adc, &adc_config, adc_channels).unwrap();
@@ -29,41 +193,230 @@ pub mod sensors {
             Sensors {
                 humidity_sensor: Hdc1080::new(bus, Ets).unwrap(),
                 light_sensor: driver,
+                east_channel,
+                west_channel,
+                wind_channel,
+                wind_calibration: WindCalibration::default(),
+                lux_calibration: LuxCalibration::default(),
             }
         }
 
-        pub fn temperature(&mut self) -> f32 {
-            (self.humidity_sensor.temperature().unwrap_or_default() * 9.0 / 5.0) + 32.0
+        /// Temperature in degrees Fahrenheit (`temperature_f`).
+        pub fn temperature(&mut self) -> Result<f32, SensorError> {
+            self.temperature_f()
         }
 
-        pub fn humidity(&mut self) -> f32 {
-            self.humidity_sensor.humidity().unwrap_or_default()
+        /// Temperature in degrees Celsius, as the HDC1080 (and the DS3231) report it.
+        pub fn temperature_c(&mut self) -> Result<f32, SensorError> {
+            self.humidity_sensor
+                .temperature()
+                .map_err(|e| SensorError::Humidity(e.kind()))
         }
 
-        pub fn east_ldr(&mut self) -> i32 {
-            let mut samples: [AdcMeasurement; 128] = [Default::default(); 128];
-            if let Ok(_) = self.light_sensor.read(&mut samples, 128) {
-                return samples[0].data() as i32;
-            }
-            -1
+        /// Temperature in degrees Fahrenheit.
+        pub fn temperature_f(&mut self) -> Result<f32, SensorError> {
+            Ok(celsius_to_fahrenheit(self.temperature_c()?))
         }
 
-        pub fn west_ldr(&mut self) -> i32 {
-            let mut samples: [AdcMeasurement; 128] = [Default::default(); 128];
-            if let Ok(_) = self.light_sensor.read(&mut samples, 128) {
-                return samples[1].data() as i32;
-            }
-            -1
+        pub fn humidity(&mut self) -> Result<f32, SensorError> {
+            self.humidity_sensor
+                .humidity()
+                .map_err(|e| SensorError::Humidity(e.kind()))
         }
 
-        pub fn balance_gap(&mut self) -> i32 {
-            let mut samples: [AdcMeasurement; 128] = [Default::default(); 128];
-            if let Ok(_) = self.light_sensor.read(&mut samples, 128) {
-                return (samples[0].data() as i32 - samples[1].data() as i32) as i32;
-            }
-            -10000
+        /// Temperature (C) and humidity (%) from one combined HDC1080 acquisition, with half
+        /// the bus traffic and self-heating of `temperature_c` plus `humidity`.
+        pub fn read_both(&mut self) -> Result<(f32, f32), SensorError> {
+            self.humidity_sensor
+                .read()
+                .map_err(|e| SensorError::Humidity(e.kind()))
+        }
+
+        /// The HDC1080's manufacturer ID, `hdc1080::MANUFACTURER_ID` when it answers. The
+        /// readings can't tell: the driver turns bus errors into zeros.
+        pub fn humidity_sensor_id(&mut self) -> Result<u16, SensorError> {
+            self.humidity_sensor
+                .get_man_id()
+                .map_err(|e| SensorError::Humidity(e.kind()))
+        }
+
+        /// Run the HDC1080 heater to drive off condensation. Readings taken while it is on
+        /// are warmer and drier than the enclosure.
+        pub fn enable_heater(&mut self, enabled: bool) -> Result<(), SensorError> {
+            self.humidity_sensor
+                .set_heater(enabled)
+                .map_err(|e| SensorError::Humidity(e.kind()))
+        }
+
+        /// Measurement resolution: 11 or 14 temperature bits, 8, 11 or 14 humidity bits.
+        /// More bits take longer to convert.
+        pub fn set_resolution(&mut self, temp_bits: u8, humidity_bits: u8) -> Result<(), SensorError> {
+            let (temp, humidity) = resolution(temp_bits, humidity_bits)
+                .ok_or(SensorError::Resolution { temp_bits, humidity_bits })?;
+            self.humidity_sensor
+                .set_t_resolution(temp)
+                .and_then(|_| self.humidity_sensor.set_h_resolution(humidity))
+                .map_err(|e| SensorError::Humidity(e.kind()))
+        }
+
+        pub fn east_ldr(&mut self) -> Result<i32, SensorError> {
+            self.read_channel_average(self.east_channel)
+        }
+
+        pub fn west_ldr(&mut self) -> Result<i32, SensorError> {
+            self.read_channel_average(self.west_channel)
+        }
+
+        /// East LDR light level in approximate lux, through the `LuxCalibration`.
+        pub fn east_lux(&mut self) -> Result<f32, SensorError> {
+            let raw = self.east_ldr()?;
+            Ok(self.lux_calibration.lux(raw))
+        }
+
+        pub fn west_lux(&mut self) -> Result<f32, SensorError> {
+            let raw = self.west_ldr()?;
+            Ok(self.lux_calibration.lux(raw))
+        }
+
+        /// Replace the lux curve, e.g. for towers built with a different divider resistor.
+        pub fn set_lux_calibration(&mut self, calibration: LuxCalibration) {
+            self.lux_calibration = calibration;
+        }
+
+        pub fn lux_calibration(&self) -> &LuxCalibration {
+            &self.lux_calibration
+        }
+
+        /// Wind speed in m/s from the anemometer channel through the `WindCalibration`.
+        pub fn wind_speed(&mut self) -> Result<f32, SensorError> {
+            let raw = self.read_channel_average(self.wind_channel)?;
+            Ok(self.wind_calibration.wind_speed(raw))
+        }
+
+        pub fn set_wind_calibration(&mut self, calibration: WindCalibration) {
+            self.wind_calibration = calibration;
+        }
+
+        pub fn wind_calibration(&self) -> WindCalibration {
+            self.wind_calibration
+        }
+
+        /// East minus west LDR reading, both averaged over the same capture window.
+        pub fn balance_gap(&mut self) -> Result<i32, SensorError> {
+            let mut samples = [AdcMeasurement::new(); WINDOW_LEN];
+            let samples = self.read_window(&mut samples)?;
+            let east = average_channel(readings(samples), self.east_channel)
+                .ok_or(SensorError::NoSamples)?;
+            let west = average_channel(readings(samples), self.west_channel)
+                .ok_or(SensorError::NoSamples)?;
+            Ok(east - west)
+        }
+
+        /// Average of the samples for ADC channel `channel_index` in one capture window.
+        pub fn read_channel_average(
+            &mut self,
+            channel_index: adc_channel_t,
+        ) -> Result<i32, SensorError> {
+            let mut samples = [AdcMeasurement::new(); WINDOW_LEN];
+            let samples = self.read_window(&mut samples)?;
+            average_channel(readings(samples), channel_index).ok_or(SensorError::NoSamples)
         }
+
+        fn read_window<'s>(
+            &mut self,
+            samples: &'s mut [AdcMeasurement],
+        ) -> Result<&'s [AdcMeasurement], SensorError> {
+            let read = self
+                .light_sensor
+                .read(samples, 128)
+                .map_err(SensorError::Adc)?;
+            Ok(&samples[..read])
+        }
+    }
+
+    fn readings(samples: &[AdcMeasurement]) -> impl Iterator<Item = (adc_channel_t, u16)> + '_ {
+        samples.iter().map(|s| (s.channel(), s.data()))
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::{
+            average_channel, celsius_to_fahrenheit, raw_to_volts, resolution, LuxCalibration, WindCalibration,
+        };
+        use hdc1080::{HResolution, TResolution};
+
+        #[test]
+        fn separates_interleaved_channels() {
+            let stream = [(1, 100), (2, 900), (1, 110), (2, 910), (1, 120), (2, 920)];
+            assert_eq!(Some(110), average_channel(stream, 1));
+            assert_eq!(Some(910), average_channel(stream, 2));
+        }
+
+        #[test]
+        fn handles_uneven_interleaving() {
+            // A window can start or end mid-pair, or drop a conversion.
+            let stream = [(2, 500), (1, 10), (1, 20), (2, 700), (1, 30)];
+            assert_eq!(Some(20), average_channel(stream, 1));
+            assert_eq!(Some(600), average_channel(stream, 2));
+        }
+
+        #[test]
+        fn missing_channel_has_no_average() {
+            let stream = [(1, 100), (1, 200)];
+            assert_eq!(None, average_channel(stream, 2));
+            assert_eq!(None, average_channel([], 1));
+        }
+
+        #[test]
+        fn wind_reading_follows_the_calibration() {
+            let calibration = WindCalibration { slope: 10.0, offset: -1.0 };
+            // 1.55 V
+            assert!((raw_to_volts(2047) - 1.55).abs() < 0.001);
+            assert!((calibration.wind_speed(2047) - 14.5).abs() < 0.01);
+            assert!((calibration.wind_speed(4095) - 30.0).abs() < 0.01);
+        }
+
+        #[test]
+        fn calm_wind_does_not_go_negative() {
+            let calibration = WindCalibration::default();
+            assert_eq!(0.0, calibration.wind_speed(0));
+            // 0.4 V at rest
+            assert!(calibration.wind_speed(528).abs() < 0.05);
+        }
+
+        #[test]
+        fn lux_interpolates_between_points_and_clamps() {
+            let calibration = LuxCalibration::new(vec![(100, 1.0), (1100, 101.0), (2100, 1101.0)]).unwrap();
+            assert_eq!(1.0, calibration.lux(0));
+            assert_eq!(51.0, calibration.lux(600));
+            assert_eq!(101.0, calibration.lux(1100));
+            assert_eq!(601.0, calibration.lux(1600));
+            assert_eq!(1101.0, calibration.lux(4095));
+        }
+
+        #[test]
+        fn rejects_unordered_lux_tables() {
+            assert!(LuxCalibration::new(vec![(0, 0.0)]).is_none());
+            assert!(LuxCalibration::new(vec![(0, 0.0), (500, 10.0), (500, 20.0)]).is_none());
+            assert!(LuxCalibration::new(LuxCalibration::default().points().to_vec()).is_some());
+        }
+
+        #[test]
+        fn converts_to_fahrenheit() {
+            assert_eq!(32.0, celsius_to_fahrenheit(0.0));
+            assert_eq!(212.0, celsius_to_fahrenheit(100.0));
+            assert_eq!(-40.0, celsius_to_fahrenheit(-40.0));
+        }
+
+        #[test]
+        fn maps_supported_resolutions_only() {
+            assert!(matches!(resolution(14, 11), Some((TResolution::_14, HResolution::_11))));
+            assert!(matches!(resolution(11, 8), Some((TResolution::_11, HResolution::_8))));
+            assert!(resolution(12, 11).is_none());
+            assert!(resolution(14, 16).is_none());
+        }
+    }
     }
 }
 
-pub use sensors::Sensors;
+pub use sensors::{celsius_to_fahrenheit, raw_to_volts, LuxCalibration, SensorError, Sensors, WindCalibration};
@@ -3,22 +3,65 @@ use serde::{Deserialize, Serialize};
 use std::fs;
 use std::path::Path;
 
+const CONFIG_PATH: &str = "config.toml";
+// `save` writes here first, so a crash mid-write never leaves a truncated config.toml
+const CONFIG_TMP_PATH: &str = "config.toml.tmp";
+// On-flash site profiles, `<name>.toml`; they take precedence over the embedded ones
+const PROFILE_DIR: &str = "profiles";
+// Profiles built into the firmware, so a site can be selected without provisioning a file
+const EMBEDDED_PROFILES: &[(&str, &str)] = &[
+    ("dallas", include_str!("../profiles/dallas.toml")),
+    ("waco", include_str!("../profiles/waco.toml")),
+];
+// Build-time profile, used when config.toml doesn't select one: SITE_PROFILE=waco cargo build
+const BUILD_PROFILE: Option<&str> = option_env!("SITE_PROFILE");
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Config {
     pub device: DeviceConfig,
     pub wifi: WifiConfig,
     pub location: LocationConfig,
+    #[serde(default)]
+    pub mqtt: MqttConfig,
+    #[serde(default)]
+    pub ota: OtaConfig,
+    #[serde(default)]
+    pub homing: HomingConfig,
+    #[serde(default)]
+    pub telemetry: TelemetryConfig,
+    #[serde(default)]
+    pub motor: MotorConfig,
+    #[serde(default)]
+    pub maintenance: MaintenanceConfig,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DeviceConfig {
     pub tower_id: u32,
+    /// MQTT client id / topic prefix; defaults to `device<tower_id>A`
+    #[serde(default)]
+    pub device_name: Option<String>,
+    /// Freeze the firmware: skip every OTA check (devices under certification/validation)
+    #[serde(default)]
+    pub ota_disabled: bool,
+    /// Site profile (see `Config::load_profile`) replacing `[location]` and `[homing]`
+    #[serde(default)]
+    pub profile: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct WifiConfig {
     pub ssid: String,
     pub password: String,
+    /// Fallback networks (e.g. a hotspot), tried in order after `ssid`
+    #[serde(default)]
+    pub networks: Vec<NetworkConfig>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NetworkConfig {
+    pub ssid: String,
+    pub password: String,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -29,49 +72,381 @@ pub struct LocationConfig {
     pub timezone_offset_hours: i32,
 }
 
-/* impl Config {
-    pub fn load() -> anyhow::Result<Self> {
-        // Embedded configuration (compiled into binary)
-        let config_content = include_str!("../config.toml");
-        let config: Config = toml::from_str(config_content)?;
-        log::info!("Loaded embedded configuration");
-        Ok(config)
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HomingDirection {
+    #[default]
+    Cw,
+    Ccw,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HomingConfig {
+    /// Which way to search for the limit switch, i.e. which side of the tower it is on
+    #[serde(default)]
+    pub direction: HomingDirection,
+    /// Override `direction` for the morning verify (homing at boot) or the evening park
+    #[serde(default)]
+    pub morning_direction: Option<HomingDirection>,
+    #[serde(default)]
+    pub evening_direction: Option<HomingDirection>,
+    /// Scale of coarse tracking moves, for drivetrains off the nominal gearing
+    #[serde(default = "default_correction_factor")]
+    pub correction_factor: f64,
+}
+
+fn default_correction_factor() -> f64 {
+    1.0
+}
+
+impl Default for HomingConfig {
+    fn default() -> Self {
+        HomingConfig {
+            direction: HomingDirection::default(),
+            morning_direction: None,
+            evening_direction: None,
+            correction_factor: default_correction_factor(),
+        }
     }
+}
 
-    pub fn save(&self) -> anyhow::Result<()> {
-        let config_content = toml::to_string_pretty(self)?;
-        fs::write("config.toml", config_content)?;
-        log::info!("Configuration saved to config.toml");
-        Ok(())
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TelemetrySink {
+    #[default]
+    Mqtt,
+    Http,
+    Both,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TelemetryConfig {
+    /// `http` or `both` for sites whose firewall blocks the broker; needs `http_url`
+    #[serde(default)]
+    pub sink: TelemetrySink,
+    /// `http://` or `https://` endpoint the JSON telemetry is POSTed to
+    #[serde(default)]
+    pub http_url: Option<String>,
+    #[serde(default)]
+    pub username: Option<String>,
+    #[serde(default)]
+    pub password: Option<String>,
+    /// Publish a timing breakdown of every tracking move to `<device>/perf/move`
+    #[serde(default)]
+    pub publish_move_perf: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MotorConfig {
+    /// Keep the driver energized between tracking moves, for drives the wind can back-drive;
+    /// costs the driver's idle current all day
+    #[serde(default)]
+    pub hold_between_moves: bool,
+    /// Least minutes between two position saves after tracking moves, to spare the flash;
+    /// 0 saves after every move
+    #[serde(default = "default_position_save_interval_mins")]
+    pub position_save_interval_mins: u64,
+}
+
+fn default_position_save_interval_mins() -> u64 {
+    motion::snapshot::DEFAULT_PERSIST_INTERVAL.as_secs() / 60
+}
+
+impl Default for MotorConfig {
+    fn default() -> Self {
+        MotorConfig {
+            hold_between_moves: false,
+            position_save_interval_mins: default_position_save_interval_mins(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MaintenanceConfig {
+    /// Minutes without button activity after which maintenance mode re-homes and resumes tracking
+    #[serde(default = "default_inactivity_timeout_mins")]
+    pub inactivity_timeout_mins: u64,
+}
+
+fn default_inactivity_timeout_mins() -> u64 {
+    30
+}
+
+impl Default for MaintenanceConfig {
+    fn default() -> Self {
+        MaintenanceConfig {
+            inactivity_timeout_mins: default_inactivity_timeout_mins(),
+        }
+    }
+}
+
+/// Everything that differs between sites rather than between towers, kept in one file per site
+/// so no site needs its own source edits.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SiteProfile {
+    pub location: LocationConfig,
+    #[serde(default)]
+    pub homing: HomingConfig,
+}
+
+impl SiteProfile {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        validate_location(&self.location)?;
+        validate_homing(&self.homing)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MqttConfig {
+    /// `mqtt://` or `mqtts://` URL, checked on load
+    #[serde(default = "default_broker_url")]
+    pub broker_url: String,
+    /// Defaults to `<device id>_pub`
+    #[serde(default)]
+    pub client_id: Option<String>,
+    #[serde(default)]
+    pub username: Option<String>,
+    #[serde(default)]
+    pub password: Option<String>,
+}
+
+fn default_broker_url() -> String {
+    network::mqtt::DEFAULT_BROKER_URL.to_string()
+}
+
+impl Default for MqttConfig {
+    fn default() -> Self {
+        MqttConfig {
+            broker_url: default_broker_url(),
+            client_id: None,
+            username: None,
+            password: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OtaConfig {
+    /// metadata.json of the firmware channel this tower follows (production, staging, ...)
+    #[serde(default = "default_metadata_url")]
+    pub metadata_url: String,
+    #[serde(default)]
+    pub username: Option<String>,
+    #[serde(default)]
+    pub password: Option<String>,
+    /// Minutes between update checks after the one at boot, day or night
+    #[serde(default = "default_check_interval_mins")]
+    pub check_interval_mins: u64,
+    /// Firmware signing key as 64 hex digits; when set, unsigned images are refused
+    #[serde(default)]
+    pub public_key: Option<String>,
+}
+
+fn default_check_interval_mins() -> u64 {
+    ota::DEFAULT_CHECK_INTERVAL.as_secs() / 60
+}
+
+fn default_metadata_url() -> String {
+    ota::DEFAULT_METADATA_URL.to_string()
+}
+
+impl Default for OtaConfig {
+    fn default() -> Self {
+        OtaConfig {
+            metadata_url: default_metadata_url(),
+            username: None,
+            password: None,
+            check_interval_mins: default_check_interval_mins(),
+            public_key: None,
+        }
     }
-} */
+}
 
 impl Config {
     pub fn load() -> anyhow::Result<Self> {
         // Try external file first
-        if Path::new("config.toml").exists() {
-            let config_content = fs::read_to_string("config.toml")?;
-            let config: Config = toml::from_str(&config_content)?;
+        let mut config: Config = if Path::new(CONFIG_PATH).exists() {
+            let config_content = fs::read_to_string(CONFIG_PATH)?;
+            let config = toml::from_str(&config_content)?;
             log::info!("Loaded configuration from file");
-            Ok(config)
+            config
         } else {
             // Fallback to embedded defaults
             let config_content = include_str!("../config.toml.example");
-            let config: Config = toml::from_str(config_content)?;
+            let config = toml::from_str(config_content)?;
             log::warn!("Using embedded default configuration");
-            Ok(config)
+            config
+        };
+        config.apply_selected_profile();
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// The site profile `name`: `profiles/<name>.toml` on flash if there is one, else the one
+    /// embedded in the firmware. Fails when neither exists or the profile doesn't validate.
+    pub fn load_profile(name: &str) -> anyhow::Result<SiteProfile> {
+        let path = format!("{}/{}.toml", PROFILE_DIR, name);
+        let profile: SiteProfile = if Path::new(&path).exists() {
+            toml::from_str(&fs::read_to_string(&path)?)?
+        } else if let Some((_, content)) = EMBEDDED_PROFILES.iter().find(|(embedded, _)| *embedded == name) {
+            toml::from_str(content)?
+        } else {
+            let known: Vec<&str> = EMBEDDED_PROFILES.iter().map(|(embedded, _)| *embedded).collect();
+            anyhow::bail!("no site profile {:?} (embedded: {})", name, known.join(", "));
+        };
+        profile
+            .validate()
+            .map_err(|e| anyhow::anyhow!("site profile {:?}: {}", name, e))?;
+        Ok(profile)
+    }
+
+    /// The profile named in `[device]`, or else the one chosen at build time.
+    pub fn selected_profile(&self) -> Option<String> {
+        self.device.profile.clone().or(BUILD_PROFILE.map(String::from))
+    }
+
+    /// Replace `[location]` and `[homing]` with the profile named in `[device]`, or else the
+    /// one chosen at build time. A profile that can't be loaded leaves them as they are.
+    fn apply_selected_profile(&mut self) {
+        let Some(name) = self.selected_profile() else {
+            return;
+        };
+        match Config::load_profile(&name) {
+            Ok(profile) => {
+                self.location = profile.location;
+                self.homing = profile.homing;
+                log::info!("Applied site profile {}", name);
+            }
+            Err(e) => log::error!(
+                "Failed to load site profile, keeping [location] and [homing] from the configuration: {}",
+                e
+            ),
         }
     }
+
+    /// Write the configuration to config.toml, for `load` to pick up on the next boot.
+    /// Invalid values are refused and leave the file untouched.
+    pub fn save(&self) -> anyhow::Result<()> {
+        self.save_to(Path::new(CONFIG_PATH), Path::new(CONFIG_TMP_PATH))
+    }
+
+    fn save_to(&self, path: &Path, tmp_path: &Path) -> anyhow::Result<()> {
+        self.validate()?;
+        let config_content = toml::to_string_pretty(self)?;
+        fs::write(tmp_path, config_content)?;
+        fs::rename(tmp_path, path)?;
+        log::info!("Configuration saved to {}", path.display());
+        Ok(())
+    }
+
+    /// Apply a `set_location` command and save the result, keeping the configuration as it
+    /// was if the new location is invalid. A site profile would replace `[location]` again at
+    /// the next boot, so towers with one refuse the change.
+    pub fn update_location(&mut self, update: &network::command::LocationUpdate) -> anyhow::Result<()> {
+        if let Some(profile) = self.selected_profile() {
+            anyhow::bail!("site profile {:?} sets the location", profile);
+        }
+        let mut updated = self.clone();
+        let location = &mut updated.location;
+        location.latitude = update.latitude.unwrap_or(location.latitude);
+        location.longitude = update.longitude.unwrap_or(location.longitude);
+        location.altitude = update.altitude.unwrap_or(location.altitude);
+        location.timezone_offset_hours = update.timezone_offset_hours.unwrap_or(location.timezone_offset_hours);
+        updated.save()?;
+        *self = updated;
+        Ok(())
+    }
+
+    /// Check that every value is usable, naming the offending field otherwise. Run by `load`
+    /// and `save`.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        validate_location(&self.location)?;
+        validate_homing(&self.homing)?;
+        // Wi-Fi passwords may be empty: open networks have none
+        if self.wifi.ssid.is_empty() {
+            anyhow::bail!("wifi.ssid is empty");
+        }
+        if self.wifi.networks.iter().any(|network| network.ssid.is_empty()) {
+            anyhow::bail!("wifi.networks has an entry with an empty ssid");
+        }
+        if self.ota.check_interval_mins == 0 {
+            anyhow::bail!("ota.check_interval_mins must be at least 1");
+        }
+        if self.maintenance.inactivity_timeout_mins == 0 {
+            anyhow::bail!("maintenance.inactivity_timeout_mins must be at least 1");
+        }
+        if let Some(key) = &self.ota.public_key {
+            ota::parse_public_key(key).map_err(|e| anyhow::anyhow!("ota.public_key: {}", e))?;
+        }
+        network::mqtt::check_broker_url(&self.mqtt.broker_url)
+            .map_err(|e| anyhow::anyhow!("mqtt.broker_url: {}", e))?;
+        match self.telemetry.http_url.as_deref() {
+            None if self.telemetry.sink != TelemetrySink::Mqtt => {
+                anyhow::bail!("telemetry.http_url is required for telemetry.sink {:?}", self.telemetry.sink)
+            }
+            Some(url) if !["http://", "https://"].iter().any(|scheme| url.to_lowercase().starts_with(scheme)) => {
+                anyhow::bail!("telemetry.http_url {:?} is not an http:// or https:// URL", url)
+            }
+            _ => {}
+        }
+        // An empty string is a typo, not "no credentials": leave the key out for that
+        for (field, value) in [
+            ("mqtt.username", &self.mqtt.username),
+            ("mqtt.password", &self.mqtt.password),
+            ("ota.username", &self.ota.username),
+            ("ota.password", &self.ota.password),
+            ("telemetry.username", &self.telemetry.username),
+            ("telemetry.password", &self.telemetry.password),
+        ] {
+            if value.as_deref() == Some("") {
+                anyhow::bail!("{} is empty", field);
+            }
+        }
+        Ok(())
+    }
 }
 
-// Helper functions for easy access
-impl Config {
-    pub fn get_wifi_ssid(&self) -> &str {
-        &self.wifi.ssid
+fn validate_location(location: &LocationConfig) -> anyhow::Result<()> {
+    if !(-90.0..=90.0).contains(&location.latitude) {
+        anyhow::bail!("location.latitude {} is outside [-90, 90]", location.latitude);
+    }
+    if !(-180.0..=180.0).contains(&location.longitude) {
+        anyhow::bail!("location.longitude {} is outside [-180, 180]", location.longitude);
+    }
+    if !(-12..=14).contains(&location.timezone_offset_hours) {
+        anyhow::bail!(
+            "location.timezone_offset_hours {} is outside [-12, 14]",
+            location.timezone_offset_hours
+        );
+    }
+    if !(-500.0..=9000.0).contains(&location.altitude) {
+        anyhow::bail!("location.altitude {} m is outside [-500, 9000]", location.altitude);
+    }
+    Ok(())
+}
+
+fn validate_homing(homing: &HomingConfig) -> anyhow::Result<()> {
+    // Far outside this is a wrong gear ratio, not a correction
+    if !(0.5..=2.0).contains(&homing.correction_factor) {
+        anyhow::bail!("homing.correction_factor {} is outside [0.5, 2.0]", homing.correction_factor);
+    }
+    Ok(())
+}
+
+fn homing_direction(direction: HomingDirection) -> motion::HomingDirection {
+    match direction {
+        HomingDirection::Cw => motion::HomingDirection::Cw,
+        HomingDirection::Ccw => motion::HomingDirection::Ccw,
     }
+}
 
-    pub fn get_wifi_password(&self) -> &str {
-        &self.wifi.password
+// Helper functions for easy access
+impl Config {
+    /// Every configured network as `(ssid, password)`, primary first, for `Wifi::connect_any`
+    pub fn get_wifi_networks(&self) -> Vec<(&str, &str)> {
+        std::iter::once((self.wifi.ssid.as_str(), self.wifi.password.as_str()))
+            .chain(self.wifi.networks.iter().map(|n| (n.ssid.as_str(), n.password.as_str())))
+            .collect()
     }
 
     pub fn get_latitude(&self) -> f64 {
@@ -90,7 +465,251 @@ impl Config {
         self.device.tower_id
     }
 
+    pub fn get_device_id(&self) -> network::device::DeviceId {
+        match &self.device.device_name {
+            Some(name) => network::device::DeviceId::new(name),
+            None => network::device::DeviceId::from_tower_id(self.device.tower_id),
+        }
+    }
+
+    pub fn is_ota_disabled(&self) -> bool {
+        self.device.ota_disabled
+    }
+
     pub fn get_timezone_offset(&self) -> i32 {
         self.location.timezone_offset_hours
     }
-} 
\ No newline at end of file
+
+    pub fn get_homing_direction(&self) -> motion::HomingDirection {
+        homing_direction(self.homing.direction)
+    }
+
+    /// `homing.direction`, unless overridden for the morning verify or the evening park.
+    pub fn get_homing_strategy(&self) -> motion::HomingStrategy {
+        motion::HomingStrategy {
+            morning: homing_direction(self.homing.morning_direction.unwrap_or(self.homing.direction)),
+            evening: homing_direction(self.homing.evening_direction.unwrap_or(self.homing.direction)),
+        }
+    }
+
+    pub fn get_correction_factor(&self) -> f64 {
+        self.homing.correction_factor
+    }
+
+    pub fn get_telemetry_transport(&self) -> network::telemetry::TelemetryTransport {
+        match self.telemetry.sink {
+            TelemetrySink::Mqtt => network::telemetry::TelemetryTransport::Mqtt,
+            TelemetrySink::Http => network::telemetry::TelemetryTransport::Http,
+            TelemetrySink::Both => network::telemetry::TelemetryTransport::Both,
+        }
+    }
+
+    /// The endpoint to POST telemetry to, when the sink uses HTTP
+    pub fn get_telemetry_http_url(&self) -> Option<&str> {
+        match self.telemetry.sink {
+            TelemetrySink::Mqtt => None,
+            TelemetrySink::Http | TelemetrySink::Both => self.telemetry.http_url.as_deref(),
+        }
+    }
+
+    pub fn get_telemetry_credentials(&self) -> (Option<&str>, Option<&str>) {
+        (self.telemetry.username.as_deref(), self.telemetry.password.as_deref())
+    }
+
+    pub fn get_publish_move_perf(&self) -> bool {
+        self.telemetry.publish_move_perf
+    }
+
+    pub fn get_hold_between_moves(&self) -> bool {
+        self.motor.hold_between_moves
+    }
+
+    pub fn get_position_save_interval(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.motor.position_save_interval_mins * 60)
+    }
+
+    pub fn get_maintenance_inactivity_timeout(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.maintenance.inactivity_timeout_mins * 60)
+    }
+
+    pub fn get_mqtt_settings(&self) -> network::mqtt::MqttSettings {
+        network::mqtt::MqttSettings {
+            broker_url: self.mqtt.broker_url.clone(),
+            client_id: self.mqtt.client_id.clone(),
+            username: self.mqtt.username.clone(),
+            password: self.mqtt.password.clone(),
+            ..network::mqtt::MqttSettings::default()
+        }
+    }
+
+    pub fn get_ota_settings(&self) -> ota::OtaSettings {
+        ota::OtaSettings {
+            metadata_url: self.ota.metadata_url.clone(),
+            username: self.ota.username.clone(),
+            password: self.ota.password.clone(),
+            check_interval: std::time::Duration::from_secs(self.ota.check_interval_mins * 60),
+            // Checked by `validate`
+            public_key: self.ota.public_key.as_deref().and_then(|key| ota::parse_public_key(key).ok()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXAMPLE: &str = include_str!("../config.toml.example");
+
+    // The example config with `key = ...` in `[section]` replaced by `line`.
+    fn with(section: &str, key: &str, line: &str) -> Config {
+        let mut in_section = false;
+        let patched: Vec<String> = EXAMPLE
+            .lines()
+            .map(|l| {
+                if l.starts_with('[') {
+                    in_section = l == format!("[{}]", section);
+                }
+                if in_section && l.split('=').next().map(str::trim) == Some(key) {
+                    line.to_string()
+                } else {
+                    l.to_string()
+                }
+            })
+            .collect();
+        toml::from_str(&patched.join("\n")).unwrap()
+    }
+
+    fn error(config: Config) -> String {
+        config.validate().unwrap_err().to_string()
+    }
+
+    #[test]
+    fn example_is_valid() {
+        let config: Config = toml::from_str(EXAMPLE).unwrap();
+        config.validate().unwrap();
+    }
+
+    #[test]
+    fn rejects_out_of_range_location() {
+        assert!(error(with("location", "latitude", "latitude = 320.0")).contains("location.latitude"));
+        assert!(error(with("location", "longitude", "longitude = -196.8")).contains("location.longitude"));
+        assert!(error(with("location", "altitude", "altitude = 12000.0")).contains("location.altitude"));
+        assert!(error(with("location", "timezone_offset_hours", "timezone_offset_hours = -50"))
+            .contains("location.timezone_offset_hours"));
+    }
+
+    #[test]
+    fn rejects_empty_credentials() {
+        assert!(error(with("wifi", "ssid", "ssid = \"\"")).contains("wifi.ssid"));
+        assert!(error(with("mqtt", "password", "password = \"\"")).contains("mqtt.password"));
+        assert!(error(with("ota", "username", "username = \"\"")).contains("ota.username"));
+    }
+
+    #[test]
+    fn rejects_zero_ota_check_interval() {
+        let config = with("ota", "check_interval_mins", "check_interval_mins = 0");
+        assert!(error(config).contains("ota.check_interval_mins"));
+    }
+
+    #[test]
+    fn save_round_trips_and_refuses_invalid_values() {
+        let dir = std::env::temp_dir().join(format!("tower-config-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let (path, tmp_path) = (dir.join("config.toml"), dir.join("config.toml.tmp"));
+
+        let config = with("location", "latitude", "latitude = 31.549333");
+        config.save_to(&path, &tmp_path).unwrap();
+        assert!(!tmp_path.exists());
+        let saved = fs::read_to_string(&path).unwrap();
+        let loaded: Config = toml::from_str(&saved).unwrap();
+        assert_eq!(toml::to_string(&config).unwrap(), toml::to_string(&loaded).unwrap());
+
+        let invalid = with("location", "latitude", "latitude = 320.0");
+        assert!(invalid.save_to(&path, &tmp_path).unwrap_err().to_string().contains("location.latitude"));
+        assert_eq!(saved, fs::read_to_string(&path).unwrap());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn rejects_zero_maintenance_timeout() {
+        let config = with("maintenance", "inactivity_timeout_mins", "inactivity_timeout_mins = 0");
+        assert!(error(config).contains("maintenance.inactivity_timeout_mins"));
+    }
+
+    #[test]
+    fn rejects_malformed_ota_public_key() {
+        let config = with("ota", "check_interval_mins", "check_interval_mins = 120\npublic_key = \"abcd\"");
+        assert!(error(config).contains("ota.public_key"));
+    }
+
+    #[test]
+    fn embedded_profiles_are_valid() {
+        for (name, _) in EMBEDDED_PROFILES {
+            Config::load_profile(name).unwrap();
+        }
+        let waco = Config::load_profile("waco").unwrap();
+        assert_eq!(HomingDirection::Ccw, waco.homing.direction);
+    }
+
+    #[test]
+    fn unknown_profile_lists_the_embedded_ones() {
+        let error = Config::load_profile("austin").unwrap_err().to_string();
+        assert!(error.contains("austin"));
+        assert!(error.contains("dallas, waco"));
+    }
+
+    #[test]
+    fn selected_profile_replaces_location_and_homing() {
+        let mut config = with("device", "tower_id", "tower_id = 1\nprofile = \"waco\"");
+        config.apply_selected_profile();
+        assert_eq!(31.549333, config.location.latitude);
+        assert_eq!(HomingDirection::Ccw, config.homing.direction);
+
+        // A missing profile keeps the configuration's own values
+        let mut config = with("device", "tower_id", "tower_id = 1\nprofile = \"austin\"");
+        config.apply_selected_profile();
+        assert_eq!(32.797868, config.location.latitude);
+        assert_eq!(HomingDirection::Cw, config.homing.direction);
+    }
+
+    #[test]
+    fn http_telemetry_needs_an_http_url() {
+        let config = with("telemetry", "sink", "sink = \"both\"");
+        assert!(error(config).contains("telemetry.http_url"));
+
+        let config = with("telemetry", "sink", "sink = \"http\"\nhttp_url = \"mqtts://mqtt.jantaus.com\"");
+        assert!(error(config).contains("telemetry.http_url"));
+
+        let config = with("telemetry", "sink", "sink = \"http\"\nhttp_url = \"https://telemetry.jantaus.com/ingest\"");
+        config.validate().unwrap();
+        assert_eq!(Some("https://telemetry.jantaus.com/ingest"), config.get_telemetry_http_url());
+    }
+
+    #[test]
+    fn motor_holds_only_when_asked() {
+        let config: Config = toml::from_str(EXAMPLE).unwrap();
+        assert!(!config.get_hold_between_moves());
+        let config = with("motor", "hold_between_moves", "hold_between_moves = true");
+        assert!(config.get_hold_between_moves());
+    }
+
+    #[test]
+    fn homing_directions_default_to_the_switch_side() {
+        let config = with("homing", "direction", "direction = \"ccw\"");
+        assert_eq!(
+            motion::HomingStrategy::uniform(motion::HomingDirection::Ccw),
+            config.get_homing_strategy()
+        );
+
+        let config = with("homing", "direction", "direction = \"ccw\"\nevening_direction = \"cw\"");
+        let strategy = config.get_homing_strategy();
+        assert_eq!(motion::HomingDirection::Ccw, strategy.morning);
+        assert_eq!(motion::HomingDirection::Cw, strategy.evening);
+    }
+
+    #[test]
+    fn rejects_bad_broker_url() {
+        let config = with("mqtt", "broker_url", "broker_url = \"https://mqtt.jantaus.com\"");
+        assert!(error(config).contains("mqtt.broker_url"));
+    }
+}
@@ -0,0 +1,32 @@
+// Heap and stack figures from ESP-IDF, logged and sent with the heartbeat to spot slow leaks.
+
+use esp_idf_svc::sys::{esp_get_free_heap_size, esp_get_minimum_free_heap_size, uxTaskGetStackHighWaterMark};
+use log::*;
+use std::ptr;
+
+// Bytes of heap free right now.
+pub fn free_heap() -> u32 {
+    // SAFETY: no arguments, only reads the allocator's counters.
+    unsafe { esp_get_free_heap_size() }
+}
+
+// Lowest `free_heap` since boot. A figure that keeps falling across days is a leak.
+pub fn min_free_heap() -> u32 {
+    // SAFETY: as for `free_heap`.
+    unsafe { esp_get_minimum_free_heap_size() }
+}
+
+// Bytes of the calling task's stack that have never been used (ESP-IDF counts stack in bytes).
+pub fn stack_high_water_mark() -> u32 {
+    // SAFETY: a null handle means the calling task.
+    unsafe { uxTaskGetStackHighWaterMark(ptr::null_mut()) }
+}
+
+pub fn log_memory() {
+    info!(
+        "Heap free: {} bytes (minimum {}), stack never used: {} bytes",
+        free_heap(),
+        min_free_heap(),
+        stack_high_water_mark()
+    );
+}
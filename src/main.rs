@@ -1,47 +1,73 @@
 // IMPORTS
-use std::time::{Duration, SystemTime};
+use std::time::{Duration, Instant, SystemTime};
 use chrono::{DateTime, FixedOffset, Utc};
-use clock::Clock;
+use clock::{is_plausible_time, Clock, DriftTracker, DEFAULT_RESYNC_INTERVAL};
+use event_log::{EventKind, EventLog};
 use log::*;
+use std::sync::mpsc::Receiver;
 use std::thread;
 use esp_idf_hal::peripherals::Peripherals;
 use esp_idf_svc::{
     eventloop::EspSystemEventLoop,
     hal::{
-        gpio::PinDriver,
         i2c::{I2cConfig, I2cDriver},
         prelude::*,
     },
     log::EspLogger,
-    nvs::{EspDefaultNvsPartition, EspNvs},
+    nvs::{EspDefaultNvsPartition, EspNvs, NvsDefault},
     ota::EspOta,
     sntp::{EspSntp, SyncStatus},
 };
-use motion::Motion;
+use buttons::{ButtonConfig, Buttons};
+use motion::{watchdog, Command, Motion, MoveOutcome, OperatingMode};
 use rgb_led::Led;
+use sensors::Sensors;
+use network::command::LocationUpdate;
+use network::heartbeat::{Heartbeat, HEARTBEAT_TOPIC};
+use network::http::HttpTelemetry;
 use network::mqtt::Mqtt;
-use ota::OtaUpdater;
+use network::selftest::{Check, SelfTestReport, SELFTEST_TOPIC};
+use ota::{OtaOutcome, OtaScheduler, OtaSettings, OtaUpdater};
 use semver::Version;
-use wifi::wifi::{Wifi, WifiState};
+use util::retry;
+use wifi::wifi::{Wifi, WifiState, DEFAULT_CONNECT_TIMEOUT};
+
+mod config;
+mod diagnostics;
+mod panic_handler;
+
+use config::Config;
 
 // Constants (Note to self: add these to .env file once done making one)
 const WIFI_CONNECT_DELAY_SECS: u64 = 20;
+// Rounds of `connect_any` over every configured network before giving up at boot, the first
+// retry after this long (doubling each time)
+const WIFI_CONNECT_ATTEMPTS: u32 = 3;
+const WIFI_RETRY_DELAY_SECS: u64 = 10;
 const TRACKING_LOOP_SLEEP_SECS: u64 = 300;
 const OTA_CHECK_DELAY_SECS: u64 = 3;
+const BUTTON_POLL_INTERVAL_MS: u64 = 10;
+// Maintenance mode jog per east/west click, and per poll while one is held down
+const JOG_STEP_DEG: f32 = 0.1;
+// Presses longer than this are long presses (a maintenance long press re-homes)
+const BUTTON_LONG_PRESS_MS: u64 = 1500;
+// Feed the anemometer to the wind stow every cycle. Off until towers have one fitted: a
+// floating ADC pin could read as a gale.
+const ANEMOMETER_FITTED: bool = false;
+// Boot self-test: limit switch samples that must agree, and how far from the 90 degree sleep
+// position a restored heading may be while the switch reads pressed
+const SELF_TEST_SWITCH_READS: usize = 5;
+const SELF_TEST_SWITCH_TOLERANCE_DEG: f32 = 5.0;
+// Trim the DS3231 aging offset from the drift measured at each daily NTP resync
+const RTC_AGING_COMPENSATION: bool = true;
+// Reboot if the tracking loop stops feeding the task watchdog for this long: four normal cycles,
+// longer than the 15 minute waits of the overnight and critical-failure loops
+const WATCHDOG_TIMEOUT_SECS: u64 = 4 * TRACKING_LOOP_SLEEP_SECS;
 
-const MQTT_BROKER_URL: &str = "mqttS://mqtt.jantaus.com:9443";
-const MQTT_CLIENT_ID: &str = "device1A_pub";
-
-const DEFAULT_VERSION: &str = "1.0.4";
-const HEADING_TAG: &str = "heading";
-
-// ======== Encoder snapshot contract (Stage 0) ========
-// If you ever change meanings / units, bump this version and ignore old snapshots on boot.
-const ENC_SNAPSHOT_VERSION: u32 = 1;
+// Version of the running firmware, the baseline for OTA updates
+const FIRMWARE_VERSION: &str = env!("CARGO_PKG_VERSION");
 
-// NVS keys for resuming position after reboot (incremental encoder; tower is non-backdrivable).
-const NVS_KEY_ENC_SNAPSHOT_VERSION: &str = "enc_snapshot_v";
-const NVS_KEY_ENC_TICKS_ADJ: &str = "enc_ticks_adj";
+// The position snapshot (heading + encoder ticks) lives in motion::snapshot.
 // Optional keys we may add later:
 // const NVS_KEY_ENC_ZERO_OFFSET: &str = "enc_zero_offset";
 // const NVS_KEY_SNAPSHOT_STATE: &str = "enc_snap_state";
@@ -50,6 +76,9 @@ const NVS_KEY_ENC_TICKS_ADJ: &str = "enc_ticks_adj";
 // This is ticks, in the adjusted coordinate system (0 at limit switch).
 const ENC_HOME_TOL_TICKS: i32 = 50;
 
+// Used only if no configuration can be loaded (see `fallback_config`)
+const DEFAULT_OTA_USER: &str = "device1A";
+const DEFAULT_OTA_PASS: &str = "device1A";
 const DEFAULT_MQTT_USER: &str = "device1A";
 const DEFAULT_MQTT_PASS: &str = "device1A";
 const DEFAULT_WIFI_SSID: &str = "Power2";
@@ -74,6 +103,8 @@ fn main() -> anyhow::Result<()> {
     // SYSTEM INITIALIZATION
     esp_idf_svc::sys::link_patches();
     EspLogger::initialize_default();
+    panic_handler::install();
+    let boot_time = Instant::now();
     let sysloop = EspSystemEventLoop::take()?;
     
     let peripherals = Peripherals::take().unwrap();
@@ -85,6 +116,50 @@ fn main() -> anyhow::Result<()> {
         }
         Err(e) => panic!("Could't get namespace {:?}", e),
     };
+    panic_handler::set_crash_store(nvs_default.clone());
+    // Boots, OTA updates, stalls, failed homing, panics and mode changes, kept across reboots
+    let event_log = match EventLog::open(nvs_default.clone()) {
+        Ok(event_log) => {
+            panic_handler::set_event_log(event_log.clone());
+            Some(event_log)
+        }
+        Err(e) => {
+            warn!("Event log unavailable: {:?}", e);
+            None
+        }
+    };
+
+    // CRASH-LOOP ROLLBACK
+
+    // A firmware that crashes before running STABLE_RUN_TIME, MAX_UNSTABLE_BOOTS times in a row,
+    // is rolled back even if it passed the boot diagnostic
+    let unstable_boots = ota::record_boot(&mut nvs);
+    if let Some(event_log) = &event_log {
+        event_log.record(
+            EventKind::Boot,
+            &format!("firmware {}, unstable boot {}", FIRMWARE_VERSION, unstable_boots),
+        );
+    }
+    if unstable_boots > ota::MAX_UNSTABLE_BOOTS {
+        let mut ota_slots = EspOta::new().expect("Failed to get OTA instance");
+        let running_factory = ota_slots.get_running_slot().map(|slot| slot.label == "factory").unwrap_or(false);
+        error!("{} unstable boots in a row", unstable_boots);
+        // The image booted next starts counting afresh
+        if let Err(e) = ota::clear_unstable_boots(&mut nvs) {
+            error!("Failed to reset unstable boot count: {:?}", e);
+        }
+        if running_factory {
+            warn!("Running from factory partition -> nothing to roll back to");
+        } else {
+            error!("Rolling back firmware after repeated crashes");
+            if let Some(event_log) = &event_log {
+                event_log.record(EventKind::Ota, &format!("rollback after {} unstable boots", unstable_boots));
+            }
+            let e = ota_slots.mark_running_slot_invalid_and_reboot();
+            error!("Rollback failed: {:?}", e);
+        }
+    }
+    spawn_stable_boot_timer(nvs_default.clone());
 
     // Encoder pins (move them once; pass into Motion::new later)
     let encoderA = peripherals.pins.gpio47;
@@ -97,61 +172,49 @@ fn main() -> anyhow::Result<()> {
     let i2c = I2cDriver::new(peripherals.i2c0, sda, scl, &config).unwrap();
     let bus: &'static _ = shared_bus::new_std!(I2cDriver = i2c).unwrap();
 
+    // Motion owns the relay, so it is created early: every deliberate reboot goes through it.
+    let mut motion = Motion::new(
+        peripherals.pins.gpio15,   // CCW Motor
+        peripherals.pins.gpio16,   // CW Motor
+        peripherals.pins.gpio17,   // Relay 
+        peripherals.pins.gpio14,   // Limit Switch 
+        encoderA,                  // Encoder A
+        encoderB,                  // Encoder B
+    );
+    motion.set_event_log(event_log);
+    match EspNvs::new(nvs_default.clone(), "storage", true) {
+        Ok(position_store) => motion.set_position_store(position_store),
+        Err(e) => warn!("Position will not be saved, every boot homes: {:?}", e),
+    }
+
      
-    //CREDENTIALS CONFIGURATION 
-    // todo!("Implement a .env");
-    
-    let mqtt_user = "device1A";
-    match nvs.set_str("mqtt_user", mqtt_user) {
-        Ok(_) => info!("Mqtt username updated"),
-        Err(e) => error!("Mqtt username not updated {:?}", e),
-    };
-    
-    let mqtt_pass = "device1A";
-    match nvs.set_str("mqtt_pass", mqtt_pass) {
-        Ok(_) => info!("Mqtt password updated"),
-        Err(e) => error!("Mqtt password not updated {:?}", e),
-    };
-    
-    let wifi_ssid = "Power2";
-    match nvs.set_str("wifi_ssid", wifi_ssid) {
-        Ok(_) => info!("Wifi ssid updated"),
-        Err(e) => error!("Wifi ssid not updated {:?}", e),
-    };
-    
-    let wifi_pass = "@Powerfuture22";
-    match nvs.set_str("wifi_pass", wifi_pass) {
-        Ok(_) => info!("Wifi password updated"),
-        Err(e) => error!("Wifi password not updated {:?}", e),
-    };
-    
-    let offset_hours = -5;
-    match nvs.set_i32("offset_hours", offset_hours) {
-        Ok(_) => info!("Timezone offset has been updated"),
-        Err(e) => error!("Timezone offset was not updated {:?}", e),
-    };
+    //CONFIGURATION
+
+    // config.toml if there is one, else the defaults embedded in the binary
+    let mut config = Config::load().unwrap_or_else(|e| {
+        error!("Failed to load configuration, using built-in defaults: {:?}", e);
+        fallback_config()
+    });
+    if config.is_ota_disabled() {
+        // Only ever freezes: a freeze set remotely must survive a config without one
+        if let Err(e) = ota::set_ota_disabled(&mut nvs, true) {
+            error!("Failed to disable OTA: {:?}", e);
+        }
+    }
 
      
     // WIFI INITIALIZATION
-    
-    let mut buffer = [0u8; 64];
-    let real_wifi_ssid = nvs
-        .get_str("wifi_ssid", &mut buffer)?
-        .expect("Wifi ssid not found")
-        .to_string();
-    let real_wifi_pass = nvs
-        .get_str("wifi_pass", &mut buffer)?
-        .expect("Wifi password not found")
-        .to_string();
 
     let mut wifi = Wifi::new(peripherals.modem, sysloop.clone(), nvs_default)?;
     log::info!("Waiting for 20 seconds before connecting to wifi");
     thread::sleep(Duration::from_secs(WIFI_CONNECT_DELAY_SECS));
-	wifi.connect(&real_wifi_ssid, &real_wifi_pass).expect("Wi-Fi connection failed");
+	let networks = config.get_wifi_networks();
+	let ssid = retry::with_backoff(WIFI_CONNECT_ATTEMPTS, Duration::from_secs(WIFI_RETRY_DELAY_SECS), |_| {
+	    wifi.connect_any(&networks, DEFAULT_CONNECT_TIMEOUT)
+	})
+	.expect("Wi-Fi connection failed");
+	info!("Connected to {}", ssid);
 	info!("Current wifi state: {:?}", wifi.state());
-    if wifi.state() == WifiState::Disconnected{
-        wifi.reconnect_if_disconnected()?;
-    }
 
      
     //TIME SYNCHRONIZATION
@@ -163,7 +226,7 @@ fn main() -> anyhow::Result<()> {
 
     let st_now = SystemTime::now();
     let dt_now_utc: DateTime<Utc> = st_now.clone().into();
-    let timezone_offset_hours: i32 = nvs.get_i32("offset_hours")?.unwrap_or(DEFAULT_TZ_OFFSET_HOURS);
+    let timezone_offset_hours: i32 = config.get_timezone_offset();
     let local_time: DateTime<FixedOffset> = DateTime::from_naive_utc_and_offset(
         dt_now_utc.naive_utc(),
         FixedOffset::east_opt(timezone_offset_hours * 3600).unwrap(),
@@ -175,21 +238,20 @@ fn main() -> anyhow::Result<()> {
      
     //MQTT INITIALIZATION
     
-    let real_mqtt_user = nvs
-        .get_str("mqtt_user", &mut buffer)?
-        .expect("Mqtt username not found")
-        .to_string();
-    let real_mqtt_pass = nvs
-        .get_str("mqtt_pass", &mut buffer)?
-        .expect("Mqtt password not found")
-        .to_string();
-
+    // Client id and every topic prefix derive from this, e.g. tower 1 is device1A
+    let device_id = config.get_device_id();
+    let status_topic = device_id.topic(network::mqtt::STATUS_TOPIC);
+    let mqtt_settings = config.get_mqtt_settings();
     let mut mqtt = Box::new(Mqtt::new_mqtt(
-        MQTT_BROKER_URL,
-        MQTT_CLIENT_ID,
-        &real_mqtt_user,
-        &real_mqtt_pass,
+        &mqtt_settings,
+        device_id,
+        &status_topic,
+        network::mqtt::STATUS_OFFLINE,
     )?);
+    match mqtt.panic_reporter(&mqtt.topic(panic_handler::PANIC_TOPIC)) {
+        Ok(reporter) => panic_handler::set_reporter(reporter),
+        Err(e) => warn!("Panics will not be reported over MQTT: {:?}", e),
+    }
 
      
     //BOOT VALIDATION
@@ -213,6 +275,7 @@ fn main() -> anyhow::Result<()> {
                 nvs.set_u8("first_boot", 0)?;
             } else {
                 error!("Boot validation failed, rolling back firmware");
+                motion.prepare_for_restart("Boot validation failed, rolling back firmware", &mut nvs, &mut mqtt);
                 valid_ota.mark_running_slot_invalid_and_reboot();
             }
         }
@@ -223,129 +286,186 @@ fn main() -> anyhow::Result<()> {
      
     //OTA UPDATE SYSTEM
 
-    let mut version_buf = [0u8; 32];
-    info!("Setting the firmware version...");
-    nvs.set_str("version", DEFAULT_VERSION)?;
-    let current_version: Version = nvs
-        .get_str("version", &mut version_buf)?
-        .map(|s| s.trim().parse::<Version>())
-        .transpose()?
-        .unwrap_or_else(|| Version::parse(DEFAULT_VERSION).unwrap());
+    let current_version = Version::parse(FIRMWARE_VERSION)?;
+    // NVS only records what was flashed; a mismatch means a rollback or a manually flashed build
+    let recorded = match ota::load_or_seed_version(&mut nvs, &current_version) {
+        Ok(flashed) if flashed == current_version => true,
+        Ok(flashed) => {
+            warn!("NVS records {} as flashed, but {} is running", flashed, current_version);
+            false
+        }
+        Err(e) => {
+            warn!("Failed to read flashed firmware version from NVS: {:?}", e);
+            false
+        }
+    };
+    if !recorded {
+        if let Err(e) = ota::record_flashed_version(&mut nvs, &current_version) {
+            error!("Failed to record firmware version in NVS: {:?}", e);
+        }
+    }
 
     info!("The current firmware version is: {}", current_version.to_string());
     let mut payload = format!("The current firmware version is: {}", current_version.to_string());
-    mqtt.publish("device1A/firmware/version", payload.as_bytes())?;
+    mqtt.publish_retained(&mqtt.topic("firmware/version"), payload.as_bytes())?;
 
-    let mut updater = OtaUpdater::new_ota(
-        current_version.clone(),
-        &mut mqtt,
-        Some("device1A"),
-        Some("device1A"),
-    )
-    .expect("Failed to create OTA updater instance");
+    let ota_settings = config.get_ota_settings();
+    // Due at boot, then every check_interval day or night (see `run_scheduled_ota_check`)
+    let mut ota_schedule = OtaScheduler::new(ota_settings.check_interval);
 
     info!("Checking for new OTA update in 3 seconds...");
     thread::sleep(Duration::from_secs(OTA_CHECK_DELAY_SECS));
-    updater.run_version_compare(&mut nvs)?;
+    run_scheduled_ota_check(&mut ota_schedule, &wifi, &current_version, &ota_settings, &mut motion, &mut mqtt, &mut nvs);
 
      
     //TOWER CONFIGURATION
 
-    let tower_latitude: f64 = DEFAULT_TOWER_LATITUDE;
-    match nvs.set_str("tower_latitude", &tower_latitude.to_string()) {
-        Ok(_) => info!("Tower latitude has been updated"),
-        Err(e) => error!("Tower latitude was not updated {:?}", e),
-    };
-
-    let tower_longitude: f64 = DEFAULT_TOWER_LONGITUDE;
-    match nvs.set_str("tower_longitude", &tower_longitude.to_string()) {
-        Ok(_) => info!("Tower longitude has been updated"),
-        Err(e) => error!("Tower longitude was not updated {:?}", e),
-    };
+    let tower_id = config.get_tower_id();
+    let latitude = config.get_latitude();
+    let longitude = config.get_longitude();
+    let altitude = config.get_altitude();
 
-    let tower_id: u32 = DEFAULT_TOWER_ID;
-    let latitude = nvs
-        .get_str("tower_latitude", &mut buffer)?
-        .unwrap_or("0")
-        .parse()
-        .unwrap_or(0.0);
-    let longitude = nvs
-        .get_str("tower_longitude", &mut buffer)?
-        .unwrap_or("0")
-        .parse()
-        .unwrap_or(0.0);
-    let altitude: f64 = 0.0;
-
-    info!("Retrieved latitude: {}, and longitude: {}", latitude, longitude);
     info!("Tower id: {}, Lat: {}, Lon: {}, Alt: {}", tower_id, latitude, longitude, altitude);
 
      
     //HARDWARE INITIALIZATION
     
-    let mut calculation = Clock::new(bus.acquire_i2c(), latitude, longitude, altitude);
-    calculation.set_date_time(&local_time.naive_local());
-    
-    let mut led = Led::new(peripherals.pins.gpio7, peripherals.rmt.channel0).unwrap();
+    let mut calculation = Clock::new(
+        bus.acquire_i2c(),
+        latitude,
+        longitude,
+        altitude,
+        FixedOffset::east_opt(timezone_offset_hours * 3600).unwrap(),
+    );
+    // An RTC that lost power counts on from a garbage date; don't trust it until NTP has set it
+    let mut rtc_lost_power = match calculation.has_lost_power() {
+        Ok(lost) => lost,
+        Err(e) => {
+            error!("Failed to read RTC status: {:?}", e);
+            false
+        }
+    };
+    if rtc_lost_power {
+        warn!("RTC lost power, its time is invalid until set from NTP");
+    }
+    // 12-hour mode makes every hour read ambiguous; the time set below is 24-hour anyway
+    match calculation.ensure_24h_mode() {
+        Ok(true) => warn!("RTC was in 12-hour mode, switched to 24-hour"),
+        Ok(false) => {}
+        Err(e) => error!("Failed to check the RTC hour mode: {:?}", e),
+    }
+    match calculation.set_date_time(&local_time.naive_local()) {
+        Ok(()) if rtc_lost_power => rtc_lost_power = !clear_rtc_power_loss(&mut calculation),
+        Ok(()) => {}
+        Err(e) => error!("Failed to set RTC time: {:?}", e),
+    }
     
-    let mut motion = Motion::new(
-        peripherals.pins.gpio15,   // CCW Motor
-        peripherals.pins.gpio16,   // CW Motor
-        peripherals.pins.gpio17,   // Relay 
-        peripherals.pins.gpio14,   // Limit Switch 
-        encoderA,                  // Encoder A
-        encoderB,                  // Encoder B
+    let mut sensors = Sensors::new(
+        bus.acquire_i2c(),
+        peripherals.adc1,
+        peripherals.pins.gpio2,    // East LDR
+        peripherals.pins.gpio3,    // West LDR
+        peripherals.pins.gpio1,    // Anemometer
     );
+
+    let mut led = Led::new(peripherals.pins.gpio7, peripherals.rmt.channel0).unwrap();
     
     motion.init();
+    motion.load_motor_tuning(&mut nvs);
+    motion.set_move_perf_reporting(config.get_publish_move_perf());
+    motion.set_site(config.get_homing_direction(), config.get_correction_factor());
+    // Anything an installer set with `calibrate` replaces the configured values
+    motion.load_calibration(&mut nvs);
+    // After the calibration, so the strategy is checked against the installed soft limits
+    if let Err(e) = motion.set_homing_strategy(config.get_homing_strategy()) {
+        warn!("Homing strategy rejected, homing {:?} both ways: {}", motion.homing_direction(), e);
+    }
+    motion.set_hold_between_moves(config.get_hold_between_moves());
+    motion.set_persist_interval(config.get_position_save_interval());
+    match mqtt.panic_reporter(&mqtt.topic(motion::MOVE_PROGRESS_TOPIC)) {
+        Ok(reporter) => motion.set_progress_reporter(Some(reporter)),
+        Err(e) => warn!("Move progress will not be published: {:?}", e),
+    }
+    // An HTTP sink that can't be created leaves telemetry on MQTT (see set_telemetry_transport)
+    let http_telemetry = config.get_telemetry_http_url().and_then(|url| {
+        let (username, password) = config.get_telemetry_credentials();
+        HttpTelemetry::new(url, username, password)
+            .map_err(|e| error!("Failed to create the HTTP telemetry sink for {}: {:?}", url, e))
+            .ok()
+    });
+    motion.set_telemetry_transport(config.get_telemetry_transport(), http_telemetry);
     led.display_healthy();
     motion.run();
 
      
     // HEADING INITIALIZATION
 
-    let heading_tag = HEADING_TAG;
-    let mut actual_heading: f32 = 90.0;
+    // A warm reboot with a trusted snapshot resumes where it left off; otherwise home below
+    let resumed = motion.restore_position();
+    let mut actual_heading: f32 = if resumed { motion.location() } else { 90.0 };
 
-    match nvs.set_u32(heading_tag, actual_heading.to_bits()) {
-        Ok(_) => info!("heading updated"),
-        Err(e) => error!("heading not updated {:?}", e),
-    };
+    self_test(
+        &wifi,
+        &mut mqtt,
+        &mut calculation,
+        &mut sensors,
+        &mut motion,
+        resumed.then_some(actual_heading),
+    );
 
-    match nvs.get_u32(heading_tag).unwrap() {
-        Some(v) => {
-            info!("{:?} = {:?}", heading_tag, v);
-            actual_heading = f32::from_bits(v);
-        }
-        None => info!("{:?} not found", heading_tag),
-    };
+    // Remote commands are only executed between tracking cycles
+    // (see motion::command for the dispatch discipline).
+    let remote_commands = mqtt.commands();
+    mqtt.set_stop_flag(motion.stop_flag());
+    // Every boot starts in Auto; replace whatever mode was retained before the reboot
+    motion.publish_mode(&mut mqtt);
 
-    let mut mb = PinDriver::input(peripherals.pins.gpio5).unwrap();  // Maintenance Button
-    let mut eb = PinDriver::input(peripherals.pins.gpio4).unwrap();  // East Button
-    let mut wb = PinDriver::input(peripherals.pins.gpio6).unwrap();  // West Button
+    let mut buttons = Buttons::with_config(
+        peripherals.pins.gpio5,    // Maintenance Button
+        peripherals.pins.gpio4,    // East Button
+        peripherals.pins.gpio6,    // West Button
+        ButtonConfig {
+            hold: Duration::from_millis(BUTTON_LONG_PRESS_MS),
+            ..ButtonConfig::default()
+        },
+    );
 
      
     // HOMING SEQUENCE
-    // todo!("Implement an encoder to re-position in case of power failure");
 
-    let limit_sw_status = motion.find_limit_switch_cw();
+    let limit_sw_status = resumed || motion.find_limit_switch();
     match limit_sw_status {
+        true if resumed => log::info!("Resumed at heading {} from NVS, homing skipped", actual_heading),
         true => log::info!("Limit switch has returned true"),
         false => {
             log::error!("Limit switch has returned false, limit switch could not be found");
+            motion.publish_limit_switch_history(&mut mqtt);
             loop {
-                if let Err(e) = mqtt.publish("device1A/tower/status", b"Critical failure: Limit switch failure!") {
+                if let Err(e) = mqtt.publish_retained(&mqtt.topic("tower/status"), b"Critical failure: Limit switch failure!") {
                     log::error!("Failed to publish critical error message: {:?}", e);
                 }
                 thread::sleep(Duration::from_secs(900)); // Loop every 15 minutes
             }
         }
     }
+    actual_heading = motion.location();
     thread::sleep(Duration::from_secs(5));
 
      
     // MAIN TRACKING LOOP
 
+    // The RTC was set from NTP above
+    let mut last_rtc_resync = Instant::now();
+    let mut rtc_drift = DriftTracker::default();
+
+    // From here on a hang reboots the tower instead of freezing it
+    match watchdog::init(Duration::from_secs(WATCHDOG_TIMEOUT_SECS)).and_then(|_| watchdog::subscribe()) {
+        Ok(()) => info!("Task watchdog armed ({}s)", WATCHDOG_TIMEOUT_SECS),
+        Err(e) => error!("Failed to arm task watchdog: {:?}", e),
+    }
+
     loop {
+        watchdog::feed();
         let st_now = SystemTime::now();
         let dt_now_utc: DateTime<Utc> = st_now.into();
         let local_time: DateTime<FixedOffset> = DateTime::from_naive_utc_and_offset(
@@ -359,60 +479,452 @@ fn main() -> anyhow::Result<()> {
 
         let now = std::time::Instant::now();
 
-        let tracking_done = motion.set_tower_position(
-            &mut calculation,
-            actual_heading,
-            0,
-            &mut mqtt,
-            current_version.clone(),
-            &mut nvs,
-            &mut wifi,
-            current_datetime.clone(),
-        );
-
-        if !tracking_done {
-            actual_heading = motion.location();
-            match nvs.set_u32(heading_tag, actual_heading.to_bits()) {
-                Ok(_) => info!("Stored stable heading in NVS: {}", actual_heading),
-                Err(e) => warn!("Failed to store heading in NVS: {:?}", e),
+        // A failed LDR read only disables L3 fine tracking for this cycle
+        let balance = match sensors.balance_gap() {
+            Ok(balance) => balance,
+            Err(e) => {
+                warn!("Light balance unavailable: {}", e);
+                0
             }
+        };
 
-            // ======== Stage 2: persist encoder snapshot (non-backdrivable tower) ========
-            // Convention: adjusted ticks are 0 at the limit switch, CW positive.
-            let enc_ticks_adj = motion.encoder_ticks_adjusted();
-            if let Err(e) = nvs.set_u32(NVS_KEY_ENC_SNAPSHOT_VERSION, ENC_SNAPSHOT_VERSION) {
-                warn!(
-                    "Failed to store encoder snapshot version in NVS ({}): {:?}",
-                    NVS_KEY_ENC_SNAPSHOT_VERSION, e
-                );
+        match (sensors.east_lux(), sensors.west_lux()) {
+            (Ok(east), Ok(west)) => info!("Light: east {:.0} lux, west {:.0} lux", east, west),
+            (Err(e), _) | (_, Err(e)) => warn!("Light level unavailable: {}", e),
+        }
+
+        let (temperature, humidity) = match sensors.read_both() {
+            Ok((temperature, humidity)) => (Some(temperature), Some(humidity)),
+            Err(e) => {
+                warn!("Temperature and humidity unavailable: {}", e);
+                (None, None)
             }
-            if let Err(e) = nvs.set_i32(NVS_KEY_ENC_TICKS_ADJ, enc_ticks_adj) {
-                warn!(
-                    "Failed to store encoder ticks in NVS ({}): {:?}",
-                    NVS_KEY_ENC_TICKS_ADJ, e
-                );
-            } else {
-                info!(
-                    "Stored encoder snapshot in NVS: {}={} (v={})",
-                    NVS_KEY_ENC_TICKS_ADJ, enc_ticks_adj, ENC_SNAPSHOT_VERSION
-                );
+        };
+        motion.set_environment(temperature, humidity);
+
+        if ANEMOMETER_FITTED {
+            match sensors.wind_speed() {
+                Ok(wind_speed) => {
+                    motion.report_wind(wind_speed, &mut mqtt);
+                }
+                Err(e) => warn!("Wind speed unavailable: {}", e),
             }
+        }
+
+        // A stowed tower stays put until the stow is cleared over MQTT (and a manual one until
+        // Auto is restored, see `set_tower_position`)
+        let tracking_done = if motion.is_stowed() {
+            info!("Tower stowed, skipping tracking cycle");
+            true
+        } else {
+            motion.set_tower_position(
+                &mut calculation,
+                actual_heading,
+                balance,
+                &mut mqtt,
+                &ota_schedule,
+                current_datetime.clone(),
+            )
+        };
+        motion.publish_stall_alert(&mut mqtt);
+        motion.publish_divergence_alarm(&mut mqtt);
+
+        if !tracking_done {
+            actual_heading = motion.location();
+            // Heading and encoder ticks together, so the next boot can skip homing
+            motion.save_position();
         } else {
             info!("True return from set tower position");
-            info!("Angle offset is less then 5");
+            info!("No move this cycle, tower stays at heading {}", motion.location());
         }
 
-        info!("Tracking loop duration (v1.0.4): {:?}", now.elapsed());
+        info!("Tracking loop duration (v{}): {:?}", current_version, now.elapsed());
         
         if wifi.state() == WifiState::Disconnected {
             warn!("Wifi disconnected, attempting to reconnect...");
-            wifi.reconnect_if_disconnected()?;
+            wifi.reconnect_with_backoff(DEFAULT_CONNECT_TIMEOUT)?;
+        }
+        run_scheduled_ota_check(&mut ota_schedule, &wifi, &current_version, &ota_settings, &mut motion, &mut mqtt, &mut nvs);
+
+        if rtc_lost_power && ntp.get_sync_status() == SyncStatus::Completed {
+            // The old time is garbage, so this isn't drift
+            let reference = Utc::now().with_timezone(&calculation.timezone_offset()).naive_local();
+            match calculation.set_date_time(&reference) {
+                Ok(()) => {
+                    rtc_lost_power = !clear_rtc_power_loss(&mut calculation);
+                    last_rtc_resync = Instant::now();
+                }
+                Err(e) => error!("Failed to set RTC time: {:?}", e),
+            }
+        } else if last_rtc_resync.elapsed() >= DEFAULT_RESYNC_INTERVAL && matches!(wifi.state(), WifiState::Connected(_)) {
+            if let Some(drift) = resync_rtc(&mut calculation, &ntp, &mut rtc_drift, last_rtc_resync.elapsed()) {
+                motion.set_rtc_drift(drift);
+                last_rtc_resync = Instant::now();
+            }
         }
         
         payload = format!("The current firmware version is: {}", current_version.to_string());
-        mqtt.publish("device1A/firmware/version", payload.as_bytes())?;
+        mqtt.publish_retained(&mqtt.topic("firmware/version"), payload.as_bytes())?;
+        publish_heartbeat(boot_time, &wifi, &mut mqtt, &mut motion);
         
-        std::thread::sleep(Duration::from_secs(TRACKING_LOOP_SLEEP_SECS)); // 5-minute cycle
+        // 5-minute cycle, watching the buttons so maintenance mode can be entered meanwhile
+        wait_for_next_cycle(
+            &mut motion,
+            &mut calculation,
+            &mut buttons,
+            &remote_commands,
+            &mut mqtt,
+            &mut nvs,
+            &mut config,
+            Duration::from_secs(TRACKING_LOOP_SLEEP_SECS),
+        );
+        actual_heading = motion.location();
+    }
+}
+
+ 
+// CONFIGURATION
+
+// The settings this firmware shipped with, for when no configuration parses.
+fn fallback_config() -> Config {
+    Config {
+        device: config::DeviceConfig {
+            tower_id: DEFAULT_TOWER_ID,
+            device_name: None,
+            ota_disabled: false,
+            profile: None,
+        },
+        wifi: config::WifiConfig {
+            ssid: DEFAULT_WIFI_SSID.to_string(),
+            password: DEFAULT_WIFI_PASS.to_string(),
+            networks: Vec::new(),
+        },
+        location: config::LocationConfig {
+            latitude: DEFAULT_TOWER_LATITUDE,
+            longitude: DEFAULT_TOWER_LONGITUDE,
+            altitude: 0.0,
+            timezone_offset_hours: DEFAULT_TZ_OFFSET_HOURS,
+        },
+        mqtt: config::MqttConfig {
+            username: Some(DEFAULT_MQTT_USER.to_string()),
+            password: Some(DEFAULT_MQTT_PASS.to_string()),
+            ..config::MqttConfig::default()
+        },
+        ota: config::OtaConfig {
+            username: Some(DEFAULT_OTA_USER.to_string()),
+            password: Some(DEFAULT_OTA_PASS.to_string()),
+            ..config::OtaConfig::default()
+        },
+        homing: config::HomingConfig::default(),
+        telemetry: config::TelemetryConfig::default(),
+        motor: config::MotorConfig::default(),
+        maintenance: config::MaintenanceConfig::default(),
+    }
+}
+
+ 
+// CRASH-LOOP ROLLBACK
+
+// Clear the unstable boot count once the firmware has run for STABLE_RUN_TIME. On its own
+// thread, so it happens on time even while the main loop waits hours for sunrise.
+fn spawn_stable_boot_timer(partition: EspDefaultNvsPartition) {
+    let spawned = thread::Builder::new().stack_size(4096).spawn(move || {
+        thread::sleep(ota::STABLE_RUN_TIME);
+        let cleared = EspNvs::new(partition, "storage", true)
+            .map_err(anyhow::Error::from)
+            .and_then(|mut nvs| ota::clear_unstable_boots(&mut nvs));
+        match cleared {
+            Ok(()) => info!("Ran stably for {:?}, cleared unstable boot count", ota::STABLE_RUN_TIME),
+            Err(e) => error!("Failed to clear unstable boot count: {:?}", e),
+        }
+    });
+    if let Err(e) = spawned {
+        error!("Failed to start stable boot timer: {:?}", e);
+    }
+}
+
+ 
+// OTA CHECK
+
+// Run `run_version_compare` if the schedule says it's due and Wi-Fi is up, restarting into a
+// newly installed image. Without Wi-Fi the check stays due and is retried next cycle.
+fn run_scheduled_ota_check(
+    schedule: &mut OtaScheduler,
+    wifi: &Wifi,
+    current_version: &Version,
+    settings: &OtaSettings,
+    motion: &mut Motion,
+    mqtt: &mut Mqtt,
+    nvs: &mut EspNvs<NvsDefault>,
+) {
+    if !schedule.is_due(Instant::now()) {
+        return;
+    }
+    if !matches!(wifi.state(), WifiState::Connected(_)) {
+        warn!("OTA check due but Wi-Fi is down, retrying next cycle");
+        return;
+    }
+    let outcome = match OtaUpdater::from_settings(current_version.clone(), mqtt, settings) {
+        Ok(mut updater) => updater.run_version_compare(nvs),
+        Err(e) => Err(e),
+    };
+    schedule.mark_checked(Instant::now());
+    match outcome {
+        Ok(OtaOutcome::Installed(version)) => {
+            let reason = format!("OTA update to {} installed", version);
+            motion.record_event(EventKind::Ota, &reason);
+            motion.safe_restart(&reason, nvs, mqtt);
+        }
+        Ok(OtaOutcome::UpToDate) => info!("Firmware up to date, next OTA check in {:?}", schedule.interval()),
+        Ok(OtaOutcome::Disabled) => info!("OTA disabled, skipping update check"),
+        // Tracking goes on with the running firmware; the next scheduled check tries again
+        Err(e) if e.is_transient() => warn!("OTA check failed, retrying in {:?}: {}", schedule.interval(), e),
+        Err(e) => error!("OTA update rejected: {}", e),
+    }
+}
+
+ 
+// RTC RESYNC
+
+// Set the RTC back to NTP time and return how far it had drifted (seconds, positive if fast).
+// ESP-IDF keeps the system time synced in the background; None if that has not completed.
+fn resync_rtc<I2C: embedded_hal::i2c::I2c>(
+    clock: &mut Clock<I2C>,
+    ntp: &EspSntp,
+    drift: &mut DriftTracker,
+    since_last: Duration,
+) -> Option<i64> {
+    if ntp.get_sync_status() != SyncStatus::Completed {
+        warn!("NTP not synced, postponing RTC resync");
+        return None;
+    }
+    let reference = Utc::now().with_timezone(&clock.timezone_offset()).naive_local();
+    let drift_secs = match clock.correct_time(&reference) {
+        Ok(drift_secs) => drift_secs,
+        Err(e) => {
+            error!("RTC resync failed: {:?}", e);
+            return None;
+        }
+    };
+    info!("RTC resynced to NTP, drift was {} s over {:?}", drift_secs, since_last);
+
+    if RTC_AGING_COMPENSATION {
+        if let Some(delta) = drift.record(drift_secs, since_last) {
+            match clock.adjust_aging_offset(delta) {
+                Ok(offset) => info!("RTC aging offset adjusted by {} to {}", delta, offset),
+                Err(e) => error!("Failed to adjust RTC aging offset: {:?}", e),
+            }
+        }
+    }
+    Some(drift_secs)
+}
+
+// Clear the OSC_STOP flag after setting the RTC from NTP. False if it is still set, so the next
+// cycle sets the time again.
+fn clear_rtc_power_loss<I2C: embedded_hal::i2c::I2c>(clock: &mut Clock<I2C>) -> bool {
+    match clock.clear_power_loss_flag() {
+        Ok(()) => {
+            info!("RTC time restored from NTP after power loss");
+            true
+        }
+        Err(e) => {
+            error!("Failed to clear RTC power loss flag: {:?}", e);
+            false
+        }
+    }
+}
+
+// Set the RTC from a remote `set_time` command and publish the outcome to tower/status. The
+// recovery path for a clock that has drifted while NTP is unreachable.
+fn set_rtc_time<I2C: embedded_hal::i2c::I2c>(clock: &mut Clock<I2C>, time: DateTime<FixedOffset>, mqtt: &mut Mqtt) {
+    let local = time.with_timezone(&clock.timezone_offset()).naive_local();
+    let status = if !is_plausible_time(&local) {
+        format!("Set time {} refused: not a plausible time", time.to_rfc3339())
+    } else {
+        match clock.set_date_time(&local) {
+            Ok(()) => format!("RTC set to {}", local),
+            Err(e) => format!("Set time {} failed: {:?}", time.to_rfc3339(), e),
+        }
+    };
+    info!("{}", status);
+    if let Err(e) = mqtt.publish_retained(&mqtt.topic("tower/status"), status.as_bytes()) {
+        error!("Failed to publish set time status: {:?}", e);
+    }
+}
+
+// Save a new site location to config.toml; the sun is tracked from it after the next boot.
+fn set_location(config: &mut Config, update: &LocationUpdate, mqtt: &mut Mqtt) {
+    let status = match config.update_location(update) {
+        Ok(()) => format!("Location saved, applies after reboot: {:?}", config.location),
+        Err(e) => format!("Set location refused: {}", e),
+    };
+    info!("{}", status);
+    if let Err(e) = mqtt.publish_retained(&mqtt.topic("tower/status"), status.as_bytes()) {
+        error!("Failed to publish set location status: {:?}", e);
+    }
+}
+
+ 
+// MAINTENANCE MODE
+ 
+// Sleep until the next tracking cycle while polling the buttons and executing remote commands.
+// A maintenance double-click enters maintenance mode and ends the wait early.
+fn wait_for_next_cycle<I2C: embedded_hal::i2c::I2c>(
+    motion: &mut Motion,
+    clock: &mut Clock<I2C>,
+    buttons: &mut Buttons,
+    remote_commands: &Receiver<Command>,
+    mqtt: &mut Mqtt,
+    nvs: &mut EspNvs<NvsDefault>,
+    config: &mut Config,
+    period: Duration,
+) {
+    let start = Instant::now();
+    while start.elapsed() < period {
+        watchdog::feed();
+        mqtt.service();
+        while let Ok(command) = remote_commands.try_recv() {
+            match command {
+                Command::SetTime { time } => set_rtc_time(clock, time, mqtt),
+                Command::SetLocation(update) => set_location(config, &update, mqtt),
+                command => motion.execute(command, mqtt),
+            }
+        }
+        // Speed, acceleration and calibration set over MQTT survive a reboot
+        motion.save_motor_tuning(nvs);
+        motion.save_calibration(nvs);
+        buttons.tick();
+        if buttons.maintenance_double() {
+            buttons.reset();
+            // Toggles: a tower put in Manual over MQTT goes back to Auto
+            if motion.mode() == OperatingMode::Manual {
+                info!("Maintenance double-click, resuming automatic tracking");
+                motion.set_mode(OperatingMode::Auto, mqtt);
+            } else {
+                run_maintenance(motion, buttons, mqtt, config.get_maintenance_inactivity_timeout());
+            }
+            return;
+        }
+        buttons.reset();
+        thread::sleep(Duration::from_millis(BUTTON_POLL_INTERVAL_MS));
+    }
+}
+
+// Automatic tracking is suspended until the tech double-clicks maintenance again. Meanwhile the
+// east/west buttons jog the tower by JOG_STEP_DEG per click, continuously while held, and a
+// maintenance long press re-homes.
+// If the buttons sit idle for `timeout` ([maintenance] inactivity_timeout_mins) the tower
+// re-homes and tracking resumes, so a tower left in maintenance doesn't lose a day's production.
+fn run_maintenance(motion: &mut Motion, buttons: &mut Buttons, mqtt: &mut Mqtt, timeout: Duration) {
+    info!("Entering maintenance mode, automatic tracking suspended");
+    // A stowed tower stays stowed through servicing
+    if motion.mode() == OperatingMode::Auto {
+        motion.set_mode(OperatingMode::Manual, mqtt);
+    }
+    if let Err(e) = mqtt.publish_retained(&mqtt.topic("tower/status"), b"Maintenance mode entered") {
+        error!("Failed to publish maintenance status: {:?}", e);
+    }
+
+    buttons.mark_activity();
+    loop {
+        watchdog::feed();
+        buttons.tick();
+        if buttons.maintenance_double() {
+            buttons.reset();
+            info!("Exiting maintenance mode, resuming tracking");
+            motion.save_moved_position();
+            leave_manual(motion, mqtt);
+            if let Err(e) = mqtt.publish_retained(&mqtt.topic("tower/status"), b"Maintenance mode exited") {
+                error!("Failed to publish maintenance status: {:?}", e);
+            }
+            return;
+        }
+        if buttons.is_maintenance_long_pressed() {
+            info!(
+                "Maintenance held for {:?}, re-homing",
+                buttons.maintenance_held_duration().unwrap_or_default()
+            );
+            buttons.reset();
+            rehome(motion, mqtt);
+            motion.save_moved_position();
+            buttons.mark_activity();
+            continue;
+        }
+        // Azimuth grows from east to west, so east jogs CCW
+        let jog = if buttons.is_east_held() || buttons.is_east_pressed() {
+            -JOG_STEP_DEG
+        } else if buttons.is_west_held() || buttons.is_west_pressed() {
+            JOG_STEP_DEG
+        } else {
+            0.0
+        };
+        buttons.reset();
+        if jog != 0.0 {
+            let outcome = motion.jog(jog, mqtt);
+            if !outcome.is_completed() {
+                warn!("Jog {} ended {:?} at heading {}", jog, outcome, motion.location());
+                motion.publish_stall_alert(mqtt);
+            }
+            // Rate limited: a held button jogs many times a second
+            motion.save_position();
+            buttons.mark_activity();
+            continue;
+        }
+
+        if buttons.idle_for() >= timeout {
+            warn!("No button activity for {:?}, leaving maintenance mode", timeout);
+            if let Err(e) = mqtt.publish_retained(
+                &mqtt.topic("tower/status"),
+                b"Maintenance inactivity timeout: re-homing and resuming tracking",
+            ) {
+                error!("Failed to publish maintenance status: {:?}", e);
+            }
+            rehome(motion, mqtt);
+            motion.save_moved_position();
+            leave_manual(motion, mqtt);
+            return;
+        }
+
+        thread::sleep(Duration::from_millis(BUTTON_POLL_INTERVAL_MS));
+    }
+}
+
+fn leave_manual(motion: &mut Motion, mqtt: &mut Mqtt) {
+    if motion.mode() == OperatingMode::Manual {
+        motion.set_mode(OperatingMode::Auto, mqtt);
+    }
+}
+
+fn rehome(motion: &mut Motion, mqtt: &mut Mqtt) {
+    if motion.rehome() {
+        if let Err(e) = mqtt.publish_retained(&mqtt.topic("tower/status"), b"Re-homed: encoder zeroed at the limit switch, heading 90") {
+            error!("Failed to publish re-home status: {:?}", e);
+        }
+    } else {
+        error!("Re-home from maintenance mode failed");
+        motion.publish_limit_switch_history(mqtt);
+        if let Err(e) = mqtt.publish_retained(&mqtt.topic("tower/status"), b"Critical failure: Limit switch failure!") {
+            error!("Failed to publish critical error message: {:?}", e);
+        }
+    }
+}
+
+// Publish this cycle's vitals to <device>/heartbeat
+fn publish_heartbeat(boot_time: Instant, wifi: &Wifi, mqtt: &mut Mqtt, motion: &mut Motion) {
+    diagnostics::log_memory();
+    let heartbeat = Heartbeat {
+        uptime_secs: boot_time.elapsed().as_secs(),
+        free_heap_bytes: diagnostics::free_heap(),
+        min_free_heap_bytes: diagnostics::min_free_heap(),
+        stack_high_water_bytes: diagnostics::stack_high_water_mark(),
+        wifi_rssi: wifi.rssi(),
+        mqtt_connected: mqtt.is_connected(),
+        location: motion.location(),
+        tracking_state: motion.tracking_state_name(),
+        mode: motion.mode().name(),
+    };
+    if let Err(e) = mqtt.publish_json(&mqtt.topic(HEARTBEAT_TOPIC), &heartbeat) {
+        error!("Failed to publish heartbeat: {:?}", e);
     }
 }
 
@@ -437,10 +949,10 @@ fn boot_diagnostic(wifi: &mut Wifi, mqtt: &mut Mqtt) -> bool {
         }
     }
 
-    const MAX_RETRIES: u8 = 3;
+    const MAX_ATTEMPTS: u32 = 3;
 
-    for attempt in 1..=MAX_RETRIES {
-        info!("Boot diagnostic MQTT attempt {}/{}", attempt, MAX_RETRIES);
+    let published = retry::with_backoff(MAX_ATTEMPTS, Duration::from_secs(1), |attempt| {
+        info!("Boot diagnostic MQTT attempt {}/{}", attempt, MAX_ATTEMPTS);
 
         let mut waited = 0;
         while !mqtt.is_connected() && waited < 12000 {
@@ -450,24 +962,100 @@ fn boot_diagnostic(wifi: &mut Wifi, mqtt: &mut Mqtt) -> bool {
 
         if !mqtt.is_connected() {
             warn!("MQTT not connected yet, retrying...");
-            continue;
+            return Err(anyhow::anyhow!("MQTT not connected"));
         }
 
-        match mqtt.publish("device1A/boot", b"Boot check...") {
-            Ok(_) => {
-                info!("MQTT boot diagnostic publish succeeded...");
-                return true;
-            }
-            Err(e) => {
-                error!("MQTT publish failed immediately: {:?}", e);
-                if attempt == MAX_RETRIES {
-                    error!("All MQTT boot diagnostic attempts failed...");
-                    return false;
-                }
-                thread::sleep(Duration::from_millis(1000));
-                continue;
+        mqtt.publish(&mqtt.topic("boot"), b"Boot check...")
+            .inspect_err(|e| error!("MQTT publish failed immediately: {:?}", e))
+    });
+    match published {
+        Ok(_) => {
+            info!("MQTT boot diagnostic publish succeeded...");
+            true
+        }
+        Err(_) => {
+            error!("All MQTT boot diagnostic attempts failed...");
+            false
+        }
+    }
+}
+
+ 
+// SELF-TEST FUNCTION
+
+// Check each subsystem on its own and publish the report to <device>/selftest, so a field tech
+// can see exactly which part failed. `expected_heading` is the heading restored from NVS, if any.
+fn self_test<C: embedded_hal::i2c::I2c, S: embedded_hal::i2c::I2c>(
+    wifi: &Wifi,
+    mqtt: &mut Mqtt,
+    clock: &mut Clock<C>,
+    sensors: &mut Sensors<'_, S>,
+    motion: &mut Motion,
+    expected_heading: Option<f32>,
+) -> SelfTestReport {
+    let wifi_check = match wifi.state() {
+        WifiState::Connected(_) => Check::pass(),
+        state => Check::fail(format!("Wi-Fi is {:?}", state)),
+    };
+    let mqtt_check = if mqtt.is_connected() {
+        Check::pass()
+    } else {
+        Check::fail("not connected to the broker")
+    };
+
+    let rtc = match (clock.get_date_time(), clock.has_lost_power()) {
+        (Err(e), _) | (_, Err(e)) => Check::fail(format!("RTC not responding: {:?}", e)),
+        (Ok(_), Ok(true)) => Check::fail("RTC lost power and its time was not restored"),
+        (Ok(time), Ok(false)) if !is_plausible_time(&time) => {
+            Check::fail(format!("RTC time {} is not plausible", time))
+        }
+        (Ok(_), Ok(false)) => Check::pass(),
+    };
+
+    let hdc1080 = match sensors.humidity_sensor_id() {
+        Ok(hdc1080::MANUFACTURER_ID) => Check::pass(),
+        Ok(id) => Check::fail(format!("HDC1080 manufacturer ID {:#06x}, expected {:#06x}", id, hdc1080::MANUFACTURER_ID)),
+        Err(e) => Check::fail(e.to_string()),
+    };
+    let adc = match sensors.balance_gap() {
+        Ok(_) => Check::pass(),
+        Err(e) => Check::fail(e.to_string()),
+    };
+
+    // A floating or broken switch input flickers between reads
+    let mut readings = [false; SELF_TEST_SWITCH_READS];
+    for reading in readings.iter_mut() {
+        *reading = motion.switch_pressed();
+        thread::sleep(Duration::from_millis(10));
+    }
+    let pressed = readings[0];
+    let limit_switch = if readings.iter().any(|&reading| reading != pressed) {
+        Check::fail("Limit switch reading is unstable")
+    } else {
+        match expected_heading {
+            // The switch sits at the 90 degree sleep position
+            Some(heading) if pressed && (heading - 90.0).abs() > SELF_TEST_SWITCH_TOLERANCE_DEG => {
+                Check::fail(format!("Limit switch pressed at restored heading {}", heading))
             }
+            _ => Check::pass(),
         }
+    };
+
+    let report = SelfTestReport {
+        wifi: wifi_check,
+        mqtt: mqtt_check,
+        rtc,
+        hdc1080,
+        adc,
+        limit_switch,
+    };
+    if report.passed() {
+        info!("Self-test passed");
+    } else {
+        error!("Self-test failed: {}", report.failures().join(", "));
+    }
+    if let Err(e) = mqtt.publish_json(&mqtt.topic(SELFTEST_TOPIC), &report) {
+        error!("Failed to publish self-test report: {:?}", e);
     }
-    return false;
+    report
 }
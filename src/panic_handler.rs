@@ -0,0 +1,97 @@
+// Panic hook: log the panic, record it in the event log, report it over MQTT if a client is
+// up, flag it for the crash-loop rollback, then reboot.
+//
+// Installed first thing in `main`, so panics during bring-up (peripherals, pin drivers) reboot
+// too; they just go unreported until `set_reporter` hands over a client.
+
+use esp_idf_svc::hal::reset::restart;
+use esp_idf_svc::nvs::{EspDefaultNvsPartition, EspNvs};
+use event_log::{EventKind, EventLog};
+use log::*;
+use network::mqtt::PanicReporter;
+use std::panic;
+use std::sync::mpsc;
+use std::sync::OnceLock;
+use std::thread;
+use std::time::Duration;
+
+// MQTT topic suffix for panic reports, e.g. device1A/panic
+pub const PANIC_TOPIC: &str = "panic";
+// How long the publish gets before the reboot, and the pause that lets the log drain
+const PUBLISH_TIMEOUT: Duration = Duration::from_secs(5);
+const REBOOT_DELAY: Duration = Duration::from_secs(1);
+
+static REPORTER: OnceLock<PanicReporter> = OnceLock::new();
+static EVENT_LOG: OnceLock<EventLog> = OnceLock::new();
+static CRASH_STORE: OnceLock<EspDefaultNvsPartition> = OnceLock::new();
+
+pub fn install() {
+    panic::set_hook(Box::new(|info| {
+        let message = info
+            .payload()
+            .downcast_ref::<&str>()
+            .copied()
+            .or_else(|| info.payload().downcast_ref::<String>().map(String::as_str))
+            .unwrap_or("<non-string panic payload>");
+        let report = match info.location() {
+            Some(location) => format!("Panic at {}:{}: {}", location.file(), location.line(), message),
+            None => format!("Panic: {}", message),
+        };
+        error!("{}", report);
+
+        if let Some(event_log) = EVENT_LOG.get() {
+            // Skipped if this thread panicked while recording another event
+            if !event_log.try_record(EventKind::Panic, &report) {
+                warn!("Event log busy, panic not recorded");
+            }
+        }
+
+        if let Some(reporter) = REPORTER.get() {
+            // Publish from a fresh thread: the panicking one may be the MQTT event thread, where a
+            // publish would deadlock, and a bounded wait keeps a stuck broker from delaying the reboot.
+            let (done_tx, done_rx) = mpsc::channel();
+            let spawned = thread::Builder::new().spawn(move || {
+                let _ = done_tx.send(reporter.publish(report.as_bytes()));
+            });
+            match spawned.map(|_| done_rx.recv_timeout(PUBLISH_TIMEOUT)) {
+                Ok(Ok(true)) => info!("Panic reported over MQTT"),
+                _ => warn!("Could not report the panic over MQTT"),
+            }
+        }
+
+        // restart() is a software reset like a deliberate one; the flag tells `ota::record_boot`
+        // this one was a crash
+        if let Some(partition) = CRASH_STORE.get() {
+            let flagged = EspNvs::new(partition.clone(), "storage", true).and_then(|mut nvs| ota::record_panic(&mut nvs));
+            if let Err(e) = flagged {
+                warn!("Could not flag the panic for the crash-loop rollback: {:?}", e);
+            }
+        }
+
+        warn!("Rebooting after panic");
+        thread::sleep(REBOOT_DELAY);
+        restart();
+    }));
+}
+
+// Report later panics through `reporter`. Only the first call takes effect.
+pub fn set_reporter(reporter: PanicReporter) {
+    if REPORTER.set(reporter).is_err() {
+        warn!("Panic reporter already set");
+    }
+}
+
+// Record later panics in `event_log`. Only the first call takes effect.
+pub fn set_event_log(event_log: EventLog) {
+    if EVENT_LOG.set(event_log).is_err() {
+        warn!("Panic event log already set");
+    }
+}
+
+// Flag later panics in the "storage" namespace of `partition`, for `ota::record_boot`. Only the
+// first call takes effect.
+pub fn set_crash_store(partition: EspDefaultNvsPartition) {
+    if CRASH_STORE.set(partition).is_err() {
+        warn!("Panic crash store already set");
+    }
+}
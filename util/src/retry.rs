@@ -0,0 +1,120 @@
+//! Retrying a fallible operation with exponential backoff, shared by the Wi-Fi, MQTT and OTA
+//! code so they all space their attempts the same way.
+
+use std::thread;
+use std::time::Duration;
+
+/// Wait after the `failures`-th consecutive failure: `base * 2^(failures-1)`, so `base`, twice
+/// that, four times ... Zero before the first failure.
+pub fn backoff_delay(base: Duration, failures: u32) -> Duration {
+    if failures == 0 {
+        return Duration::ZERO;
+    }
+    // 2^31 * base is long past any sensible wait; stop doubling there.
+    let factor = 1u32 << (failures - 1).min(31);
+    base.saturating_mul(factor)
+}
+
+/// Call `op` with the attempt number (from 1) until it succeeds or `max_attempts` have failed,
+/// sleeping `backoff_delay(base_delay, n)` after the n-th failure. On exhaustion returns the
+/// last attempt's error, without sleeping after it. At least one attempt is always made.
+pub fn with_backoff<T, E, F>(max_attempts: u32, base_delay: Duration, op: F) -> Result<T, E>
+where
+    F: FnMut(u32) -> Result<T, E>,
+{
+    retry(max_attempts, base_delay, op, thread::sleep)
+}
+
+fn retry<T, E, F, S>(max_attempts: u32, base_delay: Duration, mut op: F, mut sleep: S) -> Result<T, E>
+where
+    F: FnMut(u32) -> Result<T, E>,
+    S: FnMut(Duration),
+{
+    let mut attempt = 1;
+    loop {
+        match op(attempt) {
+            Ok(value) => return Ok(value),
+            Err(e) if attempt >= max_attempts => return Err(e),
+            Err(_) => {
+                sleep(backoff_delay(base_delay, attempt));
+                attempt += 1;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BASE: Duration = Duration::from_secs(2);
+
+    #[test]
+    fn delay_doubles_per_failure() {
+        assert_eq!(Duration::ZERO, backoff_delay(BASE, 0));
+        assert_eq!(Duration::from_secs(2), backoff_delay(BASE, 1));
+        assert_eq!(Duration::from_secs(4), backoff_delay(BASE, 2));
+        assert_eq!(Duration::from_secs(8), backoff_delay(BASE, 3));
+        // Saturates instead of overflowing
+        assert_eq!(Duration::MAX, backoff_delay(Duration::MAX, 40));
+    }
+
+    #[test]
+    fn succeeds_on_the_nth_try() {
+        let mut sleeps = Vec::new();
+        let mut attempts = Vec::new();
+        let result: Result<u32, &str> = retry(
+            5,
+            BASE,
+            |attempt| {
+                attempts.push(attempt);
+                if attempt < 3 {
+                    Err("not yet")
+                } else {
+                    Ok(attempt * 10)
+                }
+            },
+            |delay| sleeps.push(delay),
+        );
+        assert_eq!(Ok(30), result);
+        assert_eq!(vec![1, 2, 3], attempts);
+        assert_eq!(vec![Duration::from_secs(2), Duration::from_secs(4)], sleeps);
+    }
+
+    #[test]
+    fn exhaustion_returns_the_last_error() {
+        let mut sleeps = Vec::new();
+        let result: Result<(), u32> = retry(3, BASE, Err, |delay| sleeps.push(delay));
+        assert_eq!(Err(3), result);
+        // No wait after the final attempt
+        assert_eq!(2, sleeps.len());
+    }
+
+    #[test]
+    fn always_makes_one_attempt() {
+        let mut calls = 0;
+        let result: Result<(), ()> = retry(
+            0,
+            BASE,
+            |_| {
+                calls += 1;
+                Err(())
+            },
+            |_| panic!("no retry to wait for"),
+        );
+        assert!(result.is_err());
+        assert_eq!(1, calls);
+    }
+
+    #[test]
+    fn sleeps_for_real_between_attempts() {
+        let result: Result<u32, ()> = with_backoff(2, Duration::from_millis(1), |attempt| {
+            if attempt == 2 {
+                Ok(attempt)
+            } else {
+                Err(())
+            }
+        });
+        assert_eq!(Ok(2), result);
+    }
+}
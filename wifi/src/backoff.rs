@@ -0,0 +1,98 @@
+//! Capped exponential backoff between Wi-Fi reconnect attempts.
+
+use std::time::{Duration, Instant};
+use util::retry::backoff_delay;
+
+pub const DEFAULT_INITIAL_BACKOFF: Duration = Duration::from_secs(5);
+pub const DEFAULT_MAX_BACKOFF: Duration = Duration::from_secs(300);
+
+/// After the n-th consecutive failure the next attempt waits `initial * 2^(n-1)`, capped at `max`.
+#[derive(Debug, Clone)]
+pub struct Backoff {
+    initial: Duration,
+    max: Duration,
+    failures: u32,
+    next_attempt: Option<Instant>,
+}
+
+impl Backoff {
+    pub fn new(initial: Duration, max: Duration) -> Self {
+        Backoff {
+            initial,
+            max,
+            failures: 0,
+            next_attempt: None,
+        }
+    }
+
+    pub fn failures(&self) -> u32 {
+        self.failures
+    }
+
+    /// Wait after the failures so far; zero before the first failure.
+    pub fn delay(&self) -> Duration {
+        backoff_delay(self.initial, self.failures).min(self.max)
+    }
+
+    /// Whether an attempt may be made at `now`.
+    pub fn ready(&self, now: Instant) -> bool {
+        self.next_attempt.is_none_or(|at| now >= at)
+    }
+
+    pub fn record_failure(&mut self, now: Instant) {
+        self.failures = self.failures.saturating_add(1);
+        self.next_attempt = Some(now + self.delay());
+    }
+
+    pub fn reset(&mut self) {
+        self.failures = 0;
+        self.next_attempt = None;
+    }
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Backoff::new(DEFAULT_INITIAL_BACKOFF, DEFAULT_MAX_BACKOFF)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn delay_doubles_up_to_the_cap() {
+        let mut backoff = Backoff::default();
+        let now = Instant::now();
+        let mut delays = Vec::new();
+        for _ in 0..9 {
+            backoff.record_failure(now);
+            delays.push(backoff.delay().as_secs());
+        }
+        assert_eq!(vec![5, 10, 20, 40, 80, 160, 300, 300, 300], delays);
+    }
+
+    #[test]
+    fn attempts_wait_out_the_delay() {
+        let mut backoff = Backoff::default();
+        let now = Instant::now();
+        assert!(backoff.ready(now));
+        backoff.record_failure(now);
+        backoff.record_failure(now);
+        assert!(!backoff.ready(now + Duration::from_secs(9)));
+        assert!(backoff.ready(now + Duration::from_secs(10)));
+    }
+
+    #[test]
+    fn reset_allows_an_immediate_attempt() {
+        let mut backoff = Backoff::default();
+        let now = Instant::now();
+        for _ in 0..40 {
+            backoff.record_failure(now);
+        }
+        assert_eq!(DEFAULT_MAX_BACKOFF, backoff.delay());
+        backoff.reset();
+        assert!(backoff.ready(now));
+        assert_eq!(Duration::ZERO, backoff.delay());
+    }
+}
@@ -1,4 +1,7 @@
+pub mod backoff;
+
 pub mod wifi {
+    use crate::backoff::Backoff;
     use anyhow;
     use log::*;
     use esp_idf_svc::wifi::{
@@ -13,9 +16,88 @@ pub mod wifi {
     };
     use esp_idf_svc::eventloop::EspSystemEventLoop;
     use esp_idf_svc::nvs::EspDefaultNvsPartition;
-    use std::time::Duration;
+    use esp_idf_svc::sys::{esp, esp_wifi_sta_get_ap_info, wifi_ap_record_t};
+    use std::fmt;
+    use std::time::{Duration, Instant};
     use std::net::{IpAddr, Ipv4Addr};
-    use std::thread;
+
+    /// A timeout for `connect` and `reconnect_if_disconnected` that suits most sites.
+    pub const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+
+    /// Why a connection attempt timed out, as the error inside the `anyhow::Error` returned by
+    /// `Wifi::connect` (use `downcast_ref`).
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub enum ConnectError {
+        /// The station never associated with the AP (wrong SSID/password, out of range).
+        NotAssociated,
+        /// Associated, but DHCP never handed out an address.
+        NoIp,
+    }
+
+    impl fmt::Display for ConnectError {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            match self {
+                ConnectError::NotAssociated => write!(f, "Wi-Fi never associated with the access point"),
+                ConnectError::NoIp => write!(f, "Wi-Fi associated but got no IP address"),
+            }
+        }
+    }
+
+    impl std::error::Error for ConnectError {}
+
+    /// An access point seen by `Wifi::scan`.
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct AccessPoint {
+        pub ssid: String,
+        /// Signal strength in dBm.
+        pub rssi: i8,
+        pub channel: u8,
+        /// `None` if the driver couldn't tell.
+        pub auth_method: Option<AuthMethod>,
+    }
+
+    /// Authentication and PMF (802.11w) settings of the network `connect_with` joins.
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub struct WifiSecurity {
+        pub auth_method: AuthMethod,
+        pub pmf: PmfConfiguration,
+    }
+
+    impl WifiSecurity {
+        /// WPA3 Personal, which mandates PMF.
+        pub fn wpa3() -> Self {
+            WifiSecurity { auth_method: AuthMethod::WPA3Personal, pmf: PmfConfiguration::new_required() }
+        }
+
+        /// WPA2/WPA3 transition mode: PMF offered but not required, so WPA2-only APs still work.
+        pub fn wpa2_wpa3() -> Self {
+            WifiSecurity {
+                auth_method: AuthMethod::WPA2WPA3Personal,
+                pmf: PmfConfiguration::Capable { required: false },
+            }
+        }
+
+        pub fn open() -> Self {
+            WifiSecurity { auth_method: AuthMethod::None, pmf: PmfConfiguration::NotCapable }
+        }
+
+        /// Reject `pass` if it can't be used with this auth method.
+        pub fn validate(&self, pass: &str) -> anyhow::Result<()> {
+            match self.auth_method {
+                AuthMethod::None => Ok(()),
+                AuthMethod::WPA2Enterprise => Err(anyhow::anyhow!("WPA2 Enterprise is not supported")),
+                method if pass.is_empty() => Err(anyhow::anyhow!("{:?} needs a password", method)),
+                _ => Ok(()),
+            }
+        }
+    }
+
+    // WPA2 Personal without PMF, what every tower used before auth was configurable.
+    impl Default for WifiSecurity {
+        fn default() -> Self {
+            WifiSecurity { auth_method: AuthMethod::WPA2Personal, pmf: PmfConfiguration::NotCapable }
+        }
+    }
 
     /// Represents Wi-Fi connection states
     #[derive(Debug, PartialEq)]
@@ -28,6 +110,8 @@ pub mod wifi {
     /// The main Wi-Fi service abstraction
     pub struct Wifi<'a> {
         inner: BlockingWifi<EspWifi<'a>>,
+        // Spacing of `reconnect_with_backoff` attempts during an outage.
+        backoff: Backoff,
     }
 
     impl<'a> Wifi<'a> {
@@ -39,11 +123,25 @@ pub mod wifi {
         ) -> anyhow::Result<Self> {
             let esp_wifi = EspWifi::new(modem, sysloop.clone(), Some(nvs))?;
             let blocking = BlockingWifi::wrap(esp_wifi, sysloop)?;
-            Ok(Wifi { inner: blocking })
+            Ok(Wifi { inner: blocking, backoff: Backoff::default() })
         }
 
-        /// Configure and connect to a Wi-Fi network
-        pub fn connect(&mut self, ssid: &str, pass: &str) -> anyhow::Result<()> {
+        /// Configure and connect to a Wi-Fi network, waiting up to `timeout` for an IP. Uses the
+        /// default `WifiSecurity`, or an open network when `pass` is empty.
+        pub fn connect(&mut self, ssid: &str, pass: &str, timeout: Duration) -> anyhow::Result<()> {
+            let security = if pass.is_empty() { WifiSecurity::open() } else { WifiSecurity::default() };
+            self.connect_with(ssid, pass, security, timeout)
+        }
+
+        /// As `connect`, with explicit auth method and PMF settings (e.g. `WifiSecurity::wpa3()`).
+        pub fn connect_with(
+            &mut self,
+            ssid: &str,
+            pass: &str,
+            security: WifiSecurity,
+            timeout: Duration,
+        ) -> anyhow::Result<()> {
+            security.validate(pass)?;
             self.inner.set_configuration(&Configuration::Client(
                 ClientConfiguration {
                     ssid: { 
@@ -56,25 +154,102 @@ pub mod wifi {
                         p.push_str(pass).unwrap();
                         p
                     },
-                    auth_method: AuthMethod::WPA2Personal,
+                    auth_method: security.auth_method,
                     scan_method: ScanMethod::FastScan,
-                    pmf_cfg: PmfConfiguration::NotCapable,
+                    pmf_cfg: security.pmf,
                     ..Default::default()
                 },
             ))?;
 
             self.inner.start()?;
-            self.inner.connect()?;
-            self.inner.wait_netif_up()?;
+            self.connect_and_wait(timeout)
+        }
 
+        /// Connect to the first of `networks` (`(ssid, pass)` in priority order) that associates
+        /// and gets an IP within `timeout`, returning its SSID.
+        ///
+        /// Networks seen in a scan are tried first; the rest are still tried afterwards, as they
+        /// may be hidden. The error lists why every network failed.
+        pub fn connect_any(&mut self, networks: &[(&str, &str)], timeout: Duration) -> anyhow::Result<String> {
+            if networks.is_empty() {
+                return Err(anyhow::anyhow!("No Wi-Fi networks configured"));
+            }
+
+            let visible: Vec<String> = match self.scan() {
+                Ok(aps) => aps.into_iter().map(|ap| ap.ssid).collect(),
+                Err(e) => {
+                    warn!("Wi-Fi scan failed, trying every network: {:?}", e);
+                    Vec::new()
+                }
+            };
+            let (mut ordered, hidden): (Vec<_>, Vec<_>) = networks
+                .iter()
+                .partition(|(ssid, _)| visible.iter().any(|v| v.as_str() == *ssid));
+            ordered.extend(hidden);
 
-            // Wait up to 10s for connection
-            thread::sleep(Duration::from_secs(10)); 
+            let mut failures = Vec::new();
+            for (ssid, pass) in ordered {
+                info!("Trying Wi-Fi network {:?}", ssid);
+                match self.connect(ssid, pass, timeout) {
+                    Ok(()) => {
+                        info!("Connected to Wi-Fi network {:?}", ssid);
+                        return Ok(ssid.to_string());
+                    }
+                    Err(e) => {
+                        warn!("Wi-Fi network {:?} failed: {}", ssid, e);
+                        failures.push(format!("{}: {}", ssid, e));
+                        // Stop the failed attempt before reconfiguring for the next network.
+                        let _ = self.inner.wifi_mut().disconnect();
+                    }
+                }
+            }
+            Err(anyhow::anyhow!("No Wi-Fi network connected ({})", failures.join("; ")))
+        }
 
-            if !self.inner.is_connected()? {
-                return Err(anyhow::anyhow!("WiFi connection timeout"));
+        /// Access points in range, strongest first. Scanning needs the radio running, so it is
+        /// started in station mode (without connecting) if it isn't already.
+        pub fn scan(&mut self) -> anyhow::Result<Vec<AccessPoint>> {
+            if !self.inner.is_started()? {
+                self.inner.set_configuration(&Configuration::Client(ClientConfiguration::default()))?;
+                self.inner.start()?;
             }
+            let mut aps: Vec<AccessPoint> = self
+                .inner
+                .scan()?
+                .into_iter()
+                .map(|ap| AccessPoint {
+                    ssid: ap.ssid.to_string(),
+                    rssi: ap.signal_strength,
+                    channel: ap.channel,
+                    auth_method: ap.auth_method,
+                })
+                .collect();
+            aps.sort_by(|a, b| b.rssi.cmp(&a.rssi));
+            Ok(aps)
+        }
 
+        // Start associating and wait on the driver's events, returning as soon as the netif is
+        // up. Both waits share `timeout`.
+        fn connect_and_wait(&mut self, timeout: Duration) -> anyhow::Result<()> {
+            let started = Instant::now();
+            self.inner.wifi_mut().connect()?;
+
+            let associated = self
+                .inner
+                .wifi_wait_while(|| self.inner.is_connected().map(|c| !c), Some(timeout));
+            if associated.is_err() || !self.inner.is_connected()? {
+                return Err(ConnectError::NotAssociated.into());
+            }
+
+            let remaining = timeout.saturating_sub(started.elapsed());
+            let up = self
+                .inner
+                .ip_wait_while(|| self.inner.is_up().map(|up| !up), Some(remaining));
+            if up.is_err() || !self.inner.is_up()? {
+                return Err(ConnectError::NoIp.into());
+            }
+
+            info!("Wi-Fi up after {:?}", started.elapsed());
             Ok(())
         }
 
@@ -90,30 +265,61 @@ pub mod wifi {
             }
         }
 
-        pub fn reconnect_if_disconnected(&mut self) -> anyhow::Result<()>{
+        /// Signal strength of the AP the station is associated with, in dBm.
+        pub fn rssi(&self) -> Option<i8> {
+            let mut record = wifi_ap_record_t::default();
+            // SAFETY: only writes `record`; fails (ESP_ERR_WIFI_NOT_CONNECT) when not associated.
+            esp!(unsafe { esp_wifi_sta_get_ap_info(&mut record) })
+                .ok()
+                .map(|_| record.rssi)
+        }
+
+        /// Reconnect with the stored configuration if disconnected, waiting up to `timeout`.
+        /// A timeout is only logged; errors from the driver itself are returned.
+        pub fn reconnect_if_disconnected(&mut self, timeout: Duration) -> anyhow::Result<()>{
             // Check if the Wi-Fi is disconnected
             if self.state() == WifiState::Disconnected {
                 // Attempt to reconnect
                 self.inner.start()?;
-                self.inner.connect()?;
-
-                // Block for up to 10 seconds while waiting for the connection to establish
-                self.inner.wifi_wait_while(
-                    || Ok(self.state() == WifiState::Disconnected),
-                    Some(Duration::from_secs(10)),
-                )?;
-
-                // Check if the connection was successful
-                if matches!(self.state(), WifiState::Connected(_)) {
-                    info!("Successfully reconnected to Wi-Fi.");
-                } else {
-                    warn!("Failed to reconnect to Wi-Fi within 30 seconds.");
+                match self.connect_and_wait(timeout) {
+                    Ok(()) => info!("Successfully reconnected to Wi-Fi."),
+                    Err(e) => match e.downcast_ref::<ConnectError>() {
+                        Some(reason) => warn!("Failed to reconnect to Wi-Fi within {:?}: {}", timeout, reason),
+                        None => return Err(e),
+                    },
                 }
             }
 
             Ok(())
         }
 
+        /// Reconnect if disconnected, unless still backing off after earlier failures: attempts
+        /// are spaced 5s, 10s, 20s ... up to 5 minutes apart (see `backoff`), so this is cheap to
+        /// call often. Each attempt waits up to `timeout`. Returns whether Wi-Fi is connected.
+        pub fn reconnect_with_backoff(&mut self, timeout: Duration) -> anyhow::Result<bool> {
+            if self.state() != WifiState::Disconnected {
+                self.backoff.reset();
+                return Ok(true);
+            }
+            if !self.backoff.ready(Instant::now()) {
+                return Ok(false);
+            }
+
+            self.reconnect_if_disconnected(timeout)?;
+            if self.state() == WifiState::Disconnected {
+                self.backoff.record_failure(Instant::now());
+                warn!(
+                    "Wi-Fi reconnect failed {} time(s) in a row, next attempt in {:?}",
+                    self.backoff.failures(),
+                    self.backoff.delay()
+                );
+                Ok(false)
+            } else {
+                self.backoff.reset();
+                Ok(true)
+            }
+        }
+
         /// Disconnect from Wi-Fi
         pub fn disconnect(&mut self) -> anyhow::Result<()> {
             self.inner.disconnect()?;
@@ -121,3 +327,21 @@ pub mod wifi {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::wifi::WifiSecurity;
+
+    #[test]
+    fn personal_auth_needs_a_password() {
+        assert!(WifiSecurity::default().validate("").is_err());
+        assert!(WifiSecurity::wpa3().validate("").is_err());
+        assert!(WifiSecurity::wpa2_wpa3().validate("").is_err());
+        assert!(WifiSecurity::wpa3().validate("@Powerfuture22").is_ok());
+    }
+
+    #[test]
+    fn open_network_needs_no_password() {
+        assert!(WifiSecurity::open().validate("").is_ok());
+    }
+}